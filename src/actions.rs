@@ -1,4 +1,4 @@
-use super::scan::change::{same, same_strong, Change, LegacyChange};
+use super::scan::change::{same, same_strong, Change, ChangeV2, LegacyChange};
 use super::scan::DirEntryWithMeta as Entry;
 use colored::*;
 use serde::{Deserialize, Serialize};
@@ -13,10 +13,36 @@ pub enum Action {
     ResolvedLocal((Change, Change), Change),
     ResolvedRemote((Change, Change), Change),
     Identical(Change, Change), // need for bookkeeping
+    LocalMove(Move),
+    RemoteMove(Move),
 }
 
 pub type Actions = Vec<Action>;
 
+/// A removal and an addition on one side that relocate the same file or directory.
+/// `contents` holds the (old, new) descendants of a moved directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Move {
+    pub from: Entry,
+    pub to: Entry,
+    pub contents: Vec<(Entry, Entry)>,
+}
+
+impl Move {
+    /// The removals and additions this move replaces, in path order.
+    pub fn changes(&self) -> (Vec<Change>, Vec<Change>) {
+        let removed = std::iter::once(&self.from)
+            .chain(self.contents.iter().map(|(old, _)| old))
+            .map(|e| Change::Removed(e.clone()))
+            .collect();
+        let added = std::iter::once(&self.to)
+            .chain(self.contents.iter().map(|(_, new)| new))
+            .map(|e| Change::Added(e.clone()))
+            .collect();
+        (removed, added)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum LegacyAction {
     Local(LegacyChange),
@@ -46,17 +72,20 @@ impl From<LegacyAction> for Action {
     }
 }
 
-impl From<Action> for LegacyAction {
-    fn from(action: Action) -> Self {
-        match action {
-            Action::Local(c) => Self::Local(c.into()),
-            Action::Remote(c) => Self::Remote(c.into()),
-            Action::Conflict(a, b) => Self::Conflict(a.into(), b.into()),
-            Action::ResolvedLocal((a, b), c) => Self::ResolvedLocal((a.into(), b.into()), c.into()),
-            Action::ResolvedRemote((a, b), c) => {
-                Self::ResolvedRemote((a.into(), b.into()), c.into())
-            }
-            Action::Identical(a, b) => Self::Identical(a.into(), b.into()),
+fn legacy_action(action: Action) -> LegacyAction {
+    match action {
+        Action::Local(c) => LegacyAction::Local(c.into()),
+        Action::Remote(c) => LegacyAction::Remote(c.into()),
+        Action::Conflict(a, b) => LegacyAction::Conflict(a.into(), b.into()),
+        Action::ResolvedLocal((a, b), c) => {
+            LegacyAction::ResolvedLocal((a.into(), b.into()), c.into())
+        }
+        Action::ResolvedRemote((a, b), c) => {
+            LegacyAction::ResolvedRemote((a.into(), b.into()), c.into())
+        }
+        Action::Identical(a, b) => LegacyAction::Identical(a.into(), b.into()),
+        Action::LocalMove(_) | Action::RemoteMove(_) => {
+            unreachable!("moves are expanded before legacy conversion")
         }
     }
 }
@@ -66,9 +95,93 @@ pub fn from_legacy(actions: LegacyActions) -> Actions {
 }
 
 pub fn to_legacy(actions: Actions) -> LegacyActions {
+    expand_moves(actions)
+        .into_iter()
+        .map(legacy_action)
+        .collect()
+}
+
+/// Exact action layout used by the V2 RPCs, whose entries carry no extended attributes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ActionV2 {
+    Local(ChangeV2),
+    Remote(ChangeV2),
+    Conflict(ChangeV2, ChangeV2),
+    ResolvedLocal((ChangeV2, ChangeV2), ChangeV2),
+    ResolvedRemote((ChangeV2, ChangeV2), ChangeV2),
+    Identical(ChangeV2, ChangeV2),
+}
+
+pub type ActionsV2 = Vec<ActionV2>;
+
+impl From<ActionV2> for Action {
+    fn from(action: ActionV2) -> Self {
+        match action {
+            ActionV2::Local(c) => Self::Local(c.into()),
+            ActionV2::Remote(c) => Self::Remote(c.into()),
+            ActionV2::Conflict(a, b) => Self::Conflict(a.into(), b.into()),
+            ActionV2::ResolvedLocal((a, b), c) => {
+                Self::ResolvedLocal((a.into(), b.into()), c.into())
+            }
+            ActionV2::ResolvedRemote((a, b), c) => {
+                Self::ResolvedRemote((a.into(), b.into()), c.into())
+            }
+            ActionV2::Identical(a, b) => Self::Identical(a.into(), b.into()),
+        }
+    }
+}
+
+fn v2_action(action: Action) -> ActionV2 {
+    match action {
+        Action::Local(c) => ActionV2::Local(c.into()),
+        Action::Remote(c) => ActionV2::Remote(c.into()),
+        Action::Conflict(a, b) => ActionV2::Conflict(a.into(), b.into()),
+        Action::ResolvedLocal((a, b), c) => ActionV2::ResolvedLocal((a.into(), b.into()), c.into()),
+        Action::ResolvedRemote((a, b), c) => {
+            ActionV2::ResolvedRemote((a.into(), b.into()), c.into())
+        }
+        Action::Identical(a, b) => ActionV2::Identical(a.into(), b.into()),
+        Action::LocalMove(_) | Action::RemoteMove(_) => {
+            unreachable!("moves are expanded before V2 conversion")
+        }
+    }
+}
+
+pub fn from_v2(actions: ActionsV2) -> Actions {
     actions.into_iter().map(Into::into).collect()
 }
 
+pub fn to_v2(actions: Actions) -> ActionsV2 {
+    expand_moves(actions).into_iter().map(v2_action).collect()
+}
+
+/// Replaces every move with the removals and additions it was paired from.
+pub fn expand_moves(actions: Actions) -> Actions {
+    if !actions.iter().any(Action::is_move) {
+        return actions;
+    }
+    let mut expanded = Vec::with_capacity(actions.len());
+    for action in actions {
+        let (side, m): (fn(Change) -> Action, Move) = match action {
+            Action::LocalMove(m) => (Action::Local, m),
+            Action::RemoteMove(m) => (Action::Remote, m),
+            action => {
+                expanded.push(action);
+                continue;
+            }
+        };
+        let (removed, added) = m.changes();
+        expanded.extend(removed.into_iter().chain(added).map(side));
+    }
+    expanded.sort_by(|a, b| a.path().cmp(b.path()));
+    expanded
+}
+
+/// Separates moves, which are applied before any other action, from the rest.
+pub fn split_moves(actions: Actions) -> (Actions, Actions) {
+    actions.into_iter().partition(Action::is_move)
+}
+
 impl Action {
     pub fn create(loc: Option<&Change>, roc: Option<&Change>) -> Option<Action> {
         match (loc, roc) {
@@ -116,6 +229,10 @@ impl Action {
         }
     }
 
+    pub fn is_move(&self) -> bool {
+        matches!(self, Action::LocalMove(_) | Action::RemoteMove(_))
+    }
+
     pub fn is_identical(&self) -> bool {
         if let Action::Identical(_, _) = self {
             true
@@ -132,6 +249,7 @@ impl Action {
             Action::ResolvedLocal((_, _), l) => l.path(),
             Action::ResolvedRemote((_, _), r) => r.path(),
             Action::Identical(l, _r) => l.path(),
+            Action::LocalMove(m) | Action::RemoteMove(m) => m.to.path(),
         }
     }
 }
@@ -164,6 +282,8 @@ pub fn reverse(actions: &Vec<Action>) -> Vec<Action> {
                 Action::ResolvedLocal((o.clone(), n.clone()), r.clone())
             }
             Action::Identical(l, r) => Action::Identical(r.clone(), l.clone()),
+            Action::LocalMove(m) => Action::RemoteMove(m.clone()),
+            Action::RemoteMove(m) => Action::LocalMove(m.clone()),
         })
         .collect()
 }
@@ -229,6 +349,20 @@ impl fmt::Display for Action {
             Action::ResolvedLocal((_, _), l) => write!(f, "  <==== {} {}", l, show_path(l.path())),
             Action::ResolvedRemote((_, _), r) => write!(f, "{} ====>   {}", r, show_path(r.path())),
             Action::Identical(l, r) => write!(f, "{} --I-- {} {}", l, r, show_path(l.path())),
+            Action::LocalMove(m) => write!(
+                f,
+                "  <---- {} {} (from {})",
+                "R".cyan(),
+                show_path(m.to.path()),
+                show_path(m.from.path())
+            ),
+            Action::RemoteMove(m) => write!(
+                f,
+                "{} ---->   {} (from {})",
+                "R".cyan(),
+                show_path(m.to.path()),
+                show_path(m.from.path())
+            ),
         }
     }
 }
@@ -246,6 +380,46 @@ mod tests {
         assert!(shown.contains("\\u{1b}"));
         assert!(shown.contains("\\n"));
     }
+
+    #[test]
+    fn special_entries_show_their_kind_and_device_number() {
+        let fifo = Entry::test_special(PathBuf::from("fifo"), libc::S_IFIFO | 0o644, 0);
+        assert!(show_meta(&fifo, &fifo).starts_with("prw-r--r--"));
+
+        let null = Entry::test_special(
+            PathBuf::from("null"),
+            libc::S_IFCHR | 0o666,
+            libc::makedev(1, 3) as u64,
+        );
+        let shown = show_meta(&null, &null);
+        assert!(shown.starts_with("crw-rw-rw-"));
+        assert!(shown.ends_with(" 1,3"));
+    }
+
+    #[test]
+    fn reversed_moves_expand_to_removals_and_additions_on_the_other_side() {
+        let m = Move {
+            from: Entry::test_dir(PathBuf::from("old")),
+            to: Entry::test_dir(PathBuf::from("new")),
+            contents: vec![(
+                Entry::test_file(PathBuf::from("old/leaf"), 1),
+                Entry::test_file(PathBuf::from("new/leaf"), 1),
+            )],
+        };
+
+        let expanded = expand_moves(reverse(&vec![Action::LocalMove(m)]));
+
+        let paths: Vec<_> = expanded
+            .iter()
+            .map(|action| action.path().clone())
+            .collect();
+        assert_eq!(
+            paths,
+            ["new", "new/leaf", "old", "old/leaf"].map(PathBuf::from)
+        );
+        assert!(matches!(expanded[0], Action::Remote(Change::Added(_))));
+        assert!(matches!(expanded[3], Action::Remote(Change::Removed(_))));
+    }
 }
 
 pub fn details(action: &Action) -> String {
@@ -280,6 +454,7 @@ pub fn details(action: &Action) -> String {
             )
         }
         Action::Identical(l, _) => format!("{}", show_meta(change_entry(l), change_entry(l))),
+        Action::LocalMove(m) | Action::RemoteMove(m) => show_meta(&m.to, &m.from),
     }
 }
 
@@ -302,33 +477,73 @@ fn show_meta(e: &Entry, before: &Entry) -> String {
         // permissions don't matter
         format!(
             "{} -> {}",
-            highlight(show_mtime(e), e.mtime() != before.mtime()),
+            highlight(show_mtime(e, before), !e.same_mtime(before)),
             highlight(
                 e.target().as_ref().unwrap().display().to_string(),
                 e.target() != before.target()
             )
         )
+    } else if e.is_special() {
+        // the mode string already shows the kind of node
+        format!(
+            "{}{} {}{}",
+            show_ownership(e, before),
+            highlight(show_permissions(e), e.mode() != before.mode()),
+            highlight(show_mtime(e, before), !e.same_mtime(before)),
+            show_device(e, before)
+        )
     } else if e.is_dir() {
         format!(
-            "{} {}",
+            "{}{} {}",
+            show_ownership(e, before),
             highlight(show_permissions(e), e.mode() != before.mode()),
-            highlight(show_mtime(e), e.mtime() != before.mtime())
+            highlight(show_mtime(e, before), !e.same_mtime(before))
         )
     } else {
         format!(
-            "{} {} {} {}",
+            "{}{} {} {} {}",
+            show_ownership(e, before),
             highlight(show_permissions(e), e.mode() != before.mode()),
-            highlight(show_mtime(e), e.mtime() != before.mtime()),
+            highlight(show_mtime(e, before), !e.same_mtime(before)),
             highlight(show_size(e), e.size() != before.size()),
             highlight(show_checksum(e), e.checksum() != before.checksum())
         )
     }
 }
 
-fn show_mtime(e: &Entry) -> String {
-    let mtime = e.mtime();
+fn show_ownership(e: &Entry, before: &Entry) -> String {
+    match e.ownership() {
+        Some(ownership) => format!(
+            "{} ",
+            highlight(ownership.to_string(), !e.same_ownership(before))
+        ),
+        None => String::new(),
+    }
+}
+
+fn show_device(e: &Entry, before: &Entry) -> String {
+    if !e.is_device() {
+        return String::new();
+    }
+    let rdev = e.rdev() as libc::dev_t;
+    format!(
+        " {}",
+        highlight(
+            format!("{},{}", libc::major(rdev), libc::minor(rdev)),
+            e.rdev() != before.rdev()
+        )
+    )
+}
+
+fn show_mtime(e: &Entry, before: &Entry) -> String {
     use chrono::prelude::*;
-    let date_time = Local.timestamp_opt(mtime, 0).unwrap();
+    // nanoseconds only matter when they are all that changed
+    if e.mtime() == before.mtime() && !e.same_mtime(before) {
+        let nsec = e.mtime_nsec().unwrap_or(0);
+        let date_time = Local.timestamp_opt(e.mtime(), nsec).unwrap();
+        return date_time.format("%a %Y-%m-%d %H:%M:%S%.9f").to_string();
+    }
+    let date_time = Local.timestamp_opt(e.mtime(), 0).unwrap();
     date_time.format("%a %Y-%m-%d %H:%M:%S").to_string()
 }
 
@@ -0,0 +1,354 @@
+//! `--bwlimit`: token buckets that pace the detail stream in each direction.
+//!
+//! The client drives both directions of a stream, pulling the remote's frames and pushing its
+//! own, so it paces both: it waits before asking for the next remote batch once the download
+//! bucket is in debt, and before sending each batch or file byte chunk once the upload bucket
+//! is. Buckets hold a quarter second of their rate, and while a limit is set the client asks for
+//! frames no larger than that, so the link sees steady traffic rather than bursts between
+//! sleeps.
+//!
+//! `SIGUSR1` halves both caps and `SIGUSR2` doubles them for the rest of the sync.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::performance::{duration_ms, BandwidthDirection, BandwidthProfile};
+use crate::sync::SyncTuning;
+
+/// How much traffic a bucket lets through at once, and how long a frame lasts at the cap.
+const BURST: Duration = Duration::from_millis(250);
+/// The smallest frame the client asks for, however low the cap.
+const MIN_CHUNK_BYTES: u64 = 16 * 1024;
+/// Halving stops here, so that a run of signals cannot stall the sync.
+const MIN_RATE: u64 = 1024;
+
+/// Caps in bytes per second; `None` leaves a direction unthrottled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BandwidthLimit {
+    pub upload: Option<u64>,
+    pub download: Option<u64>,
+}
+
+impl BandwidthLimit {
+    /// Shrinks the negotiated frame sizes to what a bucket lets through at once, so that the
+    /// client can pace the stream. Frames only get smaller, which every peer accepts.
+    pub(crate) fn limit_tuning(&self, tuning: SyncTuning) -> SyncTuning {
+        let Some(rate) = [self.upload, self.download].iter().flatten().copied().min() else {
+            return tuning;
+        };
+        let chunk = burst_bytes(rate).max(MIN_CHUNK_BYTES);
+        let chunk = u32::try_from(chunk).unwrap_or(u32::MAX);
+        SyncTuning {
+            detail_chunk_bytes: tuning.detail_chunk_bytes.min(chunk),
+            detail_batch_payload_bytes: tuning.detail_batch_payload_bytes.min(chunk),
+            ..tuning
+        }
+    }
+}
+
+/// `<rate>` for both directions, or `<upload>/<download>`, where a rate is a size per second
+/// such as `2MiB` and `0` leaves one direction of the pair unthrottled.
+impl std::str::FromStr for BandwidthLimit {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        let rate = |value: &str| {
+            crate::cli::parse_size(value)
+                .map(|rate| Some(rate).filter(|rate| *rate > 0))
+                .map_err(|error| error.replace("size", "rate"))
+        };
+        let limit = match value.split_once('/') {
+            Some((upload, download)) => Self {
+                upload: rate(upload)?,
+                download: rate(download)?,
+            },
+            None => {
+                let rate = rate(value)?;
+                Self {
+                    upload: rate,
+                    download: rate,
+                }
+            }
+        };
+        if limit.upload.is_none() && limit.download.is_none() {
+            return Err("rate must be greater than zero".to_string());
+        }
+        Ok(limit)
+    }
+}
+
+impl fmt::Display for BandwidthLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rate = |rate: Option<u64>| match rate {
+            Some(rate) => format!("{}/s", indicatif::HumanBytes(rate)),
+            None => "unlimited".to_string(),
+        };
+        if self.upload == self.download {
+            write!(f, "{}", rate(self.upload))
+        } else {
+            write!(
+                f,
+                "upload {}, download {}",
+                rate(self.upload),
+                rate(self.download)
+            )
+        }
+    }
+}
+
+fn burst_bytes(rate: u64) -> u64 {
+    (rate as f64 * BURST.as_secs_f64()) as u64
+}
+
+/// Tokens are bytes. A take that overdraws the bucket leaves it in debt, and the taker waits
+/// until the debt is repaid, so a frame larger than the bucket still passes at the cap.
+#[derive(Debug)]
+struct TokenBucket {
+    rate: Option<u64>,
+    tokens: f64,
+    last: Instant,
+    bytes: u64,
+    throttled: Duration,
+}
+
+impl TokenBucket {
+    fn new(rate: Option<u64>, now: Instant) -> Self {
+        Self {
+            rate,
+            tokens: rate.map_or(0.0, |rate| burst_bytes(rate) as f64),
+            last: now,
+            bytes: 0,
+            throttled: Duration::ZERO,
+        }
+    }
+
+    /// Takes `bytes` tokens and returns how long to wait before sending them.
+    fn take(&mut self, bytes: u64, now: Instant) -> Duration {
+        self.bytes += bytes;
+        let Some(rate) = self.rate else {
+            return Duration::ZERO;
+        };
+        let refill = now.saturating_duration_since(self.last).as_secs_f64() * rate as f64;
+        self.tokens = (self.tokens + refill).min(burst_bytes(rate) as f64) - bytes as f64;
+        self.last = now;
+        if self.tokens >= 0.0 {
+            return Duration::ZERO;
+        }
+        let wait = Duration::from_secs_f64(-self.tokens / rate as f64);
+        self.throttled += wait;
+        wait
+    }
+
+    fn scale(&mut self, faster: bool) {
+        self.rate = self.rate.map(|rate| {
+            if faster {
+                rate.saturating_mul(2)
+            } else {
+                (rate / 2).max(MIN_RATE)
+            }
+        });
+    }
+
+    fn profile(&self, stream: Duration) -> BandwidthDirection {
+        let seconds = stream.as_secs_f64();
+        BandwidthDirection {
+            cap_bytes_per_sec: self.rate,
+            bytes: self.bytes,
+            throttled_ms: duration_ms(self.throttled),
+            achieved_bytes_per_sec: if seconds > 0.0 {
+                (self.bytes as f64 / seconds) as u64
+            } else {
+                0
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Buckets {
+    upload: TokenBucket,
+    download: TokenBucket,
+    stream: Duration,
+    adjustments: u64,
+}
+
+/// The buckets of one sync, shared with the signal task that adjusts them.
+#[derive(Debug, Clone)]
+pub(crate) struct Throttle(Arc<Mutex<Buckets>>);
+
+impl Throttle {
+    pub(crate) fn new(limit: BandwidthLimit) -> Self {
+        let now = Instant::now();
+        Self(Arc::new(Mutex::new(Buckets {
+            upload: TokenBucket::new(limit.upload, now),
+            download: TokenBucket::new(limit.download, now),
+            stream: Duration::ZERO,
+            adjustments: 0,
+        })))
+    }
+
+    fn buckets(&self) -> std::sync::MutexGuard<'_, Buckets> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Waits until `bytes` more may go to the remote.
+    pub(crate) async fn upload(&self, bytes: u64) {
+        let wait = self.buckets().upload.take(bytes, Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Charges `bytes` that came from the remote, and waits until the next batch may.
+    pub(crate) async fn download(&self, bytes: u64) {
+        let wait = self.buckets().download.take(bytes, Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Adds the wall-clock time of a stream, over which the achieved rates are measured.
+    pub(crate) fn record_stream(&self, elapsed: Duration) {
+        self.buckets().stream += elapsed;
+    }
+
+    fn adjust(&self, faster: bool) -> BandwidthLimit {
+        let mut buckets = self.buckets();
+        buckets.upload.scale(faster);
+        buckets.download.scale(faster);
+        buckets.adjustments += 1;
+        BandwidthLimit {
+            upload: buckets.upload.rate,
+            download: buckets.download.rate,
+        }
+    }
+
+    /// Halves the caps on `SIGUSR1` and doubles them on `SIGUSR2`, for as long as the throttle
+    /// is in use.
+    pub(crate) fn watch_signals(&self) -> std::io::Result<()> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut slower = signal(SignalKind::user_defined1())?;
+        let mut faster = signal(SignalKind::user_defined2())?;
+        let buckets = Arc::downgrade(&self.0);
+        tokio::spawn(async move {
+            loop {
+                let faster = tokio::select! {
+                    Some(()) = slower.recv() => false,
+                    Some(()) = faster.recv() => true,
+                    else => return,
+                };
+                // the sync this throttle paced is over
+                let Some(buckets) = buckets.upgrade() else {
+                    return;
+                };
+                eprintln!("bandwidth limit now {}", Self(buckets).adjust(faster));
+            }
+        });
+        Ok(())
+    }
+
+    pub(crate) fn profile(&self) -> BandwidthProfile {
+        let buckets = self.buckets();
+        BandwidthProfile {
+            stream_ms: duration_ms(buckets.stream),
+            adjustments: buckets.adjustments,
+            upload: buckets.upload.profile(buckets.stream),
+            download: buckets.download.profile(buckets.stream),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_one_rate_or_an_upload_and_download_pair() {
+        assert_eq!(
+            "1MiB".parse(),
+            Ok(BandwidthLimit {
+                upload: Some(1024 * 1024),
+                download: Some(1024 * 1024),
+            })
+        );
+        assert_eq!(
+            "500KB/0".parse(),
+            Ok(BandwidthLimit {
+                upload: Some(500_000),
+                download: None,
+            })
+        );
+        assert!("0".parse::<BandwidthLimit>().is_err());
+        assert!("0/0".parse::<BandwidthLimit>().is_err());
+        assert!("fast".parse::<BandwidthLimit>().is_err());
+        assert_eq!(
+            "1MiB/4MiB".parse::<BandwidthLimit>().unwrap().to_string(),
+            "upload 1.00 MiB/s, download 4.00 MiB/s"
+        );
+    }
+
+    #[test]
+    fn bucket_lets_a_burst_through_and_then_paces_at_the_rate() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(Some(4000), start);
+        assert_eq!(bucket.take(1000, start), Duration::ZERO);
+        // the bucket is empty, so 2000 bytes more wait half a second
+        assert_eq!(bucket.take(2000, start), Duration::from_millis(500));
+        // the debt is repaid after half a second, and the bucket is full again by a second
+        let later = start + Duration::from_millis(1500);
+        assert_eq!(bucket.take(1000, later), Duration::ZERO);
+        assert_eq!(bucket.bytes, 4000);
+        assert_eq!(bucket.throttled, Duration::from_millis(500));
+
+        let mut unlimited = TokenBucket::new(None, start);
+        assert_eq!(unlimited.take(u64::MAX, start), Duration::ZERO);
+    }
+
+    #[test]
+    fn each_direction_is_throttled_by_its_own_bucket() {
+        let throttled = |limit: &str, upload: u64, download: u64| {
+            let throttle = Throttle::new(limit.parse().unwrap());
+            let now = Instant::now();
+            {
+                let mut buckets = throttle.buckets();
+                buckets.upload.take(upload, now);
+                buckets.download.take(download, now);
+            }
+            let profile = throttle.profile();
+            (
+                profile.upload.throttled_ms > 0,
+                profile.download.throttled_ms > 0,
+            )
+        };
+        let megabyte = 1024 * 1024;
+        assert_eq!(throttled("64KiB/0", megabyte, megabyte), (true, false));
+        assert_eq!(throttled("0/64KiB", megabyte, megabyte), (false, true));
+        assert_eq!(throttled("64KiB", megabyte, megabyte), (true, true));
+        // traffic in one direction does not drain the other's bucket
+        assert_eq!(throttled("64KiB", megabyte, 0), (true, false));
+        assert_eq!(throttled("64KiB", 0, megabyte), (false, true));
+    }
+
+    #[test]
+    fn signals_scale_the_limited_directions_and_limits_shrink_frames() {
+        let throttle = Throttle::new("64KiB/0".parse().unwrap());
+        let limit = throttle.adjust(false);
+        assert_eq!(limit.upload, Some(32 * 1024));
+        assert_eq!(limit.download, None);
+        assert_eq!(throttle.adjust(true).upload, Some(64 * 1024));
+        assert_eq!(throttle.profile().adjustments, 2);
+
+        let tuning = limit.limit_tuning(SyncTuning::preferred());
+        assert_eq!(tuning.detail_chunk_bytes, 16 * 1024);
+        assert_eq!(tuning.detail_batch_payload_bytes, 16 * 1024);
+        assert_eq!(
+            tuning.detail_batch_frames,
+            SyncTuning::preferred().detail_batch_frames
+        );
+    }
+}
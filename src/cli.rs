@@ -2,6 +2,7 @@ use std::path::PathBuf;
 
 use color_eyre::eyre::{eyre, Result};
 
+use crate::bwlimit::BandwidthLimit;
 use crate::profile::ProfileSource;
 use crate::sync::{StagingPolicy, StagingReserve};
 
@@ -21,6 +22,48 @@ pub struct SyncOptions {
     pub staging_policy: StagingPolicy,
     pub staging_policy_explicit: bool,
     pub staging_reserve_explicit: bool,
+    /// The deflate level asked of the peer for detail frames; `None` for the profile's or the
+    /// default one.
+    pub compression: Option<u8>,
+    /// Caps on the detail stream in each direction; `None` for the profile's, if any.
+    pub bwlimit: Option<BandwidthLimit>,
+    /// Serve a peer on this host from a `duet --server` child process rather than in-process.
+    pub server_process: bool,
+    pub negated: NegatedOptions,
+}
+
+/// Profile `[options]` defaults turned off on the command line with `--no-` flags.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NegatedOptions {
+    pub batch: bool,
+    pub force: bool,
+    pub verbose: bool,
+    pub prune_ignored: bool,
+    /// `--no-exclude`: the profile's excludes; those given with `--exclude` still apply.
+    pub excludes: bool,
+    pub staging_limit: bool,
+    pub profile_performance_json: bool,
+    pub bwlimit: bool,
+}
+
+/// Where `duet --server --listen` accepts `tcp://` clients, and the key they must hold.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Listen {
+    pub address: String,
+    pub key_file: PathBuf,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TrashCommand {
+    List,
+    /// Trash names as listed, `<run>/<path>`.
+    Restore {
+        items: Vec<PathBuf>,
+    },
+    /// Apply the profile's retention, or empty the trash with `all`.
+    Purge {
+        all: bool,
+    },
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -30,7 +73,9 @@ pub enum Command {
         verbose: bool,
     },
     License,
-    Server,
+    Server {
+        listen: Option<Listen>,
+    },
     Snapshot {
         profile: String,
         statefile: Option<PathBuf>,
@@ -54,6 +99,18 @@ pub enum Command {
         clear: bool,
         yes: bool,
     },
+    Trash {
+        command: TrashCommand,
+        profile: String,
+        remote: bool,
+    },
+    CheckProfile {
+        profile: String,
+    },
+    Status {
+        profiles: Vec<String>,
+        remote: bool,
+    },
     Sync {
         profile: ProfileSource,
         path: Option<PathBuf>,
@@ -83,8 +140,16 @@ fn parse(mut pargs: pico_args::Arguments) -> Result<Command> {
     }
 
     if pargs.contains("--server") {
+        let address: Option<String> = pargs.opt_value_from_str("--listen")?;
+        let key_file = pargs.opt_value_from_os_str("--key-file", parse_path)?;
         ensure_no_args(pargs)?;
-        return Ok(Command::Server);
+        let listen = match (address, key_file) {
+            (Some(address), Some(key_file)) => Some(Listen { address, key_file }),
+            (None, None) => None,
+            (Some(_), None) => return Err(eyre!("--listen requires --key-file")),
+            (None, Some(_)) => return Err(eyre!("--key-file requires --listen")),
+        };
+        return Ok(Command::Server { listen });
     }
 
     let profile_file = pargs.opt_value_from_os_str("--profile-file", parse_path)?;
@@ -93,6 +158,19 @@ fn parse(mut pargs: pico_args::Arguments) -> Result<Command> {
     let staging_limit: Option<StagingLimit> = pargs.opt_value_from_str("--staging-limit")?;
     let staging_reserve = pargs.opt_value_from_str("--staging-reserve")?;
     let excludes = pargs.values_from_os_str("--exclude", parse_path)?;
+    let compression = pargs.opt_value_from_fn("--compression", parse_compression_level)?;
+    let bwlimit = pargs.opt_value_from_str("--bwlimit")?;
+
+    let negated = NegatedOptions {
+        batch: pargs.contains("--no-batch"),
+        force: pargs.contains("--no-force"),
+        verbose: pargs.contains("--no-verbose"),
+        prune_ignored: pargs.contains("--no-prune-ignored"),
+        excludes: pargs.contains("--no-exclude"),
+        staging_limit: pargs.contains("--no-staging-limit"),
+        profile_performance_json: pargs.contains("--no-profile-performance-json"),
+        bwlimit: pargs.contains("--no-bwlimit"),
+    };
 
     let staging_policy_explicit = staging_limit.is_some() || staging_reserve.is_some();
     let options = SyncOptions {
@@ -113,7 +191,12 @@ fn parse(mut pargs: pico_args::Arguments) -> Result<Command> {
         },
         staging_policy_explicit,
         staging_reserve_explicit: staging_reserve.is_some(),
+        compression,
+        bwlimit,
+        server_process: pargs.contains("--server-process"),
+        negated,
     };
+    reject_negated_options(&options)?;
 
     if let Some(profile_file) = profile_file {
         let path = pargs.opt_free_from_os_str(parse_path)?;
@@ -186,6 +269,66 @@ fn parse(mut pargs: pico_args::Arguments) -> Result<Command> {
                 yes: options.yes,
             }
         }
+        "trash" => {
+            let remote = pargs.contains("--remote");
+            let all = pargs.contains("--all");
+            reject_sync_options(&options)?;
+            let action: String = pargs.free_from_str()?;
+            let profile = pargs.free_from_str()?;
+            let command = match action.as_str() {
+                "list" => TrashCommand::List,
+                "restore" => {
+                    let mut items = Vec::new();
+                    while let Some(item) = pargs.opt_free_from_os_str(parse_path)? {
+                        items.push(item);
+                    }
+                    if items.is_empty() {
+                        return Err(eyre!("trash restore requires the <run>/<path> to restore"));
+                    }
+                    TrashCommand::Restore { items }
+                }
+                "purge" => TrashCommand::Purge { all },
+                _ => {
+                    return Err(eyre!(
+                        "unknown trash command: {} (expected list, restore, or purge)",
+                        action
+                    ))
+                }
+            };
+            if all && !matches!(command, TrashCommand::Purge { .. }) {
+                return Err(eyre!("--all is only supported for trash purge"));
+            }
+            Command::Trash {
+                command,
+                profile,
+                remote,
+            }
+        }
+        "status" => {
+            let remote = pargs.contains("--remote");
+            reject_sync_options(&options)?;
+            let mut profiles = Vec::new();
+            while let Some(profile) = pargs.opt_free_from_str::<String>()? {
+                if profile.starts_with('-') {
+                    return Err(eyre!("unexpected argument: {}", profile));
+                }
+                profiles.push(profile);
+            }
+            Command::Status { profiles, remote }
+        }
+        "profile" => {
+            reject_sync_options(&options)?;
+            let action: String = pargs.free_from_str()?;
+            if action != "check" {
+                return Err(eyre!(
+                    "unknown profile command: {} (expected check)",
+                    action
+                ));
+            }
+            Command::CheckProfile {
+                profile: pargs.free_from_str()?,
+            }
+        }
         _ => Command::Sync {
             profile: ProfileSource::Named(profile),
             path: pargs.opt_free_from_os_str(parse_path)?,
@@ -209,6 +352,10 @@ fn reject_sync_options(options: &SyncOptions) -> Result<()> {
         || options.profile_performance
         || options.profile_performance_json.is_some()
         || options.staging_policy_explicit
+        || options.compression.is_some()
+        || options.bwlimit.is_some()
+        || options.server_process
+        || options.negated != NegatedOptions::default()
     {
         Err(eyre!("sync options are not supported for this command"))
     } else {
@@ -216,6 +363,36 @@ fn reject_sync_options(options: &SyncOptions) -> Result<()> {
     }
 }
 
+fn reject_negated_options(options: &SyncOptions) -> Result<()> {
+    let negated = &options.negated;
+    for (flag, given, negated) in [
+        ("batch", options.batch, negated.batch),
+        ("force", options.force, negated.force),
+        ("verbose", options.verbose, negated.verbose),
+        (
+            "prune-ignored",
+            options.prune_ignored,
+            negated.prune_ignored,
+        ),
+        (
+            "staging-limit",
+            options.staging_policy.limit_bytes.is_some(),
+            negated.staging_limit,
+        ),
+        (
+            "profile-performance-json",
+            options.profile_performance_json.is_some(),
+            negated.profile_performance_json,
+        ),
+        ("bwlimit", options.bwlimit.is_some(), negated.bwlimit),
+    ] {
+        if given && negated {
+            return Err(eyre!("--{flag} and --no-{flag} cannot be used together"));
+        }
+    }
+    Ok(())
+}
+
 fn reject_recover_options(options: &SyncOptions, clear: bool) -> Result<()> {
     if options.interactive
         || options.dry_run
@@ -228,6 +405,10 @@ fn reject_recover_options(options: &SyncOptions, clear: bool) -> Result<()> {
         || options.profile_performance
         || options.profile_performance_json.is_some()
         || options.staging_policy_explicit
+        || options.compression.is_some()
+        || options.bwlimit.is_some()
+        || options.server_process
+        || options.negated != NegatedOptions::default()
         || (options.yes && !clear)
     {
         Err(eyre!(
@@ -258,7 +439,10 @@ fn ensure_help_args(pargs: pico_args::Arguments) -> Result<()> {
 
     if remaining.len() == 1 {
         let arg = remaining[0].to_string_lossy();
-        if matches!(arg.as_ref(), "recover" | "_recover") {
+        if matches!(
+            arg.as_ref(),
+            "recover" | "_recover" | "trash" | "profile" | "status"
+        ) {
             return Ok(());
         }
     }
@@ -273,7 +457,7 @@ fn parse_path(s: &std::ffi::OsStr) -> Result<PathBuf, &'static str> {
     Ok(s.into())
 }
 
-fn parse_size(value: &str) -> Result<u64, String> {
+pub(crate) fn parse_size(value: &str) -> Result<u64, String> {
     if value.is_empty() || value.trim() != value {
         return Err("size must be a nonempty value without surrounding whitespace".to_string());
     }
@@ -283,6 +467,55 @@ fn parse_size(value: &str) -> Result<u64, String> {
         .ok_or_else(|| "size exceeds the maximum supported value".to_string())
 }
 
+/// An age such as `12h`, `30d`, or `2w`, in seconds.
+pub(crate) fn parse_age(value: &str) -> Result<u64, String> {
+    let invalid = || "age must be a whole number followed by h, d, or w".to_string();
+    let unit_seconds = match value.chars().last() {
+        Some('h') => 60 * 60,
+        Some('d') => 24 * 60 * 60,
+        Some('w') => 7 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    let number = &value[..value.len() - 1];
+    if number.is_empty() || !number.bytes().all(|byte| byte.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(unit_seconds))
+        .ok_or_else(|| "age is out of range".to_string())
+}
+
+/// A deflate level for detail frames, from 0 for none to 10.
+pub(crate) fn parse_compression_level(value: &str) -> Result<u8, String> {
+    match value.parse::<u8>() {
+        Ok(level) if level <= crate::sync::MAX_COMPRESSION_LEVEL => Ok(level),
+        _ => Err(format!(
+            "compression level must be a number from 0 to {}",
+            crate::sync::MAX_COMPRESSION_LEVEL
+        )),
+    }
+}
+
+/// A duration such as `30`, `30s`, or `2m`, in seconds.
+pub(crate) fn parse_seconds(value: &str) -> Result<u64, String> {
+    let (number, unit_seconds) = match value.strip_suffix('m') {
+        Some(minutes) => (minutes, 60),
+        None => (value.strip_suffix('s').unwrap_or(value), 1),
+    };
+    if number.is_empty() || !number.bytes().all(|byte| byte.is_ascii_digit()) {
+        return Err(
+            "duration must be a whole number of seconds, or minutes followed by m".to_string(),
+        );
+    }
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(unit_seconds))
+        .ok_or_else(|| "duration is out of range".to_string())
+}
+
 impl std::str::FromStr for StagingReserve {
     type Err = String;
 
@@ -322,7 +555,7 @@ impl std::str::FromStr for StagingReserve {
     }
 }
 
-struct StagingLimit(u64);
+pub(crate) struct StagingLimit(pub(crate) u64);
 
 impl std::str::FromStr for StagingLimit {
     type Err = String;
@@ -372,6 +605,10 @@ mod tests {
             staging_policy: StagingPolicy::default(),
             staging_policy_explicit: false,
             staging_reserve_explicit: false,
+            compression: None,
+            bwlimit: None,
+            server_process: false,
+            negated: NegatedOptions::default(),
         }
     }
 
@@ -391,7 +628,24 @@ mod tests {
             Command::Version { verbose: true }
         );
         assert_eq!(parse_args(&["--license"]), Command::License);
-        assert_eq!(parse_args(&["--server"]), Command::Server);
+        assert_eq!(parse_args(&["--server"]), Command::Server { listen: None });
+        assert_eq!(
+            parse_args(&[
+                "--server",
+                "--listen",
+                "0.0.0.0:7878",
+                "--key-file",
+                "lab.key"
+            ]),
+            Command::Server {
+                listen: Some(Listen {
+                    address: "0.0.0.0:7878".to_string(),
+                    key_file: PathBuf::from("lab.key"),
+                })
+            }
+        );
+        assert!(parse_args_error(&["--server", "--listen", "0.0.0.0:7878"]).contains("--key-file"));
+        assert!(parse_args_error(&["--listen", "0.0.0.0:7878", "work"]).contains("--listen"));
         assert_eq!(parse_args(&["--help", "recover"]), Command::Help);
         assert_eq!(parse_args(&["recover", "-h"]), Command::Help);
     }
@@ -428,6 +682,10 @@ mod tests {
                     staging_policy: StagingPolicy::default(),
                     staging_policy_explicit: false,
                     staging_reserve_explicit: false,
+                    compression: None,
+                    bwlimit: None,
+                    server_process: false,
+                    negated: NegatedOptions::default(),
                 },
             }
         );
@@ -453,6 +711,31 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parses_negated_profile_options() {
+        let Command::Sync { options, .. } =
+            parse_args(&["--no-prune-ignored", "--no-exclude", "--force", "work"])
+        else {
+            panic!("expected sync command");
+        };
+
+        assert_eq!(
+            options.negated,
+            NegatedOptions {
+                prune_ignored: true,
+                excludes: true,
+                ..NegatedOptions::default()
+            }
+        );
+        assert!(options.force);
+        assert_eq!(
+            parse_args_error(&["--batch", "--no-batch", "work"]),
+            "--batch and --no-batch cannot be used together"
+        );
+        assert!(parse_args_error(&["--no-verbose", "_info", "work"])
+            .contains("sync options are not supported"));
+    }
+
     #[test]
     fn rejects_exclude_for_non_sync_commands() {
         assert!(parse_args_error(&["--exclude", "cache", "_info", "work"])
@@ -483,6 +766,10 @@ mod tests {
                     staging_policy: StagingPolicy::default(),
                     staging_policy_explicit: false,
                     staging_reserve_explicit: false,
+                    compression: None,
+                    bwlimit: None,
+                    server_process: false,
+                    negated: NegatedOptions::default(),
                 },
             }
         );
@@ -515,6 +802,10 @@ mod tests {
                     staging_policy: StagingPolicy::default(),
                     staging_policy_explicit: false,
                     staging_reserve_explicit: false,
+                    compression: None,
+                    bwlimit: None,
+                    server_process: false,
+                    negated: NegatedOptions::default(),
                 },
             }
         );
@@ -542,6 +833,10 @@ mod tests {
                     staging_policy: StagingPolicy::default(),
                     staging_policy_explicit: false,
                     staging_reserve_explicit: false,
+                    compression: None,
+                    bwlimit: None,
+                    server_process: false,
+                    negated: NegatedOptions::default(),
                 },
             }
         );
@@ -605,6 +900,48 @@ mod tests {
         assert!(!options.staging_reserve_explicit);
     }
 
+    #[test]
+    fn parses_compression_levels() {
+        let Command::Sync { options, .. } = parse_args(&["--compression", "9", "work"]) else {
+            panic!("expected sync command");
+        };
+        assert_eq!(options.compression, Some(9));
+        for value in ["11", "-1", "fast"] {
+            let error = parse_args_error(&["--compression", value, "work"]);
+            assert!(error.contains("compression level"), "{}", error);
+        }
+        assert!(
+            parse_args_error(&["--compression", "0", "_inspect", "state"])
+                .contains("not supported")
+        );
+    }
+
+    #[test]
+    fn parses_bandwidth_limits() {
+        let Command::Sync { options, .. } = parse_args(&["--bwlimit", "2MiB/0", "work"]) else {
+            panic!("expected sync command");
+        };
+        assert_eq!(
+            options.bwlimit,
+            Some(BandwidthLimit {
+                upload: Some(2 * 1024 * 1024),
+                download: None,
+            })
+        );
+        let error = parse_args_error(&["--bwlimit", "0", "work"]);
+        assert!(
+            error.contains("rate must be greater than zero"),
+            "{}",
+            error
+        );
+        assert_eq!(
+            parse_args_error(&["--bwlimit", "1M", "--no-bwlimit", "work"]),
+            "--bwlimit and --no-bwlimit cannot be used together"
+        );
+        assert!(parse_args_error(&["--bwlimit", "1M", "_info", "work"])
+            .contains("sync options are not supported"));
+    }
+
     #[test]
     fn rejects_invalid_staging_policy() {
         for value in ["0", "bogus", "auto", "unlimited", "18446744073709551616B"] {
@@ -697,6 +1034,75 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parses_status_commands() {
+        assert_eq!(
+            parse_args(&["status"]),
+            Command::Status {
+                profiles: Vec::new(),
+                remote: false,
+            }
+        );
+        assert_eq!(
+            parse_args(&["status", "--remote", "work", "home"]),
+            Command::Status {
+                profiles: vec!["work".to_string(), "home".to_string()],
+                remote: true,
+            }
+        );
+        assert_eq!(parse_args(&["--help", "status"]), Command::Help);
+        assert!(parse_args_error(&["status", "-b", "work"]).contains("sync options"));
+        assert!(parse_args_error(&["status", "work", "--clear"]).contains("unexpected"));
+    }
+
+    #[test]
+    fn parses_trash_commands() {
+        assert_eq!(
+            parse_args(&["trash", "list", "work"]),
+            Command::Trash {
+                command: TrashCommand::List,
+                profile: "work".to_string(),
+                remote: false,
+            }
+        );
+        assert_eq!(
+            parse_args(&["trash", "restore", "--remote", "work", "run/a", "run/b"]),
+            Command::Trash {
+                command: TrashCommand::Restore {
+                    items: vec![PathBuf::from("run/a"), PathBuf::from("run/b")],
+                },
+                profile: "work".to_string(),
+                remote: true,
+            }
+        );
+        assert_eq!(
+            parse_args(&["trash", "purge", "--all", "work"]),
+            Command::Trash {
+                command: TrashCommand::Purge { all: true },
+                profile: "work".to_string(),
+                remote: false,
+            }
+        );
+        assert!(parse_args_error(&["trash", "restore", "work"]).contains("requires"));
+        assert!(parse_args_error(&["trash", "empty", "work"]).contains("unknown trash command"));
+        assert!(parse_args_error(&["trash", "list", "--all", "work"]).contains("--all"));
+        assert!(parse_args_error(&["trash", "list", "-b", "work"]).contains("sync options"));
+        assert!(parse_args_error(&["trash", "list", "work", "extra"]).contains("unexpected"));
+        assert_eq!(
+            parse_args(&["profile", "check", "work"]),
+            Command::CheckProfile {
+                profile: "work".to_string(),
+            }
+        );
+        assert!(parse_args_error(&["profile", "show", "work"]).contains("unknown profile command"));
+        assert!(parse_args_error(&["profile", "check", "-n", "work"]).contains("sync options"));
+        assert_eq!(parse_age("12h"), Ok(12 * 60 * 60));
+        assert_eq!(parse_age("2w"), Ok(14 * 24 * 60 * 60));
+        assert!(parse_age("30").is_err());
+        assert!(parse_age("d").is_err());
+        assert!(parse_age("1.5d").is_err());
+    }
+
     #[test]
     fn rejects_unknown_flags_and_extra_arguments() {
         assert!(parse_args_error(&["--dryrun", "work"]).contains("unexpected argument"));
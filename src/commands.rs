@@ -3,7 +3,8 @@ use std::path::PathBuf;
 use color_eyre::eyre::{Result, WrapErr};
 use colored::*;
 
-use crate::{full, profile, scan, state, sync};
+use crate::scan::location::Location;
+use crate::{lexer, profile, remote, scan, state, sync};
 
 mod built_info {
     include!(concat!(env!("OUT_DIR"), "/built.rs"));
@@ -19,12 +20,18 @@ USAGE:
     duet [FLAGS] <profile> [path]
     duet [FLAGS] --profile-file <file> [path]
     duet recover [--clear] [--yes] [--remote] <profile-or-statefile>
+    duet trash list|restore|purge [--remote] <profile> ...
+    duet profile check <profile>
+    duet status [--remote] [<profile>...]
+    duet --server --listen <host:port> --key-file <file>
 
 FLAGS:
     -i, --interactive   interactive conflict resolution
     -y, --yes           assume yes (i.e., synchronize, if there are no conflicts)
     -b, --batch         run as a batch (abort on conflict)
-    -f, --force         in batch mode, apply what's possible, even if there are conflicts
+    -f, --force         in batch mode, apply what's possible, even if there are conflicts;
+                         with [conflicts] force = newer, file conflicts go to the newer
+                         version and the other is kept as a sidecar
     -v, --verbose       verbose output
     -n, --dry-run       check what sync would do without applying changes
         --debug-info    print protocol and capability negotiation details
@@ -39,6 +46,15 @@ FLAGS:
                          cap one staging wave (for example, 4GiB); unlimited by default
         --staging-reserve <size|percent>
                          preserve free staging space on each host; defaults to 5%
+        --compression <level>
+                         deflate file data sent to the peer at level 0-10; defaults to 1
+        --bwlimit <rate>[/<rate>]
+                         cap file data per second (for example, 2MiB), or upload/download
+        --server-process
+                         serve a local peer from a `duet --server` child process
+        --no-batch, --no-force, --no-verbose, --no-prune-ignored, --no-exclude,
+        --no-staging-limit, --no-profile-performance-json, --no-bwlimit
+                         ignore the profile's [options] default for this sync
 
         --profile-file <file>
                          read profile from a local file and keep state next to it
@@ -62,6 +78,30 @@ RECOVERY:
     profile exists. Remote recovery uses the profile's remote server and selected
     remote state id.
 
+TRASH:
+    trash list <profile>
+        list the files a profile's `[trash]` kept, as <run>/<path> with sizes
+    trash restore <profile> <run>/<path>...
+        move kept files back to their original paths, which must be free
+    trash purge <profile>
+        remove the runs outside the profile's max-age and max-size
+    trash purge --all <profile>
+        empty the trash
+
+    Trash commands act on the local side, or on the remote side with --remote.
+
+PROFILES:
+    profile check <profile>
+        parse a profile and its includes, expand its variables, and print the
+        effective configuration, or the file, line, and column of an error
+
+STATUS:
+    status [<profile>...]
+        show each profile, or all of them, with its local and remote lines, the
+        time and size of each peer's last synced snapshot, and unfinished applies
+    status --remote [<profile>...]
+        also ask each server for its snapshot and unfinished apply
+
 ARGS:
     <profile>    profile to synchronize
     <path>       path to synchronize
@@ -111,7 +151,7 @@ pub(crate) async fn snapshot(name: String, statefile: Option<PathBuf>) -> Result
         .wrap_err_with(|| format!("Failed to read profile {}", name.yellow()))?;
     println!("Using profile: {}", name.cyan());
 
-    let local_base = full(&prf.local)?;
+    let local_base = PathBuf::from(&prf.local);
     let scan_ignore = prf.scan_ignore();
 
     let mut current_entries = state::scan_entries(
@@ -119,6 +159,7 @@ pub(crate) async fn snapshot(name: String, statefile: Option<PathBuf>) -> Result
         &PathBuf::from(""),
         &prf.locations,
         &scan_ignore,
+        &prf.capture(),
     )
     .await?;
     state::hash_manifest(&local_base, &mut current_entries).await?;
@@ -136,7 +177,7 @@ pub(crate) async fn changes(name: String, statefile: Option<PathBuf>) -> Result<
         .wrap_err_with(|| format!("Failed to read profile {}", name.yellow()))?;
     println!("Using profile: {}", name.cyan());
 
-    let local_base = full(&prf.local)?;
+    let local_base = PathBuf::from(&prf.local);
     let scan_ignore = prf.scan_ignore();
 
     let statefile = match statefile {
@@ -149,6 +190,7 @@ pub(crate) async fn changes(name: String, statefile: Option<PathBuf>) -> Result<
         &scan::ScanScope::default(),
         &prf.locations,
         &scan_ignore,
+        &prf.capture(),
         Some(&statefile),
         true,
     )
@@ -157,6 +199,9 @@ pub(crate) async fn changes(name: String, statefile: Option<PathBuf>) -> Result<
     for c in changes.changes {
         println!("{} {}", c, c.path().display());
     }
+    for entry in changes.filtered {
+        println!("filtered {} ({})", entry.path.display(), entry.filter);
+    }
 
     Ok(())
 }
@@ -167,12 +212,80 @@ pub(crate) fn info(name: String) -> Result<()> {
         name.cyan(),
         profile::location(&name)?.display().to_string().yellow()
     );
+    let profile = profile::parse(&name)?;
+    print_sources(&profile, |_| true);
+    Ok(())
+}
+
+/// Prints the rules of `profile` that `shown` selects, by section, each with its file and line.
+fn print_sources(profile: &profile::Profile, shown: impl Fn(&profile::RuleSource) -> bool) {
+    let mut section = "";
+    for source in profile.sources.iter().filter(|source| shown(source)) {
+        if source.section != section {
+            section = source.section;
+            println!("{}", section.cyan());
+        }
+        println!(
+            "{:<40} {}",
+            source.rule,
+            format!("# {}:{}", source.file.display(), source.line).dimmed()
+        );
+    }
+    if profile.trash.is_some() && section != "[trash]" {
+        println!("{}", "[trash]".cyan());
+    }
+}
+
+pub(crate) fn check_profile(name: String) -> Result<()> {
+    let config = profile::load(&profile::ProfileSource::Named(name.clone()))?;
+    println!(
+        "Profile {} located at {} is valid",
+        name.cyan(),
+        profile::location(&name)?.display().to_string().yellow()
+    );
+    let profile = &config.profile;
+    println!("{:<8} {}", "local", lexer::quote(&profile.local));
+    for (index, line) in profile.remotes().enumerate() {
+        let (base, server, command) = remote::parse_remote(line)?;
+        let mut settings = Vec::new();
+        let listening = server.as_deref().and_then(crate::tcp::address).is_some();
+        if let Some(server) = server {
+            settings.push(format!("server={}", lexer::quote(&server)));
+        }
+        // a listening server runs no command
+        if !listening {
+            settings.push(format!("command={}", lexer::quote(&command)));
+        }
+        settings.push(format!("base={}", lexer::quote(&base)));
+        println!(
+            "{:<8} {:<31} {}",
+            if index == 0 { "remote" } else { "peer" },
+            settings.join(" "),
+            format!("# state {}", config.peer_state(line).display()).dimmed()
+        );
+    }
+    // the first rule is the implicit exclusion of the root
+    for location in profile.locations.iter().skip(1) {
+        let (sign, path) = match location {
+            Location::Include(path) => ('+', path),
+            Location::Exclude(path) => ('-', path),
+        };
+        println!("{}{}", sign, lexer::quote(&path.to_string_lossy()));
+    }
+    print_sources(profile, |source| !source.section.is_empty());
     Ok(())
 }
 
 pub(crate) async fn walk(path: PathBuf) -> Result<()> {
     let locations = vec![scan::location::Location::Include(PathBuf::from("."))];
-    let entries = state::scan_entries(&path, &PathBuf::new(), &locations, &Vec::new()).await?;
+    let entries = state::scan_entries(
+        &path,
+        &PathBuf::new(),
+        &locations,
+        &Vec::new(),
+        &Default::default(),
+    )
+    .await?;
     for e in entries {
         println!("{}", e.path().display());
     }
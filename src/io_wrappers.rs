@@ -7,10 +7,13 @@ use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 
 // ===== STDIN WRAPPERS =====
 
-/// A wrapper that can hold either tokio::process::ChildStdin or openssh::ChildStdin
+/// A wrapper that can hold either tokio::process::ChildStdin or openssh::ChildStdin, or the
+/// socket of an in-process or TCP server
 pub enum StdinWrapper {
     OpensshStdin(openssh::ChildStdin),
     TokioStdin(tokio::process::ChildStdin),
+    InProcess(tokio::net::unix::OwnedWriteHalf),
+    Tcp(tokio::net::tcp::OwnedWriteHalf),
 }
 
 // Asynchronous Write implementation
@@ -32,6 +35,8 @@ impl AsyncWrite for StdinWrapper {
                 // Similar to std stdin
                 Pin::new(stdin).poll_write(_cx, buf)
             }
+            StdinWrapper::InProcess(socket) => Pin::new(socket).poll_write(_cx, buf),
+            StdinWrapper::Tcp(socket) => Pin::new(socket).poll_write(_cx, buf),
         }
     }
 
@@ -47,6 +52,8 @@ impl AsyncWrite for StdinWrapper {
                 //stdin.poll_flush()
                 Pin::new(stdin).poll_flush(_cx)
             }
+            StdinWrapper::InProcess(socket) => Pin::new(socket).poll_flush(_cx),
+            StdinWrapper::Tcp(socket) => Pin::new(socket).poll_flush(_cx),
         }
     }
 
@@ -63,6 +70,8 @@ impl AsyncWrite for StdinWrapper {
                 //stdin.poll_flush()
                 Pin::new(stdin).poll_shutdown(_cx)
             }
+            StdinWrapper::InProcess(socket) => Pin::new(socket).poll_shutdown(_cx),
+            StdinWrapper::Tcp(socket) => Pin::new(socket).poll_shutdown(_cx),
         }
     }
 }
@@ -82,10 +91,13 @@ impl From<tokio::process::ChildStdin> for StdinWrapper {
 
 // ===== STDOUT WRAPPERS =====
 
-/// A wrapper that can hold either tokio::process::ChildStdout or openssh::ChildStdout
+/// A wrapper that can hold either tokio::process::ChildStdout or openssh::ChildStdout, or the
+/// socket of an in-process or TCP server
 pub enum StdoutWrapper {
     OpensshStdout(openssh::ChildStdout),
     TokioStdout(tokio::process::ChildStdout),
+    InProcess(tokio::net::unix::OwnedReadHalf),
+    Tcp(tokio::net::tcp::OwnedReadHalf),
 }
 
 // Asynchronous Read implementation
@@ -106,6 +118,8 @@ impl AsyncRead for StdoutWrapper {
                 // Similar to std stdout
                 Pin::new(stdout).poll_read(_cx, buf)
             }
+            StdoutWrapper::InProcess(socket) => Pin::new(socket).poll_read(_cx, buf),
+            StdoutWrapper::Tcp(socket) => Pin::new(socket).poll_read(_cx, buf),
         }
     }
 }
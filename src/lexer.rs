@@ -0,0 +1,248 @@
+//! Shell-style words for profile lines: single and double quotes, backslash escapes, and `$VAR`,
+//! `${VAR}`, and leading `~` expansion.
+
+use std::borrow::Cow;
+use std::fmt;
+
+/// Where the variable and `~` references in a word are expanded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expand {
+    /// Now, from this process's environment: for the local side of a profile.
+    Here,
+    /// By the side that uses the value, as it expands the remote base, so references are kept as
+    /// written.
+    Later,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LexError {
+    /// The 1-based character column the error points at.
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: {}", self.column, self.message)
+    }
+}
+
+impl std::error::Error for LexError {}
+
+/// Splits `line` into words at unquoted whitespace.
+pub fn split(line: &str, expand: Expand) -> Result<Vec<String>, LexError> {
+    lex(line, expand, true)
+}
+
+/// Reads all of `text` as one word, keeping unquoted whitespace inside it, as location rules and
+/// the local line always have.
+pub fn word(text: &str, expand: Expand) -> Result<String, LexError> {
+    Ok(lex(text.trim(), expand, false)?.pop().unwrap_or_default())
+}
+
+/// Quotes `word` so that `split` reads it back unchanged.
+pub fn quote(word: &str) -> Cow<'_, str> {
+    let plain = |c: char| c.is_alphanumeric() || "/._-+:,@%=~".contains(c);
+    if !word.is_empty() && word.chars().all(plain) {
+        Cow::Borrowed(word)
+    } else {
+        Cow::Owned(format!("'{}'", word.replace('\'', r"'\''")))
+    }
+}
+
+fn lex(text: &str, expand: Expand, split: bool) -> Result<Vec<String>, LexError> {
+    let chars: Vec<char> = text.chars().collect();
+    let error = |index: usize, message: String| LexError {
+        column: index + 1,
+        message,
+    };
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut started = false;
+    let mut index = 0;
+    while index < chars.len() {
+        let c = chars[index];
+        match c {
+            c if c.is_whitespace() && split => {
+                if started {
+                    words.push(std::mem::take(&mut current));
+                    started = false;
+                }
+                index += 1;
+                continue;
+            }
+            '\\' => {
+                let escaped = *chars
+                    .get(index + 1)
+                    .ok_or_else(|| error(index, "trailing backslash".to_string()))?;
+                current.push(escaped);
+                index += 2;
+            }
+            '\'' => {
+                let close = find(&chars, index + 1, '\'')
+                    .ok_or_else(|| error(index, "unterminated single quote".to_string()))?;
+                current.extend(&chars[index + 1..close]);
+                index = close + 1;
+            }
+            '"' => {
+                let open = index;
+                index += 1;
+                loop {
+                    match chars.get(index) {
+                        None => return Err(error(open, "unterminated double quote".to_string())),
+                        Some('"') => break,
+                        Some('\\') if matches!(chars.get(index + 1), Some('"' | '\\' | '$')) => {
+                            current.push(chars[index + 1]);
+                            index += 2;
+                        }
+                        Some('$') => index = variable(&chars, index, expand, &mut current)?,
+                        Some(&c) => {
+                            current.push(c);
+                            index += 1;
+                        }
+                    }
+                }
+                index += 1;
+            }
+            '$' => index = variable(&chars, index, expand, &mut current)?,
+            '~' if !started
+                && expand == Expand::Here
+                && chars
+                    .get(index + 1)
+                    .is_none_or(|&c| c == '/' || (split && c.is_whitespace())) =>
+            {
+                current.push_str(&shellexpand::tilde("~"));
+                index += 1;
+            }
+            c => {
+                current.push(c);
+                index += 1;
+            }
+        }
+        started = true;
+    }
+    if started || !split {
+        words.push(current);
+    }
+    Ok(words)
+}
+
+fn find(chars: &[char], from: usize, target: char) -> Option<usize> {
+    (from..chars.len()).find(|&index| chars[index] == target)
+}
+
+/// Reads the `$NAME` or `${NAME}` reference at `index` into `current`, returning the index after
+/// it. A `$` that starts no name is literal.
+fn variable(
+    chars: &[char],
+    index: usize,
+    expand: Expand,
+    current: &mut String,
+) -> Result<usize, LexError> {
+    let is_name = |c: &char| c.is_ascii_alphanumeric() || *c == '_';
+    let (name, end) = if chars.get(index + 1) == Some(&'{') {
+        let close = find(chars, index + 2, '}').ok_or_else(|| LexError {
+            column: index + 1,
+            message: "unterminated ${ reference".to_string(),
+        })?;
+        (&chars[index + 2..close], close + 1)
+    } else {
+        let length = chars[index + 1..].iter().take_while(|c| is_name(c)).count();
+        (&chars[index + 1..index + 1 + length], index + 1 + length)
+    };
+    if name.is_empty() && end == index + 1 {
+        current.push('$');
+        return Ok(end);
+    }
+    let name: String = name.iter().collect();
+    if name.is_empty()
+        || !name.chars().all(|c| is_name(&c))
+        || name.starts_with(|c: char| c.is_ascii_digit())
+    {
+        return Err(LexError {
+            column: index + 1,
+            message: format!("invalid variable name {:?}", name),
+        });
+    }
+    match expand {
+        Expand::Here => {
+            let value = std::env::var(&name).map_err(|_| LexError {
+                column: index + 1,
+                message: format!("variable {} is not set", name),
+            })?;
+            current.push_str(&value);
+        }
+        Expand::Later => current.extend(&chars[index..end]),
+    }
+    Ok(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(line: &str) -> Vec<String> {
+        split(line, Expand::Later).unwrap()
+    }
+
+    #[test]
+    fn splits_at_unquoted_whitespace_and_honors_quotes_and_escapes() {
+        assert_eq!(
+            words("ssh  host duet /data"),
+            vec!["ssh", "host", "duet", "/data"]
+        );
+        assert_eq!(
+            words(r#"'/opt/my duet/duet' "/data/My Files" a\ b"#),
+            vec!["/opt/my duet/duet", "/data/My Files", "a b"]
+        );
+        assert_eq!(
+            words(r#"base="/x y"'z' "a\"b\c""#),
+            vec!["base=/x yz", r#"a"b\c"#]
+        );
+        assert_eq!(words("''"), vec![""]);
+        assert!(words("  ").is_empty());
+        assert_eq!(word("  a b  ", Expand::Later).unwrap(), "a b");
+    }
+
+    #[test]
+    fn expands_variables_here_and_keeps_them_for_later() {
+        std::env::set_var("DUET_LEXER_TEST_DIR", "/srv/data");
+        let here = |line| split(line, Expand::Here).unwrap();
+        assert_eq!(
+            here(
+                r#"${DUET_LEXER_TEST_DIR}/a "$DUET_LEXER_TEST_DIR b" '${DUET_LEXER_TEST_DIR}' \$x $"#
+            ),
+            vec![
+                "/srv/data/a",
+                "/srv/data b",
+                "${DUET_LEXER_TEST_DIR}",
+                "$x",
+                "$"
+            ]
+        );
+        assert_eq!(here("~/x")[0], format!("{}/x", shellexpand::tilde("~")));
+        assert_eq!(here("a~")[0], "a~");
+        assert_eq!(words("~/x ${HOME}/y"), vec!["~/x", "${HOME}/y"]);
+    }
+
+    #[test]
+    fn errors_point_at_the_offending_column() {
+        let error = |line| split(line, Expand::Here).unwrap_err().to_string();
+        assert_eq!(error(r#"ssh "host"#), "column 5: unterminated double quote");
+        assert_eq!(error("a 'b"), "column 3: unterminated single quote");
+        assert_eq!(error(r"a\"), "column 2: trailing backslash");
+        assert_eq!(error("x ${A"), "column 3: unterminated ${ reference");
+        assert_eq!(error("${A-B}"), "column 1: invalid variable name \"A-B\"");
+        assert_eq!(
+            error("${DUET_LEXER_TEST_UNSET}"),
+            "column 1: variable DUET_LEXER_TEST_UNSET is not set"
+        );
+    }
+
+    #[test]
+    fn quoted_words_split_back_unchanged() {
+        for word in ["/data/My Files", "it's", "", "plain/path-1.txt", "$HOME"] {
+            assert_eq!(words(&quote(word)), vec![word]);
+        }
+    }
+}
@@ -1,9 +1,12 @@
 use color_eyre::eyre::Result;
 
 mod actions;
+mod bwlimit;
 mod cli;
 mod commands;
 mod io_wrappers;
+mod lexer;
+mod merge;
 mod orchestrator;
 mod performance;
 mod profile;
@@ -16,6 +19,8 @@ mod scan;
 mod state;
 mod sync;
 mod sync_error;
+mod tcp;
+mod trash;
 mod utils;
 #[macro_use]
 extern crate serde_derive;
@@ -32,7 +37,10 @@ pub async fn main() -> Result<()> {
         Command::Help => commands::show_help(),
         Command::Version { verbose } => commands::version(verbose),
         Command::License => commands::license(),
-        Command::Server => return rpc::server().await,
+        Command::Server { listen: None } => return rpc::server().await,
+        Command::Server {
+            listen: Some(listen),
+        } => return rpc::listen(listen).await,
         Command::Snapshot { profile, statefile } => {
             return commands::snapshot(profile, statefile).await;
         }
@@ -53,6 +61,15 @@ pub async fn main() -> Result<()> {
             }
             return commands::recover(target, clear, yes);
         }
+        Command::Trash {
+            command,
+            profile,
+            remote,
+        } => return orchestrator::trash(profile, command, remote).await,
+        Command::CheckProfile { profile } => return commands::check_profile(profile),
+        Command::Status { profiles, remote } => {
+            return orchestrator::status(profiles, remote).await
+        }
         Command::Sync {
             profile,
             path,
@@ -0,0 +1,221 @@
+use std::fs;
+use std::io;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{Result, WrapErr};
+
+use crate::profile;
+use crate::scan::{ContentDigest, DirEntryWithMeta as Entry};
+use crate::sync;
+
+/// Subdirectory of the store that holds the last merge with conflicts for each path.
+const CONFLICTS_DIR: &str = "conflicts";
+
+/// The outcome of a line-based three-way merge.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Merged {
+    Clean(Vec<u8>),
+    /// The merge with both sides of each conflicting hunk between markers, local first.
+    Conflicted(Vec<u8>),
+}
+
+/// Merges the local and remote versions of a file against their common base, line by line.
+pub fn merge(base: &[u8], local: &[u8], remote: &[u8]) -> Merged {
+    match diffy::merge_bytes(base, local, remote) {
+        Ok(merged) => Merged::Clean(merged),
+        Err(marked) => Merged::Conflicted(marked),
+    }
+}
+
+/// Content-addressed store of the synced contents of the files a profile's `[merge]` globs
+/// match, kept next to the local state file and named by each file's BLAKE2b-256 digest.
+pub struct MergeBases {
+    dir: PathBuf,
+    patterns: Vec<(String, regex::Regex)>,
+}
+
+impl MergeBases {
+    pub fn new(state: &Path, patterns: &[String]) -> Result<Self> {
+        let mut compiled = Vec::new();
+        for pattern in patterns {
+            compiled.push((
+                pattern.clone(),
+                fnmatch_regex::glob_to_regex(pattern)
+                    .wrap_err_with(|| format!("invalid merge pattern {pattern}"))?,
+            ));
+        }
+        Ok(Self {
+            dir: state.with_extension("bases"),
+            patterns: compiled,
+        })
+    }
+
+    pub fn matches(&self, path: &Path) -> bool {
+        self.patterns
+            .iter()
+            .any(|(pattern, regex)| profile::glob_matches(pattern, regex, path))
+    }
+
+    /// The stored contents with this digest, if the store has them intact.
+    pub fn get(&self, digest: &ContentDigest) -> Result<Option<Vec<u8>>> {
+        let path = self.dir.join(digest.to_string());
+        match fs::read(&path) {
+            Ok(contents) if sync::content_digest(&contents) == *digest => Ok(Some(contents)),
+            Ok(_) => Ok(None),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => {
+                Err(error).wrap_err_with(|| format!("failed to read merge base {}", path.display()))
+            }
+        }
+    }
+
+    /// Stores the contents of the matching files in `entries`, the local snapshot after a
+    /// successful sync, reading the ones the store lacks from under `base`, and removes the bases
+    /// no entry refers to anymore. Files that changed since the snapshot are skipped.
+    pub fn record(&self, base: &Path, entries: &[Entry]) -> Result<()> {
+        let mut keep = std::collections::HashSet::new();
+        for entry in entries {
+            let Some(digest) = entry.digest() else {
+                continue;
+            };
+            if !entry.is_file() || !self.matches(entry.path()) {
+                continue;
+            }
+            let name = digest.to_string();
+            if !self.dir.join(&name).exists() {
+                let filename = sync::safe_join(base, entry.path())?;
+                let contents = match fs::read(&filename) {
+                    Ok(contents) => contents,
+                    Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+                    Err(error) => {
+                        return Err(error).wrap_err_with(|| {
+                            format!("failed to read merge base source {}", filename.display())
+                        })
+                    }
+                };
+                if sync::content_digest(&contents) != digest {
+                    continue;
+                }
+                self.store(&name, &contents)?;
+            }
+            keep.insert(name);
+        }
+
+        let stored = match fs::read_dir(&self.dir) {
+            Ok(stored) => stored,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(error) => {
+                return Err(error).wrap_err_with(|| {
+                    format!("failed to list merge bases in {}", self.dir.display())
+                })
+            }
+        };
+        for item in stored {
+            let item = item.wrap_err("failed to list merge bases")?;
+            let name = item.file_name();
+            if name == CONFLICTS_DIR || keep.contains(name.to_string_lossy().as_ref()) {
+                continue;
+            }
+            fs::remove_file(item.path()).wrap_err_with(|| {
+                format!("failed to remove merge base {}", item.path().display())
+            })?;
+        }
+        Ok(())
+    }
+
+    fn store(&self, name: &str, contents: &[u8]) -> Result<()> {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&self.dir)
+            .wrap_err_with(|| format!("failed to create merge bases {}", self.dir.display()))?;
+        use atomicwrites::{AllowOverwrite, AtomicFile};
+        let path = self.dir.join(name);
+        AtomicFile::new(&path, AllowOverwrite)
+            .write(|file| {
+                use std::io::Write;
+                file.write_all(contents)
+            })
+            .wrap_err_with(|| format!("failed to save merge base {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Saves a merge with conflict markers for `path`, replacing the previous one, and returns
+    /// where it is.
+    pub fn keep_conflicted(&self, path: &Path, contents: &[u8]) -> Result<PathBuf> {
+        let kept = self.dir.join(CONFLICTS_DIR).join(path);
+        if let Some(parent) = kept.parent() {
+            fs::DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(parent)
+                .wrap_err_with(|| format!("failed to create {}", parent.display()))?;
+        }
+        fs::write(&kept, contents)
+            .wrap_err_with(|| format!("failed to save conflicted merge {}", kept.display()))?;
+        Ok(kept)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edits_to_different_lines_merge_cleanly() {
+        let base = b"one\ntwo\nthree\n";
+        let local = b"one local\ntwo\nthree\n";
+        let remote = b"one\ntwo\nthree remote\n";
+        assert_eq!(
+            merge(base, local, remote),
+            Merged::Clean(b"one local\ntwo\nthree remote\n".to_vec())
+        );
+    }
+
+    #[test]
+    fn edits_to_the_same_line_keep_both_between_markers() {
+        let Merged::Conflicted(marked) = merge(b"line\n", b"local\n", b"remote\n") else {
+            panic!("expected a conflict");
+        };
+        let marked = String::from_utf8(marked).unwrap();
+        assert!(marked.starts_with("<<<<<<<"), "{}", marked);
+        assert!(marked.find("local") < marked.find("remote"), "{}", marked);
+    }
+
+    #[test]
+    fn record_keeps_matching_synced_files_and_drops_stale_bases() {
+        let root = tempfile::tempdir().unwrap();
+        let state = root.path().join("profile.snp");
+        let base = root.path().join("base");
+        fs::create_dir(&base).unwrap();
+        fs::write(base.join("a.md"), "notes\n").unwrap();
+        fs::write(base.join("b.txt"), "other\n").unwrap();
+        let entry = |name: &str| {
+            let mut entry = Entry::test_file_from_path(PathBuf::from(name), &base.join(name));
+            entry.set_digest(Some(sync::content_digest(
+                &fs::read(base.join(name)).unwrap(),
+            )));
+            entry
+        };
+        let bases = MergeBases::new(&state, &["*.md".to_string()]).unwrap();
+
+        bases
+            .record(&base, &[entry("a.md"), entry("b.txt")])
+            .unwrap();
+        let digest = sync::content_digest(b"notes\n");
+        assert_eq!(bases.get(&digest).unwrap(), Some(b"notes\n".to_vec()));
+        assert_eq!(
+            fs::read_dir(state.with_extension("bases")).unwrap().count(),
+            1
+        );
+
+        fs::write(base.join("a.md"), "edited\n").unwrap();
+        bases.record(&base, &[entry("a.md")]).unwrap();
+        assert_eq!(bases.get(&digest).unwrap(), None);
+        assert_eq!(
+            bases.get(&sync::content_digest(b"edited\n")).unwrap(),
+            Some(b"edited\n".to_vec())
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::{hash_map::DefaultHasher, HashSet};
+use std::collections::{hash_map::DefaultHasher, BTreeMap, HashMap, HashSet};
 use std::hash::{Hash, Hasher};
 use std::path::{Component, Path, PathBuf};
 use std::process::ExitStatus;
@@ -11,8 +11,10 @@ use colored::*;
 use essrpc::{RPCError, RPCErrorKind};
 use openssh::{ControlPersist, KnownHosts, Session, SessionBuilder};
 
-use crate::actions::{num_identical, num_unresolved_conflicts, reverse, Action, Actions};
-use crate::cli::SyncOptions;
+use crate::actions::{num_identical, num_unresolved_conflicts, reverse, Action, Actions, Move};
+use crate::bwlimit;
+use crate::cli::{SyncOptions, TrashCommand};
+use crate::merge::{self, MergeBases};
 use crate::performance::{
     DetailTransferStats, PerformanceProfile, StagingProfile, StreamingProfile,
 };
@@ -25,6 +27,8 @@ use crate::scan::{self, Change};
 use crate::state;
 use crate::sync as sync_ops;
 use crate::sync_error;
+use crate::tcp;
+use crate::trash::{self, TrashRetention};
 use crate::utils;
 
 const PROFILE_ERROR_CODE: u8 = 2;
@@ -142,6 +146,18 @@ impl InterruptState {
         }
     }
 
+    /// Readies a completed sync for the next peer, unless an interrupt arrived meanwhile.
+    fn try_begin_next_peer(&self) -> bool {
+        self.phase
+            .compare_exchange(
+                InterruptPhase::Complete as u8,
+                InterruptPhase::PreCommit as u8,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            )
+            .is_ok()
+    }
+
     fn complete(&self) -> bool {
         loop {
             let phase = self.phase.load(Ordering::SeqCst);
@@ -196,8 +212,12 @@ impl InterruptState {
 
 struct SyncContext {
     profile: profile::Profile,
+    /// The remote line of the peer this context synchronizes with.
+    peer: String,
     local_id: String,
     legacy_local_id: Option<String>,
+    /// Both sides keep this profile's trash under the same id, whichever peer a sync visits.
+    trash_id: String,
     local_base: PathBuf,
     remote_base: String,
     remote_server: Option<String>,
@@ -228,6 +248,50 @@ fn apply_profile_staging_reserve(
     false
 }
 
+/// Fills in the options the command line left unset from the profile's `[options]` section,
+/// except its excludes, which `prepare_contexts` adds to the scan scope. Returns whether the
+/// profile's staging limit applies.
+fn apply_profile_options(options: &mut SyncOptions, defaults: &profile::ProfileOptions) -> bool {
+    let negated = options.negated.clone();
+    // batch would win over an --interactive given on the command line
+    let batch = defaults.batch.filter(|_| !options.interactive);
+    let flags = [
+        (&mut options.batch, batch, negated.batch),
+        (&mut options.force, defaults.force, negated.force),
+        (&mut options.verbose, defaults.verbose, negated.verbose),
+        (
+            &mut options.prune_ignored,
+            defaults.prune_ignored,
+            negated.prune_ignored,
+        ),
+    ];
+    for (option, default, negated) in flags {
+        if negated {
+            *option = false;
+        } else if !*option {
+            *option = default.unwrap_or(false);
+        }
+    }
+    if options.compression.is_none() {
+        options.compression = defaults.compression;
+    }
+    if options.bwlimit.is_none() && !negated.bwlimit {
+        options.bwlimit = defaults.bwlimit;
+    }
+    if options.profile_performance_json.is_none() && !negated.profile_performance_json {
+        options.profile_performance_json = defaults.profile_performance_json.clone();
+    }
+    if options.staging_policy.limit_bytes.is_some() || negated.staging_limit {
+        return false;
+    }
+    if let Some(limit) = defaults.staging_limit {
+        options.staging_policy.limit_bytes = Some(limit);
+        options.staging_policy_explicit = true;
+        return true;
+    }
+    false
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum StagingPolicySource {
     Default,
@@ -236,6 +300,9 @@ enum StagingPolicySource {
     CliLimitAndReserve,
     Profile,
     CliLimitAndProfile,
+    ProfileLimit,
+    ProfileLimitAndReserve,
+    ProfileLimitAndCliReserve,
 }
 
 impl StagingPolicySource {
@@ -249,6 +316,13 @@ impl StagingPolicySource {
             Self::CliLimitAndProfile => {
                 Some("the --staging-limit option and profile [staging] reserve setting")
             }
+            Self::ProfileLimit => Some("the profile [options] staging-limit setting"),
+            Self::ProfileLimitAndReserve => {
+                Some("the profile [options] staging-limit and [staging] reserve settings")
+            }
+            Self::ProfileLimitAndCliReserve => {
+                Some("the --staging-reserve option and profile [options] staging-limit setting")
+            }
         }
     }
 }
@@ -269,20 +343,125 @@ impl ApplyStrategy {
 pub async fn sync(
     source: ProfileSource,
     path: Option<PathBuf>,
-    mut options: SyncOptions,
+    options: SyncOptions,
 ) -> Result<SyncOutcome> {
     let interrupt = InterruptState::new();
     install_ctrlc_handler(interrupt.clone())?;
+    env_logger::init();
+
+    let mut contexts =
+        prepare_contexts(source, path, &options.excludes, !options.negated.excludes)?;
+    if contexts.len() == 1 {
+        let context = contexts.remove(0);
+        return sync_peer(context, options, &interrupt, &mut Vec::new()).await;
+    }
+
+    // Peers are visited in turn, so what the local side learns from one reaches the next as
+    // local changes. What later peers send reaches earlier ones on the next sync.
+    let dry_run = options.dry_run;
+    let count = contexts.len();
+    let mut summary = PeerSummary::default();
+    for (index, context) in contexts.into_iter().enumerate() {
+        if index > 0 && !interrupt.try_begin_next_peer() {
+            return Ok(SyncOutcome::Interrupted);
+        }
+        println!("Peer {}/{}: {}", index + 1, count, context.peer.cyan());
+        let peer = context.peer.clone();
+        let mut exchanged = Vec::new();
+        let outcome = sync_peer(context, options.clone(), &interrupt, &mut exchanged).await?;
+        summary.record(&peer, exchanged);
+        if outcome != SyncOutcome::Success {
+            summary.print();
+            return Ok(outcome);
+        }
+    }
+    if !dry_run {
+        summary.print();
+    }
+    Ok(SyncOutcome::Success)
+}
+
+/// A change one peer's sync carried: `received` from the peer, or sent to it.
+struct Exchange {
+    path: PathBuf,
+    received: bool,
+}
+
+fn exchanges(actions: &Actions) -> Vec<Exchange> {
+    actions
+        .iter()
+        .filter_map(|action| {
+            // actions name the side they apply to
+            let received = match action {
+                Action::Local(_) | Action::ResolvedLocal(..) | Action::LocalMove(_) => true,
+                Action::Remote(_) | Action::ResolvedRemote(..) | Action::RemoteMove(_) => false,
+                Action::Conflict(..) | Action::Identical(..) => return None,
+            };
+            Some(Exchange {
+                path: action.path().clone(),
+                received,
+            })
+        })
+        .collect()
+}
+
+/// Where each change of a hub-and-spoke sync came from, and the sides it reached, by path.
+#[derive(Default)]
+struct PeerSummary {
+    changes: BTreeMap<PathBuf, (String, Vec<String>)>,
+}
+
+impl PeerSummary {
+    fn record(&mut self, peer: &str, exchanged: Vec<Exchange>) {
+        for exchange in exchanged {
+            if exchange.received {
+                self.changes
+                    .insert(exchange.path, (peer.to_string(), vec!["local".to_string()]));
+            } else {
+                self.changes
+                    .entry(exchange.path)
+                    .or_insert_with(|| ("local".to_string(), Vec::new()))
+                    .1
+                    .push(peer.to_string());
+            }
+        }
+    }
+
+    fn print(&self) {
+        if self.changes.is_empty() {
+            return;
+        }
+        println!("Changes by peer:");
+        for (path, (origin, destinations)) in &self.changes {
+            println!(
+                "  {} from {} to {}",
+                crate::actions::show_path(path),
+                origin.cyan(),
+                destinations.join(", ").cyan()
+            );
+        }
+    }
+}
+
+/// Synchronizes the local side with one peer, appending the changes it carried to `exchanged`
+/// once they are applied.
+async fn sync_peer(
+    context: SyncContext,
+    mut options: SyncOptions,
+    interrupt: &InterruptState,
+    exchanged: &mut Vec<Exchange>,
+) -> Result<SyncOutcome> {
+    let interrupt = interrupt.clone();
     let total_start = Instant::now();
+    let cli_staging_limit_explicit = options.staging_policy.limit_bytes.is_some();
+    let profile_staging_limit_applied =
+        apply_profile_options(&mut options, &context.profile.options);
     let print_performance = options.profile_performance;
     let performance_json = options.profile_performance_json.clone();
     let profiling_enabled = print_performance || performance_json.is_some();
     let mut performance = PerformanceProfile::default();
 
     let setup_start = Instant::now();
-    env_logger::init();
-
-    let context = prepare_context(source, path, &options.excludes)?;
     sync_ops::check_apply_attempt_clear(&context.local_state)?;
     performance.record_phase("setup", setup_start.elapsed());
     if interrupt.is_cancel_requested() {
@@ -291,8 +470,10 @@ pub async fn sync(
 
     let SyncContext {
         profile: prf,
+        peer: _,
         local_id,
         legacy_local_id,
+        trash_id,
         local_base,
         remote_base,
         remote_server,
@@ -302,24 +483,32 @@ pub async fn sync(
         remote_state_dir,
         server_log,
     } = context;
-    let cli_staging_limit_explicit = options.staging_policy.limit_bytes.is_some();
     let cli_staging_reserve_explicit = options.staging_reserve_explicit;
     let profile_staging_reserve_applied =
         apply_profile_staging_reserve(&mut options, prf.staging_reserve);
     let staging_policy_source = match (
         cli_staging_limit_explicit,
+        profile_staging_limit_applied,
         cli_staging_reserve_explicit,
         profile_staging_reserve_applied,
     ) {
-        (false, false, false) => StagingPolicySource::Default,
-        (true, false, false) => StagingPolicySource::CliLimit,
-        (false, true, false) => StagingPolicySource::CliReserve,
-        (true, true, false) => StagingPolicySource::CliLimitAndReserve,
-        (false, false, true) => StagingPolicySource::Profile,
-        (true, false, true) => StagingPolicySource::CliLimitAndProfile,
-        (_, true, true) => unreachable!("a CLI reserve must override the profile reserve"),
+        (false, false, false, false) => StagingPolicySource::Default,
+        (true, _, false, false) => StagingPolicySource::CliLimit,
+        (false, false, true, false) => StagingPolicySource::CliReserve,
+        (true, _, true, false) => StagingPolicySource::CliLimitAndReserve,
+        (false, false, false, true) => StagingPolicySource::Profile,
+        (true, _, false, true) => StagingPolicySource::CliLimitAndProfile,
+        (false, true, false, false) => StagingPolicySource::ProfileLimit,
+        (false, true, false, true) => StagingPolicySource::ProfileLimitAndReserve,
+        (false, true, true, false) => StagingPolicySource::ProfileLimitAndCliReserve,
+        (_, _, true, true) => unreachable!("a CLI reserve must override the profile reserve"),
     };
     let apply_attempt_id = new_apply_attempt_id(&local_id);
+    let merge_bases = if prf.merge.is_empty() {
+        None
+    } else {
+        Some(MergeBases::new(&local_state, &prf.merge)?)
+    };
     let locations = outbound_scan_locations(&prf.locations);
     let scan_ignore = prf.scan_ignore();
     let scan_policy =
@@ -330,11 +519,16 @@ pub async fn sync(
     };
 
     let remote_setup_start = Instant::now();
-    let remote_session = open_remote_session(remote_server).await;
+    let remote_session = open_remote_session(remote_server, &prf).await;
     if interrupt.is_cancel_requested() {
         return Ok(SyncOutcome::Interrupted);
     }
-    let mut server = remote::launch_server(&remote_session, remote_cmd, &server_log)
+    let local_server = if options.server_process {
+        remote::LocalServer::Subprocess
+    } else {
+        remote::LocalServer::InProcess
+    };
+    let mut server = remote::launch_server(&remote_session, remote_cmd, &server_log, local_server)
         .await
         .unwrap_or_else(|e| {
             let diagnostic =
@@ -343,6 +537,7 @@ pub async fn sync(
             quit::with_code(SERVER_ERROR_CODE);
         });
     interrupt.register_local_server(&server);
+    let in_process = server.is_in_process();
     let sync_result = async {
         let remote = remote::get_remote(&mut server)?;
         if interrupt.is_cancel_requested() {
@@ -361,6 +556,82 @@ pub async fn sync(
         remote.set_prune_patterns(prf.prune.clone()).await
             .map_err(|e| remote_rpc_error("Couldn't set remote prune patterns", e))?;
     }
+    if scan_ignore.iter().any(|pattern| scan::ignore::is_path_aware(pattern)) {
+        require_remote_capability(&remote_info, rpc::CAPABILITY_PATH_IGNORE)?;
+    }
+    let xattrs = !prf.xattrs.is_empty();
+    if xattrs {
+        require_remote_capability(&remote_info, rpc::CAPABILITY_XATTRS)?;
+        remote.set_xattr_namespaces(prf.xattrs.clone()).await
+            .map_err(|e| remote_rpc_error("Couldn't set remote extended attribute namespaces", e))?;
+    }
+    if prf.ownership.is_some() {
+        require_remote_capability(&remote_info, rpc::CAPABILITY_OWNERSHIP)?;
+        remote.set_ownership_mode(prf.ownership).await
+            .map_err(|e| remote_rpc_error("Couldn't set remote ownership mode", e))?;
+    }
+    if !prf.special.is_empty() {
+        require_remote_capability(&remote_info, rpc::CAPABILITY_SPECIAL_FILES)?;
+        remote.set_special_files(prf.special).await
+            .map_err(|e| remote_rpc_error("Couldn't set remote special file kinds", e))?;
+    }
+    if prf.conflicts.is_some()
+        || prf
+            .resolve
+            .iter()
+            .any(|rule| rule.policy == profile::ResolvePolicy::KeepBoth)
+    {
+        require_remote_capability(&remote_info, rpc::CAPABILITY_CONFLICT_SIDECARS)?;
+    }
+    if prf.trash.is_some() {
+        require_remote_capability(&remote_info, rpc::CAPABILITY_TRASH)?;
+    }
+    if merge_bases.is_some() {
+        require_remote_capability(&remote_info, rpc::CAPABILITY_STREAMED_DETAIL_BATCHES)?;
+    }
+    // both sides send holes as hole frames, and entries carry their allocated size over V3
+    let sparse = has_remote_capability(&remote_info, rpc::CAPABILITY_SPARSE_FILES);
+    if sparse {
+        remote.set_detail_holes(true).await
+            .map_err(|e| remote_rpc_error("Couldn't enable remote hole frames", e))?;
+    }
+    // both sides deflate literal bytes at the level the remote agreed to
+    let detail_compression = if has_remote_capability(&remote_info, rpc::CAPABILITY_COMPRESSED_DETAILS) {
+        let level = options.compression.unwrap_or(sync_ops::DEFAULT_COMPRESSION_LEVEL);
+        Some(remote.set_detail_compression(level).await
+            .map_err(|e| remote_rpc_error("Couldn't negotiate detail compression", e))?)
+    } else {
+        None
+    };
+    let detail_encoding = DetailEncoding {
+        holes: sparse,
+        compression: detail_compression,
+        file_copies: in_process,
+    };
+    // entries with captured metadata travel in the V3 layout, which also carries nanosecond
+    // mtimes; without it both sides compare mtimes to the second
+    let mut capture = prf.capture();
+    // both sides read per-directory ignore files, or neither does
+    let ignore_files = has_remote_capability(&remote_info, rpc::CAPABILITY_IGNORE_FILES);
+    if !prf.ignore_files.is_empty() {
+        require_remote_capability(&remote_info, rpc::CAPABILITY_IGNORE_FILES)?;
+    }
+    if ignore_files {
+        remote.set_ignore_files(capture.ignore_files.clone()).await
+            .map_err(|e| remote_rpc_error("Couldn't set remote ignore files", e))?;
+    } else {
+        log::info!("peer lacks {}; not reading per-directory ignore files", rpc::CAPABILITY_IGNORE_FILES);
+        capture.ignore_files.clear();
+    }
+    if !capture.filters.is_empty() {
+        require_remote_capability(&remote_info, rpc::CAPABILITY_ENTRY_FILTERS)?;
+        remote.set_entry_filters(capture.filters).await
+            .map_err(|e| remote_rpc_error("Couldn't set remote entry filters", e))?;
+    }
+    let nsec = has_remote_capability(&remote_info, rpc::CAPABILITY_MTIME_NSEC);
+    // link groups come with the scan entries, which only the V3 layout carries
+    let link_groups = has_remote_capability(&remote_info, rpc::CAPABILITY_HARD_LINKS);
+    let v3 = sparse || nsec || link_groups || !capture.xattrs.is_empty() || capture.ownership.is_some() || !capture.special.is_empty();
     if let Some(remote_state_dir) = remote_state_dir {
         require_remote_capability(&remote_info, rpc::CAPABILITY_PROFILE_FILE_STATE_DIR)?;
         remote.set_remote_state_dir(remote_state_dir).await.map_err(remote_state_dir_error)?;
@@ -377,7 +648,7 @@ pub async fn sync(
 
     let local_fut = async {
         let start = Instant::now();
-        let result = state::old_and_changes(&local_base, &scope, &locations, &scan_ignore, Some(&local_state), strong).await;
+        let result = state::old_and_changes(&local_base, &scope, &locations, &scan_ignore, &capture, Some(&local_state), strong).await;
         (result, start.elapsed())
     };
     let remote_scope = scope.clone();
@@ -385,18 +656,24 @@ pub async fn sync(
     let remote_ignore = scan_ignore.clone();
     let remote_fut = async {
         let start = Instant::now();
-        let result = if !remote_scope.excludes.is_empty() {
+        let result = if v3 {
+            remote.changes_scope_v3(remote_scope, remote_locations, remote_ignore, remote_id.clone(), strong).await
+                .map_err(|e| remote_rpc_error("Couldn't get remote changes with captured metadata", e))
+        } else if !remote_scope.excludes.is_empty() {
             remote.changes_scope(remote_scope, remote_locations, remote_ignore, remote_id.clone(), strong).await
+                .map(Into::into)
                 .map_err(|e| remote_rpc_error("Couldn't get remote scoped changes", e))
         } else if strong {
             remote.changes_v2(remote_scope.restrict, remote_locations, remote_ignore, remote_id.clone()).await
+                .map(Into::into)
                 .map_err(|e| remote_rpc_error("Couldn't get remote V2 changes", e))
         } else {
             remote.changes(remote_scope.restrict, remote_locations, remote_ignore, remote_id.clone()).await
-                .map(|changes| state::ChangesV2 {
+                .map(|changes| state::ChangesV3 {
                     changes: changes.into_iter().map(Into::into).collect(),
                     current: Vec::new(),
                     migration_needed: false,
+                    skipped: Vec::new(),
                 })
                 .map_err(|e| remote_rpc_error("Couldn't get remote changes", e))
         };
@@ -413,9 +690,27 @@ pub async fn sync(
     if interrupt.is_cancel_requested() {
         return Ok(SyncOutcome::Interrupted);
     }
+    print_skipped_sockets("local", &local_context.skipped);
+    print_skipped_sockets("remote", &remote_context.skipped);
+    let remote_filtered = if capture.filters.is_empty() {
+        Vec::new()
+    } else {
+        remote.filtered_entries().await
+            .map_err(|e| remote_rpc_error("Couldn't get remote filtered entries", e))?
+    };
+    print_filtered_entries("local", &local_context.filtered, options.dry_run);
+    print_filtered_entries("remote", &remote_filtered, options.dry_run);
     let mut local_all_old = local_context.all_old;
-    let local_changes = local_context.changes;
-    let remote_changes = remote_context.changes;
+    let mut local_changes = local_context.changes;
+    let mut remote_changes = remote_context.changes;
+    // neither side touches an entry that either side's [filter] keeps out
+    let filtered: HashSet<&Path> = local_context.filtered.iter().chain(&remote_filtered)
+        .map(|entry| entry.path.as_path())
+        .collect();
+    if !filtered.is_empty() {
+        local_changes.retain(|change| !state::under_any(&filtered, change.path()));
+        remote_changes.retain(|change| !state::under_any(&filtered, change.path()));
+    }
 
     performance.counters.local_entries = local_all_old.len();
     performance.counters.local_changes = local_changes.len();
@@ -425,6 +720,10 @@ pub async fn sync(
 
     let tuning_start = Instant::now();
     let tuning = negotiate_sync_tuning(&remote, &remote_info).await?;
+    // a limited stream is paced frame by frame
+    let tuning = options
+        .bwlimit
+        .map_or(tuning, |limit| limit.limit_tuning(tuning));
     performance.record_phase("sync_tuning", tuning_start.elapsed());
     performance.sync_tuning = Some(tuning.normalized());
     if interrupt.is_cancel_requested() {
@@ -444,12 +743,34 @@ pub async fn sync(
             &remote_context.current,
         )
     } else {
-        build_actions(&local_changes, &remote_changes, strong)
+        build_actions(
+            &local_changes,
+            &remote_changes,
+            strong,
+            strong && has_remote_capability(&remote_info, rpc::CAPABILITY_MOVES),
+        )
     };
     if options.debug_info {
-        show_debug_info(&remote_info, tuning);
+        show_debug_info(&remote_info, tuning, &prf.ssh);
     }
     performance.counters.total_actions = actions.len();
+    let merged = match &merge_bases {
+        Some(bases) if !migration => {
+            merge_conflicts(&remote, &local_base, bases, &mut actions, strong, v3, tuning).await?
+        }
+        _ => HashMap::new(),
+    };
+    let mut keep_both = HashSet::new();
+    for (path, rule) in resolution::resolve_by_rules(&mut actions, &prf.resolve)? {
+        println!(
+            "Resolved {} by [resolve] rule {}",
+            crate::actions::show_path(&path),
+            rule
+        );
+        if rule.policy == profile::ResolvePolicy::KeepBoth {
+            keep_both.insert(path);
+        }
+    }
     let resolution = if options.dry_run {
         show_dry_run_actions(&actions, options.verbose);
         AllResolution::Proceed
@@ -457,7 +778,11 @@ pub async fn sync(
         println!("Migrating synchronized state to strong content digests");
         AllResolution::Proceed
     } else {
-        resolve_actions(&mut actions, options.clone())?
+        resolve_actions(
+            &mut actions,
+            options.clone(),
+            prf.force_newer && prf.conflicts.is_some(),
+        )?
     };
     performance.counters.unresolved_conflicts = num_unresolved_conflicts(actions.iter());
     performance.counters.identical_actions = num_identical(actions.iter());
@@ -487,6 +812,10 @@ pub async fn sync(
     }
 
     if actions.is_empty() && !options.dry_run {
+        if let Some(bases) = &merge_bases {
+            bases.record(&local_base, &state::load_entries(&local_state)?)?;
+        }
+        record_sync_success(&remote, &remote_info, &local_state).await?;
         return Ok(SyncOutcome::Success);
     }
 
@@ -511,15 +840,52 @@ pub async fn sync(
 
     log::debug!("synchronizing");
 
+    let pending_paths: HashSet<PathBuf> = actions
+        .iter()
+        .filter(|action| !matches!(action, Action::Identical(_, _)))
+        .map(|action| action.path().clone())
+        .collect();
     let (actions, conflict_dependent_paths) =
         filter_unresolved_conflict_dependencies(actions);
+    let applied = exchanges(&actions);
     for path in conflict_dependent_paths {
         println!(
             "Skipping {} because it is structurally dependent on an unresolved conflict",
             crate::actions::show_path(&path)
         );
     }
-    let actions: Arc<Actions> = Arc::new(actions);
+    // the local files are rewritten with clean merges only once the preflight passed
+    let (actions, merge_writes) = if options.dry_run {
+        (actions, Vec::new())
+    } else {
+        merged_actions(actions, merged)
+    };
+    // without a [conflicts] section, only the conflicts settled by keep-both rules keep sidecars
+    let sidecars = match prf.conflicts {
+        _ if options.dry_run => None,
+        Some(policy) => Some((policy, None)),
+        None if !keep_both.is_empty() => Some((profile::SidecarPolicy::Tracked, Some(&keep_both))),
+        None => None,
+    };
+    let (actions, hard_links) = if !options.dry_run
+        && strong
+        && !migration
+        && link_groups
+    {
+        split_hard_links(
+            actions,
+            &pending_paths,
+            &local_all_old,
+            &local_context.current,
+            &remote_context.current,
+        )
+    } else {
+        (actions, Vec::new())
+    };
+    // moves are preflighted as the removals and additions they stand for, and renamed only
+    // once the preflight passed
+    let planned = actions;
+    let actions: Arc<Actions> = Arc::new(crate::actions::expand_moves(planned.clone()));
     performance.counters.active_actions = actions.len();
 
     if options.dry_run && actions.is_empty() {
@@ -552,6 +918,9 @@ pub async fn sync(
         apply_options,
     )
     .await?;
+    if capture.ownership.is_some() {
+        report_ownership_skips(&remote, &local_base, actions.as_ref(), &remote_actions).await?;
+    }
     sync_ops::preflight_state_save(&local_state)?;
     sync_ops::preflight_apply_with_policy(
         &local_base,
@@ -559,6 +928,68 @@ pub async fn sync(
         Some(&scan_policy),
         apply_options,
     )?;
+    let has_sidecars = sidecars.is_some_and(|(_, only)| {
+        planned
+            .iter()
+            .any(|action| keeps_sidecar(action, only) && sidecar_loser(action).is_some())
+    });
+    let (actions, remote_actions) = if !options.dry_run
+        && (planned.iter().any(Action::is_move) || has_sidecars || !merge_writes.is_empty())
+    {
+        if !interrupt.try_begin_commit() {
+            return Ok(SyncOutcome::Interrupted);
+        }
+        let prepare_attempt_id = format!("{}-prepare", apply_attempt_id);
+        start_step_attempt(
+            &remote,
+            &remote_info,
+            &local_base,
+            &local_state,
+            &planned,
+            &prepare_attempt_id,
+            strong,
+            v3,
+        )
+        .await?;
+        let actions =
+            apply_moves(&remote, &local_base, &mut local_all_old, planned.clone()).await?;
+        let actions = match sidecars {
+            Some((policy, only)) => {
+                keep_conflict_sidecars(&remote, &local_base, actions, policy, only).await?
+            }
+            None => actions,
+        };
+        let actions = write_merges(
+            &local_base,
+            &local_state,
+            actions,
+            &merge_writes,
+            &local_all_old,
+            &scan_policy,
+            apply_options,
+            tuning.signature_window_config(),
+        )?;
+        finish_step_attempt(
+            &remote,
+            &remote_info,
+            &local_base,
+            &local_state,
+            &planned,
+            &local_all_old,
+            &prepare_attempt_id,
+            &remote_id,
+            strong,
+        )
+        .await?;
+        if !interrupt.try_reset_after_checkpoint() {
+            return Ok(SyncOutcome::Interrupted);
+        }
+        performance.counters.active_actions = actions.len();
+        let remote_actions = reverse(&actions);
+        (Arc::new(actions), remote_actions)
+    } else {
+        (actions, remote_actions)
+    };
     let can_stream_details =
         has_remote_capability(&remote_info, rpc::CAPABILITY_STREAMED_DETAIL_BATCHES)
             && sync_ops::can_stream_details(&actions)
@@ -582,6 +1013,15 @@ pub async fn sync(
     if !can_stream_details {
         preflight_non_streamed_detail_size(actions.as_ref(), &remote_actions)?;
     }
+    let throttle = options.bwlimit.map(bwlimit::Throttle::new);
+    if let Some(throttle) = &throttle {
+        if !can_stream_details {
+            eprintln!("Warning: file data is not streamed in this sync, so --bwlimit does not pace it.");
+        }
+        throttle
+            .watch_signals()
+            .wrap_err("Couldn't watch for bandwidth limit signals")?;
+    }
     let can_prepare_remote_apply =
         has_remote_capability(&remote_info, rpc::CAPABILITY_APPLY_ATTEMPT_PREPARE);
     let can_prepare_remote_apply_with_id =
@@ -677,7 +1117,7 @@ pub async fn sync(
     if options.dry_run {
         require_remote_capability(&remote_info, rpc::CAPABILITY_PREFLIGHT_APPLY)?;
         if strong {
-            remote.preflight_apply_v2(remote_actions, apply_options).await
+            remote.preflight_apply_v2(crate::actions::to_v2(remote_actions), apply_options).await
                 .map_err(|e| remote_rpc_error("Failed to preflight remote apply", e))?;
         } else {
             remote.preflight_apply(crate::actions::to_legacy(remote_actions), apply_options).await
@@ -703,6 +1143,9 @@ pub async fn sync(
         }
         return Ok(SyncOutcome::Success);
     }
+    if let Some(retention) = prf.trash {
+        keep_in_trash(&remote, &local_base, &trash_id, actions.as_ref(), retention).await?;
+    }
     if apply_options.prune_ignored {
         remote
             .set_apply_options(apply_options)
@@ -710,7 +1153,7 @@ pub async fn sync(
             .map_err(|e| remote_rpc_error("Failed to set remote apply options", e))?;
     }
     if !apply_strategy.is_staged() {
-        set_remote_actions(&remote, remote_actions, strong).await?;
+        set_remote_actions(&remote, remote_actions, strong, v3).await?;
     }
     performance.record_phase("preflight_and_set_actions", preflight_start.elapsed());
     log::debug!("set remote actions");
@@ -759,7 +1202,7 @@ pub async fn sync(
     (local_signatures, remote_signatures)
     };
 
-    let local_all_old = if apply_strategy == ApplyStrategy::StagedStream {
+    let mut local_all_old = if apply_strategy == ApplyStrategy::StagedStream {
         let plan = staging_plan
             .as_ref()
             .expect("staged strategy must have a staging plan");
@@ -797,7 +1240,7 @@ pub async fn sync(
                 plan.waves.len()
             );
             let set_actions_start = Instant::now();
-            set_remote_actions(&remote, remote_wave_actions, strong).await?;
+            set_remote_actions(&remote, remote_wave_actions, strong, v3).await?;
             record_phase_aggregate(
                 &mut performance,
                 "remote_set_actions_rpc",
@@ -863,10 +1306,12 @@ pub async fn sync(
                 apply_options,
                 remote_stream_performance_enabled(profiling_enabled, &remote_info),
                 has_remote_capability(&remote_info, rpc::CAPABILITY_FILE_BYTE_CHUNKS),
+                detail_encoding,
                 remote_uses_staged_marker_profile(&remote_info),
                 Some(&wave_attempt_id),
                 Some(options.staging_policy),
                 Some(&interrupt),
+                throttle.as_ref(),
                 StreamProgressMode::Staged {
                     wave_number: wave_index + 1,
                     wave_count: plan.waves.len(),
@@ -876,7 +1321,7 @@ pub async fn sync(
             let StreamDetailedChangesRun::Complete(mut stream_result) = stream_result else {
                 return Ok(SyncOutcome::Interrupted);
             };
-            record_stream_performance(&mut performance, &mut stream_result);
+            record_stream_performance(&mut performance, &mut stream_result, throttle.as_ref());
             let StreamApplyOutcome::Staged {
                 mut prepared,
                 local_report,
@@ -1240,17 +1685,19 @@ pub async fn sync(
             apply_options,
             remote_stream_performance_enabled(profiling_enabled, &remote_info),
             has_remote_capability(&remote_info, rpc::CAPABILITY_FILE_BYTE_CHUNKS),
+            detail_encoding,
             false,
             None,
             None,
             None,
+            throttle.as_ref(),
             StreamProgressMode::Legacy,
         )
         .await?;
         let StreamDetailedChangesRun::Complete(mut stream_result) = stream_result else {
             unreachable!("legacy stream cannot be cancelled after commit")
         };
-        record_stream_performance(&mut performance, &mut stream_result);
+        record_stream_performance(&mut performance, &mut stream_result, throttle.as_ref());
         let StreamApplyOutcome::Legacy(local_all_old) = stream_result.outcome else {
             unreachable!("legacy stream returned staged outcome");
         };
@@ -1340,7 +1787,39 @@ pub async fn sync(
         local_all_old
     };
 
-    if !apply_strategy.is_staged() {
+    if apply_strategy.is_staged() && !hard_links.is_empty() {
+        // Each staged wave already saved its snapshots and cleared its markers; the links run
+        // under an attempt of their own and are saved on top of them.
+        let link_actions: Actions = hard_links.iter().map(|link| link.action.clone()).collect();
+        let links_attempt_id = format!("{}-links", apply_attempt_id);
+        start_step_attempt(
+            &remote,
+            &remote_info,
+            &local_base,
+            &local_state,
+            &link_actions,
+            &links_attempt_id,
+            strong,
+            v3,
+        )
+        .await?;
+        apply_hard_links(&remote, &local_base, &mut local_all_old, &hard_links).await?;
+        finish_step_attempt(
+            &remote,
+            &remote_info,
+            &local_base,
+            &local_state,
+            &link_actions,
+            &local_all_old,
+            &links_attempt_id,
+            &remote_id,
+            strong,
+        )
+        .await?;
+    } else if !apply_strategy.is_staged() {
+        if !hard_links.is_empty() {
+            apply_hard_links(&remote, &local_base, &mut local_all_old, &hard_links).await?;
+        }
         sync_ops::mark_apply_attempt_state_save(
             "local",
             &local_state,
@@ -1401,6 +1880,11 @@ pub async fn sync(
         performance.record_phase("state_save_total", state_save_start.elapsed());
     }
 
+    if let Some(bases) = &merge_bases {
+        bases.record(&local_base, &state::load_entries(&local_state)?)?;
+    }
+    exchanged.extend(applied);
+
     if profiling_enabled {
         performance.finish(total_start.elapsed());
         if print_performance {
@@ -1410,6 +1894,7 @@ pub async fn sync(
             performance.write_json(&path)?;
         }
     }
+    record_sync_success(&remote, &remote_info, &local_state).await?;
 
     Ok(SyncOutcome::Success)
     }
@@ -1437,6 +1922,7 @@ pub async fn recover_remote(target: PathBuf, clear: bool, yes: bool) -> Result<(
     let context = prepare_context(ProfileSource::Named(profile_name.to_string()), None, &[])?;
 
     let SyncContext {
+        profile,
         local_id,
         legacy_local_id,
         remote_server,
@@ -1446,15 +1932,20 @@ pub async fn recover_remote(target: PathBuf, clear: bool, yes: bool) -> Result<(
         ..
     } = context;
 
-    let remote_session = open_remote_session(remote_server).await;
-    let mut server = remote::launch_server(&remote_session, remote_cmd, &server_log)
-        .await
-        .unwrap_or_else(|e| {
-            let diagnostic =
-                sync_error::render_report("setup", "launch server", Some(server_log.clone()), e);
-            eprintln!("{}", diagnostic.cyan());
-            quit::with_code(SERVER_ERROR_CODE);
-        });
+    let remote_session = open_remote_session(remote_server, &profile).await;
+    let mut server = remote::launch_server(
+        &remote_session,
+        remote_cmd,
+        &server_log,
+        remote::LocalServer::InProcess,
+    )
+    .await
+    .unwrap_or_else(|e| {
+        let diagnostic =
+            sync_error::render_report("setup", "launch server", Some(server_log.clone()), e);
+        eprintln!("{}", diagnostic.cyan());
+        quit::with_code(SERVER_ERROR_CODE);
+    });
     let result = async {
         let remote = remote::get_remote(&mut server)?;
         let remote_info = remote.server_info().await.map_err(server_info_error)?;
@@ -1515,41 +2006,324 @@ fn remote_recovery_profile_name(target: &Path) -> Result<&str> {
     Ok(name)
 }
 
-async fn prepare_remote_apply_attempt<R>(
-    remote: &R,
-    supported: bool,
-    supports_attempt_id: bool,
-    attempt_id: &str,
-) -> Result<()>
-where
-    R: DuetServerAsync,
-{
-    if supported {
-        if supports_attempt_id {
-            remote
-                .prepare_apply_attempt_with_id(attempt_id.to_string())
-                .await
-                .map_err(|e| remote_rpc_error("Couldn't prepare remote apply recovery", e))?;
-        } else {
-            remote
-                .prepare_apply_attempt()
-                .await
-                .map_err(|e| remote_rpc_error("Couldn't prepare remote apply recovery", e))?;
+pub async fn trash(target: String, command: TrashCommand, remote: bool) -> Result<()> {
+    env_logger::init();
+    let context = prepare_context(ProfileSource::Named(target.clone()), None, &[])?;
+    let retention = match command {
+        TrashCommand::Purge { all: true } => TrashRetention::everything(),
+        TrashCommand::Purge { all: false } => match context.profile.trash {
+            Some(retention) if retention.is_limited() => retention,
+            _ => {
+                return Err(eyre!(
+                    "profile {} sets no trash max-age or max-size; use `duet trash purge --all {}` to empty the trash",
+                    target,
+                    target
+                ))
+            }
+        },
+        _ => TrashRetention::default(),
+    };
+    let SyncContext {
+        profile,
+        local_id,
+        local_base,
+        remote_base,
+        remote_server,
+        remote_cmd,
+        server_log,
+        ..
+    } = context;
+
+    if !remote {
+        match command {
+            TrashCommand::List => print_trash_items("local", trash::list(&local_base, &local_id)?),
+            TrashCommand::Restore { items } => {
+                for item in items {
+                    let path = trash::restore(&local_base, &local_id, &item)?;
+                    print_trash_restore("local", &path);
+                }
+            }
+            TrashCommand::Purge { .. } => {
+                print_trash_purge("local", trash::purge(&local_base, &local_id, retention)?)
+            }
         }
-        test_pause_after_remote_apply_prepare().await;
+        return Ok(());
     }
-    Ok(())
-}
 
-async fn negotiate_sync_tuning<R>(
-    remote: &R,
-    info: &rpc::ServerInfo,
-) -> Result<sync_ops::SyncTuning>
-where
-    R: DuetServerAsync,
-{
-    if !has_remote_capability(info, rpc::CAPABILITY_SYNC_TUNING) {
-        return Ok(sync_ops::SyncTuning::legacy());
+    let remote_session = open_remote_session(remote_server, &profile).await;
+    let mut server = remote::launch_server(
+        &remote_session,
+        remote_cmd,
+        &server_log,
+        remote::LocalServer::InProcess,
+    )
+    .await
+    .unwrap_or_else(|e| {
+        let diagnostic =
+            sync_error::render_report("setup", "launch server", Some(server_log.clone()), e);
+        eprintln!("{}", diagnostic.cyan());
+        quit::with_code(SERVER_ERROR_CODE);
+    });
+    let result = async {
+        let remote = remote::get_remote(&mut server)?;
+        let remote_info = remote.server_info().await.map_err(server_info_error)?;
+        require_remote_capability(&remote_info, rpc::CAPABILITY_TRASH)?;
+        remote
+            .set_base(remote_base)
+            .await
+            .map_err(|e| remote_rpc_error("Couldn't set server base", e))?;
+        match command {
+            TrashCommand::List => print_trash_items(
+                "remote",
+                remote
+                    .list_trash(local_id)
+                    .await
+                    .map_err(|e| remote_rpc_error("Failed to list the remote trash", e))?,
+            ),
+            TrashCommand::Restore { items } => {
+                for item in items {
+                    let path = remote
+                        .restore_from_trash(local_id.clone(), item)
+                        .await
+                        .map_err(|e| {
+                            remote_rpc_error("Failed to restore from the remote trash", e)
+                        })?;
+                    print_trash_restore("remote", &path);
+                }
+            }
+            TrashCommand::Purge { .. } => print_trash_purge(
+                "remote",
+                remote
+                    .purge_trash(local_id, retention)
+                    .await
+                    .map_err(|e| remote_rpc_error("Failed to purge the remote trash", e))?,
+            ),
+        }
+        Ok(())
+    }
+    .await;
+    finalize_server(result, server.wait().await)
+}
+
+/// Shows the named profiles, or every profile in the configuration directory, with each peer's
+/// snapshot and unfinished apply marker, and with `remote` the server side of each. A profile
+/// that fails to load, or a server that can't be reached, is reported and the others still shown.
+pub async fn status(profiles: Vec<String>, remote: bool) -> Result<()> {
+    env_logger::init();
+    let names = if profiles.is_empty() {
+        profile::names()?
+    } else {
+        profiles
+    };
+    if names.is_empty() {
+        println!("No profiles in ~/.config/duet");
+        return Ok(());
+    }
+    let mut failed = 0;
+    for name in &names {
+        println!("{}", name.cyan());
+        if let Err(e) = profile_status(name, remote).await {
+            println!("  {}", format!("{:#}", e).red());
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        return Err(eyre!(
+            "unable to show the status of {} of {} profiles",
+            failed,
+            names.len()
+        ));
+    }
+    Ok(())
+}
+
+async fn profile_status(name: &str, remote: bool) -> Result<()> {
+    let source = ProfileSource::Named(name.to_string());
+    let config = profile::load(&source)?;
+    let local_base = PathBuf::from(&config.profile.local);
+    println!("  {:<8} {}", "local", local_base.display());
+    let mut unreachable = 0;
+    for context in peer_contexts(&source, &config, local_base, scan::ScanScope::default())? {
+        let kind = if context.peer == config.profile.remote {
+            "remote"
+        } else {
+            "peer"
+        };
+        println!("  {:<8} {}", kind, context.peer);
+        print_state_summary("synced", &state::summarize(&context.local_state)?);
+        print_apply_attempt(sync_ops::describe_apply_attempt(&context.local_state)?);
+        if remote {
+            if let Err(e) = remote_status(context).await {
+                println!("    {:<8} {}", "server", format!("{:#}", e).red());
+                unreachable += 1;
+            }
+        }
+    }
+    if unreachable > 0 {
+        return Err(eyre!("unable to inspect {} of its servers", unreachable));
+    }
+    Ok(())
+}
+
+/// The server side of one peer: its snapshot and apply marker, under the state id a sync would
+/// select.
+async fn remote_status(context: SyncContext) -> Result<()> {
+    let SyncContext {
+        profile,
+        local_id,
+        legacy_local_id,
+        remote_server,
+        remote_cmd,
+        remote_state_dir,
+        server_log,
+        ..
+    } = context;
+    let remote_session = match remote_server {
+        Some(server) => match deferred_session(&server, &profile) {
+            Some(session) => session,
+            None => remote::RemoteSession::Mux(
+                connect_remote_session(server, &profile.ssh)
+                    .await
+                    .map_err(|e| eyre!("{}", ssh_diagnostic(&e, &profile.ssh)))?,
+            ),
+        },
+        None => remote::RemoteSession::None,
+    };
+    let mut server = remote::launch_server(
+        &remote_session,
+        remote_cmd,
+        &server_log,
+        remote::LocalServer::InProcess,
+    )
+    .await?;
+    let result = async {
+        let remote = remote::get_remote(&mut server)?;
+        let remote_info = remote.server_info().await.map_err(server_info_error)?;
+        require_remote_capability(&remote_info, rpc::CAPABILITY_STATE_SUMMARY)?;
+        if let Some(remote_state_dir) = remote_state_dir {
+            require_remote_capability(&remote_info, rpc::CAPABILITY_PROFILE_FILE_STATE_DIR)?;
+            remote
+                .set_remote_state_dir(remote_state_dir)
+                .await
+                .map_err(remote_state_dir_error)?;
+        }
+        let remote_id =
+            select_remote_state_id(&remote, &remote_info, local_id, legacy_local_id).await?;
+        let summary = remote
+            .state_summary(remote_id.clone())
+            .await
+            .map_err(|e| remote_rpc_error("Failed to summarize remote state", e))?;
+        let attempt = remote
+            .describe_apply_attempt(remote_id)
+            .await
+            .map_err(|e| remote_rpc_error("Failed to inspect remote recovery marker", e))?;
+        print_state_summary("server", &summary);
+        print_apply_attempt(attempt);
+        Ok(())
+    }
+    .await;
+    finalize_server(result, server.wait().await)
+}
+
+fn print_state_summary(label: &str, summary: &state::StateSummary) {
+    match summary.synced {
+        Some(synced) => println!(
+            "    {:<8} {}, {} entries ({})",
+            label,
+            chrono::DateTime::<chrono::Local>::from(synced).format("%a %Y-%m-%d %H:%M:%S"),
+            summary.entries,
+            show_bytes(summary.bytes)
+        ),
+        None if summary.entries == 0 => println!("    {:<8} never", label),
+        None => println!(
+            "    {:<8} no successful sync recorded, {} entries ({})",
+            label,
+            summary.entries,
+            show_bytes(summary.bytes)
+        ),
+    }
+}
+
+fn print_apply_attempt(description: Option<String>) {
+    if let Some(description) = description {
+        println!("    {}", "unfinished apply:".yellow());
+        for line in description.lines() {
+            println!("      {}", line);
+        }
+    }
+}
+
+fn print_trash_items(side: &str, items: Vec<trash::TrashItem>) {
+    if items.is_empty() {
+        println!("The {} trash is empty", side);
+    }
+    for item in items {
+        println!(
+            "{:>10}  {}",
+            show_bytes(item.size),
+            crate::actions::show_path(&item.name())
+        );
+    }
+}
+
+fn print_trash_restore(side: &str, path: &PathBuf) {
+    println!(
+        "Restored {} from the {} trash",
+        crate::actions::show_path(path),
+        side
+    );
+}
+
+fn print_trash_purge(side: &str, purged: trash::TrashPurge) {
+    println!(
+        "Purged {} runs ({}) from the {} trash",
+        purged.runs,
+        show_bytes(purged.bytes),
+        side
+    );
+}
+
+fn show_bytes(bytes: u64) -> String {
+    byte_unit::Byte::from_u64(bytes)
+        .get_appropriate_unit(byte_unit::UnitType::Decimal)
+        .to_string()
+}
+
+async fn prepare_remote_apply_attempt<R>(
+    remote: &R,
+    supported: bool,
+    supports_attempt_id: bool,
+    attempt_id: &str,
+) -> Result<()>
+where
+    R: DuetServerAsync,
+{
+    if supported {
+        if supports_attempt_id {
+            remote
+                .prepare_apply_attempt_with_id(attempt_id.to_string())
+                .await
+                .map_err(|e| remote_rpc_error("Couldn't prepare remote apply recovery", e))?;
+        } else {
+            remote
+                .prepare_apply_attempt()
+                .await
+                .map_err(|e| remote_rpc_error("Couldn't prepare remote apply recovery", e))?;
+        }
+        test_pause_after_remote_apply_prepare().await;
+    }
+    Ok(())
+}
+
+async fn negotiate_sync_tuning<R>(
+    remote: &R,
+    info: &rpc::ServerInfo,
+) -> Result<sync_ops::SyncTuning>
+where
+    R: DuetServerAsync,
+{
+    if !has_remote_capability(info, rpc::CAPABILITY_SYNC_TUNING) {
+        return Ok(sync_ops::SyncTuning::legacy());
     }
 
     remote
@@ -1577,6 +2351,24 @@ where
     Ok(legacy_id.unwrap_or(stable_id))
 }
 
+/// Records a successful sync on both peers, so `duet status` shows when each last finished one.
+async fn record_sync_success<R>(
+    remote: &R,
+    info: &rpc::ServerInfo,
+    local_state: &Path,
+) -> Result<()>
+where
+    R: DuetServerAsync,
+{
+    if has_remote_capability(info, rpc::CAPABILITY_STATE_SUMMARY) {
+        remote
+            .mark_synced()
+            .await
+            .map_err(|e| remote_rpc_error("Failed to record remote sync time", e))?;
+    }
+    state::mark_synced(local_state)
+}
+
 fn new_apply_attempt_id(local_id: &str) -> String {
     let since_epoch = SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -1645,18 +2437,537 @@ fn can_use_staging_plan(
     Ok(false)
 }
 
-async fn set_remote_actions<R>(remote: &R, actions: Actions, strong: bool) -> Result<()>
+async fn set_remote_actions<R>(remote: &R, actions: Actions, strong: bool, v3: bool) -> Result<()>
 where
     R: DuetServerAsync,
 {
-    if strong {
-        remote.set_actions_v2(actions).await
+    if v3 {
+        remote.set_actions_v3(actions).await
+    } else if strong {
+        remote.set_actions_v2(crate::actions::to_v2(actions)).await
     } else {
         remote.set_actions(crate::actions::to_legacy(actions)).await
     }
     .map_err(|e| remote_rpc_error("Failed to set remote actions", e))
 }
 
+/// Opens an apply attempt on both sides for a step that runs apart from the regular apply, such
+/// as the renames of moves and conflict sidecars and the writes of clean merges before it. The
+/// markers record `actions`.
+#[allow(clippy::too_many_arguments)]
+async fn start_step_attempt<R>(
+    remote: &R,
+    remote_info: &rpc::ServerInfo,
+    local_base: &Path,
+    local_state: &Path,
+    actions: &[Action],
+    attempt_id: &str,
+    strong: bool,
+    v3: bool,
+) -> Result<()>
+where
+    R: DuetServerAsync,
+{
+    // the remote records the actions it accepted, which also preflights them there
+    let remote_actions = reverse(&crate::actions::expand_moves(actions.to_vec()));
+    set_remote_actions(remote, remote_actions, strong, v3).await?;
+    prepare_remote_apply_attempt(
+        remote,
+        has_remote_capability(remote_info, rpc::CAPABILITY_APPLY_ATTEMPT_PREPARE),
+        has_remote_capability(remote_info, rpc::CAPABILITY_APPLY_ATTEMPT_ID),
+        attempt_id,
+    )
+    .await?;
+    sync_ops::start_apply_attempt("local", local_state, local_base, actions, Some(attempt_id))
+}
+
+/// Saves both snapshots once a step opened by `start_step_attempt` is done, and clears its
+/// markers, so the regular apply starts from a checkpoint.
+#[allow(clippy::too_many_arguments)]
+async fn finish_step_attempt<R>(
+    remote: &R,
+    remote_info: &rpc::ServerInfo,
+    local_base: &Path,
+    local_state: &Path,
+    actions: &[Action],
+    local_all_old: &Vec<scan::DirEntryWithMeta>,
+    attempt_id: &str,
+    remote_id: &str,
+    strong: bool,
+) -> Result<()>
+where
+    R: DuetServerAsync,
+{
+    sync_ops::mark_apply_attempt_state_save(
+        "local",
+        local_state,
+        local_base,
+        actions,
+        Some(attempt_id),
+    )?;
+    let coordinated_cleanup =
+        has_remote_capability(remote_info, rpc::CAPABILITY_COORDINATED_MARKER_CLEANUP);
+    let format = if strong {
+        state::SnapshotFormat::V2
+    } else {
+        state::SnapshotFormat::LegacyV1
+    };
+    state::save_entries_as(local_state, local_all_old, format).wrap_err_with(|| {
+        format!(
+            "failed to save local state {}\n{}",
+            local_state.display(),
+            STATE_SAVE_RECOVERY_ADVICE
+        )
+    })?;
+    if coordinated_cleanup {
+        remote.save_state_pending(strong).await
+    } else if strong {
+        remote.save_state_v2().await
+    } else {
+        remote.save_state().await
+    }
+    .map_err(|e| post_state_save_rpc_error("failed to save remote state", e))?;
+    if coordinated_cleanup {
+        remote
+            .clear_apply_attempt(remote_id.to_string())
+            .await
+            .map_err(|e| remote_rpc_error("failed to clear remote recovery marker", e))?;
+    }
+    sync_ops::finish_apply_attempt(local_state)
+}
+
+/// Applies the moves among `actions` as renames on both sides, before the remaining actions
+/// are staged. Directories a move had to create are left in the returned actions as
+/// modifications, so the regular apply only sets their metadata.
+async fn apply_moves<R>(
+    remote: &R,
+    local_base: &Path,
+    local_all_old: &mut Vec<scan::DirEntryWithMeta>,
+    actions: Actions,
+) -> Result<Actions>
+where
+    R: DuetServerAsync,
+{
+    let (moves, actions) = crate::actions::split_moves(actions);
+    if moves.is_empty() {
+        return Ok(actions);
+    }
+    let remote_created = remote
+        .apply_moves(reverse(&moves))
+        .await
+        .map_err(|e| remote_rpc_error("Failed to apply remote moves", e))?;
+    let local_created = sync_ops::apply_moves(local_base, &moves, local_all_old)?;
+    let created_at = |created: &[scan::DirEntryWithMeta], path: &Path| {
+        created
+            .binary_search_by(|entry| entry.path().as_path().cmp(path))
+            .ok()
+            .map(|i| created[i].clone())
+    };
+    let mut local_created = local_created;
+    let mut remote_created = remote_created;
+    local_created.sort();
+    remote_created.sort();
+    Ok(actions
+        .into_iter()
+        .map(|action| match action {
+            Action::Local(Change::Added(dir)) if dir.is_dir() => {
+                match created_at(&local_created, dir.path()) {
+                    Some(created) => Action::Local(Change::Modified(created, dir)),
+                    None => Action::Local(Change::Added(dir)),
+                }
+            }
+            Action::Remote(Change::Added(dir)) if dir.is_dir() => {
+                match created_at(&remote_created, dir.path()) {
+                    Some(created) => Action::Remote(Change::Modified(created, dir)),
+                    None => Action::Remote(Change::Added(dir)),
+                }
+            }
+            action => action,
+        })
+        .collect())
+}
+
+/// Runs a line-based three-way merge for each conflict between two edits of a file that the
+/// profile's `[merge]` globs match and whose base is in the store. A clean merge resolves the
+/// conflict in favour of the local version and its contents are returned, to be written once the
+/// run goes ahead; a merge with conflicts is kept with markers and left to the prompt.
+async fn merge_conflicts<R>(
+    remote: &R,
+    local_base: &Path,
+    bases: &MergeBases,
+    actions: &mut Actions,
+    strong: bool,
+    v3: bool,
+    tuning: sync_ops::SyncTuning,
+) -> Result<HashMap<PathBuf, Vec<u8>>>
+where
+    R: DuetServerAsync,
+{
+    let mut candidates = Vec::new();
+    let mut remote_entries = Vec::new();
+    for (index, action) in actions.iter().enumerate() {
+        let Action::Conflict(Change::Modified(old, local), Change::Modified(_, remote)) = action
+        else {
+            continue;
+        };
+        if !old.is_file() || !local.is_file() || !remote.is_file() || !bases.matches(local.path()) {
+            continue;
+        }
+        let (Some(base_digest), Some(local_digest)) = (old.digest(), local.digest()) else {
+            continue;
+        };
+        let Some(base) = bases.get(&base_digest)? else {
+            continue;
+        };
+        let filename = sync_ops::safe_join(local_base, local.path())?;
+        let contents = std::fs::read(&filename)
+            .wrap_err_with(|| format!("failed to read {} to merge", filename.display()))?;
+        if sync_ops::content_digest(&contents) != local_digest {
+            return Err(eyre!(
+                "local file {} changed since the scan",
+                filename.display()
+            ));
+        }
+        candidates.push((index, base, contents));
+        remote_entries.push(remote.clone());
+    }
+    if candidates.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let remote_contents = fetch_remote_files(remote, &remote_entries, strong, v3, tuning).await?;
+    let mut merged = HashMap::new();
+    for ((index, base, local), remote) in candidates.into_iter().zip(remote_contents) {
+        let path = actions[index].path().clone();
+        match merge::merge(&base, &local, &remote) {
+            merge::Merged::Clean(contents) => {
+                println!("Merged {} cleanly", crate::actions::show_path(&path));
+                actions[index] = resolution::resolve_local(&actions[index]);
+                merged.insert(path, contents);
+            }
+            merge::Merged::Conflicted(contents) => {
+                let kept = bases.keep_conflicted(&path, &contents)?;
+                println!(
+                    "Merging {} left conflicts; the merge with markers is in {}",
+                    crate::actions::show_path(&path),
+                    kept.display()
+                );
+            }
+        }
+    }
+    Ok(merged)
+}
+
+/// Reads the current contents of remote files through a detail stream that sends each of them
+/// as an addition, and checks them against the scanned digests.
+async fn fetch_remote_files<R>(
+    remote: &R,
+    entries: &[scan::DirEntryWithMeta],
+    strong: bool,
+    v3: bool,
+    tuning: sync_ops::SyncTuning,
+) -> Result<Vec<Vec<u8>>>
+where
+    R: DuetServerAsync,
+{
+    let additions = entries
+        .iter()
+        .map(|entry| Action::Remote(Change::Added(entry.clone())))
+        .collect();
+    set_remote_actions(remote, additions, strong, v3).await?;
+    let stream = remote
+        .begin_detail_stream(Vec::new(), tuning.detail_chunk_bytes() as u32)
+        .await
+        .map_err(|e| remote_rpc_error("Couldn't begin remote detail stream", e))?;
+    let mut contents = vec![Vec::new(); entries.len()];
+    loop {
+        let frames = remote
+            .next_detail_chunks(
+                stream,
+                tuning.detail_batch_frames() as u32,
+                tuning.detail_batch_payload_bytes() as u32,
+            )
+            .await
+            .map_err(|e| remote_rpc_error("Couldn't read remote detail stream", e))?;
+        if frames.is_empty() {
+            break;
+        }
+        for frame in frames {
+            let frame = frame.decompressed()?;
+            let buffer = contents
+                .get_mut(frame.action_index as usize)
+                .ok_or_else(|| {
+                    eyre!("unexpected detail frame for action {}", frame.action_index)
+                })?;
+            match frame.payload {
+                sync_ops::DetailPayload::FileBytes(bytes) => buffer.extend_from_slice(&bytes),
+                sync_ops::DetailPayload::FileHole(len) => {
+                    buffer.resize(buffer.len() + len as usize, 0)
+                }
+                sync_ops::DetailPayload::FileCopy { source, .. } => buffer.extend(
+                    std::fs::read(&source)
+                        .wrap_err_with(|| format!("unable to read {}", source.display()))?,
+                ),
+                _ => {}
+            }
+        }
+    }
+    set_remote_actions(remote, Vec::new(), strong, v3).await?;
+    for (entry, contents) in entries.iter().zip(&contents) {
+        if entry.digest() != Some(sync_ops::content_digest(contents)) {
+            return Err(eyre!(
+                "remote file {} changed since the scan",
+                entry.path().display()
+            ));
+        }
+    }
+    Ok(contents)
+}
+
+/// Turns each conflict that a clean merge resolved, and that still resolves in favour of the
+/// local version, into a local edit with the merged contents for the remote. The local
+/// modifications that write the merges over the local files are returned with their contents.
+fn merged_actions(
+    actions: Actions,
+    mut merged: HashMap<PathBuf, Vec<u8>>,
+) -> (Actions, Vec<(Action, Vec<u8>)>) {
+    let now = filetime::FileTime::now();
+    let mut writes = Vec::new();
+    let actions = actions
+        .into_iter()
+        .map(|action| match action {
+            Action::ResolvedRemote(_, Change::Modified(remote, local))
+                if merged.contains_key(local.path()) =>
+            {
+                let contents = merged.remove(local.path()).expect("checked above");
+                let new = local.with_contents(&contents, now);
+                writes.push((
+                    Action::Local(Change::Modified(local, new.clone())),
+                    contents,
+                ));
+                Action::Remote(Change::Modified(remote, new))
+            }
+            action => action,
+        })
+        .collect();
+    (actions, writes)
+}
+
+/// Writes the merges from `merged_actions` over the local files through the regular apply of
+/// their local modifications, and points the remote's edits at the files that apply left. The
+/// snapshot keeps the unmerged entries until the remote has the merges too.
+#[allow(clippy::too_many_arguments)]
+fn write_merges(
+    local_base: &PathBuf,
+    local_state: &Path,
+    actions: Actions,
+    writes: &[(Action, Vec<u8>)],
+    local_all_old: &[scan::DirEntryWithMeta],
+    scan_policy: &sync_ops::ScanPolicy,
+    apply_options: sync_ops::ApplyOptions,
+    window_config: sync_ops::SignatureWindowConfig,
+) -> Result<Actions> {
+    if writes.is_empty() {
+        return Ok(actions);
+    }
+    let mut details = Vec::with_capacity(writes.len());
+    for (action, contents) in writes {
+        let Action::Local(Change::Modified(local, _)) = action else {
+            unreachable!("merged_actions writes local modifications");
+        };
+        details.push(sync_ops::merged_file_detail(
+            local_base,
+            local,
+            contents,
+            window_config,
+        )?);
+    }
+    let writes: Actions = writes.iter().map(|(action, _)| action.clone()).collect();
+    let mut written = local_all_old.to_vec();
+    sync_ops::apply_detailed_changes_with_policy(
+        local_base,
+        &writes,
+        &details,
+        &mut written,
+        Some(local_state),
+        Some(scan_policy),
+        apply_options,
+    )
+    .wrap_err(POST_PREFLIGHT_RECOVERY_ADVICE)?;
+    actions
+        .into_iter()
+        .map(|action| match action {
+            Action::Remote(Change::Modified(remote, new))
+                if writes.iter().any(|write| write.path() == new.path()) =>
+            {
+                let merged = written
+                    .binary_search_by(|entry| entry.path().cmp(new.path()))
+                    .map(|i| written[i].clone())
+                    .map_err(|_| eyre!("merged file {} was not written", new.path().display()))?;
+                Ok(Action::Remote(Change::Modified(remote, merged)))
+            }
+            action => Ok(action),
+        })
+        .collect()
+}
+
+/// Renames the version each resolved conflict overwrites or removes to a sidecar on the side it
+/// loses on, then turns the resolution into an addition to the freed path. Tracked sidecars are
+/// also copied to the other side in this run. With `only`, just the conflicts on those paths keep
+/// a sidecar.
+async fn keep_conflict_sidecars<R>(
+    remote: &R,
+    local_base: &Path,
+    actions: Actions,
+    policy: profile::SidecarPolicy,
+    only: Option<&HashSet<PathBuf>>,
+) -> Result<Actions>
+where
+    R: DuetServerAsync,
+{
+    let keeps = |action: &Action| keeps_sidecar(action, only);
+    let mut local_losers = Vec::new();
+    let mut remote_losers = Vec::new();
+    for action in actions.iter().filter(|action| keeps(action)) {
+        match sidecar_loser(action) {
+            Some((true, entry)) => local_losers.push(entry.clone()),
+            Some((false, entry)) => remote_losers.push(entry.clone()),
+            None => {}
+        }
+    }
+    if local_losers.is_empty() && remote_losers.is_empty() {
+        return Ok(actions);
+    }
+    let remote_kept = if remote_losers.is_empty() {
+        Vec::new()
+    } else {
+        remote
+            .keep_conflict_sidecars(remote_losers)
+            .await
+            .map_err(|e| remote_rpc_error("Failed to keep remote conflict sidecars", e))?
+    };
+    let local_kept = sync_ops::keep_conflict_sidecars(local_base, &local_losers)?;
+    for (side, kept) in [("local", &local_kept), ("remote", &remote_kept)] {
+        for entry in kept {
+            println!(
+                "Kept the {side} version of a conflict as {}",
+                crate::actions::show_path(entry.path())
+            );
+        }
+    }
+
+    let mut actions: Actions = actions
+        .into_iter()
+        .map(|action| {
+            if keeps(&action) {
+                resolve_to_freed_path(action)
+            } else {
+                action
+            }
+        })
+        .collect();
+    if policy == profile::SidecarPolicy::Tracked {
+        actions.extend(
+            local_kept
+                .into_iter()
+                .map(|entry| Action::Remote(Change::Added(entry))),
+        );
+        actions.extend(
+            remote_kept
+                .into_iter()
+                .map(|entry| Action::Local(Change::Added(entry))),
+        );
+        actions.sort_by(|a, b| a.path().cmp(b.path()));
+    }
+    Ok(actions)
+}
+
+/// Links the regular files the apply is about to remove or replace into each side's trash, and
+/// purges the runs that fall outside the profile's retention.
+async fn keep_in_trash<R>(
+    remote: &R,
+    local_base: &Path,
+    trash_id: &str,
+    actions: &[Action],
+    retention: TrashRetention,
+) -> Result<()>
+where
+    R: DuetServerAsync,
+{
+    let (local_replaced, remote_replaced) = trash::replaced_files(actions);
+    if !remote_replaced.is_empty() || retention.is_limited() {
+        remote
+            .keep_in_trash(trash_id.to_string(), remote_replaced, retention)
+            .await
+            .map_err(|e| {
+                remote_rpc_error("Failed to keep replaced remote files in the trash", e)
+            })?;
+    }
+    trash::keep(local_base, trash_id, &local_replaced, retention)?;
+    Ok(())
+}
+
+fn keeps_sidecar(action: &Action, only: Option<&HashSet<PathBuf>>) -> bool {
+    only.is_none_or(|paths| paths.contains(action.path()))
+}
+
+/// The regular file a resolved conflict overwrites or removes, and whether it is the local one.
+fn sidecar_loser(action: &Action) -> Option<(bool, &scan::DirEntryWithMeta)> {
+    let (local, change) = match action {
+        Action::ResolvedLocal((change, _), _) => (true, change),
+        Action::ResolvedRemote((_, change), _) => (false, change),
+        _ => return None,
+    };
+    match change {
+        Change::Added(entry) | Change::Modified(_, entry) if entry.is_file() => {
+            Some((local, entry))
+        }
+        _ => None,
+    }
+}
+
+// Once the losing file is a sidecar, its path is free: the winner is added there, and a
+// resolution to a removal leaves nothing to do on either side.
+fn resolve_to_freed_path(action: Action) -> Action {
+    let Some((_, loser)) = sidecar_loser(&action) else {
+        return action;
+    };
+    let loser = loser.clone();
+    match action {
+        Action::ResolvedLocal(changes, Change::Modified(_, new)) => {
+            Action::ResolvedLocal(changes, Change::Added(new))
+        }
+        Action::ResolvedRemote(changes, Change::Modified(_, new)) => {
+            Action::ResolvedRemote(changes, Change::Added(new))
+        }
+        Action::ResolvedLocal((_, other), Change::Removed(_)) => {
+            Action::Identical(Change::Removed(loser), other)
+        }
+        Action::ResolvedRemote((other, _), Change::Removed(_)) => {
+            Action::Identical(other, Change::Removed(loser))
+        }
+        action => action,
+    }
+}
+
+/// Links the names that `split_hard_links` held back from the regular apply, on both sides.
+async fn apply_hard_links<R>(
+    remote: &R,
+    local_base: &Path,
+    local_all_old: &mut Vec<scan::DirEntryWithMeta>,
+    links: &[sync_ops::HardLink],
+) -> Result<()>
+where
+    R: DuetServerAsync,
+{
+    remote
+        .apply_hard_links(links.iter().map(sync_ops::HardLink::reverse).collect())
+        .await
+        .map_err(|e| post_preflight_rpc_error("remote hard links failed after apply", e))?;
+    sync_ops::apply_hard_links(local_base, links, local_all_old)
+        .wrap_err(POST_PREFLIGHT_RECOVERY_ADVICE)
+}
+
 fn print_staging_plan_summary(
     plan: &sync_ops::StagingWavePlan,
     local_budget: sync_ops::StagingBudget,
@@ -1762,6 +3073,7 @@ fn actions_have_directory_to_nondirectory_change(actions: &[Action]) -> bool {
         | Action::ResolvedRemote((left, right), resolved) => {
             replacement(left) || replacement(right) || replacement(resolved)
         }
+        Action::LocalMove(_) | Action::RemoteMove(_) => false,
     })
 }
 
@@ -1868,6 +3180,64 @@ where
     Ok(apply_options)
 }
 
+/// Prints the entries whose ownership either side will leave unchanged; they do not block the sync.
+async fn report_ownership_skips<R>(
+    remote: &R,
+    local_base: &Path,
+    local_actions: &Actions,
+    remote_actions: &Actions,
+) -> Result<()>
+where
+    R: DuetServerAsync,
+{
+    let local = sync_ops::ownership_report(local_base, local_actions)?;
+    let remote = remote
+        .ownership_report(remote_actions.clone())
+        .await
+        .map_err(|e| remote_rpc_error("Failed to get remote ownership report", e))?;
+    print_ownership_skips("local", &local);
+    print_ownership_skips("remote", &remote);
+    Ok(())
+}
+
+fn print_skipped_sockets(side: &str, skipped: &[PathBuf]) {
+    if skipped.is_empty() {
+        return;
+    }
+    println!("{} sockets skipped by [special]:", side.cyan());
+    for path in skipped {
+        println!("  {}", path.display());
+    }
+}
+
+fn print_filtered_entries(side: &str, filtered: &[state::FilteredEntry], list: bool) {
+    if filtered.is_empty() {
+        return;
+    }
+    if !list {
+        println!(
+            "{} {} entries filtered by [filter]",
+            side.cyan(),
+            filtered.len()
+        );
+        return;
+    }
+    println!("{} entries filtered by [filter]:", side.cyan());
+    for entry in filtered {
+        println!("  {} ({})", entry.path.display(), entry.filter);
+    }
+}
+
+fn print_ownership_skips(side: &str, skipped: &[sync_ops::OwnershipSkip]) {
+    if skipped.is_empty() {
+        return;
+    }
+    println!("{} ownership changes that will be skipped:", side.cyan());
+    for skip in skipped {
+        println!("  {}: {}", skip.path.display(), skip.reason);
+    }
+}
+
 async fn remote_preflight_report<R>(
     remote: &R,
     remote_info: &rpc::ServerInfo,
@@ -1880,7 +3250,10 @@ where
     if has_remote_capability(remote_info, rpc::CAPABILITY_REMOVAL_BLOCKER_REPORT) {
         return if has_remote_capability(remote_info, rpc::CAPABILITY_CONTENT_DIGEST_BLAKE2B256) {
             remote
-                .removal_blocker_report_v2(remote_actions.clone(), apply_options)
+                .removal_blocker_report_v2(
+                    crate::actions::to_v2(remote_actions.clone()),
+                    apply_options,
+                )
                 .await
         } else {
             remote
@@ -1897,7 +3270,7 @@ where
     }
     if has_remote_capability(remote_info, rpc::CAPABILITY_CONTENT_DIGEST_BLAKE2B256) {
         remote
-            .preflight_apply_report_v2(remote_actions.clone(), apply_options)
+            .preflight_apply_report_v2(crate::actions::to_v2(remote_actions.clone()), apply_options)
             .await
     } else {
         remote
@@ -2038,11 +3411,22 @@ fn finalize_server<T>(primary: Result<T>, wait: Result<ExitStatus>) -> Result<T>
     }
 }
 
-fn prepare_context(
+fn prepare_context(
+    source: ProfileSource,
+    path: Option<PathBuf>,
+    excludes: &[PathBuf],
+) -> Result<SyncContext> {
+    Ok(prepare_contexts(source, path, excludes, false)?.remove(0))
+}
+
+/// One context per remote line of the profile, each with its own local snapshot and state id.
+/// With `profile_excludes`, the profile's `[options]` excludes join `excludes` in the scope.
+fn prepare_contexts(
     source: ProfileSource,
     path: Option<PathBuf>,
     excludes: &[PathBuf],
-) -> Result<SyncContext> {
+    profile_excludes: bool,
+) -> Result<Vec<SyncContext>> {
     let config = profile::load(&source).unwrap_or_else(|e| {
         let diagnostic =
             sync_error::render_error("setup", "load profile", profile_source_path(&source), e);
@@ -2050,48 +3434,74 @@ fn prepare_context(
         quit::with_code(PROFILE_ERROR_CODE);
     });
 
-    let local_base = crate::full(&config.profile.local).map_err(|e| {
-        eyre!(
-            "{}",
-            sync_error::render_report(
-                "setup",
-                "resolve local base",
-                Some(PathBuf::from(&config.profile.local)),
-                e
-            )
-        )
-    })?;
-    let (remote_base, remote_server, remote_cmd) = remote::parse_remote(&config.profile.remote)
-        .map_err(|e| {
-            eyre!(
-                "{}",
-                sync_error::render_report("setup", "parse remote", None, e)
-            )
-        })?;
-
-    let scope = normalize_scope(&local_base, path, excludes)?;
+    // the profile parser already expanded the local line
+    let local_base = PathBuf::from(&config.profile.local);
+
+    let mut excludes = excludes.to_vec();
+    if profile_excludes {
+        // relative to the local directory, wherever the sync runs from
+        excludes.extend(
+            config
+                .profile
+                .options
+                .excludes
+                .iter()
+                .map(|path| local_base.join(path)),
+        );
+    }
+    let scope = normalize_scope(&local_base, path, &excludes)?;
     println!(
         "Using profile: {} {}",
         config.display_name.cyan(),
         scope.restrict.display().to_string().yellow()
     );
 
-    let remote_state_dir = remote_state_dir_for_source(&source, remote_server.as_deref(), &config)?;
-    let local_ids = local_ids(&config.identity)?;
+    peer_contexts(&source, &config, local_base, scope)
+}
 
-    Ok(SyncContext {
-        profile: config.profile,
-        local_id: local_ids.stable,
-        legacy_local_id: local_ids.legacy,
-        local_base,
-        remote_base,
-        remote_server,
-        remote_cmd,
-        scope,
-        local_state: config.local_state,
-        remote_state_dir,
-        server_log: config.server_log,
-    })
+/// The contexts of `config`'s remote lines, scanning `scope` under `local_base`.
+fn peer_contexts(
+    source: &ProfileSource,
+    config: &profile::ProfileConfig,
+    local_base: PathBuf,
+    scope: scan::ScanScope,
+) -> Result<Vec<SyncContext>> {
+    let ids = local_ids(&config.identity)?;
+    let mut contexts = Vec::new();
+    for peer in config.profile.remotes() {
+        let (remote_base, remote_server, remote_cmd) = remote::parse_remote(peer).map_err(|e| {
+            eyre!(
+                "{}",
+                sync_error::render_report("setup", "parse remote", None, e)
+            )
+        })?;
+        let remote_state_dir =
+            remote_state_dir_for_source(source, remote_server.as_deref(), config)?;
+        // further peers pair with this replica under their own ids, which never had legacy ones
+        let (local_id, legacy_local_id) = if *peer == config.profile.remote {
+            (ids.stable.clone(), ids.legacy.clone())
+        } else {
+            let peer_ids = local_ids(&format!("{}\n{}", config.identity, peer))?;
+            (peer_ids.stable, None)
+        };
+
+        contexts.push(SyncContext {
+            profile: config.profile.clone(),
+            peer: peer.clone(),
+            local_id,
+            legacy_local_id,
+            trash_id: ids.stable.clone(),
+            local_base: local_base.clone(),
+            remote_base,
+            remote_server,
+            remote_cmd,
+            scope: scope.clone(),
+            local_state: config.peer_state(peer),
+            remote_state_dir,
+            server_log: config.server_log.clone(),
+        });
+    }
+    Ok(contexts)
 }
 
 fn remote_state_dir_error(error: RPCError) -> color_eyre::eyre::Report {
@@ -2178,6 +3588,7 @@ enum StreamActivity {
 fn record_stream_performance(
     performance: &mut PerformanceProfile,
     result: &mut StreamDetailedChangesResult,
+    throttle: Option<&bwlimit::Throttle>,
 ) {
     record_phase_aggregate(
         performance,
@@ -2210,6 +3621,8 @@ fn record_stream_performance(
         result.remote_apply_duration,
     );
     performance.counters.streamed_details = true;
+    // the throttle counts across waves
+    performance.counters.bandwidth = throttle.map(bwlimit::Throttle::profile);
     merge_streaming_profile(
         &mut performance.counters.streaming,
         std::mem::take(&mut result.profile),
@@ -2326,6 +3739,25 @@ fn merge_transfer_stats(target: &mut DetailTransferStats, source: DetailTransfer
     target.max_batch_payload_bytes = target
         .max_batch_payload_bytes
         .max(source.max_batch_payload_bytes);
+    target.literal_wire_bytes = target
+        .literal_wire_bytes
+        .saturating_add(source.literal_wire_bytes);
+    target.literal_logical_bytes = target
+        .literal_logical_bytes
+        .saturating_add(source.literal_logical_bytes);
+    target.compressed_frames = target
+        .compressed_frames
+        .saturating_add(source.compressed_frames);
+}
+
+/// How both producers encode detail frames, as agreed with the remote.
+#[derive(Debug, Clone, Copy)]
+struct DetailEncoding {
+    holes: bool,
+    /// The agreed deflate level, or `None` for a peer without `compressed-details-v1`.
+    compression: Option<u8>,
+    /// Whole files travel as `FileCopy` frames, with an in-process server.
+    file_copies: bool,
 }
 
 async fn stream_detailed_changes<R>(
@@ -2341,10 +3773,12 @@ async fn stream_detailed_changes<R>(
     apply_options: sync_ops::ApplyOptions,
     remote_stream_performance: bool,
     file_byte_chunks: bool,
+    detail_encoding: DetailEncoding,
     staged_marker_profile: bool,
     staged_attempt_id: Option<&str>,
     staging_policy: Option<sync_ops::StagingPolicy>,
     interrupt: Option<&InterruptState>,
+    throttle: Option<&bwlimit::Throttle>,
     progress_mode: StreamProgressMode,
 ) -> Result<StreamDetailedChangesRun>
 where
@@ -2397,11 +3831,13 @@ where
         apply_options,
         remote_stream_performance,
         file_byte_chunks,
+        detail_encoding,
         staged_marker_profile,
         staged_attempt_id,
         staging_policy,
         staged_remote_apply_stream,
         interrupt,
+        throttle,
         progress_mode,
     )
     .await;
@@ -2464,11 +3900,13 @@ async fn stream_detailed_changes_started<R>(
     apply_options: sync_ops::ApplyOptions,
     remote_stream_performance: bool,
     file_byte_chunks: bool,
+    detail_encoding: DetailEncoding,
     staged_marker_profile: bool,
     staged_attempt_id: Option<&str>,
     staging_policy: Option<sync_ops::StagingPolicy>,
     staged_remote_apply_stream: Option<sync_ops::ApplyStreamId>,
     interrupt: Option<&InterruptState>,
+    throttle: Option<&bwlimit::Throttle>,
     progress_mode: StreamProgressMode,
 ) -> Result<StreamDetailedChangesRun>
 where
@@ -2486,7 +3924,10 @@ where
         actions.clone(),
         remote_signatures,
         tuning.detail_chunk_bytes(),
-    );
+    )
+    .with_holes(detail_encoding.holes)
+    .with_compression(detail_encoding.compression.unwrap_or(0))
+    .with_file_copies(detail_encoding.file_copies);
     let mut local_applier = if let Some(attempt_id) = staged_attempt_id {
         sync_ops::DetailApplier::new_capacity_aware_staged_with_attempt_and_policy(
             local_base.clone(),
@@ -2507,7 +3948,8 @@ where
             scan_policy,
             apply_options,
         )
-    };
+    }
+    .with_file_copies(detail_encoding.file_copies);
 
     let remote_detail_stream = remote
         .begin_detail_stream(local_signatures, tuning.detail_chunk_bytes() as u32)
@@ -2531,6 +3973,7 @@ where
     let mut local_apply_duration = Duration::default();
     let mut local_detail_duration = Duration::default();
     let mut remote_apply_duration = Duration::default();
+    let stream_start = Instant::now();
     while !local_done || !remote_done {
         if interrupt.is_some_and(InterruptState::is_cancel_requested) {
             return Ok(StreamDetailedChangesRun::Interrupted);
@@ -2554,6 +3997,11 @@ where
                 return Ok(StreamDetailedChangesRun::Interrupted);
             }
             profile.remote_to_local.record_batch(&frames);
+            if let Some(throttle) = throttle {
+                throttle
+                    .download(sync_ops::detail_frames_wire_bytes(&frames))
+                    .await;
+            }
             if frames.is_empty() {
                 remote_done = true;
             } else {
@@ -2616,6 +4064,7 @@ where
                     frames,
                     file_byte_chunks,
                     interrupt,
+                    throttle,
                 )
                 .await?
                 {
@@ -2631,6 +4080,9 @@ where
             }
         }
     }
+    if let Some(throttle) = throttle {
+        throttle.record_stream(stream_start.elapsed());
+    }
 
     progress.set_message(stream_finishing_message(progress_mode));
     let start = Instant::now();
@@ -2692,10 +4144,13 @@ where
         StreamApplyOutcome::Legacy(local_all_old)
     };
     if remote_stream_performance {
-        let remote_server_profile = remote
-            .stream_performance()
-            .await
-            .map_err(|e| remote_rpc_error("Couldn't read remote stream performance", e))?;
+        // peers that compress details count wire and logical literal bytes apart
+        let remote_server_profile = if detail_encoding.compression.is_some() {
+            remote.stream_performance_v2().await
+        } else {
+            remote.stream_performance().await.map(Into::into)
+        }
+        .map_err(|e| remote_rpc_error("Couldn't read remote stream performance", e))?;
         if !remote_server_profile.is_empty() {
             profile.remote_server = Some(remote_server_profile);
         }
@@ -2746,11 +4201,17 @@ async fn apply_detail_frames<R>(
     frames: Vec<sync_ops::DetailFrame>,
     file_byte_chunks: bool,
     interrupt: Option<&InterruptState>,
+    throttle: Option<&bwlimit::Throttle>,
 ) -> Result<bool>
 where
     R: DuetServerAsync,
 {
     if !file_byte_chunks {
+        if let Some(throttle) = throttle {
+            throttle
+                .upload(sync_ops::detail_frames_wire_bytes(&frames))
+                .await;
+        }
         remote
             .apply_detail_chunks(remote_apply_stream, frames)
             .await
@@ -2761,6 +4222,11 @@ where
     for batch in route_file_byte_frames(frames) {
         match batch {
             ApplyDetailBatch::Frames(frames) => {
+                if let Some(throttle) = throttle {
+                    throttle
+                        .upload(sync_ops::detail_frames_wire_bytes(&frames))
+                        .await;
+                }
                 remote
                     .apply_detail_chunks(remote_apply_stream, frames)
                     .await
@@ -2769,6 +4235,9 @@ where
                     })?;
             }
             ApplyDetailBatch::FileByteChunk(chunk) => {
+                if let Some(throttle) = throttle {
+                    throttle.upload(chunk.len() as u64).await;
+                }
                 remote
                     .apply_file_byte_chunk(remote_apply_stream, chunk)
                     .await
@@ -3011,7 +4480,11 @@ fn format_capabilities(capabilities: &[impl AsRef<str>]) -> String {
     }
 }
 
-fn show_debug_info(info: &rpc::ServerInfo, tuning: sync_ops::SyncTuning) {
+fn show_debug_info(
+    info: &rpc::ServerInfo,
+    tuning: sync_ops::SyncTuning,
+    ssh: &profile::SshSettings,
+) {
     println!("Debug information:");
     println!("  client protocol: {}", rpc::PROTOCOL_VERSION);
     println!(
@@ -3029,6 +4502,7 @@ fn show_debug_info(info: &rpc::ServerInfo, tuning: sync_ops::SyncTuning) {
         format_capabilities(&agreed_capabilities(info))
     );
     println!("  sync tuning: {}", format_sync_tuning(tuning));
+    println!("  ssh settings: {}", ssh);
 }
 
 fn format_sync_tuning(tuning: sync_ops::SyncTuning) -> String {
@@ -3084,69 +4558,96 @@ fn profile_source_path(source: &ProfileSource) -> Option<PathBuf> {
     }
 }
 
-async fn open_remote_session(remote_server: Option<String>) -> Option<Session> {
-    if let Some(server) = remote_server {
-        let session_result = SessionBuilder::default()
-            .control_directory(std::env::temp_dir())
-            .control_persist(ControlPersist::ClosedAfterInitialConnection)
-            .known_hosts_check(KnownHosts::Strict)
-            .connect_mux(server)
-            .await;
-        match session_result {
-            Ok(session) => Some(session),
-            Err(e) => {
-                let diagnostic = sync_error::render_message(
-                    "setup",
-                    "open SSH session",
-                    None,
-                    ssh_diagnostic(&e),
-                );
-                eprintln!("{}", diagnostic.cyan());
-                log::error!("Unable to get SSH session: {:?}", e);
-                quit::with_code(SSH_ERROR_CODE);
-            }
+async fn open_remote_session(
+    remote_server: Option<String>,
+    profile: &profile::Profile,
+) -> remote::RemoteSession {
+    let Some(server) = remote_server else {
+        return remote::RemoteSession::None;
+    };
+    if let Some(session) = deferred_session(&server, profile) {
+        return session;
+    }
+    let ssh = &profile.ssh;
+    match connect_remote_session(server, ssh).await {
+        Ok(session) => remote::RemoteSession::Mux(session),
+        Err(e) => {
+            let diagnostic = sync_error::render_message(
+                "setup",
+                "open SSH session",
+                None,
+                ssh_diagnostic(&e, ssh),
+            );
+            eprintln!("{}", diagnostic.cyan());
+            log::error!("Unable to get SSH session: {:?}", e);
+            quit::with_code(SSH_ERROR_CODE);
         }
-    } else {
-        None
     }
 }
 
-fn ssh_diagnostic(error: &openssh::Error) -> String {
-    let display = error.to_string();
-    let debug = format!("{:?}", error);
-    ssh_permission_hint(&display, &debug).unwrap_or(display)
+/// The session for `server` that `remote::launch_server()` opens itself, and reports the
+/// failures of then: a `tcp://` server, or an SSH one with the system `ssh` transport.
+fn deferred_session(server: &str, profile: &profile::Profile) -> Option<remote::RemoteSession> {
+    if let Some(address) = tcp::address(server) {
+        return Some(remote::RemoteSession::Tcp {
+            address: address.to_string(),
+            key_file: profile.tcp_key_file.clone(),
+        });
+    }
+    (profile.ssh.transport == Some(profile::SshTransport::System)).then(|| {
+        remote::RemoteSession::System {
+            server: server.to_string(),
+            settings: profile.ssh.clone(),
+        }
+    })
 }
 
-fn ssh_permission_hint(display: &str, debug: &str) -> Option<String> {
-    let combined = format!("{}\n{}", display, debug).to_lowercase();
-
-    if combined.contains("bad permissions")
-        || combined.contains("bad owner or permissions")
-        || combined.contains("permissions are too open")
-        || combined.contains("unprotected private key")
-    {
-        return Some(format!(
-            "{}. OpenSSH rejected a key or SSH config because its permissions are too open; try `chmod 700 ~/.ssh` and `chmod 600 ~/.ssh/<private-key>`, then retry.",
-            display
-        ));
+async fn connect_remote_session(
+    server: String,
+    ssh: &profile::SshSettings,
+) -> Result<Session, openssh::Error> {
+    let mut builder = SessionBuilder::default();
+    builder
+        .control_directory(ssh.control_dir.clone().unwrap_or_else(std::env::temp_dir))
+        .control_persist(ControlPersist::ClosedAfterInitialConnection)
+        .known_hosts_check(KnownHosts::Strict);
+    if let Some(port) = ssh.port {
+        builder.port(port);
     }
-
-    if combined.contains("permission denied") && combined.contains("publickey") {
-        return Some(format!(
-            "{}. SSH public-key authentication failed; check that the correct key is loaded and that private key permissions are not too open (`chmod 600 ~/.ssh/<private-key>`).",
-            display
-        ));
+    if let Some(user) = &ssh.user {
+        builder.user(user.clone());
+    }
+    if let Some(identity) = &ssh.identity {
+        builder.keyfile(identity);
     }
+    if !ssh.proxy_jump.is_empty() {
+        builder.jump_hosts(&ssh.proxy_jump);
+    }
+    if let Some(timeout) = ssh.connect_timeout {
+        builder.connect_timeout(Duration::from_secs(timeout));
+    }
+    if let Some(keepalive) = ssh.keepalive {
+        builder.server_alive_interval(Duration::from_secs(keepalive));
+    }
+    builder.connect_mux(server).await
+}
 
-    None
+fn ssh_diagnostic(error: &openssh::Error, ssh: &profile::SshSettings) -> String {
+    let display = error.to_string();
+    let debug = format!("{:?}", error);
+    remote::with_ssh_settings(
+        remote::ssh_permission_hint(&display, &debug).unwrap_or(display),
+        ssh,
+    )
 }
 
 fn build_actions(
     local_changes: &state::Changes,
     remote_changes: &state::Changes,
     strong: bool,
+    moves: bool,
 ) -> Actions {
-    utils::match_sorted(local_changes.iter(), remote_changes.iter())
+    let actions = utils::match_sorted(local_changes.iter(), remote_changes.iter())
         .filter_map(|(lc, rc)| {
             if strong {
                 Action::create_strong(lc, rc)
@@ -3154,7 +4655,337 @@ fn build_actions(
                 Action::create(lc, rc)
             }
         })
-        .collect()
+        .collect();
+    if moves {
+        pair_moves(actions)
+    } else {
+        actions
+    }
+}
+
+// A change only one side made, and whether it is applied locally.
+fn one_sided(action: &Action) -> Option<(bool, &Change)> {
+    match action {
+        Action::Local(change) => Some((true, change)),
+        Action::Remote(change) => Some((false, change)),
+        _ => None,
+    }
+}
+
+/// Holds back one-sided file additions and modifications whose source is a hard link to
+/// another name in the same scan, by the link groups the scan recorded, so the receiving side
+/// links them instead of writing a copy.
+/// The first name of each link group that the receiving side will have, either because it is
+/// transferred in this run or because it is already synchronized, becomes the link target.
+fn split_hard_links(
+    actions: Actions,
+    pending_paths: &HashSet<PathBuf>,
+    all_old: &[scan::DirEntryWithMeta],
+    local_current: &[scan::DirEntryWithMeta],
+    remote_current: &[scan::DirEntryWithMeta],
+) -> (Actions, Vec<sync_ops::HardLink>) {
+    let index: HashMap<&Path, usize> = actions
+        .iter()
+        .enumerate()
+        .map(|(i, action)| (action.path().as_path(), i))
+        .collect();
+    let mut linked = vec![false; actions.len()];
+    let mut links = Vec::new();
+    // Changes applied locally come from the remote scan, and vice versa.
+    for (local, source) in [(true, remote_current), (false, local_current)] {
+        let mut groups: HashMap<(u64, u64), Vec<&scan::DirEntryWithMeta>> = HashMap::new();
+        for entry in source.iter().filter(|entry| entry.is_file()) {
+            if let Some(link) = entry.link() {
+                groups
+                    .entry((link.dev, link.group))
+                    .or_default()
+                    .push(entry);
+            }
+        }
+        for group in groups.values().filter(|group| group.len() > 1) {
+            let mut target: Option<scan::DirEntryWithMeta> = None;
+            for member in group {
+                let Some(&i) = index.get(member.path().as_path()) else {
+                    if target.is_none() && !pending_paths.contains(member.path()) {
+                        target = synchronized_link_target(all_old, member);
+                    }
+                    continue;
+                };
+                let new = match &actions[i] {
+                    Action::Identical(_, change) if target.is_none() => {
+                        target = transferred_link_target(change).cloned();
+                        continue;
+                    }
+                    action => match one_sided(action) {
+                        Some((side, change)) if side == local => {
+                            match transferred_link_target(change) {
+                                Some(new) => new,
+                                None => continue,
+                            }
+                        }
+                        _ => continue,
+                    },
+                };
+                match &target {
+                    None => target = Some(new.clone()),
+                    Some(target)
+                        if target.digest() == new.digest() && target.size() == new.size() =>
+                    {
+                        linked[i] = true;
+                        links.push(sync_ops::HardLink {
+                            target: target.clone(),
+                            action: actions[i].clone(),
+                        });
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+    }
+    if links.is_empty() {
+        return (actions, links);
+    }
+    links.sort_by(|a, b| a.action.path().cmp(b.action.path()));
+    let actions = actions
+        .into_iter()
+        .zip(linked)
+        .filter_map(|(action, linked)| (!linked).then_some(action))
+        .collect();
+    (actions, links)
+}
+
+// A file that an addition or modification leaves on the receiving side with known contents.
+fn transferred_link_target(change: &Change) -> Option<&scan::DirEntryWithMeta> {
+    match change {
+        Change::Added(new) | Change::Modified(_, new)
+            if new.is_file() && new.digest().is_some() =>
+        {
+            Some(new)
+        }
+        _ => None,
+    }
+}
+
+// A file both sides already share, according to the snapshot.
+fn synchronized_link_target(
+    all_old: &[scan::DirEntryWithMeta],
+    member: &scan::DirEntryWithMeta,
+) -> Option<scan::DirEntryWithMeta> {
+    let i = all_old
+        .binary_search_by(|entry| entry.path().cmp(member.path()))
+        .ok()?;
+    let old = &all_old[i];
+    (old.is_file()
+        && old.digest().is_some()
+        && old.digest() == member.digest()
+        && old.size() == member.size())
+    .then(|| old.clone())
+}
+
+/// Pairs one-sided removals with additions on the same side into moves. A directory pairs
+/// with an added directory that kept its inode when its whole subtree moved unchanged; a
+/// file pairs with an added file of the same content digest, preferring one that kept its
+/// inode. Empty files pair only by inode.
+fn pair_moves(actions: Actions) -> Actions {
+    let index: HashMap<&Path, usize> = actions
+        .iter()
+        .enumerate()
+        .map(|(i, action)| (action.path().as_path(), i))
+        .collect();
+    let subtree_end = |i: usize| {
+        let path = actions[i].path();
+        i + 1
+            + actions[i + 1..]
+                .iter()
+                .take_while(|action| action.path().starts_with(path))
+                .count()
+    };
+    let mut consumed = vec![false; actions.len()];
+    let mut moves = Vec::new();
+
+    let mut added_dirs: HashMap<(bool, u64), usize> = HashMap::new();
+    let mut added_files: HashMap<(bool, scan::ContentDigest, u64), Vec<usize>> = HashMap::new();
+    for (j, action) in actions.iter().enumerate() {
+        if let Some((local, Change::Added(to))) = one_sided(action) {
+            if to.is_dir() && to.ino() != 0 {
+                added_dirs.insert((local, to.ino()), j);
+            } else if let (true, Some(digest)) = (to.is_file(), to.digest()) {
+                added_files
+                    .entry((local, digest, to.size()))
+                    .or_default()
+                    .push(j);
+            }
+        }
+    }
+
+    for i in 0..actions.len() {
+        let Some((local, Change::Removed(from))) = one_sided(&actions[i]) else {
+            continue;
+        };
+        if consumed[i] || !from.is_dir() {
+            continue;
+        }
+        let Some(&j) = added_dirs.get(&(local, from.ino())) else {
+            continue;
+        };
+        let Some((_, Change::Added(to))) = one_sided(&actions[j]) else {
+            unreachable!("indexed directory additions are one-sided");
+        };
+        let (end_i, end_j) = (subtree_end(i), subtree_end(j));
+        if consumed[j] || !movable(&actions, &index, local, from.path(), to.path()) {
+            continue;
+        }
+        let Some(contents) = moved_subtree(&actions[i..end_i], &actions[j..end_j], local) else {
+            continue;
+        };
+        consumed[i..end_i].iter_mut().for_each(|c| *c = true);
+        consumed[j..end_j].iter_mut().for_each(|c| *c = true);
+        moves.push((
+            local,
+            Move {
+                from: from.clone(),
+                to: to.clone(),
+                contents,
+            },
+        ));
+    }
+
+    for i in 0..actions.len() {
+        let Some((local, Change::Removed(from))) = one_sided(&actions[i]) else {
+            continue;
+        };
+        let (false, true, Some(digest)) = (consumed[i], from.is_file(), from.digest()) else {
+            continue;
+        };
+        let Some(candidates) = added_files.get_mut(&(local, digest, from.size())) else {
+            continue;
+        };
+        candidates.retain(|&j| !consumed[j]);
+        let target_path = |j: usize| actions[j].path().as_path();
+        let same_ino = candidates
+            .iter()
+            .position(|&j| match one_sided(&actions[j]) {
+                Some((_, Change::Added(to))) => to.ino() == from.ino(),
+                _ => false,
+            });
+        let chosen = same_ino.or_else(|| {
+            (from.size() > 0)
+                .then(|| {
+                    candidates.iter().position(|&j| {
+                        movable(&actions, &index, local, from.path(), target_path(j))
+                    })
+                })
+                .flatten()
+        });
+        let Some(position) = chosen else {
+            continue;
+        };
+        let j = candidates[position];
+        if !movable(&actions, &index, local, from.path(), target_path(j)) {
+            continue;
+        }
+        let Some((_, Change::Added(to))) = one_sided(&actions[j]) else {
+            unreachable!("indexed file additions are one-sided");
+        };
+        candidates.remove(position);
+        consumed[i] = true;
+        consumed[j] = true;
+        moves.push((
+            local,
+            Move {
+                from: from.clone(),
+                to: to.clone(),
+                contents: Vec::new(),
+            },
+        ));
+    }
+
+    if moves.is_empty() {
+        return actions;
+    }
+    let mut paired: Actions = actions
+        .into_iter()
+        .zip(consumed)
+        .filter_map(|(action, consumed)| (!consumed).then_some(action))
+        .collect();
+    paired.extend(moves.into_iter().map(|(local, m)| {
+        if local {
+            Action::LocalMove(m)
+        } else {
+            Action::RemoteMove(m)
+        }
+    }));
+    paired.sort_by(|a, b| a.path().cmp(b.path()));
+    paired
+}
+
+// Everything above the source may only change on the moving side, and everything above the
+// destination must be a directory that side added, so the move can run before the other
+// actions without crossing a conflict.
+fn movable(
+    actions: &[Action],
+    index: &HashMap<&Path, usize>,
+    local: bool,
+    from: &Path,
+    to: &Path,
+) -> bool {
+    if to.starts_with(from) || from.starts_with(to) {
+        return false;
+    }
+    let source_parents_ok = from.ancestors().skip(1).all(|ancestor| {
+        index
+            .get(ancestor)
+            .is_none_or(|&i| matches!(one_sided(&actions[i]), Some((side, _)) if side == local))
+    });
+    let destination_parents_ok = to.ancestors().skip(1).all(|ancestor| {
+        index.get(ancestor).is_none_or(|&i| {
+            matches!(
+                one_sided(&actions[i]),
+                Some((side, Change::Added(entry))) if side == local && entry.is_dir()
+            )
+        })
+    });
+    source_parents_ok && destination_parents_ok
+}
+
+// Matches the removed subtree below `removed[0]` with the added subtree below `added[0]`,
+// entry by entry; renaming the directory must reproduce every added entry exactly.
+fn moved_subtree(
+    removed: &[Action],
+    added: &[Action],
+    local: bool,
+) -> Option<Vec<(scan::DirEntryWithMeta, scan::DirEntryWithMeta)>> {
+    if removed.len() != added.len() {
+        return None;
+    }
+    let (from, to) = (removed[0].path(), added[0].path());
+    let mut contents = Vec::with_capacity(removed.len() - 1);
+    for (old, new) in removed[1..].iter().zip(&added[1..]) {
+        let (Some((old_side, Change::Removed(old))), Some((new_side, Change::Added(new)))) =
+            (one_sided(old), one_sided(new))
+        else {
+            return None;
+        };
+        let unchanged = old_side == local
+            && new_side == local
+            && old.path().strip_prefix(from).ok() == new.path().strip_prefix(to).ok()
+            && unchanged_by_move(old, new);
+        if !unchanged {
+            return None;
+        }
+        contents.push((old.clone(), new.clone()));
+    }
+    Some(contents)
+}
+
+// The same comparison `same_strong` makes for additions, ignoring the path.
+fn unchanged_by_move(old: &scan::DirEntryWithMeta, new: &scan::DirEntryWithMeta) -> bool {
+    (old.is_symlink() && new.is_symlink() || old.mode() == new.mode())
+        && old.target() == new.target()
+        && old.is_dir() == new.is_dir()
+        && (old.is_dir() || old.same_mtime(new))
+        && (!old.is_file()
+            || old.size() == new.size() && old.digest().is_some() && old.digest() == new.digest())
 }
 
 fn filter_unresolved_conflict_dependencies(actions: Actions) -> (Actions, Vec<PathBuf>) {
@@ -3204,7 +5035,7 @@ fn action_removes_directory(action: &Action) -> bool {
         Action::Identical(local, remote) => {
             change_removes_directory(local) || change_removes_directory(remote)
         }
-        Action::Conflict(_, _) => false,
+        Action::Conflict(_, _) | Action::LocalMove(_) | Action::RemoteMove(_) => false,
     }
 }
 
@@ -3350,7 +5181,11 @@ fn finish_dry_run(total_actions: usize, active_actions: usize, unresolved_confli
     }
 }
 
-fn resolve_actions(actions: &mut Actions, options: SyncOptions) -> Result<AllResolution> {
+fn resolve_actions(
+    actions: &mut Actions,
+    options: SyncOptions,
+    force_newer: bool,
+) -> Result<AllResolution> {
     let SyncOptions {
         interactive,
         yes,
@@ -3374,6 +5209,10 @@ fn resolve_actions(actions: &mut Actions, options: SyncOptions) -> Result<AllRes
     let num_identical = num_identical(actions.iter());
 
     let resolution = if batch {
+        // with sidecars keeping the other version, an unattended run may settle file conflicts
+        if force && force_newer {
+            resolution::resolve_newer(actions);
+        }
         resolution::show_actions(&actions, verbose);
         if force {
             AllResolution::Force
@@ -3563,7 +5402,7 @@ fn remote_state_dir_for_source(
     match source {
         ProfileSource::Named(_) => Ok(None),
         ProfileSource::File(_) if remote_server.is_some() => Err(eyre!(
-            "--profile-file cannot be used with SSH remotes because the derived remote state directory {} is local to this client; use a named profile or a local remote",
+            "--profile-file cannot be used with SSH or TCP remotes because the derived remote state directory {} is local to this client; use a named profile or a local remote",
             config.remote_state_dir.display()
         )),
         ProfileSource::File(_) => Ok(Some(config.remote_state_dir.clone())),
@@ -3632,6 +5471,10 @@ mod tests {
             },
             staging_policy_explicit: true,
             staging_reserve_explicit: false,
+            compression: None,
+            bwlimit: None,
+            server_process: false,
+            negated: crate::cli::NegatedOptions::default(),
         }
     }
 
@@ -3663,6 +5506,54 @@ mod tests {
         );
     }
 
+    #[test]
+    fn profile_options_apply_unless_cli_sets_or_negates_them() {
+        let defaults = profile::ProfileOptions {
+            batch: Some(true),
+            force: Some(true),
+            verbose: Some(false),
+            prune_ignored: Some(true),
+            excludes: vec![PathBuf::from("cache")],
+            staging_limit: Some(456),
+            profile_performance_json: Some(PathBuf::from("/tmp/perf.json")),
+            compression: Some(6),
+            bwlimit: Some("1MiB".parse().unwrap()),
+        };
+        let mut options = staging_test_options();
+        options.batch = false;
+        options.verbose = true;
+        options.staging_policy.limit_bytes = None;
+        options.negated.force = true;
+
+        assert!(apply_profile_options(&mut options, &defaults));
+        assert!(options.batch);
+        assert!(!options.force);
+        assert!(options.verbose);
+        assert!(options.prune_ignored);
+        assert_eq!(options.staging_policy.limit_bytes, Some(456));
+        assert_eq!(
+            options.profile_performance_json,
+            Some(PathBuf::from("/tmp/perf.json"))
+        );
+        assert_eq!(options.compression, Some(6));
+        assert_eq!(options.bwlimit, defaults.bwlimit);
+        // excludes join the scope in prepare_contexts
+        assert!(options.excludes.is_empty());
+
+        let mut options = staging_test_options();
+        options.batch = false;
+        options.interactive = true;
+        options.negated.profile_performance_json = true;
+        options.compression = Some(0);
+        options.negated.bwlimit = true;
+        assert!(!apply_profile_options(&mut options, &defaults));
+        assert_eq!(options.compression, Some(0));
+        assert_eq!(options.bwlimit, None);
+        assert!(!options.batch);
+        assert_eq!(options.staging_policy.limit_bytes, Some(123));
+        assert_eq!(options.profile_performance_json, None);
+    }
+
     #[test]
     fn precommit_interrupt_wins_over_commit() {
         let interrupt = InterruptState::new();
@@ -3802,6 +5693,147 @@ mod tests {
         assert_eq!(skipped, vec![PathBuf::from("tree")]);
     }
 
+    #[test]
+    fn same_side_removal_and_addition_with_equal_digest_pair_into_a_move() {
+        let actions = vec![
+            Action::Local(Change::Removed(digested_entry("a.txt", b"contents"))),
+            Action::Local(Change::Added(digested_entry("b.txt", b"contents"))),
+            Action::Remote(Change::Added(digested_entry("c.txt", b"contents"))),
+        ];
+
+        let paired = pair_moves(actions);
+
+        assert_eq!(paired.len(), 2);
+        let Action::LocalMove(m) = &paired[0] else {
+            panic!("expected a local move, got {:?}", paired[0]);
+        };
+        assert_eq!(m.from.path(), Path::new("a.txt"));
+        assert_eq!(m.to.path(), Path::new("b.txt"));
+        assert!(matches!(paired[1], Action::Remote(Change::Added(_))));
+    }
+
+    #[test]
+    fn empty_files_pair_only_by_inode() {
+        let mut from = digested_entry("a.txt", b"");
+        from.set_ino(7);
+        let mut to = digested_entry("b.txt", b"");
+        to.set_ino(8);
+        let actions = vec![
+            Action::Remote(Change::Removed(from.clone())),
+            Action::Remote(Change::Added(to.clone())),
+        ];
+        assert!(!pair_moves(actions).iter().any(Action::is_move));
+
+        to.set_ino(7);
+        let actions = vec![
+            Action::Remote(Change::Removed(from)),
+            Action::Remote(Change::Added(to)),
+        ];
+        assert!(matches!(
+            pair_moves(actions).as_slice(),
+            [Action::RemoteMove(_)]
+        ));
+    }
+
+    #[test]
+    fn renamed_directory_pairs_with_its_unchanged_contents() {
+        let mut from = scan::DirEntryWithMeta::test_dir(PathBuf::from("old"));
+        from.set_ino(11);
+        let mut to = scan::DirEntryWithMeta::test_dir(PathBuf::from("new"));
+        to.set_ino(11);
+        let actions = |leaf: &[u8]| {
+            let mut actions = vec![
+                Action::Local(Change::Removed(from.clone())),
+                Action::Local(Change::Removed(digested_entry("old/leaf.txt", b"leaf"))),
+                Action::Local(Change::Added(to.clone())),
+                Action::Local(Change::Added(digested_entry("new/leaf.txt", leaf))),
+            ];
+            actions.sort_by(|a, b| a.path().cmp(b.path()));
+            actions
+        };
+
+        let paired = pair_moves(actions(b"leaf"));
+        let [Action::LocalMove(m)] = paired.as_slice() else {
+            panic!("expected a single directory move, got {:?}", paired);
+        };
+        assert_eq!(m.from.path(), Path::new("old"));
+        assert_eq!(m.contents.len(), 1);
+
+        let paired = pair_moves(actions(b"edit"));
+        assert!(!paired
+            .iter()
+            .any(|action| action.is_move() && action.path() == Path::new("new")));
+    }
+
+    #[test]
+    fn move_under_a_conflicting_directory_is_not_paired() {
+        let directory = scan::DirEntryWithMeta::test_dir(PathBuf::from("dir"));
+        let mut changed_directory = directory.clone();
+        changed_directory.set_mode(0o700);
+        let actions = vec![
+            Action::Local(Change::Removed(digested_entry("a.txt", b"contents"))),
+            Action::Conflict(
+                Change::Removed(directory.clone()),
+                Change::Modified(directory, changed_directory),
+            ),
+            Action::Local(Change::Added(digested_entry("dir/a.txt", b"contents"))),
+        ];
+
+        assert!(!pair_moves(actions).iter().any(Action::is_move));
+    }
+
+    fn linked_entry(path: &str, contents: &[u8], group: u64) -> scan::DirEntryWithMeta {
+        let mut entry = digested_entry(path, contents);
+        entry.set_link(Some(scan::LinkIdentity {
+            dev: 1,
+            nlink: 2,
+            group,
+        }));
+        entry
+    }
+
+    #[test]
+    fn later_names_of_a_transferred_link_group_become_hard_links() {
+        let local_current = vec![
+            linked_entry("a", b"shared", 5),
+            linked_entry("b", b"shared", 5),
+            linked_entry("c", b"other", 6),
+        ];
+        let actions: Actions = local_current
+            .iter()
+            .map(|entry| Action::Remote(Change::Added(entry.clone())))
+            .collect();
+
+        let (actions, links) = split_hard_links(actions, &HashSet::new(), &[], &local_current, &[]);
+
+        let paths: Vec<_> = actions.iter().map(|action| action.path().clone()).collect();
+        assert_eq!(paths, vec![PathBuf::from("a"), PathBuf::from("c")]);
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].target.path(), Path::new("a"));
+        assert_eq!(links[0].action.path(), Path::new("b"));
+    }
+
+    #[test]
+    fn synchronized_name_is_a_link_target_unless_it_has_pending_changes() {
+        let remote_current = vec![
+            linked_entry("a", b"shared", 5),
+            linked_entry("b", b"shared", 5),
+        ];
+        let all_old = vec![digested_entry("a", b"shared")];
+        let actions = || vec![Action::Local(Change::Added(remote_current[1].clone()))];
+
+        let (remaining, links) =
+            split_hard_links(actions(), &HashSet::new(), &all_old, &[], &remote_current);
+        assert!(remaining.is_empty());
+        assert_eq!(links[0].target.path(), Path::new("a"));
+
+        let pending = HashSet::from([PathBuf::from("a")]);
+        let (remaining, links) =
+            split_hard_links(actions(), &pending, &all_old, &[], &remote_current);
+        assert_eq!(remaining.len(), 1);
+        assert!(links.is_empty());
+    }
+
     #[test]
     fn unresolved_directory_conflict_suppresses_descendant_actions() {
         let old_directory = scan::DirEntryWithMeta::test_dir(PathBuf::from("tree"));
@@ -4165,10 +6197,25 @@ mod tests {
             profile: profile::Profile {
                 local: "/local".to_string(),
                 remote: "ssh host /remote".to_string(),
+                peers: Vec::new(),
                 locations: Vec::new(),
                 ignore: Vec::new(),
                 prune: Vec::new(),
+                xattrs: Vec::new(),
+                ownership: None,
+                special: profile::SpecialFiles::default(),
+                conflicts: None,
+                force_newer: false,
+                resolve: Vec::new(),
+                merge: Vec::new(),
+                trash: None,
                 staging_reserve: None,
+                ssh: profile::SshSettings::default(),
+                tcp_key_file: None,
+                options: profile::ProfileOptions::default(),
+                sources: Vec::new(),
+                ignore_files: Vec::new(),
+                filters: Default::default(),
             },
             local_state: PathBuf::from("profile.snp"),
             remote_state_dir: PathBuf::from("profile.remotes"),
@@ -4563,6 +6610,33 @@ mod tests {
         assert!(!actions_require_creatable_added_parents(&actions));
     }
 
+    #[test]
+    fn sidecar_frees_the_path_for_the_winning_version() {
+        let file = |checksum| scan::DirEntryWithMeta::test_file(PathBuf::from("a.txt"), checksum);
+        let local = Change::Modified(file(0), file(1));
+        let remote = Change::Modified(file(0), file(2));
+        let overwritten =
+            Action::ResolvedLocal((local.clone(), remote), Change::Modified(file(1), file(2)));
+        assert!(matches!(sidecar_loser(&overwritten), Some((true, e)) if e.checksum() == 1));
+        assert!(matches!(
+            resolve_to_freed_path(overwritten),
+            Action::ResolvedLocal(_, Change::Added(new)) if new.checksum() == 2
+        ));
+
+        let removed =
+            Action::ResolvedLocal((local, Change::Removed(file(0))), Change::Removed(file(1)));
+        assert!(matches!(
+            resolve_to_freed_path(removed),
+            Action::Identical(Change::Removed(kept), Change::Removed(_)) if kept.checksum() == 1
+        ));
+
+        let restored = Action::ResolvedRemote(
+            (Change::Modified(file(0), file(1)), Change::Removed(file(0))),
+            Change::Added(file(1)),
+        );
+        assert!(sidecar_loser(&restored).is_none());
+    }
+
     #[test]
     fn agreed_capabilities_intersects_client_and_server_capabilities() {
         let info = rpc::ServerInfo {
@@ -4637,16 +6711,4 @@ mod tests {
 
         assert_eq!(format_capabilities(&capabilities), "none");
     }
-
-    #[test]
-    fn ssh_permission_diagnostic_mentions_chmod_hint() {
-        let diagnostic = ssh_permission_hint(
-            "Bad owner or permissions on /home/user/.ssh/config",
-            "ignored",
-        )
-        .unwrap();
-
-        assert!(diagnostic.contains("chmod 700 ~/.ssh"));
-        assert!(diagnostic.contains("chmod 600 ~/.ssh/<private-key>"));
-    }
 }
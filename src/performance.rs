@@ -106,6 +106,13 @@ impl PerformanceProfile {
                 print_transfer("remote-server apply", &remote_server.apply_transfer);
             }
         }
+        if let Some(bandwidth) = &self.counters.bandwidth {
+            print_bandwidth("upload", &bandwidth.upload);
+            print_bandwidth("download", &bandwidth.download);
+            if bandwidth.adjustments > 0 {
+                println!("  bandwidth adjustments: {}", bandwidth.adjustments);
+            }
+        }
     }
 }
 
@@ -133,6 +140,7 @@ pub struct ProfileCounters {
     pub staging: Option<StagingProfile>,
     pub streamed_details: bool,
     pub streaming: StreamingProfile,
+    pub bandwidth: Option<BandwidthProfile>,
 }
 
 #[derive(Debug, Default, Serialize)]
@@ -154,6 +162,25 @@ pub struct StagingProfile {
     pub remote_cow_oversize_waves: usize,
 }
 
+/// What `--bwlimit` let through, measured over the wall-clock time of the detail streams.
+#[derive(Debug, Default, Serialize)]
+pub struct BandwidthProfile {
+    pub stream_ms: u64,
+    /// How often `SIGUSR1` or `SIGUSR2` changed the caps.
+    pub adjustments: u64,
+    pub upload: BandwidthDirection,
+    pub download: BandwidthDirection,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct BandwidthDirection {
+    /// The cap when the sync finished; `None` if the direction was not limited.
+    pub cap_bytes_per_sec: Option<u64>,
+    pub bytes: u64,
+    pub throttled_ms: u64,
+    pub achieved_bytes_per_sec: u64,
+}
+
 #[derive(Debug, Default, Serialize)]
 pub struct StreamingProfile {
     pub remote_to_local: DetailTransferStats,
@@ -172,6 +199,47 @@ pub struct RemoteStreamProfile {
     pub apply_transfer: DetailTransferStats,
 }
 
+/// `RemoteStreamProfile` with `LegacyDetailTransferStats`, for peers without
+/// `compressed-details-v1`.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct LegacyRemoteStreamProfile {
+    pub detail_generate_ms: u64,
+    pub detail_batches: u64,
+    pub apply_frames_ms: u64,
+    pub apply_finish_ms: u64,
+    pub apply_batches: u64,
+    pub detail_transfer: LegacyDetailTransferStats,
+    pub apply_transfer: LegacyDetailTransferStats,
+}
+
+impl From<RemoteStreamProfile> for LegacyRemoteStreamProfile {
+    fn from(profile: RemoteStreamProfile) -> Self {
+        Self {
+            detail_generate_ms: profile.detail_generate_ms,
+            detail_batches: profile.detail_batches,
+            apply_frames_ms: profile.apply_frames_ms,
+            apply_finish_ms: profile.apply_finish_ms,
+            apply_batches: profile.apply_batches,
+            detail_transfer: profile.detail_transfer.into(),
+            apply_transfer: profile.apply_transfer.into(),
+        }
+    }
+}
+
+impl From<LegacyRemoteStreamProfile> for RemoteStreamProfile {
+    fn from(profile: LegacyRemoteStreamProfile) -> Self {
+        Self {
+            detail_generate_ms: profile.detail_generate_ms,
+            detail_batches: profile.detail_batches,
+            apply_frames_ms: profile.apply_frames_ms,
+            apply_finish_ms: profile.apply_finish_ms,
+            apply_batches: profile.apply_batches,
+            detail_transfer: profile.detail_transfer.into(),
+            apply_transfer: profile.apply_transfer.into(),
+        }
+    }
+}
+
 impl RemoteStreamProfile {
     pub fn is_empty(&self) -> bool {
         self.detail_batches == 0 && self.apply_batches == 0 && self.apply_finish_ms == 0
@@ -193,6 +261,10 @@ pub struct DetailTransferStats {
     pub diff_copy_frames: u64,
     pub max_batch_frames: u64,
     pub max_batch_payload_bytes: u64,
+    /// Literal file and diff bytes as the frames carried them, deflated or not, and as written.
+    pub literal_wire_bytes: u64,
+    pub literal_logical_bytes: u64,
+    pub compressed_frames: u64,
 }
 
 impl DetailTransferStats {
@@ -203,6 +275,8 @@ impl DetailTransferStats {
         self.file_bytes += len;
         self.message_payload_bytes += len;
         self.reconstructed_bytes += len;
+        self.literal_wire_bytes += len;
+        self.literal_logical_bytes += len;
         self.max_batch_frames = self.max_batch_frames.max(1);
         self.max_batch_payload_bytes = self.max_batch_payload_bytes.max(len);
     }
@@ -222,25 +296,37 @@ impl DetailTransferStats {
             match &frame.payload {
                 DetailPayload::FileBytes(bytes) => {
                     let len = bytes.len() as u64;
-                    self.file_byte_frames += 1;
-                    self.file_bytes += len;
-                    self.message_payload_bytes += len;
-                    self.reconstructed_bytes += len;
+                    self.record_file_bytes(len, len);
                     batch_payload_bytes += len;
                 }
                 DetailPayload::DiffBytes(bytes) => {
                     let len = bytes.len() as u64;
-                    self.diff_literal_frames += 1;
-                    self.diff_literal_bytes += len;
-                    self.message_payload_bytes += len;
-                    self.reconstructed_bytes += len;
+                    self.record_diff_literal(len, len);
                     batch_payload_bytes += len;
                 }
+                DetailPayload::FileBytesCompressed(bytes) => {
+                    let wire = bytes.data.len() as u64;
+                    self.compressed_frames += 1;
+                    self.record_file_bytes(wire, u64::from(bytes.len));
+                    batch_payload_bytes += wire;
+                }
+                DetailPayload::DiffBytesCompressed(bytes) => {
+                    let wire = bytes.data.len() as u64;
+                    self.compressed_frames += 1;
+                    self.record_diff_literal(wire, u64::from(bytes.len));
+                    batch_payload_bytes += wire;
+                }
                 DetailPayload::DiffCopy { len, .. } => {
                     self.diff_copy_frames += 1;
                     self.diff_copy_bytes += *len;
                     self.reconstructed_bytes += *len;
                 }
+                DetailPayload::FileCopy { len, .. } => {
+                    self.record_file_bytes(0, *len);
+                }
+                DetailPayload::FileHole(len) | DetailPayload::DiffHole(len) => {
+                    self.reconstructed_bytes += *len;
+                }
                 DetailPayload::FileBegin
                 | DetailPayload::FileEnd
                 | DetailPayload::DiffBegin
@@ -250,6 +336,89 @@ impl DetailTransferStats {
 
         self.max_batch_payload_bytes = self.max_batch_payload_bytes.max(batch_payload_bytes);
     }
+
+    fn record_file_bytes(&mut self, wire: u64, logical: u64) {
+        self.file_byte_frames += 1;
+        self.file_bytes += logical;
+        self.record_literal(wire, logical);
+    }
+
+    fn record_diff_literal(&mut self, wire: u64, logical: u64) {
+        self.diff_literal_frames += 1;
+        self.diff_literal_bytes += logical;
+        self.record_literal(wire, logical);
+    }
+
+    fn record_literal(&mut self, wire: u64, logical: u64) {
+        self.message_payload_bytes += wire;
+        self.reconstructed_bytes += logical;
+        self.literal_wire_bytes += wire;
+        self.literal_logical_bytes += logical;
+    }
+}
+
+/// `DetailTransferStats` as peers without `compressed-details-v1` send them, before the literal
+/// byte counters.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct LegacyDetailTransferStats {
+    pub batches: u64,
+    pub empty_batches: u64,
+    pub frames: u64,
+    pub message_payload_bytes: u64,
+    pub reconstructed_bytes: u64,
+    pub file_bytes: u64,
+    pub diff_literal_bytes: u64,
+    pub diff_copy_bytes: u64,
+    pub file_byte_frames: u64,
+    pub diff_literal_frames: u64,
+    pub diff_copy_frames: u64,
+    pub max_batch_frames: u64,
+    pub max_batch_payload_bytes: u64,
+}
+
+impl From<DetailTransferStats> for LegacyDetailTransferStats {
+    fn from(stats: DetailTransferStats) -> Self {
+        Self {
+            batches: stats.batches,
+            empty_batches: stats.empty_batches,
+            frames: stats.frames,
+            message_payload_bytes: stats.message_payload_bytes,
+            reconstructed_bytes: stats.reconstructed_bytes,
+            file_bytes: stats.file_bytes,
+            diff_literal_bytes: stats.diff_literal_bytes,
+            diff_copy_bytes: stats.diff_copy_bytes,
+            file_byte_frames: stats.file_byte_frames,
+            diff_literal_frames: stats.diff_literal_frames,
+            diff_copy_frames: stats.diff_copy_frames,
+            max_batch_frames: stats.max_batch_frames,
+            max_batch_payload_bytes: stats.max_batch_payload_bytes,
+        }
+    }
+}
+
+impl From<LegacyDetailTransferStats> for DetailTransferStats {
+    fn from(stats: LegacyDetailTransferStats) -> Self {
+        // without compression every literal byte went over the wire as it is
+        let literal_bytes = stats.file_bytes + stats.diff_literal_bytes;
+        Self {
+            batches: stats.batches,
+            empty_batches: stats.empty_batches,
+            frames: stats.frames,
+            message_payload_bytes: stats.message_payload_bytes,
+            reconstructed_bytes: stats.reconstructed_bytes,
+            file_bytes: stats.file_bytes,
+            diff_literal_bytes: stats.diff_literal_bytes,
+            diff_copy_bytes: stats.diff_copy_bytes,
+            file_byte_frames: stats.file_byte_frames,
+            diff_literal_frames: stats.diff_literal_frames,
+            diff_copy_frames: stats.diff_copy_frames,
+            max_batch_frames: stats.max_batch_frames,
+            max_batch_payload_bytes: stats.max_batch_payload_bytes,
+            literal_wire_bytes: literal_bytes,
+            literal_logical_bytes: literal_bytes,
+            compressed_frames: 0,
+        }
+    }
 }
 
 pub fn duration_ms(duration: Duration) -> u64 {
@@ -258,7 +427,7 @@ pub fn duration_ms(duration: Duration) -> u64 {
 
 fn print_transfer(label: &str, stats: &DetailTransferStats) {
     println!(
-        "  stream {}: batches={} empty-batches={} frames={} payload={} reconstructed={} file-bytes={} diff-literal={} diff-copy={} max-batch-frames={} max-batch-payload={}",
+        "  stream {}: batches={} empty-batches={} frames={} payload={} reconstructed={} file-bytes={} diff-literal={} diff-copy={} literal-wire={} literal-logical={} compressed-frames={} max-batch-frames={} max-batch-payload={}",
         label,
         stats.batches,
         stats.empty_batches,
@@ -268,11 +437,29 @@ fn print_transfer(label: &str, stats: &DetailTransferStats) {
         indicatif::HumanBytes(stats.file_bytes),
         indicatif::HumanBytes(stats.diff_literal_bytes),
         indicatif::HumanBytes(stats.diff_copy_bytes),
+        indicatif::HumanBytes(stats.literal_wire_bytes),
+        indicatif::HumanBytes(stats.literal_logical_bytes),
+        stats.compressed_frames,
         stats.max_batch_frames,
         indicatif::HumanBytes(stats.max_batch_payload_bytes)
     );
 }
 
+fn print_bandwidth(label: &str, direction: &BandwidthDirection) {
+    let cap = match direction.cap_bytes_per_sec {
+        Some(cap) => format!("{}/s", indicatif::HumanBytes(cap)),
+        None => "unlimited".to_string(),
+    };
+    println!(
+        "  bandwidth {}: achieved={}/s cap={} bytes={} throttled={} ms",
+        label,
+        indicatif::HumanBytes(direction.achieved_bytes_per_sec),
+        cap,
+        indicatif::HumanBytes(direction.bytes),
+        direction.throttled_ms
+    );
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
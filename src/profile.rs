@@ -3,30 +3,513 @@ use std::io::{self, prelude::*, BufReader};
 use std::path::{Component, Path, PathBuf};
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use serde::{Deserialize, Serialize};
 use shellexpand;
 
+use crate::lexer;
 use crate::scan::location::{Location, Locations};
+use crate::scan::{DirEntryWithMeta, OwnershipMode};
 use crate::sync::StagingReserve;
+use crate::trash::{self, TrashRetention};
 
 pub type Ignore = Vec<String>;
 pub type Prune = Vec<String>;
+/// Extended attribute namespaces (such as `user`) whose attributes are synchronized.
+pub type XattrNamespaces = Vec<String>;
 
-#[derive(Debug)]
+/// Special files a profile's `[special]` section records instead of rejecting.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpecialFiles {
+    pub fifos: bool,
+    /// Character and block devices; recreating them requires root.
+    pub devices: bool,
+    /// Skip sockets and report them rather than failing the scan.
+    pub skip_sockets: bool,
+}
+
+impl SpecialFiles {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Kinds of entries a `[filter]` section's `types` line keeps.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntryTypes {
+    pub files: bool,
+    pub symlinks: bool,
+    pub directories: bool,
+}
+
+/// Entries a profile's `[filter]` section keeps out of synchronization. Sizes and ages apply to
+/// regular files; ages are in seconds before the scan.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntryFilters {
+    pub max_size: Option<u64>,
+    pub min_size: Option<u64>,
+    /// Keep only files modified at most this long ago.
+    pub newer_than: Option<u64>,
+    /// Keep only files modified at least this long ago.
+    pub older_than: Option<u64>,
+    /// Keep only these kinds of entries; special files are never filtered by type.
+    pub types: Option<EntryTypes>,
+}
+
+impl EntryFilters {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// The filter that keeps `entry` out when scanned at `now` (in seconds since the epoch).
+    pub fn filtered_by(&self, entry: &DirEntryWithMeta, now: i64) -> Option<&'static str> {
+        if let Some(types) = self.types {
+            let kept = if entry.is_dir() {
+                types.directories
+            } else if entry.is_symlink() {
+                types.symlinks
+            } else {
+                types.files || entry.is_special()
+            };
+            if !kept {
+                return Some("types");
+            }
+        }
+        if !entry.is_file() {
+            return None;
+        }
+        let age = now.saturating_sub(entry.mtime()).max(0) as u64;
+        let age_over = |limit: u64| age > limit;
+        if self.max_size.is_some_and(|max| entry.size() > max) {
+            Some("max-size")
+        } else if self.min_size.is_some_and(|min| entry.size() < min) {
+            Some("min-size")
+        } else if self.newer_than.is_some_and(age_over) {
+            Some("newer-than")
+        } else if self.older_than.is_some_and(|limit| !age_over(limit)) {
+            Some("older-than")
+        } else {
+            None
+        }
+    }
+}
+
+/// Sync option defaults from a profile's `[options]` section. Flags on the command line take
+/// precedence, and `--no-` flags turn these defaults off.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProfileOptions {
+    pub batch: Option<bool>,
+    pub force: Option<bool>,
+    pub verbose: Option<bool>,
+    pub prune_ignored: Option<bool>,
+    /// Paths to leave out of each sync, relative to the local directory.
+    pub excludes: Vec<PathBuf>,
+    pub staging_limit: Option<u64>,
+    pub profile_performance_json: Option<PathBuf>,
+    pub compression: Option<u8>,
+    pub bwlimit: Option<crate::bwlimit::BandwidthLimit>,
+}
+
+/// SSH connection settings from a profile's `[ssh]` section, which take precedence over
+/// `~/.ssh/config` for the profile's SSH remotes. Durations are in seconds.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SshSettings {
+    pub port: Option<u16>,
+    pub user: Option<String>,
+    pub identity: Option<PathBuf>,
+    /// `ProxyJump` hosts, in the order the connection passes through them.
+    pub proxy_jump: Vec<String>,
+    /// Where the multiplexing control socket lives, instead of the temporary directory.
+    pub control_dir: Option<PathBuf>,
+    pub connect_timeout: Option<u64>,
+    /// `ServerAliveInterval`: how often to probe an idle connection.
+    pub keepalive: Option<u64>,
+    /// How the connection is made; multiplexed through `openssh` by default.
+    pub transport: Option<SshTransport>,
+}
+
+impl SshSettings {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl std::fmt::Display for SshSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut settings = Vec::new();
+        if let Some(port) = self.port {
+            settings.push(format!("port={}", port));
+        }
+        if let Some(user) = &self.user {
+            settings.push(format!("user={}", user));
+        }
+        if let Some(identity) = &self.identity {
+            settings.push(format!("identity={}", identity.display()));
+        }
+        if !self.proxy_jump.is_empty() {
+            settings.push(format!("proxy-jump={}", self.proxy_jump.join(",")));
+        }
+        if let Some(control_dir) = &self.control_dir {
+            settings.push(format!("control-dir={}", control_dir.display()));
+        }
+        if let Some(timeout) = self.connect_timeout {
+            settings.push(format!("connect-timeout={}s", timeout));
+        }
+        if let Some(keepalive) = self.keepalive {
+            settings.push(format!("keepalive={}s", keepalive));
+        }
+        if let Some(transport) = self.transport {
+            settings.push(format!("transport={}", transport));
+        }
+        if settings.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", settings.join(", "))
+        }
+    }
+}
+
+/// How a profile's SSH remotes are reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SshTransport {
+    /// A multiplexed session that `openssh` opens without a terminal, so authentication must
+    /// not prompt.
+    Mux,
+    /// The system `ssh` command, run on this terminal so it can prompt for passwords,
+    /// keyboard-interactive codes, and hardware tokens.
+    System,
+}
+
+impl std::str::FromStr for SshTransport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mux" => Ok(Self::Mux),
+            "system" => Ok(Self::System),
+            _ => Err(format!("{s} (expected mux or system)")),
+        }
+    }
+}
+
+impl std::fmt::Display for SshTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Mux => write!(f, "mux"),
+            Self::System => write!(f, "system"),
+        }
+    }
+}
+
+/// What happens to the sidecar copy that keeps the losing version of a resolved conflict.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SidecarPolicy {
+    /// Synchronize sidecars like any other file.
+    Tracked,
+    /// Keep sidecars only on the side they were made on, hidden from scans.
+    Ignored,
+}
+
+impl std::str::FromStr for SidecarPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tracked" => Ok(Self::Tracked),
+            "ignored" => Ok(Self::Ignored),
+            _ => Err(format!("{s} (expected tracked or ignored)")),
+        }
+    }
+}
+
+/// How a `[resolve]` rule settles a conflict.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResolvePolicy {
+    /// The version with the later modification time wins, the local one on a tie.
+    Newer,
+    Local,
+    Remote,
+    /// The larger version wins, the local one on a tie.
+    Larger,
+    /// The newer version wins and the other is kept as a conflict sidecar.
+    KeepBoth,
+}
+
+impl std::str::FromStr for ResolvePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "newer" => Ok(Self::Newer),
+            "local" => Ok(Self::Local),
+            "remote" => Ok(Self::Remote),
+            "larger" => Ok(Self::Larger),
+            "keep-both" => Ok(Self::KeepBoth),
+            _ => Err(format!(
+                "{s} (expected newer, local, remote, larger, or keep-both)"
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for ResolvePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Newer => "newer",
+            Self::Local => "local",
+            Self::Remote => "remote",
+            Self::Larger => "larger",
+            Self::KeepBoth => "keep-both",
+        })
+    }
+}
+
+/// A `[resolve]` line: conflicts on paths matching `pattern` are settled by `policy`. Patterns
+/// without a `/` match the basename, others the whole relative path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolveRule {
+    pub pattern: String,
+    pub policy: ResolvePolicy,
+}
+
+impl std::fmt::Display for ResolveRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} = {}", self.pattern, self.policy)
+    }
+}
+
+/// Matches `path` against the compiled glob `pattern`: patterns without a `/` match the
+/// basename, others the whole relative path.
+pub(crate) fn glob_matches(pattern: &str, regex: &regex::Regex, path: &Path) -> bool {
+    if pattern.contains('/') {
+        regex.is_match(&path.to_string_lossy())
+    } else {
+        path.file_name()
+            .is_some_and(|name| regex.is_match(&name.to_string_lossy()))
+    }
+}
+
+/// The per-directory ignore file scans always honor.
+pub const IGNORE_FILE: &str = ".duetignore";
+
+/// Glob matching the sidecar names, which scans skip under `SidecarPolicy::Ignored`.
+pub const SIDECAR_PATTERN: &str = "*.duet-conflict-*";
+
+/// Optional per-entry metadata the scanner captures on both sides.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetadataCapture {
+    pub xattrs: XattrNamespaces,
+    pub ownership: Option<OwnershipMode>,
+    pub special: SpecialFiles,
+    /// Names of the per-directory ignore files the scanner reads, later ones taking precedence.
+    pub ignore_files: Vec<String>,
+    pub filters: EntryFilters,
+}
+
+#[derive(Debug, Clone)]
 pub struct Profile {
     pub local: String,
     pub remote: String,
+    /// Further remote lines: peers synchronized with the local side in turn, after `remote`.
+    pub peers: Vec<String>,
     pub locations: Locations,
     pub ignore: Ignore,
     pub prune: Prune,
+    /// Per-directory ignore files to honor besides `.duetignore`, such as `.gitignore`.
+    pub ignore_files: Vec<String>,
+    pub filters: EntryFilters,
+    pub xattrs: XattrNamespaces,
+    pub ownership: Option<OwnershipMode>,
+    pub special: SpecialFiles,
+    pub conflicts: Option<SidecarPolicy>,
+    /// `[conflicts] force = newer`: batch `--force` settles file conflicts for the newer version,
+    /// keeping the other as a sidecar, instead of skipping them.
+    pub force_newer: bool,
+    pub resolve: Vec<ResolveRule>,
+    /// Globs of the text files whose synced contents are kept as merge bases.
+    pub merge: Vec<String>,
+    /// Keep removed and replaced files in the trash, with this retention.
+    pub trash: Option<TrashRetention>,
     pub staging_reserve: Option<StagingReserve>,
+    pub ssh: SshSettings,
+    /// The pre-shared key for `tcp://` remotes, from the `[tcp]` section.
+    pub tcp_key_file: Option<PathBuf>,
+    pub options: ProfileOptions,
+    /// Every rule in effect, in order within each section, with the file it came from.
+    pub sources: Vec<RuleSource>,
+}
+
+/// A profile line in effect and where it was read, as `duet _info` shows it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleSource {
+    /// The section header, empty for the local and remote lines and the location rules.
+    pub section: &'static str,
+    pub rule: String,
+    pub file: PathBuf,
+    pub line: usize,
+}
+
+impl RuleSource {
+    /// The setting a line of a single-valued section sets, such as `reserve` in `[staging]`.
+    fn setting(&self) -> Option<&str> {
+        match self.section {
+            "[staging]" | "[ssh]" | "[tcp]" | "[filter]" | "[ownership]" | "[conflicts]"
+            | "[trash]" => self.rule.split_once('=').map(|(key, _)| key.trim()),
+            // excludes add up, like the other lists
+            "[options]" => self
+                .rule
+                .split_once('=')
+                .map(|(key, _)| key.trim())
+                .filter(|key| *key != "exclude"),
+            _ => None,
+        }
+    }
 }
 
 impl Profile {
+    /// Merges an included profile fragment (`self`) with the file that includes it, or with a
+    /// later fragment (`over`), which takes precedence: its location rules come later, so they
+    /// win; its `[resolve]` rules come first; and its settings replace the fragment's. Other
+    /// lists are concatenated.
+    fn overridden_by(self, over: Profile) -> Profile {
+        let trash = match (self.trash, over.trash) {
+            (Some(base), Some(over)) => Some(TrashRetention {
+                max_age_secs: over.max_age_secs.or(base.max_age_secs),
+                max_bytes: over.max_bytes.or(base.max_bytes),
+            }),
+            (base, over) => over.or(base),
+        };
+        let overridden = |source: &RuleSource| {
+            source.setting().is_some_and(|setting| {
+                over.sources.iter().any(|other| {
+                    other.section == source.section && other.setting() == Some(setting)
+                })
+            })
+        };
+        let mut sources: Vec<RuleSource> = over
+            .sources
+            .iter()
+            .filter(|source| source.section == "[resolve]")
+            .cloned()
+            .collect();
+        sources.extend(
+            self.sources
+                .iter()
+                .filter(|source| !overridden(source))
+                .cloned(),
+        );
+        sources.extend(
+            over.sources
+                .iter()
+                .filter(|source| source.section != "[resolve]")
+                .cloned(),
+        );
+
+        let mut xattrs = self.xattrs;
+        for namespace in over.xattrs {
+            if !xattrs.contains(&namespace) {
+                xattrs.push(namespace);
+            }
+        }
+        let mut ignore_files = self.ignore_files;
+        for name in over.ignore_files {
+            if !ignore_files.contains(&name) {
+                ignore_files.push(name);
+            }
+        }
+
+        Profile {
+            local: over.local,
+            remote: over.remote,
+            peers: over.peers,
+            locations: [self.locations, over.locations].concat(),
+            ignore: [self.ignore, over.ignore].concat(),
+            prune: [self.prune, over.prune].concat(),
+            ignore_files,
+            filters: EntryFilters {
+                max_size: over.filters.max_size.or(self.filters.max_size),
+                min_size: over.filters.min_size.or(self.filters.min_size),
+                newer_than: over.filters.newer_than.or(self.filters.newer_than),
+                older_than: over.filters.older_than.or(self.filters.older_than),
+                types: over.filters.types.or(self.filters.types),
+            },
+            xattrs,
+            ownership: over.ownership.or(self.ownership),
+            special: SpecialFiles {
+                fifos: self.special.fifos || over.special.fifos,
+                devices: self.special.devices || over.special.devices,
+                skip_sockets: self.special.skip_sockets || over.special.skip_sockets,
+            },
+            conflicts: over.conflicts.or(self.conflicts),
+            force_newer: self.force_newer || over.force_newer,
+            resolve: [over.resolve, self.resolve].concat(),
+            merge: [self.merge, over.merge].concat(),
+            trash,
+            staging_reserve: over.staging_reserve.or(self.staging_reserve),
+            ssh: SshSettings {
+                port: over.ssh.port.or(self.ssh.port),
+                user: over.ssh.user.or(self.ssh.user),
+                identity: over.ssh.identity.or(self.ssh.identity),
+                proxy_jump: if over.ssh.proxy_jump.is_empty() {
+                    self.ssh.proxy_jump
+                } else {
+                    over.ssh.proxy_jump
+                },
+                control_dir: over.ssh.control_dir.or(self.ssh.control_dir),
+                connect_timeout: over.ssh.connect_timeout.or(self.ssh.connect_timeout),
+                keepalive: over.ssh.keepalive.or(self.ssh.keepalive),
+                transport: over.ssh.transport.or(self.ssh.transport),
+            },
+            tcp_key_file: over.tcp_key_file.or(self.tcp_key_file),
+            options: ProfileOptions {
+                batch: over.options.batch.or(self.options.batch),
+                force: over.options.force.or(self.options.force),
+                verbose: over.options.verbose.or(self.options.verbose),
+                prune_ignored: over.options.prune_ignored.or(self.options.prune_ignored),
+                excludes: [self.options.excludes, over.options.excludes].concat(),
+                staging_limit: over.options.staging_limit.or(self.options.staging_limit),
+                profile_performance_json: over
+                    .options
+                    .profile_performance_json
+                    .or(self.options.profile_performance_json),
+                compression: over.options.compression.or(self.options.compression),
+                bwlimit: over.options.bwlimit.or(self.options.bwlimit),
+            },
+            sources,
+        }
+    }
+
+    /// The remote lines in the order a sync visits them.
+    pub fn remotes(&self) -> impl Iterator<Item = &String> {
+        std::iter::once(&self.remote).chain(&self.peers)
+    }
+
     pub fn scan_ignore(&self) -> Ignore {
         let mut ignore = self.ignore.clone();
         ignore.extend(self.prune.iter().cloned());
+        if self.conflicts == Some(SidecarPolicy::Ignored) {
+            ignore.push(SIDECAR_PATTERN.to_string());
+        }
+        if self.trash.is_some() {
+            ignore.push(trash::TRASH_DIR.to_string());
+        }
         ignore
     }
+
+    pub fn capture(&self) -> MetadataCapture {
+        MetadataCapture {
+            xattrs: self.xattrs.clone(),
+            ownership: self.ownership,
+            special: self.special,
+            ignore_files: self
+                .ignore_files
+                .iter()
+                .map(String::as_str)
+                .chain([IGNORE_FILE])
+                .map(str::to_string)
+                .collect(),
+            filters: self.filters,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -45,6 +528,25 @@ pub struct ProfileConfig {
     pub server_log: PathBuf,
 }
 
+impl ProfileConfig {
+    /// The local snapshot kept against `remote`: `local_state` for the profile's first remote,
+    /// and for a further peer a sibling named after a digest of its remote line, so each pair
+    /// keeps its own state.
+    pub fn peer_state(&self, remote: &str) -> PathBuf {
+        if remote == self.profile.remote {
+            return self.local_state.clone();
+        }
+        let digest: String = blake2_rfc::blake2b::blake2b(4, &[], remote.as_bytes())
+            .as_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        let mut state = self.local_state.clone();
+        state.set_extension(format!("{}.snp", digest));
+        state
+    }
+}
+
 fn config_dir() -> Result<PathBuf, io::Error> {
     let expanded = shellexpand::full("~/.config/duet/").map_err(|e| {
         io::Error::new(
@@ -55,6 +557,32 @@ fn config_dir() -> Result<PathBuf, io::Error> {
     Ok(PathBuf::from(expanded.into_owned()))
 }
 
+/// The names of the profiles in the configuration directory, sorted.
+pub fn names() -> Result<Vec<String>, io::Error> {
+    let dir = config_dir()?;
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(io::Error::new(
+                e.kind(),
+                format!("unable to list {}: {}", dir.display(), e),
+            ))
+        }
+    };
+    let mut names = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|extension| extension == "prf") {
+            if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
 pub fn location(name: &str) -> Result<PathBuf, io::Error> {
     validate_profile_name(name)?;
     let mut base = config_dir()?;
@@ -208,44 +736,111 @@ fn default_server_log() -> Result<PathBuf, io::Error> {
 }
 
 pub fn parse(name: &str) -> Result<Profile, io::Error> {
-    let profile_location = location(name)?;
-    log::debug!("Loading {:?}", profile_location);
-
-    parse_file(&profile_location)
+    parse_file(&location(name)?)
 }
 
 pub fn parse_file(profile_location: &Path) -> Result<Profile, io::Error> {
+    let mut including: Vec<PathBuf> = std::fs::canonicalize(profile_location)
+        .into_iter()
+        .collect();
+    let mut p = parse_included(profile_location, &mut including, false)?;
+    p.locations.insert(0, Location::Exclude(PathBuf::from("."))); // implicitly exclude .
+                                                                  // group the rules by section, as `duet _info` prints them
+    p.sources.sort_by_key(|source| {
+        SECTION_HEADERS
+            .iter()
+            .position(|header| *header == source.section)
+    });
+    Ok(p)
+}
+
+/// Parses a profile, or with `fragment` a file it includes, which has no local and remote lines.
+/// `including` holds the files whose includes led here, to detect cycles.
+fn parse_included(
+    profile_location: &Path,
+    including: &mut Vec<PathBuf>,
+    fragment: bool,
+) -> Result<Profile, io::Error> {
     log::debug!("Loading {:?}", profile_location);
 
-    let f = File::open(profile_location)?;
+    let f = File::open(profile_location).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("unable to open {}: {}", profile_location.display(), e),
+        )
+    })?;
     let reader = BufReader::new(f);
 
     let mut p = Profile {
         local: String::new(),
         remote: String::new(),
-        locations: vec![Location::Exclude(PathBuf::from("."))], // implicitly exclude .
+        peers: Vec::new(),
+        locations: Vec::new(),
         ignore: Vec::new(),
         prune: Vec::new(),
+        ignore_files: Vec::new(),
+        filters: EntryFilters::default(),
+        xattrs: Vec::new(),
+        ownership: None,
+        special: SpecialFiles::default(),
+        conflicts: None,
+        force_newer: false,
+        resolve: Vec::new(),
+        merge: Vec::new(),
+        trash: None,
         staging_reserve: None,
+        ssh: SshSettings::default(),
+        tcp_key_file: None,
+        options: ProfileOptions::default(),
+        sources: Vec::new(),
     };
 
-    let mut locations = 0;
+    // fragments merged in include order, each overriding the ones before it
+    let mut included: Option<Profile> = None;
+    // 0 and 1 before the local and remote lines, 2 while further remote lines may follow
+    let mut locations = if fragment { 3 } else { 0 };
     let mut section = ProfileSection::Locations;
-    for line in reader.lines() {
+    for (index, line) in reader.lines().enumerate() {
+        let number = index + 1;
         let line = line?;
         let trimmed = line.trim();
         if trimmed.is_empty() || trimmed.starts_with('#') {
             continue;
         }
 
+        if locations == 2 {
+            if trimmed.starts_with(['+', '-', '[']) || trimmed.starts_with("include ") {
+                locations += 1;
+            } else if line == p.remote || p.peers.contains(&line) {
+                return Err(line_error(
+                    profile_location,
+                    number,
+                    "duplicate remote line",
+                ));
+            } else {
+                check_remote(profile_location, number, &line)?;
+                p.peers.push(line.clone());
+            }
+        }
+        if locations < 3 {
+            p.sources.push(RuleSource {
+                section: section.header(),
+                rule: line.clone(),
+                file: profile_location.to_path_buf(),
+                line: number,
+            });
+        }
         if locations == 0 {
-            p.local = line.to_string();
+            p.local = line_word(profile_location, number, &line, &line)?;
             locations += 1;
             continue;
         } else if locations == 1 {
+            check_remote(profile_location, number, &line)?;
             p.remote = line;
             locations += 1;
             continue;
+        } else if locations == 2 {
+            continue;
         }
 
         if trimmed == "[ignore]" {
@@ -260,120 +855,651 @@ pub fn parse_file(profile_location: &Path) -> Result<Profile, io::Error> {
             section = ProfileSection::Staging;
             continue;
         }
+        if trimmed == "[ssh]" {
+            section = ProfileSection::Ssh;
+            continue;
+        }
+        if trimmed == "[tcp]" {
+            section = ProfileSection::Tcp;
+            continue;
+        }
+        if trimmed == "[options]" {
+            section = ProfileSection::Options;
+            continue;
+        }
+        if trimmed == "[filter]" {
+            section = ProfileSection::Filter;
+            continue;
+        }
+        if trimmed == "[ignore-files]" {
+            section = ProfileSection::IgnoreFiles;
+            continue;
+        }
+        if trimmed == "[xattrs]" {
+            section = ProfileSection::Xattrs;
+            continue;
+        }
+        if trimmed == "[ownership]" {
+            section = ProfileSection::Ownership;
+            continue;
+        }
+        if trimmed == "[special]" {
+            section = ProfileSection::Special;
+            continue;
+        }
+        if trimmed == "[conflicts]" {
+            section = ProfileSection::Conflicts;
+            continue;
+        }
+        if trimmed == "[resolve]" {
+            section = ProfileSection::Resolve;
+            continue;
+        }
+        if trimmed == "[merge]" {
+            section = ProfileSection::Merge;
+            continue;
+        }
+        if trimmed == "[trash]" {
+            section = ProfileSection::Trash;
+            p.trash.get_or_insert_with(TrashRetention::default);
+            continue;
+        }
+        if trimmed == "[include]" {
+            section = ProfileSection::Include;
+            continue;
+        }
+        let include = match trimmed.strip_prefix("include ") {
+            Some(target) => Some(target.trim()),
+            None if section == ProfileSection::Include => Some(trimmed),
+            None => None,
+        };
+        if let Some(target) = include {
+            let target = include_path(profile_location, target)
+                .map_err(|e| line_error(profile_location, number, e))?;
+            let canonical = std::fs::canonicalize(&target)
+                .map_err(|e| line_error(profile_location, number, e))?;
+            if including.contains(&canonical) {
+                return Err(line_error(
+                    profile_location,
+                    number,
+                    format!("include cycle through {}", target.display()),
+                ));
+            }
+            including.push(canonical);
+            let fragment = parse_included(&target, including, true)
+                .map_err(|e| line_error(profile_location, number, e))?;
+            including.pop();
+            included = Some(match included {
+                Some(earlier) => earlier.overridden_by(fragment),
+                None => fragment,
+            });
+            continue;
+        }
 
+        p.sources.push(RuleSource {
+            section: section.header(),
+            rule: trimmed.to_string(),
+            file: profile_location.to_path_buf(),
+            line: number,
+        });
         match section {
+            ProfileSection::Include => unreachable!("include lines are handled above"),
             ProfileSection::Locations => {
                 if let Some(path) = trimmed.strip_prefix('+') {
-                    p.locations
-                        .push(Location::Include(PathBuf::from(path.trim())));
+                    let path = line_word(profile_location, number, &line, path)?;
+                    p.locations.push(Location::Include(PathBuf::from(path)));
                 } else if let Some(path) = trimmed.strip_prefix('-') {
-                    p.locations
-                        .push(Location::Exclude(PathBuf::from(path.trim())));
+                    let path = line_word(profile_location, number, &line, path)?;
+                    p.locations.push(Location::Exclude(PathBuf::from(path)));
                 } else {
-                    return parse_error(&line);
+                    return parse_error(profile_location, number, &line);
                 }
             }
             ProfileSection::Ignore => p.ignore.push(line),
             ProfileSection::Prune => p.prune.push(line),
-            ProfileSection::Staging => {
+            ProfileSection::Xattrs => {
+                if trimmed.contains(|c: char| c == '.' || c.is_whitespace()) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("invalid extended attribute namespace: {}", trimmed),
+                    ));
+                }
+                if !p.xattrs.iter().any(|namespace| namespace == trimmed) {
+                    p.xattrs.push(trimmed.to_string());
+                }
+            }
+            ProfileSection::Special => match trimmed {
+                "fifo" => p.special.fifos = true,
+                "device" => p.special.devices = true,
+                "socket" => p.special.skip_sockets = true,
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "invalid special file kind: {} (expected fifo, device, or socket)",
+                            trimmed
+                        ),
+                    ))
+                }
+            },
+            ProfileSection::Ownership => {
                 let Some((key, value)) = trimmed.split_once('=') else {
-                    return parse_error(&line);
+                    return parse_error(profile_location, number, &line);
                 };
-                if key.trim() != "reserve" || value.trim().is_empty() {
-                    return parse_error(&line);
+                if key.trim() != "mode" || value.trim().is_empty() {
+                    return parse_error(profile_location, number, &line);
                 }
-                if p.staging_reserve.is_some() {
+                if p.ownership.is_some() {
                     return Err(io::Error::new(
                         io::ErrorKind::InvalidInput,
-                        "duplicate staging reserve setting",
+                        "duplicate ownership mode setting",
                     ));
                 }
-                p.staging_reserve = Some(value.trim().parse().map_err(|error: String| {
+                p.ownership = Some(value.trim().parse().map_err(|error: String| {
                     io::Error::new(
                         io::ErrorKind::InvalidInput,
-                        format!("invalid staging reserve: {error}"),
+                        format!("invalid ownership mode: {error}"),
                     )
                 })?);
             }
-        }
-    }
-
-    Ok(p)
-}
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum ProfileSection {
-    Locations,
-    Ignore,
-    Prune,
-    Staging,
-}
-
-fn parse_error(line: &str) -> Result<Profile, io::Error> {
-    Err(io::Error::new(
-        io::ErrorKind::InvalidInput,
-        format!("can't parse line: {}", line),
-    ))
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::io::Write;
-
-    #[test]
-    fn rejects_profile_names_with_path_components() {
-        assert!(location("work").is_ok());
-        assert!(location("../work").is_err());
-        assert!(location("/tmp/work").is_err());
-        assert!(location("work/other").is_err());
-        assert!(location("work\\other").is_err());
-        assert!(location(".").is_err());
-        assert!(location("..").is_err());
-    }
-
-    #[test]
-    fn parses_include_exclude_markers_after_leading_whitespace() {
-        let mut file = tempfile::NamedTempFile::new().unwrap();
-        writeln!(file, "/local").unwrap();
-        writeln!(file, "remote /remote").unwrap();
-        writeln!(file, "  +src").unwrap();
-        writeln!(file, "  -target").unwrap();
-        writeln!(file, "  [ignore]").unwrap();
-        writeln!(file, "*.tmp").unwrap();
-        writeln!(file, "  [prune]").unwrap();
-        writeln!(file, "__pycache__").unwrap();
-
-        let profile = parse_file(file.path()).unwrap();
-
-        assert!(matches!(
-            &profile.locations[1],
-            Location::Include(path) if path == &PathBuf::from("src")
-        ));
-        assert!(matches!(
-            &profile.locations[2],
-            Location::Exclude(path) if path == &PathBuf::from("target")
-        ));
-        assert_eq!(profile.ignore, vec!["*.tmp".to_string()]);
-        assert_eq!(profile.prune, vec!["__pycache__".to_string()]);
-        assert_eq!(
-            profile.scan_ignore(),
-            vec!["*.tmp".to_string(), "__pycache__".to_string()]
-        );
-        assert_eq!(profile.staging_reserve, None);
-    }
-
-    #[test]
-    fn parses_staging_reserve_and_can_switch_to_later_sections() {
-        let mut file = tempfile::NamedTempFile::new().unwrap();
-        writeln!(file, "/local").unwrap();
-        writeln!(file, "remote /remote").unwrap();
-        writeln!(file, "+.").unwrap();
-        writeln!(file, "[staging]").unwrap();
-        writeln!(file, "reserve = 12.5%").unwrap();
-        writeln!(file, "[ignore]").unwrap();
-        writeln!(file, "*.tmp").unwrap();
-
+            ProfileSection::Conflicts => {
+                let Some((key, value)) = trimmed.split_once('=') else {
+                    return parse_error(profile_location, number, &line);
+                };
+                match (key.trim(), value.trim()) {
+                    ("force", "newer") => {
+                        p.force_newer = true;
+                        continue;
+                    }
+                    ("sidecar", value) if !value.is_empty() => {}
+                    _ => return parse_error(profile_location, number, &line),
+                }
+                if p.conflicts.is_some() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "duplicate conflict sidecar setting",
+                    ));
+                }
+                p.conflicts = Some(value.trim().parse().map_err(|error: String| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("invalid conflict sidecar policy: {error}"),
+                    )
+                })?);
+            }
+            ProfileSection::Resolve => {
+                let Some((pattern, policy)) = trimmed.rsplit_once('=') else {
+                    return parse_error(profile_location, number, &line);
+                };
+                let pattern = pattern.trim();
+                if pattern.is_empty() {
+                    return parse_error(profile_location, number, &line);
+                }
+                fnmatch_regex::glob_to_regex(pattern).map_err(|error| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("invalid resolve pattern {pattern}: {error}"),
+                    )
+                })?;
+                p.resolve.push(ResolveRule {
+                    pattern: pattern.to_string(),
+                    policy: policy.trim().parse().map_err(|error: String| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("invalid resolve policy: {error}"),
+                        )
+                    })?,
+                });
+            }
+            ProfileSection::IgnoreFiles => {
+                if trimmed.contains('/') || trimmed == "." || trimmed == ".." {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("invalid ignore file name: {}", trimmed),
+                    ));
+                }
+                if trimmed != IGNORE_FILE && !p.ignore_files.iter().any(|name| name == trimmed) {
+                    p.ignore_files.push(trimmed.to_string());
+                }
+            }
+            ProfileSection::Merge => {
+                fnmatch_regex::glob_to_regex(trimmed).map_err(|error| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("invalid merge pattern {trimmed}: {error}"),
+                    )
+                })?;
+                p.merge.push(trimmed.to_string());
+            }
+            ProfileSection::Filter => {
+                let Some((key, value)) = trimmed.split_once('=') else {
+                    return parse_error(profile_location, number, &line);
+                };
+                let (key, value) = (key.trim(), value.trim());
+                let invalid = |error: String| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("invalid filter {key}: {error}"),
+                    )
+                };
+                let filters = &mut p.filters;
+                let duplicate = match key {
+                    "max-size" => filters
+                        .max_size
+                        .replace(crate::cli::parse_size(value).map_err(invalid)?)
+                        .is_some(),
+                    "min-size" => filters
+                        .min_size
+                        .replace(crate::cli::parse_size(value).map_err(invalid)?)
+                        .is_some(),
+                    "newer-than" => filters
+                        .newer_than
+                        .replace(crate::cli::parse_age(value).map_err(invalid)?)
+                        .is_some(),
+                    "older-than" => filters
+                        .older_than
+                        .replace(crate::cli::parse_age(value).map_err(invalid)?)
+                        .is_some(),
+                    "types" => {
+                        let mut types = EntryTypes::default();
+                        for kind in value.split(',').map(str::trim) {
+                            match kind {
+                                "files" => types.files = true,
+                                "symlinks" => types.symlinks = true,
+                                "directories" => types.directories = true,
+                                _ => {
+                                    return Err(invalid(format!(
+                                        "{kind} (expected files, symlinks, or directories)"
+                                    )))
+                                }
+                            }
+                        }
+                        filters.types.replace(types).is_some()
+                    }
+                    _ => return parse_error(profile_location, number, &line),
+                };
+                if duplicate {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("duplicate filter {key} setting"),
+                    ));
+                }
+            }
+            ProfileSection::Trash => {
+                let Some((key, value)) = trimmed.split_once('=') else {
+                    return parse_error(profile_location, number, &line);
+                };
+                let value = value.trim();
+                let retention = p.trash.get_or_insert_with(TrashRetention::default);
+                let (setting, parsed) = match key.trim() {
+                    "max-age" => (&mut retention.max_age_secs, crate::cli::parse_age(value)),
+                    "max-size" => (&mut retention.max_bytes, crate::cli::parse_size(value)),
+                    _ => return parse_error(profile_location, number, &line),
+                };
+                if setting.is_some() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("duplicate trash {} setting", key.trim()),
+                    ));
+                }
+                *setting = Some(parsed.map_err(|error| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("invalid trash {}: {error}", key.trim()),
+                    )
+                })?);
+            }
+            ProfileSection::Tcp => {
+                let Some((key, value)) = trimmed.split_once('=') else {
+                    return parse_error(profile_location, number, &line);
+                };
+                if key.trim() != "key-file" {
+                    return parse_error(profile_location, number, &line);
+                }
+                let value = line_word(profile_location, number, &line, value)?;
+                if value.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "invalid tcp key-file: empty value",
+                    ));
+                }
+                if p.tcp_key_file.replace(PathBuf::from(value)).is_some() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "duplicate tcp key-file setting",
+                    ));
+                }
+            }
+            ProfileSection::Ssh => {
+                let Some((key, value)) = trimmed.split_once('=') else {
+                    return parse_error(profile_location, number, &line);
+                };
+                let key = key.trim();
+                let value = line_word(profile_location, number, &line, value)?;
+                let invalid = |error: String| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("invalid ssh {key}: {error}"),
+                    )
+                };
+                if value.is_empty() {
+                    return Err(invalid("empty value".to_string()));
+                }
+                let ssh = &mut p.ssh;
+                let duplicate = match key {
+                    "port" => ssh
+                        .port
+                        .replace(
+                            value
+                                .parse()
+                                .map_err(|_| invalid(format!("{value} is not a port number")))?,
+                        )
+                        .is_some(),
+                    "user" => ssh.user.replace(value).is_some(),
+                    "identity" => ssh.identity.replace(PathBuf::from(value)).is_some(),
+                    "proxy-jump" => {
+                        let hosts: Vec<String> = value
+                            .split(',')
+                            .map(|host| host.trim().to_string())
+                            .collect();
+                        if hosts.iter().any(String::is_empty) {
+                            return Err(invalid(format!("empty host in {value}")));
+                        }
+                        !std::mem::replace(&mut ssh.proxy_jump, hosts).is_empty()
+                    }
+                    "control-dir" => ssh.control_dir.replace(PathBuf::from(value)).is_some(),
+                    "connect-timeout" => ssh
+                        .connect_timeout
+                        .replace(crate::cli::parse_seconds(&value).map_err(invalid)?)
+                        .is_some(),
+                    "keepalive" => ssh
+                        .keepalive
+                        .replace(crate::cli::parse_seconds(&value).map_err(invalid)?)
+                        .is_some(),
+                    "transport" => ssh
+                        .transport
+                        .replace(value.parse().map_err(invalid)?)
+                        .is_some(),
+                    _ => return parse_error(profile_location, number, &line),
+                };
+                if duplicate {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("duplicate ssh {key} setting"),
+                    ));
+                }
+            }
+            ProfileSection::Options => {
+                let Some((key, value)) = trimmed.split_once('=') else {
+                    return parse_error(profile_location, number, &line);
+                };
+                let key = key.trim();
+                let value = line_word(profile_location, number, &line, value)?;
+                let invalid = |error: String| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("invalid option {key}: {error}"),
+                    )
+                };
+                if value.is_empty() {
+                    return Err(invalid("empty value".to_string()));
+                }
+                let flag = || match value.as_str() {
+                    "true" => Ok(true),
+                    "false" => Ok(false),
+                    _ => Err(invalid(format!("expected true or false, not {value}"))),
+                };
+                let options = &mut p.options;
+                let duplicate = match key {
+                    "batch" => options.batch.replace(flag()?).is_some(),
+                    "force" => options.force.replace(flag()?).is_some(),
+                    "verbose" => options.verbose.replace(flag()?).is_some(),
+                    "prune-ignored" => options.prune_ignored.replace(flag()?).is_some(),
+                    "exclude" => {
+                        options.excludes.push(PathBuf::from(value));
+                        false
+                    }
+                    "staging-limit" => {
+                        let limit: crate::cli::StagingLimit = value.parse().map_err(invalid)?;
+                        options.staging_limit.replace(limit.0).is_some()
+                    }
+                    "profile-performance-json" => options
+                        .profile_performance_json
+                        .replace(PathBuf::from(value))
+                        .is_some(),
+                    "compression" => {
+                        let level = crate::cli::parse_compression_level(&value).map_err(invalid)?;
+                        options.compression.replace(level).is_some()
+                    }
+                    "bwlimit" => options
+                        .bwlimit
+                        .replace(value.parse().map_err(invalid)?)
+                        .is_some(),
+                    _ => return parse_error(profile_location, number, &line),
+                };
+                if duplicate {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("duplicate option {key}"),
+                    ));
+                }
+            }
+            ProfileSection::Staging => {
+                let Some((key, value)) = trimmed.split_once('=') else {
+                    return parse_error(profile_location, number, &line);
+                };
+                if key.trim() != "reserve" || value.trim().is_empty() {
+                    return parse_error(profile_location, number, &line);
+                }
+                if p.staging_reserve.is_some() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "duplicate staging reserve setting",
+                    ));
+                }
+                p.staging_reserve = Some(value.trim().parse().map_err(|error: String| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("invalid staging reserve: {error}"),
+                    )
+                })?);
+            }
+        }
+    }
+
+    Ok(match included {
+        Some(fragments) => fragments.overridden_by(p),
+        None => p,
+    })
+}
+
+/// Resolves an include target relative to the directory of the file that includes it.
+fn include_path(profile_location: &Path, target: &str) -> Result<PathBuf, io::Error> {
+    let expanded = shellexpand::full(target).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unable to expand {}: {}", target, e),
+        )
+    })?;
+    let target = PathBuf::from(expanded.into_owned());
+    Ok(match profile_location.parent() {
+        Some(dir) if target.is_relative() => dir.join(target),
+        _ => target,
+    })
+}
+
+/// Section headers in the order `duet _info` lists them, starting with the location rules.
+const SECTION_HEADERS: &[&str] = &[
+    "",
+    "[ignore]",
+    "[prune]",
+    "[staging]",
+    "[ssh]",
+    "[tcp]",
+    "[options]",
+    "[filter]",
+    "[ignore-files]",
+    "[xattrs]",
+    "[ownership]",
+    "[special]",
+    "[conflicts]",
+    "[resolve]",
+    "[merge]",
+    "[trash]",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProfileSection {
+    Include,
+    Locations,
+    Ignore,
+    Prune,
+    Staging,
+    Ssh,
+    Tcp,
+    Options,
+    Filter,
+    IgnoreFiles,
+    Xattrs,
+    Ownership,
+    Special,
+    Conflicts,
+    Resolve,
+    Merge,
+    Trash,
+}
+
+impl ProfileSection {
+    fn header(self) -> &'static str {
+        match self {
+            Self::Include => "[include]",
+            Self::Locations => "",
+            Self::Ignore => "[ignore]",
+            Self::Prune => "[prune]",
+            Self::Staging => "[staging]",
+            Self::Ssh => "[ssh]",
+            Self::Tcp => "[tcp]",
+            Self::Options => "[options]",
+            Self::Filter => "[filter]",
+            Self::IgnoreFiles => "[ignore-files]",
+            Self::Xattrs => "[xattrs]",
+            Self::Ownership => "[ownership]",
+            Self::Special => "[special]",
+            Self::Conflicts => "[conflicts]",
+            Self::Resolve => "[resolve]",
+            Self::Merge => "[merge]",
+            Self::Trash => "[trash]",
+        }
+    }
+}
+
+fn parse_error(location: &Path, number: usize, line: &str) -> Result<Profile, io::Error> {
+    Err(line_error(
+        location,
+        number,
+        format!("can't parse line: {}", line),
+    ))
+}
+
+/// Reads `text`, a part of `line`, as one shell-style word with its references expanded here.
+fn line_word(location: &Path, number: usize, line: &str, text: &str) -> Result<String, io::Error> {
+    lexer::word(text, lexer::Expand::Here).map_err(|error| {
+        let start = text.trim_start().as_ptr() as usize - line.as_ptr() as usize;
+        lex_error(location, number, line[..start].chars().count(), &error)
+    })
+}
+
+/// Checks a remote line, as the sync will parse it.
+fn check_remote(location: &Path, number: usize, line: &String) -> Result<(), io::Error> {
+    match crate::remote::parse_remote(line) {
+        Ok(_) => Ok(()),
+        Err(error) => Err(match error.downcast_ref::<lexer::LexError>() {
+            Some(error) => lex_error(location, number, 0, error),
+            None => line_error(location, number, error),
+        }),
+    }
+}
+
+/// An error at a character of a line, `offset` characters before the column `error` counts from.
+fn lex_error(location: &Path, number: usize, offset: usize, error: &lexer::LexError) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+            "{}:{}:{}: {}",
+            location.display(),
+            number,
+            offset + error.column,
+            error.message
+        ),
+    )
+}
+
+fn line_error(location: &Path, number: usize, error: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{}:{}: {}", location.display(), number, error),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Write;
+
+    #[test]
+    fn rejects_profile_names_with_path_components() {
+        assert!(location("work").is_ok());
+        assert!(location("../work").is_err());
+        assert!(location("/tmp/work").is_err());
+        assert!(location("work/other").is_err());
+        assert!(location("work\\other").is_err());
+        assert!(location(".").is_err());
+        assert!(location("..").is_err());
+    }
+
+    #[test]
+    fn parses_include_exclude_markers_after_leading_whitespace() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "/local").unwrap();
+        writeln!(file, "remote /remote").unwrap();
+        writeln!(file, "  +src").unwrap();
+        writeln!(file, "  -target").unwrap();
+        writeln!(file, "  [ignore]").unwrap();
+        writeln!(file, "*.tmp").unwrap();
+        writeln!(file, "  [prune]").unwrap();
+        writeln!(file, "__pycache__").unwrap();
+
+        let profile = parse_file(file.path()).unwrap();
+
+        assert!(matches!(
+            &profile.locations[1],
+            Location::Include(path) if path == &PathBuf::from("src")
+        ));
+        assert!(matches!(
+            &profile.locations[2],
+            Location::Exclude(path) if path == &PathBuf::from("target")
+        ));
+        assert_eq!(profile.ignore, vec!["*.tmp".to_string()]);
+        assert_eq!(profile.prune, vec!["__pycache__".to_string()]);
+        assert_eq!(
+            profile.scan_ignore(),
+            vec!["*.tmp".to_string(), "__pycache__".to_string()]
+        );
+        assert_eq!(profile.staging_reserve, None);
+    }
+
+    #[test]
+    fn parses_staging_reserve_and_can_switch_to_later_sections() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "/local").unwrap();
+        writeln!(file, "remote /remote").unwrap();
+        writeln!(file, "+.").unwrap();
+        writeln!(file, "[staging]").unwrap();
+        writeln!(file, "reserve = 12.5%").unwrap();
+        writeln!(file, "[ignore]").unwrap();
+        writeln!(file, "*.tmp").unwrap();
+
         let profile = parse_file(file.path()).unwrap();
 
         assert_eq!(
@@ -402,6 +1528,119 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parses_ssh_settings_and_rejects_invalid_ones() {
+        std::env::set_var("DUET_PROFILE_TEST_KEY", "/keys/id_backup");
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "/local").unwrap();
+        writeln!(file, "ssh host duet /remote").unwrap();
+        writeln!(file, "[ssh]").unwrap();
+        writeln!(file, "port = 2222").unwrap();
+        writeln!(file, "user = backup").unwrap();
+        writeln!(file, "identity = $DUET_PROFILE_TEST_KEY").unwrap();
+        writeln!(file, "proxy-jump = bastion, gate").unwrap();
+        writeln!(file, "control-dir = '/run/duet sockets'").unwrap();
+        writeln!(file, "connect-timeout = 2m").unwrap();
+        writeln!(file, "keepalive = 15").unwrap();
+        writeln!(file, "transport = system").unwrap();
+
+        let profile = parse_file(file.path()).unwrap();
+
+        assert_eq!(
+            profile.ssh,
+            SshSettings {
+                port: Some(2222),
+                user: Some("backup".to_string()),
+                identity: Some(PathBuf::from("/keys/id_backup")),
+                proxy_jump: vec!["bastion".to_string(), "gate".to_string()],
+                control_dir: Some(PathBuf::from("/run/duet sockets")),
+                connect_timeout: Some(120),
+                keepalive: Some(15),
+                transport: Some(SshTransport::System),
+            }
+        );
+        assert_eq!(
+            profile.ssh.to_string(),
+            "port=2222, user=backup, identity=/keys/id_backup, proxy-jump=bastion,gate, \
+             control-dir=/run/duet sockets, connect-timeout=120s, keepalive=15s, transport=system"
+        );
+        assert_eq!(SshSettings::default().to_string(), "none");
+
+        for settings in [
+            "port = 70000",
+            "keepalive = 1h",
+            "user =",
+            "proxy-jump = a,,b",
+            "transport = tty",
+            "compression = yes",
+            "port = 22\nport = 2222",
+        ] {
+            let mut file = tempfile::NamedTempFile::new().unwrap();
+            writeln!(file, "/local").unwrap();
+            writeln!(file, "ssh host duet /remote").unwrap();
+            writeln!(file, "[ssh]").unwrap();
+            writeln!(file, "{settings}").unwrap();
+
+            assert!(parse_file(file.path()).is_err(), "accepted {:?}", settings);
+        }
+    }
+
+    #[test]
+    fn parses_option_defaults_and_merges_them_with_includes() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("shared.prf"),
+            "[options]\nbatch = true\nprune-ignored = true\nexclude = cache\n",
+        )
+        .unwrap();
+        let path = dir.path().join("work.prf");
+        std::fs::write(
+            &path,
+            "/local\nremote /remote\ninclude shared.prf\n[options]\nprune-ignored = false\n\
+             exclude = 'build output'\nstaging-limit = 1GiB\nprofile-performance-json = /tmp/p.json\n\
+             compression = 6\nbwlimit = 1MiB/4MiB\n",
+        )
+        .unwrap();
+
+        let profile = parse_file(&path).unwrap();
+
+        assert_eq!(
+            profile.options,
+            ProfileOptions {
+                batch: Some(true),
+                force: None,
+                verbose: None,
+                prune_ignored: Some(false),
+                excludes: vec![PathBuf::from("cache"), PathBuf::from("build output")],
+                staging_limit: Some(1024 * 1024 * 1024),
+                profile_performance_json: Some(PathBuf::from("/tmp/p.json")),
+                compression: Some(6),
+                bwlimit: Some(crate::bwlimit::BandwidthLimit {
+                    upload: Some(1024 * 1024),
+                    download: Some(4 * 1024 * 1024),
+                }),
+            }
+        );
+
+        for settings in [
+            "batch = yes",
+            "staging-limit = 0",
+            "compression = 11",
+            "bwlimit = 0/0",
+            "dry-run = true",
+            "force = true\nforce = false",
+            "exclude =",
+        ] {
+            let mut file = tempfile::NamedTempFile::new().unwrap();
+            writeln!(file, "/local").unwrap();
+            writeln!(file, "remote /remote").unwrap();
+            writeln!(file, "[options]").unwrap();
+            writeln!(file, "{settings}").unwrap();
+
+            assert!(parse_file(file.path()).is_err(), "accepted {:?}", settings);
+        }
+    }
+
     #[test]
     fn staging_section_can_follow_ignore_or_prune() {
         for section in ["ignore", "prune"] {
@@ -428,6 +1667,457 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parses_xattr_namespaces() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "/local").unwrap();
+        writeln!(file, "remote /remote").unwrap();
+        writeln!(file, "+.").unwrap();
+        writeln!(file, "[xattrs]").unwrap();
+        writeln!(file, "user").unwrap();
+        writeln!(file, "  trusted").unwrap();
+        writeln!(file, "user").unwrap();
+        writeln!(file, "[ignore]").unwrap();
+        writeln!(file, "*.tmp").unwrap();
+
+        let profile = parse_file(file.path()).unwrap();
+
+        assert_eq!(
+            profile.xattrs,
+            vec!["user".to_string(), "trusted".to_string()]
+        );
+        assert_eq!(profile.ignore, vec!["*.tmp".to_string()]);
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "/local").unwrap();
+        writeln!(file, "remote /remote").unwrap();
+        writeln!(file, "[xattrs]").unwrap();
+        writeln!(file, "user.provenance").unwrap();
+        assert!(parse_file(file.path()).is_err());
+    }
+
+    #[test]
+    fn parses_special_file_kinds() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "/local").unwrap();
+        writeln!(file, "remote /remote").unwrap();
+        writeln!(file, "+.").unwrap();
+        writeln!(file, "[special]").unwrap();
+        writeln!(file, "fifo").unwrap();
+        writeln!(file, "socket").unwrap();
+
+        let profile = parse_file(file.path()).unwrap();
+        assert_eq!(
+            profile.special,
+            SpecialFiles {
+                fifos: true,
+                devices: false,
+                skip_sockets: true,
+            }
+        );
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "/local").unwrap();
+        writeln!(file, "remote /remote").unwrap();
+        writeln!(file, "[special]").unwrap();
+        writeln!(file, "door").unwrap();
+        assert!(parse_file(file.path()).is_err());
+    }
+
+    #[test]
+    fn parses_ownership_mode() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "/local").unwrap();
+        writeln!(file, "remote /remote").unwrap();
+        writeln!(file, "+.").unwrap();
+        writeln!(file, "[ownership]").unwrap();
+        writeln!(file, "mode = names").unwrap();
+
+        let profile = parse_file(file.path()).unwrap();
+        assert_eq!(profile.ownership, Some(OwnershipMode::Names));
+        assert_eq!(profile.capture().ownership, Some(OwnershipMode::Names));
+
+        for invalid in ["mode = owner", "group", "mode = group\nmode = numeric"] {
+            let mut file = tempfile::NamedTempFile::new().unwrap();
+            writeln!(file, "/local").unwrap();
+            writeln!(file, "remote /remote").unwrap();
+            writeln!(file, "[ownership]").unwrap();
+            writeln!(file, "{}", invalid).unwrap();
+            assert!(parse_file(file.path()).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn parses_conflict_sidecar_policy() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "/local").unwrap();
+        writeln!(file, "remote /remote").unwrap();
+        writeln!(file, "+.").unwrap();
+        writeln!(file, "[ignore]").unwrap();
+        writeln!(file, "*.tmp").unwrap();
+        writeln!(file, "[conflicts]").unwrap();
+        writeln!(file, "sidecar = ignored").unwrap();
+        writeln!(file, "force = newer").unwrap();
+
+        let profile = parse_file(file.path()).unwrap();
+        assert_eq!(profile.conflicts, Some(SidecarPolicy::Ignored));
+        assert!(profile.force_newer);
+        assert_eq!(
+            profile.scan_ignore(),
+            vec!["*.tmp".to_string(), SIDECAR_PATTERN.to_string()]
+        );
+
+        for invalid in [
+            "sidecar = kept",
+            "keep",
+            "force = local",
+            "sidecar = tracked\nsidecar = ignored",
+        ] {
+            let mut file = tempfile::NamedTempFile::new().unwrap();
+            writeln!(file, "/local").unwrap();
+            writeln!(file, "remote /remote").unwrap();
+            writeln!(file, "[conflicts]").unwrap();
+            writeln!(file, "{}", invalid).unwrap();
+            assert!(parse_file(file.path()).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn parses_resolve_rules_in_order() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "/local").unwrap();
+        writeln!(file, "remote /remote").unwrap();
+        writeln!(file, "[resolve]").unwrap();
+        writeln!(file, "docs/*.md = keep-both").unwrap();
+        writeln!(file, "*.log=larger").unwrap();
+        writeln!(file, "* = newer").unwrap();
+
+        let profile = parse_file(file.path()).unwrap();
+        assert_eq!(
+            profile.resolve,
+            vec![
+                ResolveRule {
+                    pattern: "docs/*.md".to_string(),
+                    policy: ResolvePolicy::KeepBoth,
+                },
+                ResolveRule {
+                    pattern: "*.log".to_string(),
+                    policy: ResolvePolicy::Larger,
+                },
+                ResolveRule {
+                    pattern: "*".to_string(),
+                    policy: ResolvePolicy::Newer,
+                },
+            ]
+        );
+        assert_eq!(profile.resolve[0].to_string(), "docs/*.md = keep-both");
+
+        for invalid in ["*.log", "= local", "*.log = oldest", "[a = local"] {
+            let mut file = tempfile::NamedTempFile::new().unwrap();
+            writeln!(file, "/local").unwrap();
+            writeln!(file, "remote /remote").unwrap();
+            writeln!(file, "[resolve]").unwrap();
+            writeln!(file, "{}", invalid).unwrap();
+            assert!(parse_file(file.path()).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn parses_merge_patterns() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "/local").unwrap();
+        writeln!(file, "remote /remote").unwrap();
+        writeln!(file, "[merge]").unwrap();
+        writeln!(file, "*.md").unwrap();
+        writeln!(file, "  papers/*.tex").unwrap();
+
+        let profile = parse_file(file.path()).unwrap();
+        assert_eq!(profile.merge, vec!["*.md", "papers/*.tex"]);
+        assert!(profile.resolve.is_empty());
+
+        let regex = fnmatch_regex::glob_to_regex("*.md").unwrap();
+        assert!(glob_matches("*.md", &regex, Path::new("notes/a.md")));
+        let regex = fnmatch_regex::glob_to_regex("papers/*.tex").unwrap();
+        assert!(glob_matches(
+            "papers/*.tex",
+            &regex,
+            Path::new("papers/a.tex")
+        ));
+        assert!(!glob_matches(
+            "papers/*.tex",
+            &regex,
+            Path::new("b/papers/a.tex")
+        ));
+    }
+
+    #[test]
+    fn parses_filters_and_applies_them_to_entries() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "/local").unwrap();
+        writeln!(file, "remote /remote").unwrap();
+        writeln!(file, "[filter]").unwrap();
+        writeln!(file, "max-size = 1KiB").unwrap();
+        writeln!(file, "newer-than = 2d").unwrap();
+        writeln!(file, "types = files, directories").unwrap();
+
+        let filters = parse_file(file.path()).unwrap().filters;
+        assert_eq!(filters.max_size, Some(1024));
+        assert_eq!(filters.newer_than, Some(2 * 24 * 60 * 60));
+        assert_eq!(filters.min_size, None);
+
+        let file = |size| DirEntryWithMeta::test_file_with_size(PathBuf::from("a"), size, 0);
+        assert_eq!(filters.filtered_by(&file(1), 0), None);
+        assert_eq!(filters.filtered_by(&file(2048), 0), Some("max-size"));
+        assert_eq!(
+            filters.filtered_by(&file(1), 3 * 24 * 60 * 60),
+            Some("newer-than")
+        );
+        let link = DirEntryWithMeta::test_symlink(PathBuf::from("a"), PathBuf::from("b"));
+        assert_eq!(filters.filtered_by(&link, 0), Some("types"));
+        let dir = DirEntryWithMeta::test_dir(PathBuf::from("a"));
+        assert_eq!(filters.filtered_by(&dir, 3 * 24 * 60 * 60), None);
+        let only_files = EntryFilters {
+            types: Some(EntryTypes {
+                files: true,
+                ..EntryTypes::default()
+            }),
+            ..EntryFilters::default()
+        };
+        assert_eq!(only_files.filtered_by(&dir, 0), Some("types"));
+
+        for invalid in [
+            "max-size = big",
+            "older-than = 3",
+            "types = files, sockets",
+            "max-size = 1\nmax-size = 2",
+            "newest = 1d",
+        ] {
+            let mut file = tempfile::NamedTempFile::new().unwrap();
+            writeln!(file, "/local").unwrap();
+            writeln!(file, "remote /remote").unwrap();
+            writeln!(file, "[filter]").unwrap();
+            writeln!(file, "{}", invalid).unwrap();
+            assert!(parse_file(file.path()).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn parses_ignore_files() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "/local").unwrap();
+        writeln!(file, "remote /remote").unwrap();
+        writeln!(file, "[ignore-files]").unwrap();
+        writeln!(file, ".gitignore").unwrap();
+        writeln!(file, ".duetignore").unwrap();
+
+        let profile = parse_file(file.path()).unwrap();
+        assert_eq!(profile.ignore_files, vec![".gitignore"]);
+        assert_eq!(
+            profile.capture().ignore_files,
+            vec![".gitignore", IGNORE_FILE]
+        );
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "/local").unwrap();
+        writeln!(file, "remote /remote").unwrap();
+        writeln!(file, "[ignore-files]").unwrap();
+        writeln!(file, "dir/.gitignore").unwrap();
+        assert!(parse_file(file.path()).is_err());
+    }
+
+    #[test]
+    fn further_remote_lines_are_peers_with_their_own_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hub.prf");
+        fs::write(
+            &path,
+            "/local\n/laptop\nssh cluster /scratch/x\n\n+docs\n[ignore]\n*.tmp\n",
+        )
+        .unwrap();
+
+        let config = load(&ProfileSource::File(path.clone())).unwrap();
+        let remotes: Vec<_> = config.profile.remotes().collect();
+        assert_eq!(remotes, vec!["/laptop", "ssh cluster /scratch/x"]);
+        assert_eq!(config.profile.locations.len(), 2);
+        assert_eq!(config.peer_state("/laptop"), config.local_state);
+        let peer_state = config.peer_state("ssh cluster /scratch/x");
+        assert_ne!(peer_state, config.local_state);
+        assert_eq!(peer_state.parent(), config.local_state.parent());
+        assert!(peer_state.to_str().unwrap().ends_with(".snp"));
+
+        fs::write(&path, "/local\n/laptop\n/laptop\n").unwrap();
+        let error = parse_file(&path).unwrap_err().to_string();
+        assert!(error.contains(":3: duplicate remote line"), "{}", error);
+    }
+
+    #[test]
+    fn lines_are_shell_words_with_expansion_and_precise_errors() {
+        std::env::set_var("DUET_PROFILE_TEST_ROOT", "/srv/work");
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("words.prf");
+        fs::write(
+            &path,
+            "\"${DUET_PROFILE_TEST_ROOT}/My Files\"\n\
+             command='/opt/my duet/duet' base='${HOME}/My Files'\n\
+             +${DUET_PROFILE_TEST_ROOT}/docs\n\
+             - 'with  spaces '\n\
+             +plain words\n",
+        )
+        .unwrap();
+
+        let profile = parse_file(&path).unwrap();
+        assert_eq!(profile.local, "/srv/work/My Files");
+        let paths: Vec<_> = profile
+            .locations
+            .iter()
+            .map(|l| l.path().as_path())
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                Path::new("."),
+                Path::new("/srv/work/docs"),
+                Path::new("with  spaces "),
+                Path::new("plain words"),
+            ]
+        );
+
+        let error = |contents: &str| {
+            fs::write(&path, contents).unwrap();
+            parse_file(&path).unwrap_err().to_string()
+        };
+        let location = path.display();
+        assert_eq!(
+            error("/local\n/remote\n  -\"a/b\n"),
+            format!("{}:3:4: unterminated double quote", location)
+        );
+        assert_eq!(
+            error("/local\nssh 'host /remote\n"),
+            format!("{}:2:5: unterminated single quote", location)
+        );
+        assert_eq!(
+            error("${DUET_PROFILE_TEST_UNSET}\n/remote\n"),
+            format!(
+                "{}:1:1: variable DUET_PROFILE_TEST_UNSET is not set",
+                location
+            )
+        );
+        assert!(error("/local\nbase=/x port=22\n")
+            .starts_with(&format!("{}:2: unknown remote setting port", location)));
+    }
+
+    #[test]
+    fn included_fragments_yield_to_the_including_file() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("common.prf"),
+            "-build\n[ignore]\n*.tmp\n[resolve]\n* = newer\n[staging]\nreserve = 5%\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("extra.prf"), "[merge]\n*.md\n").unwrap();
+        let main = dir.path().join("main.prf");
+        fs::write(
+            &main,
+            "/local\nremote /remote\ninclude common.prf\n+build\n[include]\nextra.prf\n\
+             [resolve]\n*.log = larger\n[staging]\nreserve = 1GiB\n",
+        )
+        .unwrap();
+
+        let profile = parse_file(&main).unwrap();
+        assert_eq!(profile.local, "/local");
+        assert!(matches!(
+            &profile.locations[..],
+            [Location::Exclude(_), Location::Exclude(a), Location::Include(b)]
+                if a == Path::new("build") && b == Path::new("build")
+        ));
+        assert_eq!(profile.ignore, vec!["*.tmp"]);
+        assert_eq!(profile.merge, vec!["*.md"]);
+        let patterns: Vec<_> = profile.resolve.iter().map(|rule| &rule.pattern).collect();
+        assert_eq!(patterns, vec!["*.log", "*"]);
+        assert_eq!(
+            profile.staging_reserve,
+            Some(StagingReserve::Bytes(1024 * 1024 * 1024))
+        );
+
+        let reserve: Vec<_> = profile
+            .sources
+            .iter()
+            .filter(|source| source.section == "[staging]")
+            .collect();
+        assert_eq!(reserve.len(), 1);
+        assert_eq!((reserve[0].file.as_path(), reserve[0].line), (&*main, 10));
+        let ignore = profile
+            .sources
+            .iter()
+            .find(|source| source.section == "[ignore]")
+            .unwrap();
+        assert_eq!(ignore.rule, "*.tmp");
+        assert_eq!(ignore.file, dir.path().join("common.prf"));
+        assert_eq!(ignore.line, 3);
+    }
+
+    #[test]
+    fn include_cycles_are_reported_with_line_numbers() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = dir.path().join("main.prf");
+        fs::write(&main, "/local\nremote /remote\n\ninclude a.prf\n").unwrap();
+        fs::write(dir.path().join("a.prf"), "[ignore]\n*.tmp\ninclude b.prf\n").unwrap();
+        fs::write(dir.path().join("b.prf"), "include a.prf\n").unwrap();
+
+        let error = parse_file(&main).unwrap_err().to_string();
+        assert!(error.contains("main.prf:4:"), "{}", error);
+        assert!(error.contains("a.prf:3:"), "{}", error);
+        assert!(error.contains("b.prf:1: include cycle"), "{}", error);
+
+        fs::write(dir.path().join("b.prf"), "include ../missing.prf\n").unwrap();
+        let error = parse_file(&main).unwrap_err().to_string();
+        assert!(error.contains("b.prf:1:"), "{}", error);
+    }
+
+    #[test]
+    fn parses_trash_retention() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "/local").unwrap();
+        writeln!(file, "remote /remote").unwrap();
+        writeln!(file, "[trash]").unwrap();
+        writeln!(file, "max-age = 2w").unwrap();
+        writeln!(file, "max-size = 1KiB").unwrap();
+
+        let profile = parse_file(file.path()).unwrap();
+        assert_eq!(
+            profile.trash,
+            Some(TrashRetention {
+                max_age_secs: Some(14 * 24 * 60 * 60),
+                max_bytes: Some(1024),
+            })
+        );
+        assert_eq!(profile.scan_ignore(), vec![trash::TRASH_DIR.to_string()]);
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "/local").unwrap();
+        writeln!(file, "remote /remote").unwrap();
+        writeln!(file, "[trash]").unwrap();
+        assert_eq!(
+            parse_file(file.path()).unwrap().trash,
+            Some(TrashRetention::default())
+        );
+
+        for invalid in [
+            "max-age = 30",
+            "max-age = -1d",
+            "max-size = lots",
+            "keep = 1d",
+            "max-age = 1d\nmax-age = 2d",
+        ] {
+            let mut file = tempfile::NamedTempFile::new().unwrap();
+            writeln!(file, "/local").unwrap();
+            writeln!(file, "remote /remote").unwrap();
+            writeln!(file, "[trash]").unwrap();
+            writeln!(file, "{}", invalid).unwrap();
+            assert!(parse_file(file.path()).is_err(), "{}", invalid);
+        }
+    }
+
     #[test]
     fn keeps_implicit_root_exclude_before_source_rules() {
         let mut file = tempfile::NamedTempFile::new().unwrap();
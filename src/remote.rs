@@ -1,23 +1,110 @@
+use std::collections::VecDeque;
 #[cfg(unix)]
 use std::convert::TryFrom;
-use std::path::Path;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
 use std::process::{ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
 
 use color_eyre::eyre::{eyre, Result, WrapErr};
 use essrpc::transports::BincodeAsyncClientTransport;
 use essrpc::AsyncRPCClient;
 use openssh::{RemoteChild, Session};
 use readwrite::ReadWriteTokio;
-use tokio::io::{BufReader as AsyncBufReader, BufWriter as AsyncBufWriter};
-use tokio::process::{Child, Command as TokioCommand};
+use tokio::io::{AsyncBufReadExt, BufReader as AsyncBufReader, BufWriter as AsyncBufWriter};
+use tokio::process::{Child, ChildStderr, Command as TokioCommand};
 
 use crate::io_wrappers::{StdinWrapper, StdoutWrapper};
+use crate::profile::SshSettings;
+use crate::{lexer, tcp};
 
+/// How many of the last lines the system `ssh` command writes to standard error are kept for
+/// its diagnostic.
+const SSH_STDERR_LINES: usize = 8;
+
+/// The exit status `ssh` reports for its own errors, as opposed to the remote command's.
+const SSH_ERROR_STATUS: i32 = 255;
+
+/// Parses a remote line into its base, SSH server, and duet command. The line is split into
+/// shell-style words, either positionally, `[ssh <server>] [duet-command] <base-path>`, or as
+/// `server=`, `command=`, and `base=` settings in any order. The server is expanded here; the
+/// command and base keep their `$VAR` and `~` references for the side that runs them.
+///
+/// A `tcp://host:port/base` line, or a `server=tcp://host:port` setting, names a server that
+/// `duet --server --listen` runs already, so its command is unused.
 pub(crate) fn parse_remote(remote: &String) -> Result<(String, Option<String>, String)> {
-    let elements: Vec<&str> = remote.split_whitespace().collect();
-    if elements.is_empty() {
+    let words = lexer::split(remote, lexer::Expand::Later)?;
+    if words.is_empty() {
         return Err(eyre!("remote profile entry is empty"));
     }
+    let (remote_base, remote_server, remote_cmd) = if words[0].starts_with(tcp::SCHEME) {
+        parse_tcp_remote(&words)?
+    } else if is_setting(&words[0]) {
+        parse_remote_settings(&words)?
+    } else {
+        parse_remote_words(&words)?
+    };
+    let remote_server = match remote_server {
+        Some(server) => Some(
+            shellexpand::full(&server)
+                .wrap_err_with(|| format!("unable to expand remote server {}", server))?
+                .into_owned(),
+        ),
+        None => None,
+    };
+    if let Some(address) = remote_server.as_deref().and_then(tcp::address) {
+        validate_tcp_address(address)?;
+    }
+    Ok((remote_base, remote_server, remote_cmd))
+}
+
+fn parse_tcp_remote(words: &[String]) -> Result<(String, Option<String>, String)> {
+    if let Some(word) = words.get(1) {
+        return Err(eyre!(
+            "couldn't parse remote profile entry: unexpected {} after a tcp:// remote",
+            lexer::quote(word)
+        ));
+    }
+    let rest = &words[0][tcp::SCHEME.len()..];
+    let (address, base) = rest
+        .split_once('/')
+        .filter(|(_, base)| !base.is_empty())
+        .ok_or_else(|| eyre!("tcp remote must have the form `tcp://<host>:<port>/<base-path>`"))?;
+    // `tcp://host:port/~/dir` is relative to the server's home
+    let base = if base.starts_with('~') {
+        base.to_string()
+    } else {
+        format!("/{}", base)
+    };
+    Ok((
+        base,
+        Some(format!("{}{}", tcp::SCHEME, address)),
+        "duet".to_string(),
+    ))
+}
+
+fn validate_tcp_address(address: &str) -> Result<()> {
+    let valid = address.rsplit_once(':').is_some_and(|(host, port)| {
+        !host.is_empty() && port.parse::<u16>().is_ok_and(|port| port != 0)
+    });
+    if valid {
+        Ok(())
+    } else {
+        Err(eyre!(
+            "invalid tcp server address {} (expected <host>:<port>)",
+            address
+        ))
+    }
+}
+
+const REMOTE_SETTINGS: &[&str] = &["server", "command", "base"];
+
+fn is_setting(word: &str) -> bool {
+    word.split_once('=')
+        .is_some_and(|(key, _)| REMOTE_SETTINGS.contains(&key))
+}
+
+fn parse_remote_words(elements: &[String]) -> Result<(String, Option<String>, String)> {
     let (remote_server, i) = if elements[0] == "ssh" {
         if elements.len() < 3 {
             return Err(eyre!(
@@ -35,16 +122,78 @@ pub(crate) fn parse_remote(remote: &String) -> Result<(String, Option<String>, S
     };
     if i < elements.len() {
         Err(eyre!(
-            "couldn't parse remote profile entry; remote commands and paths containing spaces are not supported"
+            "couldn't parse remote profile entry: unexpected {}; quote remote commands and paths that contain spaces",
+            lexer::quote(&elements[i])
         ))
     } else {
         Ok((remote_base, remote_server, remote_cmd))
     }
 }
 
+fn parse_remote_settings(words: &[String]) -> Result<(String, Option<String>, String)> {
+    let mut settings: [Option<String>; 3] = Default::default();
+    for word in words {
+        let Some((key, value)) = word.split_once('=') else {
+            return Err(eyre!(
+                "expected a `key=value` remote setting, found {}",
+                lexer::quote(word)
+            ));
+        };
+        let Some(index) = REMOTE_SETTINGS.iter().position(|setting| *setting == key) else {
+            return Err(eyre!(
+                "unknown remote setting {} (expected server, command, or base)",
+                key
+            ));
+        };
+        if value.is_empty() {
+            return Err(eyre!("remote setting {} is empty", key));
+        }
+        if settings[index].replace(value.to_string()).is_some() {
+            return Err(eyre!("duplicate remote setting {}", key));
+        }
+    }
+    let [server, command, base] = settings;
+    let base = base.ok_or_else(|| eyre!("remote settings must include base=<path>"))?;
+    Ok((base, server, command.unwrap_or_else(|| "duet".to_string())))
+}
+
+/// How `launch_server` serves a peer on this host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LocalServer {
+    /// On a thread of this process, without running the profile's duet command.
+    InProcess,
+    /// As a `duet --server` child process, the way SSH peers are served.
+    Subprocess,
+}
+
+/// The connection to a remote line's host.
+pub(crate) enum RemoteSession {
+    /// No connection: the peer is on this host.
+    None,
+    /// A multiplexed `openssh` session.
+    Mux(Session),
+    /// The system `ssh` command, which `launch_server` runs with the server.
+    System {
+        server: String,
+        settings: SshSettings,
+    },
+    /// A `duet --server --listen` server, which `launch_server` connects to with the key.
+    Tcp {
+        address: String,
+        key_file: Option<PathBuf>,
+    },
+}
+
 pub(crate) enum Server<'a> {
     Local(Child),
     Remote(RemoteChild<'a>),
+    /// The system `ssh` command running the server, its standard error, and the settings it
+    /// was run with.
+    System(Child, SshStderr, &'a SshSettings),
+    /// The authenticated connection, until `get_remote` takes it.
+    Tcp(Option<tokio::net::TcpStream>),
+    /// The client's end of the socket, until `get_remote` takes it, and the server's task.
+    InProcess(Option<UnixStream>, tokio::task::JoinHandle<()>),
 }
 
 impl Server<'_> {
@@ -52,10 +201,15 @@ impl Server<'_> {
     pub(crate) fn local_process_group(&self) -> Option<i32> {
         match self {
             Server::Local(server) => server.id().and_then(|id| i32::try_from(id).ok()),
-            Server::Remote(_) => None,
+            // `ssh` stays in this process group, so it can prompt on the terminal
+            Server::Remote(_) | Server::System(..) | Server::Tcp(_) | Server::InProcess(..) => None,
         }
     }
 
+    pub(crate) fn is_in_process(&self) -> bool {
+        matches!(self, Server::InProcess(..))
+    }
+
     pub(crate) async fn wait(self) -> Result<ExitStatus> {
         match self {
             Server::Local(mut server) => server
@@ -66,15 +220,103 @@ impl Server<'_> {
                 .wait()
                 .await
                 .wrap_err("failed to wait for remote duet server over SSH"),
+            Server::System(mut server, stderr, settings) => {
+                let status = server
+                    .wait()
+                    .await
+                    .wrap_err("failed to wait for remote duet server over ssh")?;
+                let stderr = stderr.finish().await;
+                match system_ssh_diagnostic(status, &stderr, settings) {
+                    Some(diagnostic) => Err(eyre!("{}", diagnostic)),
+                    None => Ok(status),
+                }
+            }
+            // the listening server outlives the connection, which closed with the client
+            Server::Tcp(_) => Ok(std::os::unix::process::ExitStatusExt::from_raw(0)),
+            Server::InProcess(socket, task) => {
+                // the server stops once the client's end closes
+                drop(socket);
+                task.await
+                    .wrap_err("failed to wait for in-process duet server")?;
+                Ok(std::os::unix::process::ExitStatusExt::from_raw(0))
+            }
         }
     }
 }
 
 pub(crate) async fn launch_server<'a>(
-    session: &'a Option<Session>,
+    session: &'a RemoteSession,
     cmd: String,
     server_log: &Path,
+    local: LocalServer,
 ) -> Result<Server<'a>> {
+    if let RemoteSession::System { server, settings } = session {
+        let mut command = TokioCommand::new("ssh");
+        command
+            .args(system_ssh_args(server, &cmd, settings))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        // Unlike a local server, `ssh` stays in the terminal's foreground process group to
+        // prompt, so it ignores the interrupt that the sync handles by stopping gracefully.
+        #[cfg(unix)]
+        unsafe {
+            command.pre_exec(|| {
+                libc::signal(libc::SIGINT, libc::SIG_IGN);
+                Ok(())
+            });
+        }
+        let mut child = command.spawn().wrap_err_with(|| {
+            format!(
+                "failed to run `ssh` to launch remote duet server `{}` on {}",
+                cmd, server
+            )
+        })?;
+        let stderr = child
+            .stderr
+            .take()
+            .ok_or_else(|| eyre!("failed to open ssh stderr"))?;
+
+        log::trace!("launched remote server through ssh");
+
+        return Ok(Server::System(child, SshStderr::forward(stderr), settings));
+    }
+    if let RemoteSession::Tcp { address, key_file } = session {
+        let key_file = key_file.as_ref().ok_or_else(|| {
+            eyre!(
+                "tcp remote {} needs a pre-shared key; set `key-file` in the profile's [tcp] section",
+                address
+            )
+        })?;
+        let key = tcp::Key::load(key_file)?;
+        let mut stream = tokio::net::TcpStream::connect(address)
+            .await
+            .wrap_err_with(|| format!("failed to connect to duet server at {}", address))?;
+        stream
+            .set_nodelay(true)
+            .wrap_err_with(|| format!("failed to connect to duet server at {}", address))?;
+        tcp::connect(&mut stream, &key)
+            .await
+            .wrap_err_with(|| format!("failed to authenticate duet server at {}", address))?;
+
+        log::trace!("connected to remote server over tcp");
+
+        return Ok(Server::Tcp(Some(stream)));
+    }
+    let session = match session {
+        RemoteSession::Mux(session) => Some(session),
+        _ => None,
+    };
+    if session.is_none() && local == LocalServer::InProcess {
+        let (client, server) =
+            UnixStream::pair().wrap_err("failed to create in-process server socket")?;
+        let task = crate::rpc::spawn_in_process_server(server)?;
+
+        log::trace!("started in-process server");
+
+        return Ok(Server::InProcess(Some(client), task));
+    }
     if let Some(session) = session {
         let server = session
             .command(&cmd)
@@ -128,7 +370,7 @@ pub(crate) fn get_remote<'a>(
     >,
 > {
     let (server_in, server_out) = match server {
-        Server::Local(server) => {
+        Server::Local(server) | Server::System(server, ..) => {
             let server_in = server
                 .stdin
                 .take()
@@ -156,6 +398,28 @@ pub(crate) fn get_remote<'a>(
                 StdoutWrapper::OpensshStdout(server_out),
             )
         }
+        Server::Tcp(stream) => {
+            let (server_out, server_in) = stream
+                .take()
+                .ok_or_else(|| eyre!("failed to open tcp server connection"))?
+                .into_split();
+            (StdinWrapper::Tcp(server_in), StdoutWrapper::Tcp(server_out))
+        }
+        Server::InProcess(socket, _) => {
+            let socket = socket
+                .take()
+                .ok_or_else(|| eyre!("failed to open in-process server socket"))?;
+            socket
+                .set_nonblocking(true)
+                .wrap_err("failed to open in-process server socket")?;
+            let (server_out, server_in) = tokio::net::UnixStream::from_std(socket)
+                .wrap_err("failed to open in-process server socket")?
+                .into_split();
+            (
+                StdinWrapper::InProcess(server_in),
+                StdoutWrapper::InProcess(server_out),
+            )
+        }
     };
 
     let server_io = ReadWriteTokio::new(
@@ -170,6 +434,133 @@ pub(crate) fn get_remote<'a>(
 
 use crate::rpc::DuetServerAsyncRPCClient;
 
+/// The arguments that make `ssh` run `cmd --server` on `server` with the profile's `[ssh]`
+/// settings. Without `-T`, no terminal is allocated on the remote side, while authentication
+/// still prompts on this one. `control-dir` only concerns multiplexed sessions.
+pub(crate) fn system_ssh_args(server: &str, cmd: &str, settings: &SshSettings) -> Vec<String> {
+    let mut args = vec!["-T".to_string()];
+    if let Some(port) = settings.port {
+        args.extend(["-p".to_string(), port.to_string()]);
+    }
+    if let Some(user) = &settings.user {
+        args.extend(["-l".to_string(), user.clone()]);
+    }
+    if let Some(identity) = &settings.identity {
+        args.extend(["-i".to_string(), identity.to_string_lossy().into_owned()]);
+    }
+    if !settings.proxy_jump.is_empty() {
+        args.extend(["-J".to_string(), settings.proxy_jump.join(",")]);
+    }
+    if let Some(timeout) = settings.connect_timeout {
+        args.extend(["-o".to_string(), format!("ConnectTimeout={}", timeout)]);
+    }
+    if let Some(keepalive) = settings.keepalive {
+        args.extend([
+            "-o".to_string(),
+            format!("ServerAliveInterval={}", keepalive),
+        ]);
+    }
+    args.extend([
+        "--".to_string(),
+        server.to_string(),
+        format!("{} --server", lexer::quote(cmd)),
+    ]);
+    args
+}
+
+/// Copies what the system `ssh` command writes to standard error, its own messages and the
+/// remote server's, to this process's, and keeps the last lines for a diagnostic.
+pub(crate) struct SshStderr {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl SshStderr {
+    fn forward(stderr: ChildStderr) -> Self {
+        let lines = Arc::new(Mutex::new(VecDeque::new()));
+        let kept = Arc::clone(&lines);
+        let task = tokio::spawn(async move {
+            let mut reader = AsyncBufReader::new(stderr).lines();
+            while let Ok(Some(line)) = reader.next_line().await {
+                eprintln!("{}", line);
+                let mut kept = kept.lock().unwrap();
+                if kept.len() == SSH_STDERR_LINES {
+                    kept.pop_front();
+                }
+                kept.push_back(line);
+            }
+        });
+        Self { lines, task }
+    }
+
+    /// The kept lines once `ssh` has closed standard error.
+    async fn finish(self) -> Vec<String> {
+        let _ = self.task.await;
+        let lines = self.lines.lock().unwrap();
+        lines.iter().cloned().collect()
+    }
+}
+
+/// Why the system `ssh` command failed, if it did, from its last lines of standard error,
+/// with the hints the multiplexed session's errors get.
+fn system_ssh_diagnostic(
+    status: ExitStatus,
+    stderr: &[String],
+    settings: &SshSettings,
+) -> Option<String> {
+    if status.code() != Some(SSH_ERROR_STATUS) {
+        return None;
+    }
+    let lines: Vec<&str> = stderr
+        .iter()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .collect();
+    let display = if lines.is_empty() {
+        format!("ssh exited with status {}", SSH_ERROR_STATUS)
+    } else {
+        format!("ssh failed: {}", lines.join("; ").trim_end_matches('.'))
+    };
+    Some(with_ssh_settings(
+        ssh_permission_hint(&display, "").unwrap_or(display),
+        settings,
+    ))
+}
+
+/// Names the profile's `[ssh]` settings after `diagnostic`, since they override `~/.ssh/config`
+/// and a wrong one looks like any other connection failure.
+pub(crate) fn with_ssh_settings(diagnostic: String, ssh: &SshSettings) -> String {
+    if ssh.is_empty() {
+        diagnostic
+    } else {
+        format!("{} (profile [ssh] settings: {})", diagnostic, ssh)
+    }
+}
+
+pub(crate) fn ssh_permission_hint(display: &str, debug: &str) -> Option<String> {
+    let combined = format!("{}\n{}", display, debug).to_lowercase();
+
+    if combined.contains("bad permissions")
+        || combined.contains("bad owner or permissions")
+        || combined.contains("permissions are too open")
+        || combined.contains("unprotected private key")
+    {
+        return Some(format!(
+            "{}. OpenSSH rejected a key or SSH config because its permissions are too open; try `chmod 700 ~/.ssh` and `chmod 600 ~/.ssh/<private-key>`, then retry.",
+            display
+        ));
+    }
+
+    if combined.contains("permission denied") && combined.contains("publickey") {
+        return Some(format!(
+            "{}. SSH public-key authentication failed; check that the correct key is loaded and that private key permissions are not too open (`chmod 600 ~/.ssh/<private-key>`).",
+            display
+        ));
+    }
+
+    None
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -202,6 +593,33 @@ mod tests {
         assert_eq!(cmd, "/bin/duet");
     }
 
+    #[test]
+    fn parses_quoted_words_and_remote_settings() {
+        let (base, server, cmd) =
+            parse_remote(&r#"ssh host '/opt/my duet/duet' "/data/My Files""#.to_string()).unwrap();
+        assert_eq!(base, "/data/My Files");
+        assert_eq!(server, Some("host".to_string()));
+        assert_eq!(cmd, "/opt/my duet/duet");
+
+        let (base, server, cmd) =
+            parse_remote(&r#"base="${HOME}/My Files" server=host"#.to_string()).unwrap();
+        assert_eq!(base, "${HOME}/My Files");
+        assert_eq!(server, Some("host".to_string()));
+        assert_eq!(cmd, "duet");
+
+        let (_, server, cmd) = parse_remote(&"command=~/bin/duet base=/x".to_string()).unwrap();
+        assert_eq!(server, None);
+        assert_eq!(cmd, "~/bin/duet");
+
+        let error = |remote: &str| parse_remote(&remote.to_string()).unwrap_err().to_string();
+        assert!(error("server=host").contains("base=<path>"));
+        assert!(error("base=/x port=22").contains("unknown remote setting port"));
+        assert!(error("base=/x base=/y").contains("duplicate remote setting base"));
+        assert!(error("base=/x /y").contains("key=value"));
+        assert!(error("duet /a /b").contains("unexpected /b"));
+        assert_eq!(error("ssh 'host"), "column 5: unterminated single quote");
+    }
+
     #[test]
     fn rejects_incomplete_ssh_remote() {
         let error = parse_remote(&"ssh example.com".to_string())
@@ -211,6 +629,29 @@ mod tests {
         assert!(error.contains("ssh <server>"));
     }
 
+    #[test]
+    fn parses_tcp_remotes() {
+        let (base, server, _) = parse_remote(&"tcp://lab1:7878/srv/data".to_string()).unwrap();
+        assert_eq!(base, "/srv/data");
+        assert_eq!(server, Some("tcp://lab1:7878".to_string()));
+
+        let (base, server, _) = parse_remote(&"tcp://[::1]:7878/~/data".to_string()).unwrap();
+        assert_eq!(base, "~/data");
+        assert_eq!(server, Some("tcp://[::1]:7878".to_string()));
+
+        let (base, server, _) =
+            parse_remote(&"server=tcp://lab1:7878 base='/My Files'".to_string()).unwrap();
+        assert_eq!(base, "/My Files");
+        assert_eq!(server, Some("tcp://lab1:7878".to_string()));
+
+        let error = |remote: &str| parse_remote(&remote.to_string()).unwrap_err().to_string();
+        assert!(error("tcp://lab1:7878").contains("tcp://<host>:<port>/<base-path>"));
+        assert!(error("tcp://lab1:7878/").contains("tcp://<host>:<port>/<base-path>"));
+        assert!(error("tcp://lab1/data").contains("invalid tcp server address lab1"));
+        assert!(error("tcp://lab1:0/data").contains("invalid tcp server address"));
+        assert!(error("tcp://lab1:7878/a /b").contains("unexpected /b"));
+    }
+
     #[cfg(unix)]
     #[tokio::test]
     async fn local_server_has_its_own_process_group_and_can_be_waited_on() {
@@ -227,13 +668,18 @@ mod tests {
             .set_permissions(std::fs::Permissions::from_mode(0o700))
             .unwrap();
         let command = command.path().to_string_lossy().to_string();
-        let mut server = launch_server(&None, command, Path::new("/dev/null"))
-            .await
-            .unwrap();
+        let mut server = launch_server(
+            &RemoteSession::None,
+            command,
+            Path::new("/dev/null"),
+            LocalServer::Subprocess,
+        )
+        .await
+        .unwrap();
 
         let child_pid = match &mut server {
             Server::Local(child) => child.id().unwrap() as libc::pid_t,
-            Server::Remote(_) => unreachable!(),
+            _ => unreachable!(),
         };
         assert_eq!(unsafe { libc::getpgid(child_pid) }, child_pid);
         assert_ne!(unsafe { libc::getpgrp() }, child_pid);
@@ -241,4 +687,107 @@ mod tests {
         let status = server.wait().await.unwrap();
         assert!(status.success());
     }
+
+    #[test]
+    fn ssh_permission_diagnostic_mentions_chmod_hint() {
+        let diagnostic = ssh_permission_hint(
+            "Bad owner or permissions on /home/user/.ssh/config",
+            "ignored",
+        )
+        .unwrap();
+
+        assert!(diagnostic.contains("chmod 700 ~/.ssh"));
+        assert!(diagnostic.contains("chmod 600 ~/.ssh/<private-key>"));
+    }
+
+    #[test]
+    fn ssh_diagnostic_names_profile_ssh_settings() {
+        let settings = SshSettings {
+            port: Some(2222),
+            proxy_jump: vec!["bastion".to_string()],
+            ..SshSettings::default()
+        };
+
+        assert_eq!(
+            with_ssh_settings("Connection timed out".to_string(), &settings),
+            "Connection timed out (profile [ssh] settings: port=2222, proxy-jump=bastion)"
+        );
+        assert_eq!(
+            with_ssh_settings("Connection timed out".to_string(), &SshSettings::default()),
+            "Connection timed out"
+        );
+    }
+
+    #[test]
+    fn system_ssh_args_carry_profile_settings_and_quote_the_command() {
+        let settings = SshSettings {
+            port: Some(2222),
+            user: Some("backup".to_string()),
+            identity: Some("/keys/id backup".into()),
+            proxy_jump: vec!["bastion".to_string(), "gate".to_string()],
+            control_dir: Some("/run/duet".into()),
+            connect_timeout: Some(30),
+            keepalive: Some(15),
+            transport: Some(crate::profile::SshTransport::System),
+        };
+
+        assert_eq!(
+            system_ssh_args("host", "/opt/my duet/duet", &settings),
+            [
+                "-T",
+                "-p",
+                "2222",
+                "-l",
+                "backup",
+                "-i",
+                "/keys/id backup",
+                "-J",
+                "bastion,gate",
+                "-o",
+                "ConnectTimeout=30",
+                "-o",
+                "ServerAliveInterval=15",
+                "--",
+                "host",
+                "'/opt/my duet/duet' --server",
+            ]
+        );
+        assert_eq!(
+            system_ssh_args("host", "duet", &SshSettings::default()),
+            ["-T", "--", "host", "duet --server"]
+        );
+    }
+
+    #[test]
+    fn system_ssh_failures_get_the_multiplexed_session_hints() {
+        use std::os::unix::process::ExitStatusExt;
+        let settings = SshSettings {
+            port: Some(2222),
+            ..SshSettings::default()
+        };
+        let stderr = vec![
+            "Warning: Permanently added 'host' (ED25519) to the list of known hosts.".to_string(),
+            "user@host: Permission denied (publickey).".to_string(),
+        ];
+
+        let diagnostic =
+            system_ssh_diagnostic(ExitStatus::from_raw(255 << 8), &stderr, &settings).unwrap();
+        assert!(
+            diagnostic.starts_with("ssh failed: Warning"),
+            "{}",
+            diagnostic
+        );
+        assert!(diagnostic.contains("public-key authentication failed"));
+        assert!(diagnostic.ends_with("(profile [ssh] settings: port=2222)"));
+        assert_eq!(
+            system_ssh_diagnostic(ExitStatus::from_raw(255 << 8), &[], &SshSettings::default())
+                .unwrap(),
+            "ssh exited with status 255"
+        );
+        // the remote server's own failures are reported as the server's
+        assert_eq!(
+            system_ssh_diagnostic(ExitStatus::from_raw(1 << 8), &stderr, &settings),
+            None
+        );
+    }
 }
@@ -1,8 +1,11 @@
-use color_eyre::eyre::Result;
+use std::path::PathBuf;
+
+use color_eyre::eyre::{Result, WrapErr};
 use colored::*;
 
 use crate::actions::{self, num_identical, num_unresolved_conflicts, Action, Actions};
-use crate::scan::Change;
+use crate::profile::{self, ResolvePolicy, ResolveRule};
+use crate::scan::{Change, DirEntryWithMeta};
 
 enum Resolution {
     Local,
@@ -53,6 +56,103 @@ fn resolve_action(action: &Action, resolution: Resolution) -> Action {
     }
 }
 
+/// The new local and remote versions of a conflict between two regular files.
+fn conflicting_files<'a>(
+    lc: &'a Change,
+    rc: &'a Change,
+) -> Option<(&'a DirEntryWithMeta, &'a DirEntryWithMeta)> {
+    match (lc, rc) {
+        (Change::Added(ln), Change::Added(rn))
+        | (Change::Modified(_, ln), Change::Modified(_, rn))
+            if ln.is_file() && rn.is_file() =>
+        {
+            Some((ln, rn))
+        }
+        _ => None,
+    }
+}
+
+fn newer(ln: &DirEntryWithMeta, rn: &DirEntryWithMeta) -> Resolution {
+    let mtime = |e: &DirEntryWithMeta| (e.mtime(), e.mtime_nsec().unwrap_or(0));
+    if mtime(rn) > mtime(ln) {
+        Resolution::Local
+    } else {
+        Resolution::Remote
+    }
+}
+
+/// Resolves conflicts between two versions of a regular file in favor of the more recently
+/// modified one (the local one on a tie), for unattended runs that keep the other version as a
+/// conflict sidecar. Other conflicts stay unresolved.
+pub fn resolve_newer(actions: &mut Actions) {
+    for action in actions.iter_mut() {
+        let Action::Conflict(lc, rc) = action else {
+            continue;
+        };
+        let Some((ln, rn)) = conflicting_files(lc, rc) else {
+            continue;
+        };
+        let resolution = newer(ln, rn);
+        *action = resolve_action(action, resolution);
+    }
+}
+
+/// Resolves a conflict in favour of the local version, as choosing it at the prompt does.
+pub fn resolve_local(action: &Action) -> Action {
+    resolve_action(action, Resolution::Remote)
+}
+
+/// Settles conflicts on paths matching the profile's `[resolve]` rules, before any prompt. The
+/// first rule whose pattern matches a conflict decides it; `newer`, `larger`, and `keep-both` only
+/// decide conflicts between two regular files and leave the rest unresolved. Returns the path of
+/// every resolved conflict with the rule that resolved it.
+pub fn resolve_by_rules<'a>(
+    actions: &mut Actions,
+    rules: &'a [ResolveRule],
+) -> Result<Vec<(PathBuf, &'a ResolveRule)>> {
+    use fnmatch_regex::glob_to_regex;
+    let mut patterns = Vec::new();
+    for rule in rules {
+        let pattern = &rule.pattern;
+        patterns.push(
+            glob_to_regex(pattern)
+                .wrap_err_with(|| format!("invalid resolve pattern {pattern}"))?,
+        );
+    }
+
+    let mut resolved = Vec::new();
+    for action in actions.iter_mut() {
+        let Action::Conflict(lc, rc) = action else {
+            continue;
+        };
+        let path = lc.path();
+        let Some(index) = rules
+            .iter()
+            .zip(&patterns)
+            .position(|(rule, regex)| profile::glob_matches(&rule.pattern, regex, path))
+        else {
+            continue;
+        };
+        let rule = &rules[index];
+        let resolution = match rule.policy {
+            ResolvePolicy::Local => Resolution::Remote,
+            ResolvePolicy::Remote => Resolution::Local,
+            ResolvePolicy::Newer | ResolvePolicy::KeepBoth => match conflicting_files(lc, rc) {
+                Some((ln, rn)) => newer(ln, rn),
+                None => continue,
+            },
+            ResolvePolicy::Larger => match conflicting_files(lc, rc) {
+                Some((ln, rn)) if rn.size() > ln.size() => Resolution::Local,
+                Some(_) => Resolution::Remote,
+                None => continue,
+            },
+        };
+        resolved.push((path.clone(), rule));
+        *action = resolve_action(action, resolution);
+    }
+    Ok(resolved)
+}
+
 pub fn show_actions(actions: &Actions, verbose: bool) {
     let num_identical = num_identical(actions.iter());
     for a in actions {
@@ -609,6 +709,96 @@ mod tests {
         DirEntryWithMeta::test_file(PathBuf::from(path), checksum)
     }
 
+    #[test]
+    fn newer_file_wins_and_other_conflicts_stay_unresolved() {
+        let mut newer = entry("a", 2);
+        newer.set_mtime_nsec(Some(5));
+        let mut actions = vec![
+            Action::Conflict(Change::Added(entry("a", 1)), Change::Added(newer)),
+            Action::Conflict(
+                Change::Modified(entry("b", 0), entry("b", 1)),
+                Change::Modified(entry("b", 0), entry("b", 2)),
+            ),
+            Action::Conflict(
+                Change::Modified(entry("c", 0), entry("c", 1)),
+                Change::Removed(entry("c", 0)),
+            ),
+        ];
+
+        resolve_newer(&mut actions);
+
+        assert!(matches!(
+            &actions[0],
+            Action::ResolvedLocal(_, Change::Modified(_, new)) if new.checksum() == 2
+        ));
+        assert!(matches!(
+            &actions[1],
+            Action::ResolvedRemote(_, Change::Modified(_, new)) if new.checksum() == 1
+        ));
+        assert!(actions[2].is_unresolved_conflict());
+    }
+
+    #[test]
+    fn first_matching_resolve_rule_decides_each_conflict() {
+        let rule = |pattern: &str, policy| ResolveRule {
+            pattern: pattern.to_string(),
+            policy,
+        };
+        let rules = vec![
+            rule("docs/*.md", ResolvePolicy::Remote),
+            rule("*.md", ResolvePolicy::Local),
+            rule("*.log", ResolvePolicy::Larger),
+            rule("*.txt", ResolvePolicy::Newer),
+        ];
+        let conflict = |path: &str, local: DirEntryWithMeta, remote: DirEntryWithMeta| {
+            Action::Conflict(
+                Change::Modified(entry(path, 0), local),
+                Change::Modified(entry(path, 0), remote),
+            )
+        };
+        let sized = |path: &str, size, checksum| {
+            DirEntryWithMeta::test_file_with_size(PathBuf::from(path), size, checksum)
+        };
+        let mut actions = vec![
+            conflict("docs/a.md", entry("docs/a.md", 1), entry("docs/a.md", 2)),
+            conflict("notes/b.md", entry("notes/b.md", 1), entry("notes/b.md", 2)),
+            conflict("run.log", sized("run.log", 1, 1), sized("run.log", 5, 2)),
+            Action::Conflict(
+                Change::Modified(entry("c.txt", 0), entry("c.txt", 1)),
+                Change::Removed(entry("c.txt", 0)),
+            ),
+            conflict("d.rs", entry("d.rs", 1), entry("d.rs", 2)),
+        ];
+
+        let resolved = resolve_by_rules(&mut actions, &rules).unwrap();
+
+        assert_eq!(
+            resolved
+                .iter()
+                .map(|(path, rule)| (path.to_str().unwrap(), rule.pattern.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("docs/a.md", "docs/*.md"),
+                ("notes/b.md", "*.md"),
+                ("run.log", "*.log"),
+            ]
+        );
+        assert!(matches!(
+            &actions[0],
+            Action::ResolvedLocal(_, Change::Modified(_, new)) if new.checksum() == 2
+        ));
+        assert!(matches!(
+            &actions[1],
+            Action::ResolvedRemote(_, Change::Modified(_, new)) if new.checksum() == 1
+        ));
+        assert!(matches!(
+            &actions[2],
+            Action::ResolvedLocal(_, Change::Modified(_, new)) if new.checksum() == 2
+        ));
+        assert!(actions[3].is_unresolved_conflict());
+        assert!(actions[4].is_unresolved_conflict());
+    }
+
     #[test]
     fn local_resolution_turns_added_added_conflict_into_local_update() {
         let local = Change::Added(entry("file.txt", 1));
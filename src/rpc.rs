@@ -9,16 +9,17 @@ use essrpc::transports::{BincodeTransport, ReadWrite};
 use essrpc::{RPCError, RPCErrorKind, RPCServer};
 use serde::{Deserialize, Serialize};
 
-use crate::actions::{self, Actions, LegacyActions};
-use crate::performance::{duration_ms, RemoteStreamProfile};
+use crate::actions::{self, Actions, ActionsV2, LegacyActions};
+use crate::performance::{duration_ms, LegacyRemoteStreamProfile, RemoteStreamProfile};
 use crate::profile;
 use crate::scan::location::Locations;
-use crate::state::{ChangesV2, Entries, LegacyChanges, SnapshotFormat};
+use crate::state::{ChangesV2, ChangesV3, Entries, LegacyChanges, SnapshotFormat};
 use crate::sync::{
     self, ApplyStreamId, ChangeDetails, DetailFrame, DetailProducer, DetailStreamId,
     SignatureWithPath,
 };
 use crate::sync_error::{self, StructuredSyncError};
+use crate::trash;
 
 pub(crate) const SERVER_LOG_ENV: &str = "DUET_SERVER_LOG";
 pub(crate) const PROTOCOL_VERSION: u32 = 2;
@@ -49,6 +50,20 @@ pub(crate) const CAPABILITY_SCAN_EXCLUDES: &str = "scan-excludes-v1";
 pub(crate) const CAPABILITY_STAGED_COMMIT_PROFILE: &str = "staged-commit-profile-v1";
 pub(crate) const CAPABILITY_STAGED_VALIDATION_RECEIPT: &str = "staged-validation-receipt-v1";
 pub(crate) const CAPABILITY_STAGED_MARKER_PROFILE: &str = "staged-marker-profile-v1";
+pub(crate) const CAPABILITY_MOVES: &str = "moves-v1";
+pub(crate) const CAPABILITY_HARD_LINKS: &str = "hard-links-v1";
+pub(crate) const CAPABILITY_XATTRS: &str = "xattrs-v1";
+pub(crate) const CAPABILITY_OWNERSHIP: &str = "ownership-v1";
+pub(crate) const CAPABILITY_SPECIAL_FILES: &str = "special-files-v1";
+pub(crate) const CAPABILITY_SPARSE_FILES: &str = "sparse-files-v1";
+pub(crate) const CAPABILITY_MTIME_NSEC: &str = "mtime-nsec-v1";
+pub(crate) const CAPABILITY_CONFLICT_SIDECARS: &str = "conflict-sidecars-v1";
+pub(crate) const CAPABILITY_TRASH: &str = "trash-v1";
+pub(crate) const CAPABILITY_PATH_IGNORE: &str = "path-ignore-v1";
+pub(crate) const CAPABILITY_IGNORE_FILES: &str = "ignore-files-v1";
+pub(crate) const CAPABILITY_ENTRY_FILTERS: &str = "entry-filters-v1";
+pub(crate) const CAPABILITY_STATE_SUMMARY: &str = "state-summary-v1";
+pub(crate) const CAPABILITY_COMPRESSED_DETAILS: &str = "compressed-details-v1";
 const CLIENT_CAPABILITIES: &[&str] = &[
     CAPABILITY_PROFILE_FILE_STATE_DIR,
     CAPABILITY_STREAMED_DETAILS,
@@ -77,6 +92,20 @@ const CLIENT_CAPABILITIES: &[&str] = &[
     CAPABILITY_STAGED_COMMIT_PROFILE,
     CAPABILITY_STAGED_VALIDATION_RECEIPT,
     CAPABILITY_STAGED_MARKER_PROFILE,
+    CAPABILITY_MOVES,
+    CAPABILITY_HARD_LINKS,
+    CAPABILITY_XATTRS,
+    CAPABILITY_OWNERSHIP,
+    CAPABILITY_SPECIAL_FILES,
+    CAPABILITY_SPARSE_FILES,
+    CAPABILITY_MTIME_NSEC,
+    CAPABILITY_CONFLICT_SIDECARS,
+    CAPABILITY_TRASH,
+    CAPABILITY_PATH_IGNORE,
+    CAPABILITY_IGNORE_FILES,
+    CAPABILITY_ENTRY_FILTERS,
+    CAPABILITY_STATE_SUMMARY,
+    CAPABILITY_COMPRESSED_DETAILS,
 ];
 
 pub(crate) fn client_capabilities() -> &'static [&'static str] {
@@ -150,7 +179,7 @@ pub trait DuetServer {
         &mut self,
         request: sync::SyncTuningRequest,
     ) -> Result<sync::SyncTuning, RPCError>;
-    fn stream_performance(&self) -> Result<RemoteStreamProfile, RPCError>;
+    fn stream_performance(&self) -> Result<LegacyRemoteStreamProfile, RPCError>;
     fn apply_file_byte_chunk(
         &mut self,
         stream_id: ApplyStreamId,
@@ -187,20 +216,20 @@ pub trait DuetServer {
         ignore: profile::Ignore,
         remote_id: String,
     ) -> Result<ChangesV2, RPCError>;
-    fn set_actions_v2(&mut self, actions: Actions) -> Result<(), RPCError>;
+    fn set_actions_v2(&mut self, actions: ActionsV2) -> Result<(), RPCError>;
     fn preflight_apply_report_v2(
         &self,
-        actions: Actions,
+        actions: ActionsV2,
         options: sync::ApplyOptions,
     ) -> Result<sync::ApplyPreflightReport, RPCError>;
     fn preflight_apply_v2(
         &self,
-        actions: Actions,
+        actions: ActionsV2,
         options: sync::ApplyOptions,
     ) -> Result<(), RPCError>;
     fn removal_blocker_report_v2(
         &self,
-        actions: Actions,
+        actions: ActionsV2,
         options: sync::ApplyOptions,
     ) -> Result<sync::ApplyPreflightReport, RPCError>;
     fn save_state_v2(&self) -> Result<(), RPCError>;
@@ -256,6 +285,52 @@ pub trait DuetServer {
         &mut self,
         attempt_id: String,
     ) -> Result<sync::StagedMarkerLifecycleProfile, RPCError>;
+    fn apply_moves(&mut self, actions: Actions) -> Result<Entries, RPCError>;
+    fn apply_hard_links(&mut self, links: Vec<sync::HardLink>) -> Result<(), RPCError>;
+    fn set_xattr_namespaces(
+        &mut self,
+        namespaces: profile::XattrNamespaces,
+    ) -> Result<(), RPCError>;
+    fn changes_scope_v3(
+        &mut self,
+        scope: crate::scan::ScanScope,
+        locations: Locations,
+        ignore: profile::Ignore,
+        remote_id: String,
+        strong: bool,
+    ) -> Result<ChangesV3, RPCError>;
+    fn set_actions_v3(&mut self, actions: Actions) -> Result<(), RPCError>;
+    fn set_ownership_mode(
+        &mut self,
+        mode: Option<crate::scan::OwnershipMode>,
+    ) -> Result<(), RPCError>;
+    fn ownership_report(&self, actions: Actions) -> Result<Vec<sync::OwnershipSkip>, RPCError>;
+    fn set_special_files(&mut self, special: profile::SpecialFiles) -> Result<(), RPCError>;
+    fn set_detail_holes(&mut self, holes: bool) -> Result<(), RPCError>;
+    fn keep_conflict_sidecars(&mut self, entries: Entries) -> Result<Entries, RPCError>;
+    fn keep_in_trash(
+        &mut self,
+        trash_id: String,
+        entries: Entries,
+        retention: trash::TrashRetention,
+    ) -> Result<Option<String>, RPCError>;
+    fn list_trash(&self, trash_id: String) -> Result<Vec<trash::TrashItem>, RPCError>;
+    fn restore_from_trash(&mut self, trash_id: String, item: PathBuf) -> Result<PathBuf, RPCError>;
+    fn purge_trash(
+        &mut self,
+        trash_id: String,
+        retention: trash::TrashRetention,
+    ) -> Result<trash::TrashPurge, RPCError>;
+    fn set_ignore_files(&mut self, names: Vec<String>) -> Result<(), RPCError>;
+    fn set_entry_filters(&mut self, filters: profile::EntryFilters) -> Result<(), RPCError>;
+    fn filtered_entries(&self) -> Result<Vec<crate::state::FilteredEntry>, RPCError>;
+    fn state_summary(&self, remote_id: String) -> Result<crate::state::StateSummary, RPCError>;
+    /// Deflates the literal bytes of later detail streams at `level`, or at the highest level
+    /// this side supports if that is lower, and returns the level agreed on.
+    fn set_detail_compression(&mut self, level: u8) -> Result<u8, RPCError>;
+    fn stream_performance_v2(&self) -> Result<RemoteStreamProfile, RPCError>;
+    /// Records that the sync of the snapshot whose changes were requested finished successfully.
+    fn mark_synced(&self) -> Result<(), RPCError>;
 }
 
 enum ApplyStream {
@@ -308,6 +383,11 @@ struct DuetServerImpl {
     actions: Actions,
     scan_policy: Option<sync::ScanPolicy>,
     prune: profile::Prune,
+    capture: profile::MetadataCapture,
+    detail_holes: bool,
+    detail_compression: u8,
+    /// Set only for an in-process server, whose client shares its filesystem.
+    file_copies: bool,
     apply_options: sync::ApplyOptions,
     apply_attempt_id: Option<String>,
     detail_streams: HashMap<DetailStreamId, DetailProducer>,
@@ -319,6 +399,8 @@ struct DuetServerImpl {
     stream_performance: RemoteStreamProfile,
     current_scan: Entries,
     scope: crate::scan::ScanScope,
+    /// Entries the last scan kept out by the profile's `[filter]` section.
+    filtered: Vec<crate::state::FilteredEntry>,
 }
 
 impl DuetServerImpl {
@@ -333,6 +415,10 @@ impl DuetServerImpl {
             actions: Vec::new(),
             scan_policy: None,
             prune: Vec::new(),
+            capture: profile::MetadataCapture::default(),
+            detail_holes: false,
+            detail_compression: 0,
+            file_copies: false,
             apply_options: sync::ApplyOptions::default(),
             apply_attempt_id: None,
             detail_streams: HashMap::new(),
@@ -344,6 +430,7 @@ impl DuetServerImpl {
             stream_performance: RemoteStreamProfile::default(),
             current_scan: Vec::new(),
             scope: crate::scan::ScanScope::default(),
+            filtered: Vec::new(),
         })
     }
 
@@ -369,6 +456,7 @@ impl DuetServerImpl {
         self.scan_policy = None;
         self.current_scan.clear();
         self.scope = crate::scan::ScanScope::default();
+        self.filtered.clear();
         self.apply_options = sync::ApplyOptions::default();
         self.staging_policy = None;
         self.reset_actions_context();
@@ -400,6 +488,18 @@ impl DuetServerImpl {
         ))
     }
 
+    // trash commands run outside a sync, so they need only the base
+    fn trash_base(&self, operation: &str) -> Result<(), RPCError> {
+        if self.base.as_os_str().is_empty() {
+            return Err(rpc_error(
+                operation,
+                None,
+                "base must be set before using the trash",
+            ));
+        }
+        Ok(())
+    }
+
     fn remote_state_for_id(&self, remote_id: &str) -> Result<PathBuf, RPCError> {
         profile::validate_remote_state_id(remote_id)
             .map_err(|e| RPCError::new(RPCErrorKind::Other, e.to_string()))?;
@@ -424,7 +524,7 @@ impl DuetServerImpl {
         ignore: profile::Ignore,
         remote_id: String,
         strong: bool,
-    ) -> Result<ChangesV2, RPCError> {
+    ) -> Result<ChangesV3, RPCError> {
         self.reset_changes_context();
         sync::validate_scan_path(&scope.restrict)
             .map_err(|e| rpc_report_error("validate scan path", Some(&scope.restrict), e))?;
@@ -446,6 +546,7 @@ impl DuetServerImpl {
             &scope,
             &locations,
             &ignore,
+            &self.capture,
             Some(&remote_state),
             strong,
         ));
@@ -461,11 +562,13 @@ impl DuetServerImpl {
                 );
                 self.current_scan = context.current.clone();
                 self.scope = scope.clone();
+                self.filtered = context.filtered;
                 self.changes_ready = true;
-                Ok(ChangesV2 {
+                Ok(ChangesV3 {
                     changes: context.changes,
                     current: context.current,
                     migration_needed: context.migration_needed,
+                    skipped: context.skipped,
                 })
             }
             Err(e) => Err(rpc_report_error(
@@ -880,7 +983,10 @@ impl DuetServer for DuetServerImpl {
             self.actions.clone(),
             signatures,
             max_chunk_bytes,
-        );
+        )
+        .with_holes(self.detail_holes)
+        .with_compression(self.detail_compression)
+        .with_file_copies(self.file_copies);
         self.detail_streams.insert(id, producer);
         Ok(id)
     }
@@ -935,7 +1041,8 @@ impl DuetServer for DuetServerImpl {
             Some(remote_state.clone()),
             self.scan_policy.clone(),
             self.apply_options,
-        );
+        )
+        .with_file_copies(self.file_copies);
         self.apply_streams.insert(id, ApplyStream::Legacy(applier));
         Ok(id)
     }
@@ -1085,8 +1192,8 @@ impl DuetServer for DuetServerImpl {
         Ok(tuning)
     }
 
-    fn stream_performance(&self) -> Result<RemoteStreamProfile, RPCError> {
-        Ok(self.stream_performance.clone())
+    fn stream_performance(&self) -> Result<LegacyRemoteStreamProfile, RPCError> {
+        Ok(self.stream_performance.clone().into())
     }
 
     fn apply_file_byte_chunk(
@@ -1213,19 +1320,19 @@ impl DuetServer for DuetServerImpl {
             remote_id,
             true,
         )
+        .map(Into::into)
     }
 
-    fn set_actions_v2(&mut self, actions: Actions) -> Result<(), RPCError> {
-        sync::validate_strong_actions(&actions)
-            .map_err(|e| rpc_report_error("validate strong actions", Some(&self.base), e))?;
-        self.set_actions_internal(actions)
+    fn set_actions_v2(&mut self, actions: ActionsV2) -> Result<(), RPCError> {
+        self.set_actions_v3(actions::from_v2(actions))
     }
 
     fn preflight_apply_report_v2(
         &self,
-        actions: Actions,
+        actions: ActionsV2,
         options: sync::ApplyOptions,
     ) -> Result<sync::ApplyPreflightReport, RPCError> {
+        let actions = actions::from_v2(actions);
         sync::validate_strong_actions(&actions)
             .map_err(|e| rpc_report_error("validate strong actions", Some(&self.base), e))?;
         let remote_state = self.initialized_remote_state("preflight report")?;
@@ -1239,9 +1346,10 @@ impl DuetServer for DuetServerImpl {
 
     fn preflight_apply_v2(
         &self,
-        actions: Actions,
+        actions: ActionsV2,
         options: sync::ApplyOptions,
     ) -> Result<(), RPCError> {
+        let actions = actions::from_v2(actions);
         sync::validate_strong_actions(&actions)
             .map_err(|e| rpc_report_error("validate strong actions", Some(&self.base), e))?;
         let remote_state = self.initialized_remote_state("preflight apply")?;
@@ -1255,9 +1363,10 @@ impl DuetServer for DuetServerImpl {
 
     fn removal_blocker_report_v2(
         &self,
-        actions: Actions,
+        actions: ActionsV2,
         options: sync::ApplyOptions,
     ) -> Result<sync::ApplyPreflightReport, RPCError> {
+        let actions = actions::from_v2(actions);
         sync::validate_strong_actions(&actions)
             .map_err(|e| rpc_report_error("validate strong actions", Some(&self.base), e))?;
         self.initialized_remote_state("removal blocker report")?;
@@ -1347,7 +1456,8 @@ impl DuetServer for DuetServerImpl {
                 self.scan_policy.clone(),
                 self.apply_options,
             )
-        };
+        }
+        .with_file_copies(self.file_copies);
         self.apply_streams.insert(id, ApplyStream::Staged(applier));
         self.staged_apply = Some(StagedApplyState::Preparing {
             attempt_id,
@@ -1565,6 +1675,7 @@ impl DuetServer for DuetServerImpl {
         strong: bool,
     ) -> Result<ChangesV2, RPCError> {
         self.scan_changes(scope, locations, ignore, remote_id, strong)
+            .map(Into::into)
     }
 
     fn finish_staged_prepare_profiled(
@@ -1595,9 +1706,164 @@ impl DuetServer for DuetServerImpl {
     ) -> Result<sync::StagedMarkerLifecycleProfile, RPCError> {
         self.complete_staged_apply_inner(attempt_id)
     }
+
+    fn apply_moves(&mut self, actions: Actions) -> Result<Entries, RPCError> {
+        self.initialized_remote_state("apply moves")?;
+        sync::apply_moves(&self.base, &actions, &mut self.all_old)
+            .map_err(|e| rpc_report_error("apply moves", Some(&self.base), e))
+    }
+
+    fn apply_hard_links(&mut self, links: Vec<sync::HardLink>) -> Result<(), RPCError> {
+        self.initialized_remote_state("apply hard links")?;
+        sync::apply_hard_links(&self.base, &links, &mut self.all_old)
+            .map_err(|e| rpc_report_error("apply hard links", Some(&self.base), e))
+    }
+
+    fn set_xattr_namespaces(
+        &mut self,
+        namespaces: profile::XattrNamespaces,
+    ) -> Result<(), RPCError> {
+        self.capture.xattrs = namespaces;
+        Ok(())
+    }
+
+    fn changes_scope_v3(
+        &mut self,
+        scope: crate::scan::ScanScope,
+        locations: Locations,
+        ignore: profile::Ignore,
+        remote_id: String,
+        strong: bool,
+    ) -> Result<ChangesV3, RPCError> {
+        self.scan_changes(scope, locations, ignore, remote_id, strong)
+    }
+
+    fn set_actions_v3(&mut self, actions: Actions) -> Result<(), RPCError> {
+        sync::validate_strong_actions(&actions)
+            .map_err(|e| rpc_report_error("validate strong actions", Some(&self.base), e))?;
+        self.set_actions_internal(actions)
+    }
+
+    fn set_ownership_mode(
+        &mut self,
+        mode: Option<crate::scan::OwnershipMode>,
+    ) -> Result<(), RPCError> {
+        self.capture.ownership = mode;
+        Ok(())
+    }
+
+    fn ownership_report(&self, actions: Actions) -> Result<Vec<sync::OwnershipSkip>, RPCError> {
+        self.initialized_remote_state("ownership report")?;
+        sync::validate_actions(&actions)
+            .map_err(|e| rpc_report_error("validate actions", Some(&self.base), e))?;
+        sync::ownership_report(&self.base, &actions)
+            .map_err(|e| rpc_report_error("ownership report", Some(&self.base), e))
+    }
+
+    fn set_special_files(&mut self, special: profile::SpecialFiles) -> Result<(), RPCError> {
+        self.capture.special = special;
+        Ok(())
+    }
+
+    fn set_detail_holes(&mut self, holes: bool) -> Result<(), RPCError> {
+        self.detail_holes = holes;
+        Ok(())
+    }
+
+    fn keep_conflict_sidecars(&mut self, entries: Entries) -> Result<Entries, RPCError> {
+        self.initialized_remote_state("keep conflict sidecars")?;
+        sync::keep_conflict_sidecars(&self.base, &entries)
+            .map_err(|e| rpc_report_error("keep conflict sidecars", Some(&self.base), e))
+    }
+
+    fn keep_in_trash(
+        &mut self,
+        trash_id: String,
+        entries: Entries,
+        retention: trash::TrashRetention,
+    ) -> Result<Option<String>, RPCError> {
+        self.initialized_remote_state("keep in trash")?;
+        trash::keep(&self.base, &trash_id, &entries, retention)
+            .map_err(|e| rpc_report_error("keep in trash", Some(&self.base), e))
+    }
+
+    fn list_trash(&self, trash_id: String) -> Result<Vec<trash::TrashItem>, RPCError> {
+        self.trash_base("list trash")?;
+        trash::list(&self.base, &trash_id)
+            .map_err(|e| rpc_report_error("list trash", Some(&self.base), e))
+    }
+
+    fn restore_from_trash(&mut self, trash_id: String, item: PathBuf) -> Result<PathBuf, RPCError> {
+        self.trash_base("restore from trash")?;
+        trash::restore(&self.base, &trash_id, &item)
+            .map_err(|e| rpc_report_error("restore from trash", Some(&self.base), e))
+    }
+
+    fn purge_trash(
+        &mut self,
+        trash_id: String,
+        retention: trash::TrashRetention,
+    ) -> Result<trash::TrashPurge, RPCError> {
+        self.trash_base("purge trash")?;
+        trash::purge(&self.base, &trash_id, retention)
+            .map_err(|e| rpc_report_error("purge trash", Some(&self.base), e))
+    }
+
+    fn set_ignore_files(&mut self, names: Vec<String>) -> Result<(), RPCError> {
+        if let Some(name) = names
+            .iter()
+            .find(|name| name.contains('/') || *name == "." || *name == "..")
+        {
+            return Err(rpc_error(
+                "set ignore files",
+                None,
+                format!("invalid ignore file name: {name}"),
+            ));
+        }
+        self.capture.ignore_files = names;
+        Ok(())
+    }
+
+    fn set_entry_filters(&mut self, filters: profile::EntryFilters) -> Result<(), RPCError> {
+        self.capture.filters = filters;
+        Ok(())
+    }
+
+    fn filtered_entries(&self) -> Result<Vec<crate::state::FilteredEntry>, RPCError> {
+        if !self.changes_ready {
+            return Err(rpc_error(
+                "filtered entries",
+                None,
+                "remote changes have not been scanned",
+            ));
+        }
+        Ok(self.filtered.clone())
+    }
+
+    fn state_summary(&self, remote_id: String) -> Result<crate::state::StateSummary, RPCError> {
+        let remote_state = self.remote_state_for_id(&remote_id)?;
+        crate::state::summarize(&remote_state)
+            .map_err(|e| rpc_report_error("summarize state", Some(&remote_state), e))
+    }
+
+    fn set_detail_compression(&mut self, level: u8) -> Result<u8, RPCError> {
+        self.detail_compression = level.min(sync::MAX_COMPRESSION_LEVEL);
+        Ok(self.detail_compression)
+    }
+
+    fn stream_performance_v2(&self) -> Result<RemoteStreamProfile, RPCError> {
+        Ok(self.stream_performance.clone())
+    }
+
+    fn mark_synced(&self) -> Result<(), RPCError> {
+        let remote_state = self.initialized_remote_state("mark synced")?;
+        crate::state::mark_synced(&remote_state)
+            .map_err(|e| rpc_report_error("mark synced", Some(&remote_state), e))
+    }
 }
 
-pub async fn server() -> Result<()> {
+/// Logs to `DUET_SERVER_LOG`, or the default remote log, as a server does.
+fn open_server_log() -> Result<()> {
     let log_path = if let Some(path) = std::env::var_os(SERVER_LOG_ENV) {
         PathBuf::from(path)
     } else {
@@ -1633,7 +1899,11 @@ pub async fn server() -> Result<()> {
             "{}",
             sync_error::render_error("setup", "open remote server log", Some(log_path.clone()), e,)
         )
-    })?;
+    })
+}
+
+pub async fn server() -> Result<()> {
+    open_server_log()?;
 
     let stdin = io::stdin();
     let stdout = io::stdout();
@@ -1668,6 +1938,123 @@ pub async fn server() -> Result<()> {
     Ok(())
 }
 
+/// Serves the `tcp://` clients that connect to `listen.address` and prove they hold the key in
+/// `listen.key_file`, each on a blocking task with a server of its own, until stopped.
+pub async fn listen(listen: crate::cli::Listen) -> Result<()> {
+    open_server_log()?;
+    let key = crate::tcp::Key::load(&listen.key_file).map_err(|e| {
+        eyre!(
+            "{}",
+            sync_error::render_report("setup", "load server key", Some(listen.key_file.clone()), e)
+        )
+    })?;
+    let key = std::sync::Arc::new(key);
+    let listener = tokio::net::TcpListener::bind(&listen.address)
+        .await
+        .map_err(|e| {
+            eyre!(
+                "{}",
+                sync_error::render_error("setup", format!("listen on {}", listen.address), None, e)
+            )
+        })?;
+    let address = listener.local_addr()?;
+    log::info!("listening on {}", address);
+    eprintln!("duet server listening on {}", address);
+
+    let handshakes = std::sync::Arc::new(tokio::sync::Semaphore::new(MAX_PENDING_HANDSHAKES));
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                // such as running out of descriptors, which passes as connections close
+                log::error!("failed to accept a connection: {}", e);
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let Ok(permit) = std::sync::Arc::clone(&handshakes).try_acquire_owned() else {
+            log::warn!(
+                "dropping connection from {}: {} handshakes are already pending",
+                peer,
+                MAX_PENDING_HANDSHAKES
+            );
+            continue;
+        };
+        let key = std::sync::Arc::clone(&key);
+        tokio::spawn(async move {
+            let stream = match authenticate(stream, &key).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log::error!("connection from {} failed: {:?}", peer, e);
+                    return;
+                }
+            };
+            drop(permit);
+            log::info!("authenticated connection from {}", peer);
+            match tokio::task::spawn_blocking(move || serve_tcp(stream)).await {
+                Ok(Ok(())) => log::info!("connection from {} closed", peer),
+                Ok(Err(e)) => log::error!("connection from {} failed: {:?}", peer, e),
+                Err(e) => log::error!("connection from {} failed: {}", peer, e),
+            }
+        });
+    }
+}
+
+/// How long a client that connected has to complete the key handshake.
+const HANDSHAKE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+/// Connections that may be in the key handshake at once; further ones are dropped, so that
+/// clients without the key cannot tie up the server.
+const MAX_PENDING_HANDSHAKES: usize = 64;
+
+async fn authenticate(
+    mut stream: tokio::net::TcpStream,
+    key: &crate::tcp::Key,
+) -> Result<tokio::net::TcpStream> {
+    stream.set_nodelay(true)?;
+    tokio::time::timeout(HANDSHAKE_TIMEOUT, crate::tcp::accept(&mut stream, key))
+        .await
+        .map_err(|_| eyre!("the client did not finish the key handshake in time"))??;
+    Ok(stream)
+}
+
+fn serve_tcp(stream: tokio::net::TcpStream) -> Result<()> {
+    let stream = stream.into_std()?;
+    stream.set_nonblocking(false)?;
+
+    let server_impl = DuetServerImpl::new()?;
+    let reader = stream.try_clone()?;
+    let stdio = ReadWrite::new(io::BufReader::new(reader), io::BufWriter::new(stream));
+    let mut serve = DuetServerRPCServer::new(server_impl, BincodeTransport::new(stdio));
+    match serve.serve() {
+        Err(e) if e.kind != RPCErrorKind::TransportEOF => {
+            Err(eyre!("RPC server stopped with error: {:?}", e))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Serves a peer on this host from a thread of this process, over one end of a socket pair
+/// instead of a child's stdin and stdout. Whole files travel as `FileCopy` frames both ways.
+/// The server stops when the client closes its end.
+pub(crate) fn spawn_in_process_server(
+    socket: std::os::unix::net::UnixStream,
+) -> Result<tokio::task::JoinHandle<()>> {
+    let mut server_impl = DuetServerImpl::new()?;
+    server_impl.file_copies = true;
+    let reader = socket
+        .try_clone()
+        .wrap_err("failed to clone in-process server socket")?;
+    let stdio = ReadWrite::new(io::BufReader::new(reader), io::BufWriter::new(socket));
+    Ok(tokio::task::spawn_blocking(move || {
+        let mut serve = DuetServerRPCServer::new(server_impl, BincodeTransport::new(stdio));
+        if let Err(e) = serve.serve() {
+            if e.kind != RPCErrorKind::TransportEOF {
+                log::error!("in-process RPC server stopped with error: {:?}", e);
+            }
+        }
+    }))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1812,6 +2199,41 @@ mod tests {
         assert!(client
             .complete_staged_apply_profiled("attempt".to_string())
             .is_err());
+        assert!(client.apply_moves(Vec::new()).is_err());
+        assert!(client.apply_hard_links(Vec::new()).is_err());
+        assert!(client.set_xattr_namespaces(Vec::new()).is_err());
+        assert!(client
+            .changes_scope_v3(
+                crate::scan::ScanScope::default(),
+                Vec::new(),
+                Vec::new(),
+                "id".into(),
+                true,
+            )
+            .is_err());
+        assert!(client.set_actions_v3(Vec::new()).is_err());
+        assert!(client.set_ownership_mode(None).is_err());
+        assert!(client.ownership_report(Vec::new()).is_err());
+        assert!(client
+            .set_special_files(profile::SpecialFiles::default())
+            .is_err());
+        assert!(client.set_detail_holes(true).is_err());
+        assert!(client.keep_conflict_sidecars(Vec::new()).is_err());
+        assert!(client
+            .keep_in_trash("id".into(), Vec::new(), Default::default())
+            .is_err());
+        assert!(client.list_trash("id".into()).is_err());
+        assert!(client
+            .restore_from_trash("id".into(), PathBuf::from("run/a"))
+            .is_err());
+        assert!(client.purge_trash("id".into(), Default::default()).is_err());
+        assert!(client.set_ignore_files(Vec::new()).is_err());
+        assert!(client.set_entry_filters(Default::default()).is_err());
+        assert!(client.filtered_entries().is_err());
+        assert!(client.state_summary("id".into()).is_err());
+        assert!(client.set_detail_compression(1).is_err());
+        assert!(client.stream_performance_v2().is_err());
+        assert!(client.mark_synced().is_err());
 
         assert_eq!(
             calls.lock().unwrap().as_slice(),
@@ -1852,6 +2274,27 @@ mod tests {
                 ("finish_staged_prepare_profiled", 50),
                 ("save_staged_state_pending_profiled", 51),
                 ("complete_staged_apply_profiled", 52),
+                ("apply_moves", 53),
+                ("apply_hard_links", 54),
+                ("set_xattr_namespaces", 55),
+                ("changes_scope_v3", 56),
+                ("set_actions_v3", 57),
+                ("set_ownership_mode", 58),
+                ("ownership_report", 59),
+                ("set_special_files", 60),
+                ("set_detail_holes", 61),
+                ("keep_conflict_sidecars", 62),
+                ("keep_in_trash", 63),
+                ("list_trash", 64),
+                ("restore_from_trash", 65),
+                ("purge_trash", 66),
+                ("set_ignore_files", 67),
+                ("set_entry_filters", 68),
+                ("filtered_entries", 69),
+                ("state_summary", 70),
+                ("set_detail_compression", 71),
+                ("stream_performance_v2", 72),
+                ("mark_synced", 73),
             ]
         );
     }
@@ -1899,6 +2342,20 @@ mod tests {
                 CAPABILITY_STAGED_COMMIT_PROFILE.to_string(),
                 CAPABILITY_STAGED_VALIDATION_RECEIPT.to_string(),
                 CAPABILITY_STAGED_MARKER_PROFILE.to_string(),
+                CAPABILITY_MOVES.to_string(),
+                CAPABILITY_HARD_LINKS.to_string(),
+                CAPABILITY_XATTRS.to_string(),
+                CAPABILITY_OWNERSHIP.to_string(),
+                CAPABILITY_SPECIAL_FILES.to_string(),
+                CAPABILITY_SPARSE_FILES.to_string(),
+                CAPABILITY_MTIME_NSEC.to_string(),
+                CAPABILITY_CONFLICT_SIDECARS.to_string(),
+                CAPABILITY_TRASH.to_string(),
+                CAPABILITY_PATH_IGNORE.to_string(),
+                CAPABILITY_IGNORE_FILES.to_string(),
+                CAPABILITY_ENTRY_FILTERS.to_string(),
+                CAPABILITY_STATE_SUMMARY.to_string(),
+                CAPABILITY_COMPRESSED_DETAILS.to_string(),
             ]
         );
     }
@@ -1931,7 +2388,7 @@ mod tests {
         .unwrap();
         let locations = vec![Location::Include(PathBuf::new())];
 
-        let changes = server
+        let changes: ChangesV3 = server
             .changes_scope(
                 crate::scan::ScanScope::new(PathBuf::new(), vec![PathBuf::from("excluded")]),
                 locations.clone(),
@@ -1939,7 +2396,8 @@ mod tests {
                 "peer".to_string(),
                 true,
             )
-            .unwrap();
+            .unwrap()
+            .into();
         assert!(changes.migration_needed);
         assert!(changes
             .changes
@@ -106,6 +106,7 @@ pub struct Signature {
 /// map of blocks. The first step of the protocol is to run this
 /// function on the "source" (the remote file when downloading, the
 /// local file while uploading).
+#[allow(dead_code)]
 pub fn signature<R: Read, B: AsRef<[u8]> + AsMut<[u8]>>(
     mut r: R,
     mut block: B,
@@ -144,6 +145,92 @@ pub fn signature<R: Read, B: AsRef<[u8]> + AsMut<[u8]>>(
     })
 }
 
+/// Same as [`signature`](fn.signature.html), for a seekable file of
+/// length `len` whose holes are known. `hole_end(offset)` returns the end
+/// of the hole containing `offset`, or `None` if `offset` holds data;
+/// blocks that lie entirely inside a hole are hashed as zeros without
+/// being read.
+pub fn signature_sparse<R, B, H>(
+    mut r: R,
+    mut block: B,
+    len: u64,
+    mut hole_end: H,
+) -> Result<Signature, std::io::Error>
+where
+    R: Read + Seek,
+    B: AsRef<[u8]> + AsMut<[u8]>,
+    H: FnMut(u64) -> Result<Option<u64>, std::io::Error>,
+{
+    let mut chunks = HashMap::new();
+    let block = block.as_mut();
+    let window = block.len() as u64;
+    if window == 0 {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "signature window must be non-zero",
+        ));
+    }
+
+    let mut insert = |block: &[u8], i: u64| {
+        let hash = adler32::RollingAdler32::from_buffer(block);
+        let mut blake2 = [0; BLAKE2_SIZE];
+        blake2.clone_from_slice(blake2_rfc::blake2b::blake2b(BLAKE2_SIZE, &[], block).as_bytes());
+        chunks
+            .entry(hash.hash())
+            .or_insert(HashMap::new())
+            .insert(Blake2b(blake2), i as usize);
+    };
+
+    let mut i = 0;
+    while i < len {
+        let block_len = window.min(len - i);
+        if let Some(end) = hole_end(i)? {
+            let end = end.min(len);
+            // A later zero block replaces an earlier one, as in `signature`,
+            // so only the last whole block of the hole needs hashing.
+            let whole = (end - i) / window;
+            if whole > 0 {
+                let last = i + (whole - 1) * window;
+                let zeros = &mut block[..window as usize];
+                zeros.iter_mut().for_each(|byte| *byte = 0);
+                insert(zeros, last);
+                i += whole * window;
+                continue;
+            }
+            if end == len {
+                let zeros = &mut block[..block_len as usize];
+                zeros.iter_mut().for_each(|byte| *byte = 0);
+                insert(zeros, i);
+                break;
+            }
+        }
+
+        r.seek(SeekFrom::Start(i))?;
+        let mut j = 0;
+        while j < block_len as usize {
+            let n = r.read(&mut block[j..block_len as usize])?;
+            if n == 0 {
+                break;
+            }
+            j += n;
+        }
+        if j == 0 {
+            break;
+        }
+        insert(&block[..j], i);
+        i += j as u64;
+    }
+    // `signature` always records the trailing short (or empty) read
+    if len.is_multiple_of(window) {
+        insert(&[], len);
+    }
+
+    Ok(Signature {
+        window: window as usize,
+        chunks,
+    })
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum Block {
     FromSource(u64),
@@ -476,6 +563,30 @@ mod tests {
         assert_eq!(&restored, modified);
     }
 
+    #[test]
+    fn sparse_signature_matches_reading_the_holes_as_zeros() {
+        let mut contents = [0u8; 100];
+        contents[..10].copy_from_slice(b"0123456789");
+        contents[70..75].copy_from_slice(b"abcde");
+        // holes at [16, 64) and [80, 100)
+        let hole_end = |offset: u64| {
+            Ok(match offset {
+                16..=63 => Some(64),
+                80..=99 => Some(100),
+                _ => None,
+            })
+        };
+
+        for len in [100, 96, 64] {
+            let contents = &contents[..len];
+            let dense = signature(contents, [0; 8]).unwrap();
+            let sparse =
+                signature_sparse(std::io::Cursor::new(contents), [0; 8], len as u64, hole_end)
+                    .unwrap();
+            assert_eq!(sparse, dense, "{}", len);
+        }
+    }
+
     #[test]
     fn compare_stream_rejects_invalid_buffers() {
         let sig = Signature {
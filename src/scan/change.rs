@@ -7,7 +7,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::utils::{match_sorted, MatchSorted};
 
-use super::{DirEntryWithMeta, LegacyEntry};
+use super::{DirEntryWithMeta, EntryV2, LegacyEntry};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Change {
@@ -43,6 +43,33 @@ impl From<Change> for LegacyChange {
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ChangeV2 {
+    Added(EntryV2),
+    Removed(EntryV2),
+    Modified(EntryV2, EntryV2),
+}
+
+impl From<ChangeV2> for Change {
+    fn from(change: ChangeV2) -> Self {
+        match change {
+            ChangeV2::Added(e) => Self::Added(e.into()),
+            ChangeV2::Removed(e) => Self::Removed(e.into()),
+            ChangeV2::Modified(a, b) => Self::Modified(a.into(), b.into()),
+        }
+    }
+}
+
+impl From<Change> for ChangeV2 {
+    fn from(change: Change) -> Self {
+        match change {
+            Change::Added(e) => Self::Added(e.into()),
+            Change::Removed(e) => Self::Removed(e.into()),
+            Change::Modified(a, b) => Self::Modified(a.into(), b.into()),
+        }
+    }
+}
+
 impl Change {
     pub fn path(&self) -> &PathBuf {
         match self {
@@ -92,7 +119,10 @@ fn same_with_file_contents(
             (d1.is_symlink() && d2.is_symlink() || d1.mode == d2.mode)
                 && d1.target == d2.target
                 && d1.is_dir == d2.is_dir
-                && (d1.is_dir || d1.mtime == d2.mtime)
+                && (d1.is_dir || d1.same_mtime(d2))
+                && d1.rdev == d2.rdev
+                && d1.same_xattrs(d2)
+                && d1.same_ownership(d2)
                 && (!d1.is_file() || same_file(d1, d2))
         }
         _ => false,
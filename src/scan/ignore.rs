@@ -0,0 +1,337 @@
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use color_eyre::eyre::{Result, WrapErr};
+use fnmatch_regex::glob_to_regex;
+use regex::Regex;
+
+/// Whether an ignore or prune pattern uses the path-aware syntax: a `!` negation, a trailing `/`
+/// for directories only, or a `/` anchoring it to the sync root. Basename globs never contain a
+/// `/`, so these patterns used to match nothing, and peers without the `path-ignore-v1`
+/// capability would still read them that way.
+pub fn is_path_aware(pattern: &str) -> bool {
+    pattern.starts_with('!') || pattern.contains('/')
+}
+
+#[derive(Debug)]
+enum Segment {
+    /// `**`: any number of path components, including none.
+    AnyDepth,
+    Glob(Regex),
+}
+
+#[derive(Debug)]
+enum Matcher {
+    /// A glob without a `/`, matched against the basename at any depth.
+    Name(Regex),
+    /// A glob with a `/`, matched component by component against the path from the sync root.
+    Path(Vec<Segment>),
+}
+
+#[derive(Debug)]
+struct Pattern {
+    source: String,
+    negated: bool,
+    dir_only: bool,
+    matcher: Matcher,
+}
+
+impl Pattern {
+    fn new(source: &str) -> Result<Self> {
+        let (negated, glob) = match source.strip_prefix('!') {
+            Some(glob) => (true, glob),
+            None => (false, source),
+        };
+        let (dir_only, glob) = match glob.strip_suffix('/') {
+            Some(glob) => (true, glob),
+            None => (false, glob),
+        };
+        let matcher = if glob.contains('/') {
+            let mut segments = Vec::new();
+            for component in glob.trim_start_matches('/').split('/') {
+                segments.push(match component {
+                    "**" => Segment::AnyDepth,
+                    component => Segment::Glob(glob_to_regex(component)?),
+                });
+            }
+            Matcher::Path(segments)
+        } else {
+            Matcher::Name(glob_to_regex(glob)?)
+        };
+        Ok(Self {
+            source: source.to_string(),
+            negated,
+            dir_only,
+            matcher,
+        })
+    }
+
+    fn is_match(&self, components: &[&str], is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        match &self.matcher {
+            Matcher::Name(regex) => components.last().is_some_and(|name| regex.is_match(name)),
+            Matcher::Path(segments) => segments_match(segments, components),
+        }
+    }
+}
+
+fn segments_match(segments: &[Segment], components: &[&str]) -> bool {
+    match segments.split_first() {
+        None => components.is_empty(),
+        // a trailing `**` matches everything under the directory before it, not the directory
+        Some((Segment::AnyDepth, [])) => !components.is_empty(),
+        Some((Segment::AnyDepth, rest)) => {
+            (0..=components.len()).any(|skip| segments_match(rest, &components[skip..]))
+        }
+        Some((Segment::Glob(regex), rest)) => match components.split_first() {
+            Some((first, remaining)) => regex.is_match(first) && segments_match(rest, remaining),
+            None => false,
+        },
+    }
+}
+
+/// Compiled ignore or prune patterns. The last pattern that matches a path decides: a path is
+/// matched unless that pattern is a `!` negation.
+#[derive(Debug, Default)]
+pub struct Patterns(Vec<Pattern>);
+
+impl Patterns {
+    pub fn new(patterns: &[String], kind: &str) -> Result<Self> {
+        let mut compiled = Vec::new();
+        for pattern in patterns {
+            compiled.push(
+                Pattern::new(pattern)
+                    .wrap_err_with(|| format!("invalid {kind} pattern {pattern}"))?,
+            );
+        }
+        Ok(Self(compiled))
+    }
+
+    /// Patterns from a per-directory ignore file: one per line, skipping blank lines and `#`
+    /// comments.
+    pub fn parse(contents: &str, file: &Path) -> Result<Self> {
+        let patterns: Vec<String> = contents
+            .lines()
+            .map(str::trim_end)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect();
+        Self::new(&patterns, "ignore").wrap_err_with(|| format!("in {}", file.display()))
+    }
+
+    /// The last pattern that matches `relative`, negated or not.
+    fn last_match(&self, relative: &Path, is_dir: bool) -> Option<&Pattern> {
+        let mut components = Vec::new();
+        for component in relative.components() {
+            match component {
+                Component::Normal(name) => components.push(name.to_str()?),
+                Component::CurDir => {}
+                _ => return None,
+            }
+        }
+        self.0
+            .iter()
+            .rev()
+            .find(|pattern| pattern.is_match(&components, is_dir))
+    }
+
+    /// The pattern that matches `relative`, a path under the sync root, unless a later negation
+    /// re-includes it.
+    pub fn matching(&self, relative: &Path, is_dir: bool) -> Option<&str> {
+        self.last_match(relative, is_dir)
+            .filter(|pattern| !pattern.negated)
+            .map(|pattern| pattern.source.as_str())
+    }
+
+    pub fn is_match(&self, relative: &Path, is_dir: bool) -> bool {
+        self.matching(relative, is_dir).is_some()
+    }
+}
+
+/// The patterns of the per-directory ignore files (`.duetignore`, and the other names a profile's
+/// `[ignore-files]` section lists) in effect in a directory, read as a scan descends. Each file
+/// applies to its directory and the directories under it, with gitignore semantics: paths are
+/// matched relative to the file's directory, and a deeper file's decision overrides a
+/// shallower one's.
+#[derive(Debug, Clone, Default)]
+pub struct DirectoryIgnores(Option<Arc<Scoped>>);
+
+#[derive(Debug)]
+struct Scoped {
+    dir: PathBuf,
+    patterns: Patterns,
+    parent: DirectoryIgnores,
+}
+
+impl DirectoryIgnores {
+    /// The ignores in effect in `dir`, a directory under `base` given relative to it: these ones
+    /// and the patterns of its own ignore files named in `names`.
+    pub fn descend(&self, base: &Path, dir: &Path, names: &[String]) -> Result<Self> {
+        let mut ignores = self.clone();
+        for name in names {
+            let file = base.join(dir).join(name);
+            let contents = match std::fs::read_to_string(&file) {
+                Ok(contents) => contents,
+                Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+                Err(error) => {
+                    return Err(error)
+                        .wrap_err_with(|| format!("unable to read ignore file {}", file.display()))
+                }
+            };
+            ignores = Self(Some(Arc::new(Scoped {
+                dir: dir.to_path_buf(),
+                patterns: Patterns::parse(&contents, &file)?,
+                parent: ignores,
+            })));
+        }
+        Ok(ignores)
+    }
+
+    pub fn is_match(&self, relative: &Path, is_dir: bool) -> bool {
+        let mut scoped = &self.0;
+        while let Some(ignores) = scoped {
+            if let Ok(within) = relative.strip_prefix(&ignores.dir) {
+                if let Some(pattern) = ignores.patterns.last_match(within, is_dir) {
+                    return !pattern.negated;
+                }
+            }
+            scoped = &ignores.parent.0;
+        }
+        false
+    }
+}
+
+/// Tells whether the ignore files named in `names` under `base` ignore paths or one of the
+/// directories above them, the way a scan would find them. Like the scan, it keeps the ignores of
+/// the directories above the last path it was asked about, so paths asked about in sorted order
+/// read each ignore file once.
+pub struct IgnoreFileWalk<'a> {
+    base: &'a Path,
+    names: &'a [String],
+    /// The directories above the last path, from `base` down, with the ignores in effect in each;
+    /// `None` below an ignored directory, which a scan does not descend into.
+    stack: Vec<(PathBuf, Option<DirectoryIgnores>)>,
+}
+
+impl<'a> IgnoreFileWalk<'a> {
+    pub fn new(base: &'a Path, names: &'a [String]) -> Result<Self> {
+        let root = DirectoryIgnores::default().descend(base, Path::new(""), names)?;
+        Ok(Self {
+            base,
+            names,
+            stack: vec![(PathBuf::new(), Some(root))],
+        })
+    }
+
+    pub fn is_ignored(&mut self, relative: &Path, is_dir: bool) -> Result<bool> {
+        let parent = relative.parent().unwrap_or_else(|| Path::new(""));
+        while !parent.starts_with(&self.top().0) {
+            self.stack.pop();
+        }
+        let depth = self.top().0.components().count();
+        for component in parent.components().skip(depth) {
+            let (dir, ignores) = self.top();
+            let dir = dir.join(component);
+            let ignores = match ignores {
+                Some(ignores) if !ignores.is_match(&dir, true) => {
+                    Some(ignores.descend(self.base, &dir, self.names)?)
+                }
+                _ => None,
+            };
+            self.stack.push((dir, ignores));
+        }
+        Ok(match &self.top().1 {
+            Some(ignores) => ignores.is_match(relative, is_dir),
+            None => true,
+        })
+    }
+
+    fn top(&self) -> &(PathBuf, Option<DirectoryIgnores>) {
+        self.stack.last().expect("the base stays on the stack")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterns(patterns: &[&str]) -> Patterns {
+        let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+        Patterns::new(&patterns, "ignore").unwrap()
+    }
+
+    #[test]
+    fn basename_globs_match_at_any_depth() {
+        let ignore = patterns(&["*.tmp"]);
+        assert!(ignore.is_match(Path::new("a.tmp"), false));
+        assert!(ignore.is_match(Path::new("x/y/a.tmp"), true));
+        assert!(!ignore.is_match(Path::new("a.tmp/b"), false));
+        assert!(!is_path_aware("*.tmp"));
+    }
+
+    #[test]
+    fn path_globs_are_anchored_and_double_stars_span_directories() {
+        let ignore = patterns(&["build/**/*.o", "/out", "docs/*.log"]);
+        assert!(ignore.is_match(Path::new("build/a.o"), false));
+        assert!(ignore.is_match(Path::new("build/x/y/a.o"), false));
+        assert!(!ignore.is_match(Path::new("src/build/a.o"), false));
+        assert!(ignore.is_match(Path::new("out"), true));
+        assert!(!ignore.is_match(Path::new("src/out"), true));
+        assert!(ignore.is_match(Path::new("docs/a.log"), false));
+        assert!(!ignore.is_match(Path::new("docs/x/a.log"), false));
+
+        let ignore = patterns(&["cache/**"]);
+        assert!(!ignore.is_match(Path::new("cache"), true));
+        assert!(ignore.is_match(Path::new("cache/a/b"), false));
+    }
+
+    #[test]
+    fn negations_and_directory_only_patterns() {
+        let ignore = patterns(&["*.tmp", "!keep.tmp", "out/"]);
+        assert_eq!(ignore.matching(Path::new("a/b.tmp"), false), Some("*.tmp"));
+        assert_eq!(ignore.matching(Path::new("a/keep.tmp"), false), None);
+        assert_eq!(ignore.matching(Path::new("a/out"), true), Some("out/"));
+        assert_eq!(ignore.matching(Path::new("a/out"), false), None);
+        assert!(is_path_aware("!keep.tmp"));
+        assert!(is_path_aware("out/"));
+    }
+
+    #[test]
+    fn ignore_files_apply_below_their_directory_and_deeper_files_win() {
+        let base = tempfile::tempdir().unwrap();
+        let names = vec![".duetignore".to_string()];
+        std::fs::create_dir_all(base.path().join("proj/sub")).unwrap();
+        std::fs::write(base.path().join(".duetignore"), "*.log\n").unwrap();
+        std::fs::write(
+            base.path().join("proj/.duetignore"),
+            "# generated\n/target/\n",
+        )
+        .unwrap();
+        std::fs::write(base.path().join("proj/sub/.duetignore"), "!keep.log\n").unwrap();
+
+        let root = DirectoryIgnores::default()
+            .descend(base.path(), Path::new(""), &names)
+            .unwrap();
+        let proj = root
+            .descend(base.path(), Path::new("proj"), &names)
+            .unwrap();
+        assert!(!root.is_match(Path::new("target"), true));
+        assert!(proj.is_match(Path::new("proj/target"), true));
+        assert!(!proj.is_match(Path::new("proj/sub/target"), true));
+        assert!(proj.is_match(Path::new("proj/a.log"), false));
+
+        let mut walk = IgnoreFileWalk::new(base.path(), &names).unwrap();
+        let mut ignored = |path: &str| walk.is_ignored(Path::new(path), false).unwrap();
+        assert!(!ignored("a.txt"));
+        assert!(!ignored("proj/src.rs"));
+        assert!(ignored("proj/sub/a.log"));
+        assert!(!ignored("proj/sub/keep.log"));
+        assert!(ignored("proj/target/a.o"));
+        assert!(ignored("proj/target/deep/b.o"));
+        assert!(!ignored("proj2/target/a.o"));
+        assert!(ignored("z.log"));
+    }
+}
@@ -1,5 +1,5 @@
 use std::cmp::Ordering;
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::ffi::CString;
 use std::io::Read;
 use std::os::fd::{AsRawFd, FromRawFd};
@@ -61,27 +61,18 @@ use futures::stream::{FuturesUnordered, StreamExt};
 
 use log;
 
-use crate::profile::Ignore;
-use regex::Regex;
-pub type Regexes = Vec<Regex>;
-fn is_match(regexes: &Regexes, p: &Path) -> bool {
-    if let Some(s) = p.file_name() {
-        if let Some(s) = s.to_str() {
-            for r in regexes {
-                if r.is_match(s) {
-                    return true;
-                }
-            }
-        }
-    }
-    false
-}
+use crate::profile::{Ignore, MetadataCapture};
 
 pub mod change;
+pub mod ignore;
 pub mod location;
+pub mod ownership;
+pub mod xattr;
 
 pub use change::{changes, Change};
 use location::{Location, Locations};
+pub use ownership::{Ownership, OwnershipMode};
+pub use xattr::Xattrs;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ContentDigest(pub [u8; 32]);
@@ -106,7 +97,85 @@ pub struct DirEntryWithMeta {
     is_dir: bool,
     checksum: u32,
     digest: Option<ContentDigest>,
-    // TODO: uid and gid
+    xattrs: Option<Box<Xattrs>>,
+    ownership: Option<Box<Ownership>>,
+    rdev: u64,
+    /// Bytes the scanned regular file occupies on disk; unknown for entries that crossed a V2
+    /// boundary.
+    allocated: Option<u64>,
+    /// Nanosecond part of `mtime`; unknown for entries that crossed a V2 boundary, which are then
+    /// compared to the second.
+    mtime_nsec: Option<u32>,
+    /// Set for a regular file the scan found with more than one name.
+    link: Option<Box<LinkIdentity>>,
+}
+
+/// The inode a hard-linked regular file shares with its other names. Names of one inode get the
+/// same `group`, numbered in the order a scan first met the inode, so groups only compare within
+/// one scan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct LinkIdentity {
+    pub dev: u64,
+    pub nlink: u64,
+    pub group: u64,
+}
+
+/// Exact pre-xattr entry layout used by V2 snapshots and V2 RPCs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EntryV2 {
+    path: PathBuf,
+    size: u64,
+    mtime: i64,
+    ino: u64,
+    mode: u32,
+    target: Option<PathBuf>,
+    is_dir: bool,
+    checksum: u32,
+    digest: Option<ContentDigest>,
+}
+
+impl From<EntryV2> for DirEntryWithMeta {
+    fn from(entry: EntryV2) -> Self {
+        Self {
+            path: entry.path,
+            size: entry.size,
+            mtime: entry.mtime,
+            ino: entry.ino,
+            mode: entry.mode,
+            target: entry.target,
+            is_dir: entry.is_dir,
+            checksum: entry.checksum,
+            digest: entry.digest,
+            xattrs: None,
+            ownership: None,
+            rdev: 0,
+            allocated: None,
+            mtime_nsec: None,
+            link: None,
+        }
+    }
+}
+
+impl From<DirEntryWithMeta> for EntryV2 {
+    fn from(entry: DirEntryWithMeta) -> Self {
+        Self {
+            path: entry.path,
+            size: entry.size,
+            mtime: entry.mtime,
+            ino: entry.ino,
+            mode: entry.mode,
+            target: entry.target,
+            is_dir: entry.is_dir,
+            checksum: entry.checksum,
+            digest: entry.digest,
+        }
+    }
+}
+
+impl From<&DirEntryWithMeta> for EntryV2 {
+    fn from(entry: &DirEntryWithMeta) -> Self {
+        entry.clone().into()
+    }
 }
 
 /// Exact pre-digest entry layout used by headerless V1 snapshots and legacy RPCs.
@@ -134,6 +203,12 @@ impl From<LegacyEntry> for DirEntryWithMeta {
             is_dir: entry.is_dir,
             checksum: entry.checksum,
             digest: None,
+            xattrs: None,
+            ownership: None,
+            rdev: 0,
+            allocated: None,
+            mtime_nsec: None,
+            link: None,
         }
     }
 }
@@ -160,6 +235,43 @@ impl From<&DirEntryWithMeta> for LegacyEntry {
 }
 
 impl DirEntryWithMeta {
+    /// Describes a directory created during apply, before its final metadata is set.
+    pub(crate) fn created_directory(path: PathBuf, meta: &std::fs::Metadata) -> Self {
+        Self {
+            path,
+            size: meta.size(),
+            mtime: meta.mtime(),
+            ino: meta.ino(),
+            mode: meta.mode(),
+            target: None,
+            is_dir: true,
+            checksum: 0,
+            digest: None,
+            xattrs: None,
+            ownership: None,
+            rdev: 0,
+            allocated: None,
+            mtime_nsec: Some(meta.mtime_nsec() as u32),
+            link: None,
+        }
+    }
+
+    /// Describes this file once its contents are replaced with `data` at `mtime`.
+    pub(crate) fn with_contents(&self, data: &[u8], mtime: filetime::FileTime) -> Self {
+        let digest = blake2_rfc::blake2b::blake2b(32, &[], data);
+        let mut bytes = [0; 32];
+        bytes.copy_from_slice(digest.as_bytes());
+        Self {
+            size: data.len() as u64,
+            mtime: mtime.unix_seconds(),
+            mtime_nsec: Some(mtime.nanoseconds()),
+            checksum: adler32::RollingAdler32::from_buffer(data).hash(),
+            digest: Some(ContentDigest(bytes)),
+            allocated: None,
+            ..self.clone()
+        }
+    }
+
     #[cfg(test)]
     pub(crate) fn test_file(path: PathBuf, checksum: u32) -> Self {
         Self {
@@ -172,6 +284,12 @@ impl DirEntryWithMeta {
             is_dir: false,
             checksum,
             digest: None,
+            xattrs: None,
+            ownership: None,
+            rdev: 0,
+            allocated: None,
+            mtime_nsec: None,
+            link: None,
         }
     }
 
@@ -187,6 +305,12 @@ impl DirEntryWithMeta {
             is_dir: false,
             checksum,
             digest: None,
+            xattrs: None,
+            ownership: None,
+            rdev: 0,
+            allocated: None,
+            mtime_nsec: None,
+            link: None,
         }
     }
 
@@ -203,6 +327,12 @@ impl DirEntryWithMeta {
             is_dir: false,
             checksum: 0,
             digest: None,
+            xattrs: None,
+            ownership: None,
+            rdev: 0,
+            allocated: None,
+            mtime_nsec: None,
+            link: None,
         }
     }
 
@@ -218,6 +348,33 @@ impl DirEntryWithMeta {
             is_dir: true,
             checksum: 0,
             digest: None,
+            xattrs: None,
+            ownership: None,
+            rdev: 0,
+            allocated: None,
+            mtime_nsec: None,
+            link: None,
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn test_special(path: PathBuf, mode: u32, rdev: u64) -> Self {
+        Self {
+            path,
+            size: 0,
+            mtime: 0,
+            ino: 0,
+            mode,
+            target: None,
+            is_dir: false,
+            checksum: 0,
+            digest: None,
+            xattrs: None,
+            ownership: None,
+            rdev,
+            allocated: None,
+            mtime_nsec: None,
+            link: None,
         }
     }
 
@@ -248,6 +405,12 @@ impl DirEntryWithMeta {
             is_dir: false,
             checksum: 0,
             digest: None,
+            xattrs: None,
+            ownership: None,
+            rdev: 0,
+            allocated: None,
+            mtime_nsec: None,
+            link: None,
         }
     }
 
@@ -257,12 +420,82 @@ impl DirEntryWithMeta {
             && self.target == other.target
             && self.is_dir == other.is_dir
             && (self.is_dir || self.same_scan_identity(other))
+            && self.rdev == other.rdev
+            && self.same_captured_xattrs(other)
+            && self.same_ownership(other)
+    }
+
+    /// Compares the attributes of a previous snapshot entry (`self`) with a fresh scan. A scan
+    /// that did not capture attributes says nothing about them, and a snapshot taken before
+    /// capture was enabled only differs from attributes that are actually present.
+    fn same_captured_xattrs(&self, current: &Self) -> bool {
+        match (&self.xattrs, &current.xattrs) {
+            (_, None) => true,
+            (None, Some(current)) => current.values().is_empty(),
+            (Some(old), Some(current)) => old.values() == current.values(),
+        }
+    }
+
+    /// Compares the captured attributes of two entries, treating uncaptured ones as empty.
+    pub(crate) fn same_xattrs(&self, other: &Self) -> bool {
+        fn values(e: &DirEntryWithMeta) -> &[(Vec<u8>, Vec<u8>)] {
+            e.xattrs.as_deref().map(Xattrs::values).unwrap_or_default()
+        }
+        values(self) == values(other)
+    }
+
+    pub fn xattrs(&self) -> Option<&Xattrs> {
+        self.xattrs.as_deref()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set_xattrs(&mut self, xattrs: Option<Xattrs>) {
+        self.xattrs = xattrs.map(Box::new);
+    }
+
+    /// Compares ownership when both entries captured it; an entry scanned without ownership says
+    /// nothing about it, so enabling `[ownership]` does not report every existing entry.
+    pub(crate) fn same_ownership(&self, other: &Self) -> bool {
+        match (&self.ownership, &other.ownership) {
+            (Some(left), Some(right)) => left.same(right),
+            _ => true,
+        }
+    }
+
+    pub fn ownership(&self) -> Option<&Ownership> {
+        self.ownership.as_deref()
+    }
+
+    pub(crate) fn set_ownership(&mut self, ownership: Option<Ownership>) {
+        self.ownership = ownership.map(Box::new);
+    }
+
+    /// Whether the entry carries metadata that only the V3 layout can represent.
+    pub(crate) fn has_v3_metadata(&self) -> bool {
+        self.xattrs.is_some()
+            || self.ownership.is_some()
+            || self.is_special()
+            || self.mtime_nsec.is_some_and(|nsec| nsec != 0)
+            || self.link.is_some()
+    }
+
+    pub(crate) fn link(&self) -> Option<&LinkIdentity> {
+        self.link.as_deref()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set_link(&mut self, link: Option<LinkIdentity>) {
+        self.link = link.map(Box::new);
     }
 
     pub fn path(&self) -> &PathBuf {
         &self.path
     }
 
+    pub(crate) fn set_path(&mut self, path: PathBuf) {
+        self.path = path;
+    }
+
     pub fn target(&self) -> &Option<PathBuf> {
         &self.target
     }
@@ -277,7 +510,27 @@ impl DirEntryWithMeta {
     }
 
     fn same_scan_identity(&self, other: &Self) -> bool {
-        self.size == other.size && self.mtime == other.mtime && self.ino == other.ino
+        self.size == other.size && self.same_mtime(other) && self.ino == other.ino
+    }
+
+    /// Compares modification times to the nanosecond when both entries know them, and to the
+    /// second otherwise, so entries from peers or snapshots without nanoseconds do not look
+    /// modified.
+    pub fn same_mtime(&self, other: &Self) -> bool {
+        self.mtime == other.mtime
+            && match (self.mtime_nsec, other.mtime_nsec) {
+                (Some(left), Some(right)) => left == right,
+                _ => true,
+            }
+    }
+
+    /// Whether the entry's modification time matches the given metadata, to the second when the
+    /// entry does not know its nanoseconds.
+    pub(crate) fn mtime_matches(&self, metadata: &std::fs::Metadata) -> bool {
+        metadata.mtime() == self.mtime
+            && self
+                .mtime_nsec
+                .is_none_or(|nsec| metadata.mtime_nsec() == i64::from(nsec))
     }
 
     pub fn is_symlink(&self) -> bool {
@@ -292,6 +545,20 @@ impl DirEntryWithMeta {
         self.mtime
     }
 
+    pub fn mtime_nsec(&self) -> Option<u32> {
+        self.mtime_nsec
+    }
+
+    /// The modification time to apply, with zero nanoseconds when they are unknown.
+    pub(crate) fn mtime_filetime(&self) -> filetime::FileTime {
+        filetime::FileTime::from_unix_time(self.mtime, self.mtime_nsec.unwrap_or(0))
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set_mtime_nsec(&mut self, nsec: Option<u32>) {
+        self.mtime_nsec = nsec;
+    }
+
     pub fn size(&self) -> u64 {
         self.size
     }
@@ -318,7 +585,6 @@ impl DirEntryWithMeta {
         self.ino = ino;
     }
 
-    #[cfg(test)]
     pub(crate) fn ino(&self) -> u64 {
         self.ino
     }
@@ -333,7 +599,54 @@ impl DirEntryWithMeta {
     }
 
     pub fn is_file(&self) -> bool {
-        !(self.is_dir || self.is_symlink())
+        !(self.is_dir || self.is_symlink() || self.is_special())
+    }
+
+    /// FIFOs and character or block devices, which are recreated rather than transferred.
+    pub fn is_special(&self) -> bool {
+        matches!(
+            self.mode & libc::S_IFMT,
+            libc::S_IFIFO | libc::S_IFCHR | libc::S_IFBLK
+        )
+    }
+
+    pub fn is_device(&self) -> bool {
+        matches!(self.mode & libc::S_IFMT, libc::S_IFCHR | libc::S_IFBLK)
+    }
+
+    pub(crate) fn is_socket(&self) -> bool {
+        self.mode & libc::S_IFMT == libc::S_IFSOCK
+    }
+
+    /// Whether two special entries are the same kind of node with the same device number, so
+    /// that only their metadata differs.
+    pub(crate) fn same_special_node(&self, other: &Self) -> bool {
+        self.is_special()
+            && self.mode & libc::S_IFMT == other.mode & libc::S_IFMT
+            && self.rdev == other.rdev
+    }
+
+    /// Whether the regular file has holes: it occupies fewer bytes on disk than its length.
+    pub fn is_sparse(&self) -> bool {
+        self.allocated
+            .is_some_and(|allocated| allocated < self.size)
+    }
+
+    /// Bytes a copy of the file needs on disk when its holes are preserved; the logical size
+    /// when the allocation is unknown.
+    pub fn physical_size(&self) -> u64 {
+        self.allocated
+            .map_or(self.size, |allocated| allocated.min(self.size))
+    }
+
+    #[cfg(test)]
+    pub(crate) fn set_allocated(&mut self, allocated: Option<u64>) {
+        self.allocated = allocated;
+    }
+
+    /// Device number of a character or block device; zero for other entries.
+    pub fn rdev(&self) -> u64 {
+        self.rdev
     }
 
     pub(crate) fn compute_content_hashes<F>(
@@ -399,7 +712,7 @@ impl DirEntryWithMeta {
         if !metadata.is_file()
             || metadata.ino() != self.ino
             || metadata.size() != self.size
-            || metadata.mtime() != self.mtime
+            || !self.mtime_matches(metadata)
             || metadata.mode() != self.mode
         {
             return Err(eyre!(
@@ -508,15 +821,39 @@ struct ScanContext {
     locations: Arc<Locations>,
     scope: Arc<ScanScope>,
     base: Arc<PathBuf>,
-    ignore: Arc<Regexes>,
+    ignore: Arc<ignore::Patterns>,
+    capture: Arc<MetadataCapture>,
     dev: u64,
     tx: mpsc::Sender<DirEntryWithMeta>,
+    /// Link groups of the inodes with several names met so far, keyed by device and inode.
+    link_groups: std::sync::Mutex<HashMap<(u64, u64), u64>>,
+}
+
+impl ScanContext {
+    fn link_identity(&self, meta: &std::fs::Metadata) -> Option<LinkIdentity> {
+        if !meta.is_file() || meta.nlink() < 2 {
+            return None;
+        }
+        let mut groups = self
+            .link_groups
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let next = groups.len() as u64;
+        let group = *groups.entry((meta.dev(), meta.ino())).or_insert(next);
+        Some(LinkIdentity {
+            dev: meta.dev(),
+            nlink: meta.nlink(),
+            group,
+        })
+    }
 }
 
 #[derive(Debug)]
 struct ScanJob {
     path: PathBuf,
     pft: ParentFromTo,
+    /// Patterns of the ignore files in the directories above `path`.
+    ignores: ignore::DirectoryIgnores,
 }
 
 fn narrow_parent_from_to(pft: ParentFromTo, path: &PathBuf, locations: &Locations) -> ParentFromTo {
@@ -563,7 +900,7 @@ fn find_parent<'a>(path: &PathBuf, locations: &'a Locations, pft: &ParentFromTo)
 }
 
 async fn scan_one_directory(context: Arc<ScanContext>, job: ScanJob) -> Result<Vec<ScanJob>> {
-    let ScanJob { path, pft } = job;
+    let ScanJob { path, pft, ignores } = job;
     log::trace!("Scanning: {}", path.display());
 
     let relative_path = relative(&context.base, &path);
@@ -589,6 +926,7 @@ async fn scan_one_directory(context: Arc<ScanContext>, job: ScanJob) -> Result<V
         log::trace!("Skipping excluded: {:?}", path);
         return Ok(Vec::new());
     }
+    let ignores = ignores.descend(&context.base, relative_path, &context.capture.ignore_files)?;
 
     // read the directory
     use tokio::fs;
@@ -610,7 +948,13 @@ async fn scan_one_directory(context: Arc<ScanContext>, job: ScanJob) -> Result<V
             continue;
         }
 
-        if is_match(&context.ignore, &path) {
+        let is_dir = child
+            .file_type()
+            .await
+            .wrap_err_with(|| format!("unable to read file type of {}", path.display()))?
+            .is_dir();
+        if context.ignore.is_match(relative_path, is_dir) || ignores.is_match(relative_path, is_dir)
+        {
             log::trace!("Skipping (ignored): {:?}", path);
             continue;
         }
@@ -631,10 +975,15 @@ async fn scan_one_directory(context: Arc<ScanContext>, job: ScanJob) -> Result<V
             continue;
         }
 
-        if file_type.is_block_device()
+        let special = &context.capture.special;
+        let recorded_special = (file_type.is_fifo() && special.fifos)
+            || ((file_type.is_block_device() || file_type.is_char_device()) && special.devices)
+            || (file_type.is_socket() && special.skip_sockets);
+        if (file_type.is_block_device()
             || file_type.is_char_device()
             || file_type.is_fifo()
-            || file_type.is_socket()
+            || file_type.is_socket())
+            && !recorded_special
         {
             if context.scope.selected(relative_path) {
                 return Err(eyre!(
@@ -657,6 +1006,7 @@ async fn scan_one_directory(context: Arc<ScanContext>, job: ScanJob) -> Result<V
             child_jobs.push(ScanJob {
                 path: path.clone(),
                 pft: pft.clone(),
+                ignores: ignores.clone(),
             });
         }
 
@@ -675,6 +1025,34 @@ async fn scan_one_directory(context: Arc<ScanContext>, job: ScanJob) -> Result<V
             } else {
                 None
             };
+            let xattrs = if file_type.is_symlink()
+                || recorded_special
+                || context.capture.xattrs.is_empty()
+            {
+                None
+            } else {
+                let namespaces = context.capture.xattrs.clone();
+                let xattr_path = path.clone();
+                tokio::task::spawn_blocking(move || xattr::read(&xattr_path, &namespaces))
+                    .await
+                    .wrap_err("extended attribute reader failed")??
+            };
+            let ownership = match context.capture.ownership {
+                _ if file_type.is_symlink() || file_type.is_socket() => None,
+                None => None,
+                Some(OwnershipMode::Numeric) => {
+                    Some(Ownership::from_metadata(&meta, OwnershipMode::Numeric))
+                }
+                // name lookups may go through NSS
+                Some(mode) => {
+                    let meta = meta.clone();
+                    Some(
+                        tokio::task::spawn_blocking(move || Ownership::from_metadata(&meta, mode))
+                            .await
+                            .wrap_err("ownership name lookup failed")?,
+                    )
+                }
+            };
 
             context
                 .tx
@@ -688,6 +1066,16 @@ async fn scan_one_directory(context: Arc<ScanContext>, job: ScanJob) -> Result<V
                     is_dir: meta.is_dir(),
                     checksum: 0,
                     digest: None,
+                    xattrs: xattrs.map(Box::new),
+                    ownership: ownership.map(Box::new),
+                    rdev: if file_type.is_block_device() || file_type.is_char_device() {
+                        meta.rdev()
+                    } else {
+                        0
+                    },
+                    allocated: meta.is_file().then(|| meta.blocks().saturating_mul(512)),
+                    mtime_nsec: Some(meta.mtime_nsec() as u32),
+                    link: context.link_identity(&meta).map(Box::new),
                 })
                 .await
                 .map_err(|_| eyre!("unable to send scan result for {}", path.display()))?
@@ -737,6 +1125,7 @@ mod tests {
                 from: 0,
                 to: 0,
             },
+            ignores: Default::default(),
         }
     }
 
@@ -826,6 +1215,84 @@ mod tests {
         assert!(rx.recv().await.is_none());
     }
 
+    #[tokio::test]
+    async fn scan_records_enabled_fifos_and_sockets() {
+        let temp = tempfile::tempdir().unwrap();
+        let fifo = CString::new(temp.path().join("fifo").as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o640) }, 0);
+        let _listener = UnixListener::bind(temp.path().join("socket")).unwrap();
+        let (tx, mut rx) = mpsc::channel(8);
+        let capture = MetadataCapture {
+            special: crate::profile::SpecialFiles {
+                fifos: true,
+                devices: false,
+                skip_sockets: true,
+            },
+            ..MetadataCapture::default()
+        };
+
+        scan_scope(
+            temp.path(),
+            &ScanScope::new(PathBuf::new(), Vec::new()),
+            &vec![Location::Include(PathBuf::new())],
+            &Vec::new(),
+            &capture,
+            tx,
+        )
+        .await
+        .unwrap();
+
+        let mut entries = Vec::new();
+        while let Some(entry) = rx.recv().await {
+            entries.push(entry);
+        }
+        entries.sort_by(|a, b| a.path().cmp(b.path()));
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].path(), Path::new("fifo"));
+        assert!(entries[0].is_special() && !entries[0].is_device());
+        assert!(!entries[0].is_file());
+        assert_eq!(entries[0].mode() & 0o777, 0o640);
+        assert!(entries[0].has_v3_metadata());
+        assert!(entries[1].is_socket());
+    }
+
+    #[tokio::test]
+    async fn scan_groups_the_names_of_hard_linked_files() {
+        let temp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(temp.path().join("nested")).unwrap();
+        std::fs::write(temp.path().join("a"), b"shared").unwrap();
+        std::fs::hard_link(temp.path().join("a"), temp.path().join("nested/b")).unwrap();
+        std::fs::write(temp.path().join("c"), b"shared").unwrap();
+        std::fs::hard_link(temp.path().join("c"), temp.path().join("d")).unwrap();
+        std::fs::write(temp.path().join("single"), b"shared").unwrap();
+        let (tx, mut rx) = mpsc::channel(8);
+
+        scan_scope(
+            temp.path(),
+            &ScanScope::new(PathBuf::new(), Vec::new()),
+            &vec![Location::Include(PathBuf::new())],
+            &Vec::new(),
+            &MetadataCapture::default(),
+            tx,
+        )
+        .await
+        .unwrap();
+
+        let mut entries = HashMap::new();
+        while let Some(entry) = rx.recv().await {
+            entries.insert(entry.path().clone(), entry);
+        }
+        let link = |path: &str| entries[Path::new(path)].link().copied();
+        let a = link("a").unwrap();
+        assert_eq!(a.nlink, 2);
+        assert_eq!(link("nested/b"), Some(a));
+        assert_eq!(link("c"), link("d"));
+        assert_ne!(link("c").unwrap().group, a.group);
+        assert_eq!(link("single"), None);
+        assert_eq!(link("nested"), None);
+        assert!(entries[Path::new("a")].has_v3_metadata());
+    }
+
     #[tokio::test]
     async fn scan_ignores_excluded_special_files() {
         let temp = tempfile::tempdir().unwrap();
@@ -959,6 +1426,7 @@ mod tests {
                 Location::Include(PathBuf::from("excluded/socket")),
             ],
             &Vec::new(),
+            &MetadataCapture::default(),
             tx,
         )
         .await
@@ -1025,6 +1493,12 @@ mod tests {
             is_dir: false,
             checksum: 0,
             digest: None,
+            xattrs: None,
+            ownership: None,
+            rdev: 0,
+            allocated: None,
+            mtime_nsec: None,
+            link: None,
         };
 
         entry
@@ -1103,6 +1577,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn nanoseconds_are_compared_only_when_both_entries_know_them() {
+        let old = DirEntryWithMeta::test_file(PathBuf::from("a"), 1);
+        let mut new = old.clone();
+        new.set_mtime_nsec(Some(5));
+        assert_eq!(
+            changes(std::iter::once(&old), std::iter::once(&new)).count(),
+            0
+        );
+
+        let mut old = new.clone();
+        old.set_mtime_nsec(Some(4));
+        assert_eq!(
+            changes(std::iter::once(&old), std::iter::once(&new)).count(),
+            1
+        );
+        assert!(!change::same(
+            &Change::Added(old.clone()),
+            &Change::Added(new.clone())
+        ));
+
+        let v2: EntryV2 = new.clone().into();
+        assert!(DirEntryWithMeta::from(v2).same_mtime(&old));
+        assert!(new.has_v3_metadata());
+    }
+
     #[test]
     fn filesystem_boundary_relevance_includes_restrict_ancestors() {
         let scope = ScanScope::new(PathBuf::from("mount/wanted"), Vec::new());
@@ -1135,6 +1635,7 @@ pub async fn scan<P: AsRef<Path>, Q: AsRef<Path>>(
         &ScanScope::new(path.as_ref().to_path_buf(), Vec::new()),
         locations,
         ignore,
+        &MetadataCapture::default(),
         tx,
     )
     .await
@@ -1145,9 +1646,10 @@ pub async fn scan_scope<P: AsRef<Path>>(
     scope: &ScanScope,
     locations: &Locations,
     ignore: &Ignore,
+    capture: &MetadataCapture,
     tx: mpsc::Sender<DirEntryWithMeta>,
 ) -> Result<()> {
-    scan_scope_with_limit(base, scope, locations, ignore, tx, 64).await
+    scan_scope_with_limit(base, scope, locations, ignore, capture, tx, 64).await
 }
 
 pub(crate) async fn scan_scope_with_limit<P: AsRef<Path>>(
@@ -1155,6 +1657,7 @@ pub(crate) async fn scan_scope_with_limit<P: AsRef<Path>>(
     scope: &ScanScope,
     locations: &Locations,
     ignore: &Ignore,
+    capture: &MetadataCapture,
     tx: mpsc::Sender<DirEntryWithMeta>,
     limit: usize,
 ) -> Result<()> {
@@ -1176,14 +1679,8 @@ pub(crate) async fn scan_scope_with_limit<P: AsRef<Path>>(
     let locations = location::canonicalize(locations);
     let locations: Arc<Locations> = Arc::new(locations.iter().map(|l| l.prefix(&base)).collect());
 
-    // build ignore regex
-    use fnmatch_regex::glob_to_regex;
-    let mut ignore_regex: Regexes = Vec::new();
-    for p in ignore {
-        ignore_regex
-            .push(glob_to_regex(p).wrap_err_with(|| format!("invalid ignore pattern {p}"))?);
-    }
-    let ignore = Arc::new(ignore_regex);
+    let ignore = Arc::new(ignore::Patterns::new(ignore, "ignore")?);
+    let capture = Arc::new(capture.clone());
 
     let path = (*base).clone();
     let to = locations.len() - 1;
@@ -1192,8 +1689,10 @@ pub(crate) async fn scan_scope_with_limit<P: AsRef<Path>>(
         scope,
         base,
         ignore,
+        capture,
         dev,
         tx,
+        link_groups: Default::default(),
     });
     let initial = ScanJob {
         path,
@@ -1202,6 +1701,7 @@ pub(crate) async fn scan_scope_with_limit<P: AsRef<Path>>(
             from: 0,
             to: to,
         },
+        ignores: ignore::DirectoryIgnores::default(),
     };
     run_scan_scheduler(initial, limit, |job| {
         scan_one_directory(context.clone(), job)
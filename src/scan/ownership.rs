@@ -0,0 +1,389 @@
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::sync::Mutex;
+
+use color_eyre::eyre::{Result, WrapErr};
+use serde::{Deserialize, Serialize};
+
+/// How a profile's `[ownership]` section synchronizes file owners and groups.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OwnershipMode {
+    /// Numeric uid and gid, for machines that share an id space.
+    Numeric,
+    /// User and group names, resolved to local ids on each side.
+    Names,
+    /// Only the group, by name.
+    Group,
+}
+
+impl std::str::FromStr for OwnershipMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "numeric" => Ok(Self::Numeric),
+            "names" => Ok(Self::Names),
+            "group" => Ok(Self::Group),
+            _ => Err(format!(
+                "expected numeric, names, or group, found {:?}",
+                value
+            )),
+        }
+    }
+}
+
+/// Owner and group of an entry as captured by the scanning side.
+///
+/// Names are resolved where the entry was scanned; the applying side maps them back to its own
+/// ids, so the mode travels with the values.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ownership {
+    mode: OwnershipMode,
+    uid: u32,
+    gid: u32,
+    user: Option<String>,
+    group: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Principal<'a> {
+    Id(u32),
+    Name(&'a str),
+}
+
+impl Ownership {
+    pub(crate) fn from_metadata(meta: &std::fs::Metadata, mode: OwnershipMode) -> Self {
+        let (uid, gid) = (meta.uid(), meta.gid());
+        Self {
+            mode,
+            uid,
+            gid,
+            user: match mode {
+                OwnershipMode::Names => user_name(uid),
+                OwnershipMode::Numeric | OwnershipMode::Group => None,
+            },
+            group: match mode {
+                OwnershipMode::Names | OwnershipMode::Group => group_name(gid),
+                OwnershipMode::Numeric => None,
+            },
+        }
+    }
+
+    pub fn mode(&self) -> OwnershipMode {
+        self.mode
+    }
+
+    fn user_key(&self) -> Option<Principal<'_>> {
+        match (self.mode, &self.user) {
+            (OwnershipMode::Group, _) => None,
+            (_, Some(name)) => Some(Principal::Name(name)),
+            (_, None) => Some(Principal::Id(self.uid)),
+        }
+    }
+
+    fn group_key(&self) -> Principal<'_> {
+        match &self.group {
+            Some(name) => Principal::Name(name),
+            None => Principal::Id(self.gid),
+        }
+    }
+
+    /// Whether the two entries have the same owner and group under their synchronization mode.
+    pub(crate) fn same(&self, other: &Self) -> bool {
+        self.user_key() == other.user_key() && self.group_key() == other.group_key()
+    }
+
+    /// The local uid and gid this ownership asks for; `None` leaves that id unchanged, either
+    /// because the mode does not synchronize it or because its name is unknown here.
+    pub(crate) fn desired_ids(&self) -> (Option<u32>, Option<u32>) {
+        let uid = match (self.mode, &self.user) {
+            (OwnershipMode::Group, _) => None,
+            (_, Some(name)) => user_id(name),
+            (_, None) => Some(self.uid),
+        };
+        let gid = match &self.group {
+            Some(name) => group_id(name),
+            None => Some(self.gid),
+        };
+        (uid, gid)
+    }
+
+    /// Describes why applying this ownership over the current `uid` and `gid` would be skipped.
+    pub(crate) fn skip_reason(&self, uid: u32, gid: u32) -> Option<String> {
+        let (desired_uid, desired_gid) = self.desired_ids();
+        if let (Some(name), None) = (&self.user, desired_uid) {
+            return Some(format!("unknown user {}", name));
+        }
+        if let (Some(name), None) = (&self.group, desired_gid) {
+            return Some(format!("unknown group {}", name));
+        }
+        if permitted(uid, gid, desired_uid, desired_gid) {
+            None
+        } else {
+            Some(format!("not permitted to change ownership to {}", self))
+        }
+    }
+}
+
+impl std::fmt::Display for Ownership {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.user_key() {
+            Some(Principal::Name(name)) => write!(f, "{}:", name)?,
+            Some(Principal::Id(uid)) => write!(f, "{}:", uid)?,
+            None => write!(f, ":")?,
+        }
+        match self.group_key() {
+            Principal::Name(name) => write!(f, "{}", name),
+            Principal::Id(gid) => write!(f, "{}", gid),
+        }
+    }
+}
+
+/// Whether this process may change an entry owned by `uid`:`gid` to the desired ids. Only root
+/// may give files away; other users may move their own files between groups they belong to.
+fn permitted(uid: u32, gid: u32, desired_uid: Option<u32>, desired_gid: Option<u32>) -> bool {
+    let changes_user = desired_uid.is_some_and(|desired| desired != uid);
+    let changes_group = desired_gid.is_some_and(|desired| desired != gid);
+    if !changes_user && !changes_group {
+        return true;
+    }
+    let euid = unsafe { libc::geteuid() };
+    if euid == 0 {
+        return true;
+    }
+    !changes_user && uid == euid && desired_gid.is_some_and(is_member)
+}
+
+fn is_member(gid: u32) -> bool {
+    if gid == unsafe { libc::getegid() } {
+        return true;
+    }
+    let count = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+    if count <= 0 {
+        return false;
+    }
+    let mut groups = vec![0; count as usize];
+    let count = unsafe { libc::getgroups(count, groups.as_mut_ptr()) };
+    count > 0 && groups[..count as usize].contains(&gid)
+}
+
+/// Changes the owner and group of `file` as far as this process is permitted; ownership changes
+/// that are not permitted are skipped, since preflight already reported them.
+pub(crate) fn apply(file: &File, ownership: &Ownership, path: &Path) -> Result<()> {
+    let meta = file
+        .metadata()
+        .wrap_err_with(|| format!("failed to read ownership of {}", path.display()))?;
+    change(&meta, ownership, path, |uid, gid| unsafe {
+        libc::fchown(file.as_raw_fd(), uid, gid)
+    })
+}
+
+/// Like [`apply`], but by path without following a final symlink, for entries that cannot be
+/// opened without side effects.
+pub(crate) fn apply_path(path: &Path, ownership: &Ownership) -> Result<()> {
+    let meta = std::fs::symlink_metadata(path)
+        .wrap_err_with(|| format!("failed to read ownership of {}", path.display()))?;
+    let name = CString::new(path.as_os_str().as_bytes())
+        .wrap_err_with(|| format!("path contains an interior NUL byte: {}", path.display()))?;
+    change(&meta, ownership, path, |uid, gid| unsafe {
+        libc::lchown(name.as_ptr(), uid, gid)
+    })
+}
+
+fn change(
+    meta: &std::fs::Metadata,
+    ownership: &Ownership,
+    path: &Path,
+    chown: impl FnOnce(u32, u32) -> libc::c_int,
+) -> Result<()> {
+    let (uid, gid) = ownership.desired_ids();
+    let uid = uid.filter(|uid| *uid != meta.uid());
+    let gid = gid.filter(|gid| *gid != meta.gid());
+    if (uid.is_none() && gid.is_none()) || !permitted(meta.uid(), meta.gid(), uid, gid) {
+        return Ok(());
+    }
+    let result = chown(uid.unwrap_or(u32::MAX), gid.unwrap_or(u32::MAX));
+    if result == -1 {
+        return Err(io::Error::last_os_error())
+            .wrap_err_with(|| format!("failed to change ownership of {}", path.display()));
+    }
+    Ok(())
+}
+
+static USER_NAMES: Mutex<Option<HashMap<u32, Option<String>>>> = Mutex::new(None);
+static GROUP_NAMES: Mutex<Option<HashMap<u32, Option<String>>>> = Mutex::new(None);
+
+fn cached(
+    cache: &Mutex<Option<HashMap<u32, Option<String>>>>,
+    id: u32,
+    lookup: impl FnOnce(u32) -> Option<String>,
+) -> Option<String> {
+    let mut cache = cache
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    cache
+        .get_or_insert_with(HashMap::new)
+        .entry(id)
+        .or_insert_with(|| lookup(id))
+        .clone()
+}
+
+fn user_name(uid: u32) -> Option<String> {
+    cached(&USER_NAMES, uid, |uid| {
+        lookup(
+            |pwd, buffer, result| unsafe {
+                libc::getpwuid_r(uid, pwd, buffer.as_mut_ptr(), buffer.len(), result)
+            },
+            |pwd: &libc::passwd| c_name(pwd.pw_name),
+        )
+    })
+}
+
+fn group_name(gid: u32) -> Option<String> {
+    cached(&GROUP_NAMES, gid, |gid| {
+        lookup(
+            |grp, buffer, result| unsafe {
+                libc::getgrgid_r(gid, grp, buffer.as_mut_ptr(), buffer.len(), result)
+            },
+            |grp: &libc::group| c_name(grp.gr_name),
+        )
+    })
+}
+
+fn user_id(name: &str) -> Option<u32> {
+    let name = CString::new(name).ok()?;
+    lookup(
+        |pwd, buffer, result| unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                pwd,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                result,
+            )
+        },
+        |pwd: &libc::passwd| pwd.pw_uid,
+    )
+}
+
+fn group_id(name: &str) -> Option<u32> {
+    let name = CString::new(name).ok()?;
+    lookup(
+        |grp, buffer, result| unsafe {
+            libc::getgrnam_r(
+                name.as_ptr(),
+                grp,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                result,
+            )
+        },
+        |grp: &libc::group| grp.gr_gid,
+    )
+}
+
+fn c_name(name: *const libc::c_char) -> String {
+    unsafe { CStr::from_ptr(name) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Runs a reentrant passwd or group lookup, growing the string buffer as needed, and extracts
+/// the wanted field while the buffer it points into is still alive.
+fn lookup<T, R>(
+    mut call: impl FnMut(*mut T, &mut [libc::c_char], *mut *mut T) -> libc::c_int,
+    extract: impl FnOnce(&T) -> R,
+) -> Option<R> {
+    let mut buffer = vec![0 as libc::c_char; 1024];
+    loop {
+        let mut entry = std::mem::MaybeUninit::<T>::uninit();
+        let mut result = std::ptr::null_mut();
+        let error = call(entry.as_mut_ptr(), &mut buffer, &mut result);
+        if error == libc::ERANGE && buffer.len() < 1 << 20 {
+            buffer.resize(buffer.len() * 2, 0);
+            continue;
+        }
+        if error != 0 || result.is_null() {
+            return None;
+        }
+        return Some(extract(unsafe { &*result }));
+    }
+}
+
+#[cfg(test)]
+impl Ownership {
+    pub(crate) fn test_new(
+        mode: OwnershipMode,
+        uid: u32,
+        gid: u32,
+        user: Option<&str>,
+        group: Option<&str>,
+    ) -> Self {
+        Self {
+            mode,
+            uid,
+            gid,
+            user: user.map(str::to_string),
+            group: group.map(str::to_string),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_compare_by_name_and_group_mode_ignores_the_owner() {
+        let alice = Ownership::test_new(
+            OwnershipMode::Names,
+            1000,
+            100,
+            Some("alice"),
+            Some("staff"),
+        );
+        let alice_elsewhere =
+            Ownership::test_new(OwnershipMode::Names, 501, 20, Some("alice"), Some("staff"));
+        let bob = Ownership::test_new(OwnershipMode::Names, 1000, 100, Some("bob"), Some("staff"));
+        assert!(alice.same(&alice_elsewhere));
+        assert!(!alice.same(&bob));
+        assert_eq!(alice.to_string(), "alice:staff");
+
+        let group = Ownership::test_new(OwnershipMode::Group, 1000, 100, None, Some("staff"));
+        let other_owner = Ownership::test_new(OwnershipMode::Group, 0, 20, None, Some("staff"));
+        assert!(group.same(&other_owner));
+        assert_eq!(group.to_string(), ":staff");
+
+        let numeric = Ownership::test_new(OwnershipMode::Numeric, 1000, 100, None, None);
+        assert!(!numeric.same(&Ownership::test_new(
+            OwnershipMode::Numeric,
+            1000,
+            101,
+            None,
+            None
+        )));
+        assert_eq!(numeric.to_string(), "1000:100");
+    }
+
+    #[test]
+    fn unknown_names_are_left_unchanged_and_reported() {
+        let ownership = Ownership::test_new(
+            OwnershipMode::Names,
+            1000,
+            100,
+            Some("duet-no-such-user"),
+            Some("duet-no-such-group"),
+        );
+        assert_eq!(ownership.desired_ids(), (None, None));
+        assert_eq!(
+            ownership.skip_reason(0, 0).as_deref(),
+            Some("unknown user duet-no-such-user")
+        );
+    }
+}
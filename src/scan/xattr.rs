@@ -0,0 +1,247 @@
+use std::ffi::CString;
+use std::fs::File;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use serde::{Deserialize, Serialize};
+
+/// Extended attributes captured from the namespaces a profile selects, sorted by name.
+///
+/// The namespaces travel with the values, so an applier can remove the attributes the source no
+/// longer has without knowing which profile captured them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Xattrs {
+    namespaces: Vec<String>,
+    values: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl Xattrs {
+    pub fn values(&self) -> &[(Vec<u8>, Vec<u8>)] {
+        &self.values
+    }
+
+    fn selects(&self, name: &[u8]) -> bool {
+        selected(&self.namespaces, name)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn test_new(namespaces: &[&str], values: &[(&str, &str)]) -> Self {
+        let mut values: Vec<_> = values
+            .iter()
+            .map(|(name, value)| (name.as_bytes().to_vec(), value.as_bytes().to_vec()))
+            .collect();
+        values.sort();
+        Self {
+            namespaces: namespaces.iter().map(|ns| ns.to_string()).collect(),
+            values,
+        }
+    }
+}
+
+fn selected(namespaces: &[String], name: &[u8]) -> bool {
+    namespaces.iter().any(|namespace| {
+        name.len() > namespace.len()
+            && name.starts_with(namespace.as_bytes())
+            && name[namespace.len()] == b'.'
+    })
+}
+
+fn name_cstring(name: &[u8]) -> Result<CString> {
+    CString::new(name).map_err(|_| eyre!("extended attribute name contains a NUL byte"))
+}
+
+/// Reads the attributes of `path` (without following symlinks) in the given namespaces;
+/// `None` when no namespaces are selected.
+pub(crate) fn read(path: &Path, namespaces: &[String]) -> Result<Option<Xattrs>> {
+    if namespaces.is_empty() {
+        return Ok(None);
+    }
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| eyre!("path contains a NUL byte: {}", path.display()))?;
+    let names = sys::path_names(&c_path)
+        .wrap_err_with(|| format!("unable to list extended attributes of {}", path.display()))?;
+
+    let mut values = Vec::new();
+    for name in names.into_iter().filter(|name| selected(namespaces, name)) {
+        match sys::path_value(&c_path, &name_cstring(&name)?) {
+            Ok(value) => values.push((name, value)),
+            // removed between listing and reading
+            Err(error) if error.raw_os_error() == Some(sys::ENOATTR) => continue,
+            Err(error) => {
+                return Err(error).wrap_err_with(|| {
+                    format!(
+                        "unable to read extended attribute {} of {}",
+                        String::from_utf8_lossy(&name),
+                        path.display()
+                    )
+                })
+            }
+        }
+    }
+    values.sort();
+    Ok(Some(Xattrs {
+        namespaces: namespaces.to_vec(),
+        values,
+    }))
+}
+
+/// Makes the attributes of `file` in the captured namespaces match `xattrs` exactly.
+pub(crate) fn apply(file: &File, xattrs: &Xattrs, path: &Path) -> Result<()> {
+    let existing = sys::file_names(file)
+        .wrap_err_with(|| format!("failed to list extended attributes of {}", path.display()))?;
+    for name in existing {
+        if !xattrs.selects(&name)
+            || xattrs
+                .values
+                .binary_search_by(|(n, _)| n.as_slice().cmp(&name))
+                .is_ok()
+        {
+            continue;
+        }
+        sys::remove(file, &name_cstring(&name)?).wrap_err_with(|| {
+            format!(
+                "failed to remove extended attribute {} from {}",
+                String::from_utf8_lossy(&name),
+                path.display()
+            )
+        })?;
+    }
+    for (name, value) in &xattrs.values {
+        sys::set(file, &name_cstring(name)?, value).wrap_err_with(|| {
+            format!(
+                "failed to set extended attribute {} on {}",
+                String::from_utf8_lossy(name),
+                path.display()
+            )
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::ffi::CStr;
+    use std::fs::File;
+    use std::io;
+    use std::os::fd::AsRawFd;
+
+    pub(super) const ENOATTR: i32 = libc::ENODATA;
+
+    /// Runs a size-query-then-read xattr call, retrying when the value grows in between.
+    fn sized(mut call: impl FnMut(&mut [u8]) -> libc::ssize_t) -> io::Result<Vec<u8>> {
+        loop {
+            let size = call(&mut []);
+            if size == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut buffer = vec![0_u8; size as usize];
+            let read = call(&mut buffer);
+            if read == -1 {
+                let error = io::Error::last_os_error();
+                if error.raw_os_error() == Some(libc::ERANGE) {
+                    continue;
+                }
+                return Err(error);
+            }
+            buffer.truncate(read as usize);
+            return Ok(buffer);
+        }
+    }
+
+    fn names(call: impl FnMut(&mut [u8]) -> libc::ssize_t) -> io::Result<Vec<Vec<u8>>> {
+        match sized(call) {
+            Ok(names) => Ok(names
+                .split(|byte| *byte == 0)
+                .filter(|name| !name.is_empty())
+                .map(<[u8]>::to_vec)
+                .collect()),
+            // the filesystem does not support extended attributes, so there are none to report
+            Err(error) if error.raw_os_error() == Some(libc::ENOTSUP) => Ok(Vec::new()),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn cvt(result: libc::c_int) -> io::Result<()> {
+        if result == -1 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    pub(super) fn path_names(path: &CStr) -> io::Result<Vec<Vec<u8>>> {
+        names(|buffer| unsafe {
+            libc::llistxattr(path.as_ptr(), buffer.as_mut_ptr().cast(), buffer.len())
+        })
+    }
+
+    pub(super) fn path_value(path: &CStr, name: &CStr) -> io::Result<Vec<u8>> {
+        sized(|buffer| unsafe {
+            libc::lgetxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                buffer.as_mut_ptr().cast(),
+                buffer.len(),
+            )
+        })
+    }
+
+    pub(super) fn file_names(file: &File) -> io::Result<Vec<Vec<u8>>> {
+        names(|buffer| unsafe {
+            libc::flistxattr(file.as_raw_fd(), buffer.as_mut_ptr().cast(), buffer.len())
+        })
+    }
+
+    pub(super) fn set(file: &File, name: &CStr, value: &[u8]) -> io::Result<()> {
+        cvt(unsafe {
+            libc::fsetxattr(
+                file.as_raw_fd(),
+                name.as_ptr(),
+                value.as_ptr().cast(),
+                value.len(),
+                0,
+            )
+        })
+    }
+
+    pub(super) fn remove(file: &File, name: &CStr) -> io::Result<()> {
+        cvt(unsafe { libc::fremovexattr(file.as_raw_fd(), name.as_ptr()) })
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use std::ffi::CStr;
+    use std::fs::File;
+    use std::io;
+
+    pub(super) const ENOATTR: i32 = 0;
+
+    fn unsupported() -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "extended attribute synchronization is only supported on Linux",
+        )
+    }
+
+    pub(super) fn path_names(_path: &CStr) -> io::Result<Vec<Vec<u8>>> {
+        Err(unsupported())
+    }
+
+    pub(super) fn path_value(_path: &CStr, _name: &CStr) -> io::Result<Vec<u8>> {
+        Err(unsupported())
+    }
+
+    pub(super) fn file_names(_file: &File) -> io::Result<Vec<Vec<u8>>> {
+        Err(unsupported())
+    }
+
+    pub(super) fn set(_file: &File, _name: &CStr, _value: &[u8]) -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    pub(super) fn remove(_file: &File, _name: &CStr) -> io::Result<()> {
+        Err(unsupported())
+    }
+}
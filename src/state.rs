@@ -1,9 +1,10 @@
-use std::collections::VecDeque;
+use std::collections::{HashSet, VecDeque};
 use std::io::{BufWriter, Write};
 use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::SystemTime;
 
 use bincode::serde::{decode_from_slice, encode_into_std_write};
 use color_eyre::eyre::{eyre, Result, WrapErr};
@@ -15,9 +16,9 @@ use tokio::sync::mpsc;
 
 use crate::profile;
 use crate::progress;
-use crate::scan::change::LegacyChange;
+use crate::scan::change::{ChangeV2, LegacyChange};
 use crate::scan::location::Locations;
-use crate::scan::{self, Change, DirEntryWithMeta, LegacyEntry};
+use crate::scan::{self, Change, DirEntryWithMeta, EntryV2, LegacyEntry};
 use crate::sync;
 
 pub type Entries = Vec<DirEntryWithMeta>;
@@ -26,6 +27,7 @@ pub type LegacyChanges = Vec<LegacyChange>;
 
 const SNAPSHOT_MAGIC: &[u8; 8] = b"DUETSNP\0";
 const SNAPSHOT_VERSION_V2: u8 = 2;
+const SNAPSHOT_VERSION_V3: u8 = 3;
 const HASH_BUFFER_SIZE: usize = 1024 * 1024;
 const MAX_HASH_BUFFER_SIZE: usize = 4 * 1024 * 1024;
 const MAX_HASH_WORKERS: usize = 64;
@@ -36,6 +38,9 @@ const HASH_BUFFER_BYTES_ENV: &str = "DUET_HASH_BUFFER_BYTES";
 pub enum SnapshotFormat {
     LegacyV1,
     V2,
+    /// V2 plus the extended attributes and ownership captured for each entry and nanosecond
+    /// mtimes.
+    V3,
 }
 
 #[derive(Debug, Clone)]
@@ -44,11 +49,54 @@ pub struct LoadedEntries {
     pub format: SnapshotFormat,
 }
 
+/// Exact scan result layout returned by the V2 RPCs, without extended attributes.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChangesV2 {
+    pub changes: Vec<ChangeV2>,
+    pub current: Vec<EntryV2>,
+    pub migration_needed: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangesV3 {
     pub changes: Changes,
     pub current: Entries,
     pub migration_needed: bool,
+    /// Sockets the scan skipped at the profile's request.
+    pub skipped: Vec<PathBuf>,
+}
+
+/// An entry a scan found but the profile's `[filter]` section keeps out, with the filter's name.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FilteredEntry {
+    pub path: PathBuf,
+    pub filter: String,
+}
+
+/// Whether `path` is one of `paths` or under one of them.
+pub fn under_any(paths: &HashSet<&Path>, path: &Path) -> bool {
+    path.ancestors().any(|ancestor| paths.contains(ancestor))
+}
+
+impl From<ChangesV2> for ChangesV3 {
+    fn from(changes: ChangesV2) -> Self {
+        Self {
+            changes: changes.changes.into_iter().map(Into::into).collect(),
+            current: changes.current.into_iter().map(Into::into).collect(),
+            migration_needed: changes.migration_needed,
+            skipped: Vec::new(),
+        }
+    }
+}
+
+impl From<ChangesV3> for ChangesV2 {
+    fn from(changes: ChangesV3) -> Self {
+        Self {
+            changes: changes.changes.into_iter().map(Into::into).collect(),
+            current: changes.current.into_iter().map(Into::into).collect(),
+            migration_needed: changes.migration_needed,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -57,6 +105,8 @@ pub struct ScanContext {
     pub changes: Changes,
     pub current: Entries,
     pub migration_needed: bool,
+    pub skipped: Vec<PathBuf>,
+    pub filtered: Vec<FilteredEntry>,
 }
 
 pub fn decode_entries(contents: &[u8]) -> Result<LoadedEntries> {
@@ -66,18 +116,24 @@ pub fn decode_entries(contents: &[u8]) -> Result<LoadedEntries> {
             .get(SNAPSHOT_MAGIC.len())
             .copied()
             .ok_or_else(|| eyre!("truncated snapshot header"))?;
-        if version != SNAPSHOT_VERSION_V2 {
-            return Err(eyre!("unsupported snapshot version {version}"));
-        }
         let payload = &contents[SNAPSHOT_MAGIC.len() + 1..];
-        let (entries, consumed): (Entries, usize) = decode_from_slice(payload, config)?;
+        let (entries, consumed, format) = match version {
+            SNAPSHOT_VERSION_V2 => {
+                let (entries, consumed): (Vec<EntryV2>, usize) =
+                    decode_from_slice(payload, config)?;
+                let entries = entries.into_iter().map(Into::into).collect();
+                (entries, consumed, SnapshotFormat::V2)
+            }
+            SNAPSHOT_VERSION_V3 => {
+                let (entries, consumed): (Entries, usize) = decode_from_slice(payload, config)?;
+                (entries, consumed, SnapshotFormat::V3)
+            }
+            _ => return Err(eyre!("unsupported snapshot version {version}")),
+        };
         if consumed != payload.len() {
-            return Err(eyre!("trailing bytes in V2 snapshot"));
+            return Err(eyre!("trailing bytes in V{version} snapshot"));
         }
-        Ok(LoadedEntries {
-            entries,
-            format: SnapshotFormat::V2,
-        })
+        Ok(LoadedEntries { entries, format })
     } else {
         let (legacy, consumed): (Vec<LegacyEntry>, usize) = decode_from_slice(contents, config)?;
         if consumed != contents.len() {
@@ -119,9 +175,17 @@ fn write_entries(writer: &mut impl Write, entries: &Entries, format: SnapshotFor
             let legacy: Vec<LegacyEntry> = entries.iter().map(Into::into).collect();
             encode_into_std_write(&legacy, writer, bincode::config::legacy())?;
         }
-        SnapshotFormat::V2 => {
+        // V2 cannot carry extended attributes, ownership, or nanosecond mtimes, so entries that
+        // captured them are saved as V3.
+        SnapshotFormat::V2 if !entries.iter().any(|entry| entry.has_v3_metadata()) => {
+            let entries: Vec<EntryV2> = entries.iter().map(Into::into).collect();
             writer.write_all(SNAPSHOT_MAGIC)?;
             writer.write_all(&[SNAPSHOT_VERSION_V2])?;
+            encode_into_std_write(&entries, writer, bincode::config::legacy())?;
+        }
+        SnapshotFormat::V2 | SnapshotFormat::V3 => {
+            writer.write_all(SNAPSHOT_MAGIC)?;
+            writer.write_all(&[SNAPSHOT_VERSION_V3])?;
             encode_into_std_write(entries, writer, bincode::config::legacy())?;
         }
     }
@@ -160,6 +224,84 @@ pub fn save_entries(statefile: &PathBuf, entries: &Entries) -> Result<()> {
     save_entries_as(statefile, entries, SnapshotFormat::V2)
 }
 
+/// A snapshot as `duet status` shows it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateSummary {
+    /// When a sync of the snapshot last finished successfully; `None` before the first one.
+    pub synced: Option<SystemTime>,
+    pub entries: usize,
+    pub bytes: u64,
+}
+
+/// The file beside a snapshot that records when a sync of it last succeeded. Snapshots are also
+/// saved by checkpoints of syncs that fail afterwards, so their modification time does not tell.
+fn synced_path(statefile: &Path) -> Result<PathBuf> {
+    let file_name = statefile
+        .file_name()
+        .ok_or_else(|| eyre!("state file {} has no file name", statefile.display()))?;
+    Ok(statefile.with_file_name(format!(".{}.duet-synced", file_name.to_string_lossy())))
+}
+
+fn last_synced(statefile: &Path) -> Result<Option<SystemTime>> {
+    let path = synced_path(statefile)?;
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => {
+            return Err(error)
+                .wrap_err_with(|| format!("unable to read sync time {}", path.display()))
+        }
+    };
+    let synced = chrono::DateTime::parse_from_rfc3339(contents.trim())
+        .wrap_err_with(|| format!("invalid sync time in {}", path.display()))?;
+    Ok(Some(synced.into()))
+}
+
+pub fn summarize(statefile: &Path) -> Result<StateSummary> {
+    let synced = last_synced(statefile)?;
+    let metadata = match std::fs::metadata(statefile) {
+        Ok(metadata) => metadata,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(StateSummary {
+                synced,
+                entries: 0,
+                bytes: 0,
+            })
+        }
+        Err(error) => {
+            return Err(error)
+                .wrap_err_with(|| format!("unable to inspect state file {}", statefile.display()))
+        }
+    };
+    Ok(StateSummary {
+        synced,
+        entries: load_entries_with_format(statefile)?.entries.len(),
+        bytes: metadata.len(),
+    })
+}
+
+/// Records that a sync of the snapshot finished successfully now.
+pub fn mark_synced(statefile: &Path) -> Result<()> {
+    let path = synced_path(statefile)?;
+    if let Some(parent) = path.parent() {
+        sync::create_dir_all_durable(parent)
+            .wrap_err_with(|| format!("unable to create state directory {}", parent.display()))?;
+    }
+    let now = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Nanos, true);
+    use atomicwrites::{AllowOverwrite, AtomicFile};
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true).mode(0o600);
+    AtomicFile::new(&path, AllowOverwrite)
+        .write_with_options(
+            |file| {
+                file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+                writeln!(file, "{}", now)
+            },
+            options,
+        )
+        .wrap_err_with(|| format!("unable to save sync time {}", path.display()))
+}
+
 async fn collect_scan<F>(scanner: F, mut rx: mpsc::Receiver<DirEntryWithMeta>) -> Result<Entries>
 where
     F: std::future::Future<Output = Result<()>>,
@@ -209,12 +351,14 @@ pub async fn scan_entries(
     path: &PathBuf,
     locations: &Locations,
     ignore: &profile::Ignore,
+    capture: &profile::MetadataCapture,
 ) -> Result<Entries> {
     scan_scope_entries(
         base,
         &scan::ScanScope::new(path.clone(), Vec::new()),
         locations,
         ignore,
+        capture,
     )
     .await
 }
@@ -224,13 +368,73 @@ pub async fn scan_scope_entries(
     scope: &scan::ScanScope,
     locations: &Locations,
     ignore: &profile::Ignore,
+    capture: &profile::MetadataCapture,
 ) -> Result<Entries> {
+    let (entries, _skipped, _filtered) =
+        scan_scope_entries_and_skipped(base, scope, locations, ignore, capture).await?;
+    Ok(entries)
+}
+
+/// Scans like `scan_scope_entries()`, separating the sockets the profile asked to skip and the
+/// entries its `[filter]` section keeps out, with everything under filtered directories.
+async fn scan_scope_entries_and_skipped(
+    base: &PathBuf,
+    scope: &scan::ScanScope,
+    locations: &Locations,
+    ignore: &profile::Ignore,
+    capture: &profile::MetadataCapture,
+) -> Result<(Entries, Vec<PathBuf>, Vec<FilteredEntry>)> {
     let base = base.clone();
     let scope = scope.clone();
     let locations = locations.clone();
     let ignore = ignore.clone();
+    let capture = capture.clone();
     let (tx, rx) = mpsc::channel(32);
-    collect_scan(scan::scan_scope(&base, &scope, &locations, &ignore, tx), rx).await
+    let (entries, sockets): (Entries, Entries) = collect_scan(
+        scan::scan_scope(&base, &scope, &locations, &ignore, &capture, tx),
+        rx,
+    )
+    .await?
+    .into_iter()
+    .partition(|entry| !entry.is_socket());
+    let skipped = sockets
+        .into_iter()
+        .map(|socket| {
+            log::info!("Skipping socket {}", socket.path().display());
+            socket.path().clone()
+        })
+        .collect();
+    if capture.filters.is_empty() {
+        return Ok((entries, skipped, Vec::new()));
+    }
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64);
+    let mut kept = Vec::with_capacity(entries.len());
+    let mut filtered: Vec<FilteredEntry> = Vec::new();
+    let mut filtered_dir: Option<PathBuf> = None;
+    for entry in entries {
+        if filtered_dir
+            .as_ref()
+            .is_some_and(|dir| entry.path().starts_with(dir))
+        {
+            continue;
+        }
+        match capture.filters.filtered_by(&entry, now) {
+            Some(filter) => {
+                if entry.is_dir() {
+                    filtered_dir = Some(entry.path().clone());
+                }
+                filtered.push(FilteredEntry {
+                    path: entry.path().clone(),
+                    filter: filter.to_string(),
+                });
+            }
+            None => kept.push(entry),
+        }
+    }
+    Ok((kept, skipped, filtered))
 }
 
 pub async fn hash_manifest(base: &PathBuf, entries: &mut Entries) -> Result<()> {
@@ -450,15 +654,45 @@ pub fn replace_scope(entries: &mut Entries, scope: &scan::ScanScope, current: &E
     entries.sort();
 }
 
+/// The paths of the removals in `changes` that are still on disk under `base` because one of the
+/// per-directory ignore files in `names` ignores them now.
+fn newly_ignored(base: &Path, changes: &Changes, names: &[String]) -> Result<HashSet<PathBuf>> {
+    let mut ignored = HashSet::new();
+    if names.is_empty() {
+        return Ok(ignored);
+    }
+    let mut removed: Vec<&PathBuf> = changes
+        .iter()
+        .filter_map(|change| match change {
+            Change::Removed(old) => Some(old.path()),
+            _ => None,
+        })
+        .collect();
+    removed.sort();
+    let mut walk = scan::ignore::IgnoreFileWalk::new(base, names)?;
+    for path in removed {
+        let Ok(meta) = std::fs::symlink_metadata(base.join(path)) else {
+            continue;
+        };
+        if walk.is_ignored(path, meta.is_dir())? {
+            log::debug!("Dropping newly ignored {}", path.display());
+            ignored.insert(path.clone());
+        }
+    }
+    Ok(ignored)
+}
+
 pub async fn old_and_changes(
     base: &PathBuf,
     scope: &scan::ScanScope,
     locations: &Locations,
     ignore: &profile::Ignore,
+    capture: &profile::MetadataCapture,
     statefile: Option<&PathBuf>,
     strong: bool,
 ) -> Result<ScanContext> {
-    let restricted_current_scan = scan_scope_entries(base, scope, locations, ignore);
+    let restricted_current_scan =
+        scan_scope_entries_and_skipped(base, scope, locations, ignore, capture);
     let loaded = async {
         match statefile {
             Some(path) => load_entries_with_format(path),
@@ -470,7 +704,7 @@ pub async fn old_and_changes(
     };
     let (loaded, current) = tokio::join!(loaded, restricted_current_scan);
     let loaded = loaded?;
-    let mut current = current?;
+    let (mut current, skipped, filtered) = current?;
     current.retain(|entry| scope.selected(entry.path()));
     let restricted_old: Vec<_> = loaded
         .entries
@@ -479,6 +713,18 @@ pub async fn old_and_changes(
         .collect();
     let mut changes: Changes =
         scan::changes(restricted_old.iter().copied(), current.iter()).collect();
+    // entries that an edited ignore file ignores now, or that crossed a filter threshold, are
+    // still on disk: drop them from the baseline instead of reporting them removed, so the
+    // other side keeps its copies
+    let mut newly_ignored = newly_ignored(base, &changes, &capture.ignore_files)?;
+    let filtered_paths: HashSet<&Path> =
+        filtered.iter().map(|entry| entry.path.as_path()).collect();
+    for change in &changes {
+        if matches!(change, Change::Removed(_)) && under_any(&filtered_paths, change.path()) {
+            newly_ignored.insert(change.path().clone());
+        }
+    }
+    changes.retain(|change| !newly_ignored.contains(change.path()));
     for entry in &mut current {
         if changes
             .binary_search_by(|change| change.path().cmp(entry.path()))
@@ -532,11 +778,17 @@ pub async fn old_and_changes(
         }
     }
 
+    let mut all_old = loaded.entries;
+    if !newly_ignored.is_empty() {
+        all_old.retain(|entry| !newly_ignored.contains(entry.path()));
+    }
     Ok(ScanContext {
-        all_old: loaded.entries,
+        all_old,
         changes,
         current,
         migration_needed,
+        skipped,
+        filtered,
     })
 }
 
@@ -822,6 +1074,7 @@ mod tests {
             &PathBuf::new(),
             &locations,
             &Vec::new(),
+            &Default::default(),
         )
         .await
         .unwrap();
@@ -830,6 +1083,7 @@ mod tests {
             &PathBuf::new(),
             &locations,
             &Vec::new(),
+            &Default::default(),
         )
         .await
         .unwrap();
@@ -892,6 +1146,7 @@ mod tests {
             &scan::ScanScope::default(),
             &locations,
             &Vec::new(),
+            &Default::default(),
             Some(&state_path),
             true,
         )
@@ -913,6 +1168,85 @@ mod tests {
         assert_eq!(loaded.entries[0].digest(), Some(ContentDigest([9; 32])));
     }
 
+    #[test]
+    fn snapshot_with_captured_xattrs_is_saved_as_v3() {
+        let mut entry = DirEntryWithMeta::test_file(PathBuf::from("a"), 7);
+        entry.set_xattrs(Some(scan::Xattrs::test_new(
+            &["user"],
+            &[("user.tag", "x")],
+        )));
+        let mut bytes = Vec::new();
+        write_entries(&mut bytes, &vec![entry.clone()], SnapshotFormat::V2).unwrap();
+        let loaded = decode_entries(&bytes).unwrap();
+        assert_eq!(loaded.format, SnapshotFormat::V3);
+        assert_eq!(loaded.entries[0].xattrs(), entry.xattrs());
+
+        let mut bytes = Vec::new();
+        write_entries(
+            &mut bytes,
+            &vec![DirEntryWithMeta::test_file(PathBuf::from("a"), 7)],
+            SnapshotFormat::V2,
+        )
+        .unwrap();
+        assert_eq!(decode_entries(&bytes).unwrap().format, SnapshotFormat::V2);
+    }
+
+    #[tokio::test]
+    async fn xattr_edits_are_reported_as_modifications() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("base");
+        let state_path = dir.path().join("state");
+        std::fs::create_dir_all(base.join("tree")).unwrap();
+        std::fs::write(base.join("tree/file"), b"contents").unwrap();
+        let set = |values: &[(&str, &str)]| {
+            let file = std::fs::File::open(base.join("tree/file")).unwrap();
+            let xattrs = scan::Xattrs::test_new(&["user"], values);
+            scan::xattr::apply(&file, &xattrs, &base.join("tree/file")).unwrap();
+        };
+        set(&[("user.tag", "one")]);
+        let locations = vec![crate::scan::location::Location::Include(PathBuf::from(
+            "tree",
+        ))];
+        let capture = profile::MetadataCapture {
+            xattrs: vec!["user".to_string()],
+            ..profile::MetadataCapture::default()
+        };
+        let scope = scan::ScanScope::default();
+        let ignore = Vec::new();
+        let scan = || {
+            old_and_changes(
+                &base,
+                &scope,
+                &locations,
+                &ignore,
+                &capture,
+                Some(&state_path),
+                true,
+            )
+        };
+
+        let first = scan().await.unwrap();
+        let file = first
+            .current
+            .iter()
+            .find(|entry| entry.path() == Path::new("tree/file"))
+            .unwrap();
+        assert_eq!(
+            file.xattrs().unwrap().values(),
+            &[(b"user.tag".to_vec(), b"one".to_vec())]
+        );
+        save_entries(&state_path, &first.current).unwrap();
+        assert!(scan().await.unwrap().changes.is_empty());
+
+        set(&[("user.tag", "two")]);
+        let changes = scan().await.unwrap().changes;
+        assert_eq!(changes.len(), 1);
+        assert!(matches!(
+            &changes[0],
+            Change::Modified(_, new) if new.path() == Path::new("tree/file")
+        ));
+    }
+
     #[test]
     fn snapshot_file_is_private() {
         let dir = tempfile::tempdir().unwrap();
@@ -924,6 +1258,30 @@ mod tests {
         );
     }
 
+    #[test]
+    fn only_a_recorded_success_counts_as_synced() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_path = dir.path().join("nested/state");
+        assert_eq!(summarize(&state_path).unwrap().synced, None);
+
+        // a checkpoint of a sync that later fails saves the snapshot without recording success
+        save_entries(&state_path, &Vec::new()).unwrap();
+        assert_eq!(summarize(&state_path).unwrap().synced, None);
+
+        let before = SystemTime::now();
+        mark_synced(&state_path).unwrap();
+        let synced = summarize(&state_path).unwrap().synced.unwrap();
+        assert!(synced >= before, "{:?} < {:?}", synced, before);
+
+        save_entries(&state_path, &Vec::new()).unwrap();
+        assert_eq!(summarize(&state_path).unwrap().synced, Some(synced));
+        let sync_time = dir.path().join("nested/.state.duet-synced");
+        assert_eq!(
+            std::fs::metadata(sync_time).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+    }
+
     #[tokio::test]
     async fn restricted_migration_hashes_scope_and_preserves_outside_hybrid_entries() {
         let dir = tempfile::tempdir().unwrap();
@@ -943,6 +1301,7 @@ mod tests {
             &scan::ScanScope::new(PathBuf::from("scope"), Vec::new()),
             &locations,
             &Vec::new(),
+            &Default::default(),
             Some(&state_path),
             true,
         )
@@ -998,6 +1357,7 @@ mod tests {
             &excluded_scope,
             &locations,
             &Vec::new(),
+            &Default::default(),
             Some(&state_path),
             true,
         )
@@ -1019,6 +1379,7 @@ mod tests {
             &scan::ScanScope::new(PathBuf::from("tree"), Vec::new()),
             &locations,
             &Vec::new(),
+            &Default::default(),
             Some(&state_path),
             true,
         )
@@ -15,11 +15,12 @@ use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 
-use crate::actions::Action;
+use crate::actions::{Action, Move};
 use crate::profile::{Ignore, Prune};
+use crate::scan::ignore::Patterns;
 use crate::scan::location::{Location, Locations};
 
-use crate::rustsync::{compare, compare_stream, restore_seek, signature, DeltaOp};
+use crate::rustsync::{compare, compare_stream, restore_seek, signature_sparse, DeltaOp};
 pub use crate::rustsync::{Delta, Signature};
 
 #[allow(dead_code)]
@@ -324,13 +325,17 @@ fn staging_dependency_group(
             }
             Action::Identical(_, _) => (None, action_change(action)),
             Action::Conflict(_, _) => unreachable!("unresolved conflicts were rejected"),
+            Action::LocalMove(_) | Action::RemoteMove(_) => {
+                unreachable!("moves are applied before staging")
+            }
         };
         let detail_kind = apply_detail_kind_for_change(change);
         if local.is_none() || detail_kind.is_none() {
             continue;
         }
-        // Diff staging is planned pessimistically at the full reconstructed logical size.
-        let size = change_output_entry(change)?.size();
+        // Diff staging is planned pessimistically at the full reconstructed size, counting only
+        // the allocated bytes of a sparse output, whose holes the apply side preserves.
+        let size = change_output_entry(change)?.physical_size();
         if local == Some(true) {
             group.local_reconstructed_bytes = checked_staging_add(
                 group.local_reconstructed_bytes,
@@ -704,6 +709,10 @@ const MAX_SIGNATURE_WINDOW: u32 = 16 * 1024 * 1024;
 const MAX_DETAIL_CHUNK_BYTES: u32 = 64 * 1024 * 1024;
 const MAX_DETAIL_BATCH_FRAMES: u32 = 4096;
 const MAX_DETAIL_BATCH_PAYLOAD_BYTES: u32 = 64 * 1024 * 1024;
+/// Deflate levels for the literal bytes of detail frames: 0 sends them as they are.
+pub const DEFAULT_COMPRESSION_LEVEL: u8 = 1;
+pub const MAX_COMPRESSION_LEVEL: u8 = 10;
+const MIN_COMPRESSED_LITERAL_BYTES: usize = 512;
 const COPY_BUFFER_BYTES: usize = 128 * 1024;
 const SYNCED_MODE_MASK: u32 = 0o7777;
 const DEFAULT_OUTPUT_BATCH_FILES: usize = 256;
@@ -756,6 +765,13 @@ impl ApplyPreflightReport {
     }
 }
 
+/// An entry whose captured ownership this side will not apply, and why.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OwnershipSkip {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct RemovalBlocker {
     pub parent: PathBuf,
@@ -1071,6 +1087,13 @@ pub fn validate_actions(actions: &[Action]) -> Result<()> {
                 validate_change_paths(left)?;
                 validate_change_paths(right)?;
             }
+            Action::LocalMove(m) | Action::RemoteMove(m) => {
+                return Err(eyre!(
+                    "move of {} to {} must be applied before the remaining actions",
+                    m.from.path().display(),
+                    m.to.path().display()
+                ));
+            }
         }
     }
     Ok(())
@@ -1085,6 +1108,9 @@ fn validate_staged_structure(actions: &[Action], all_old: &[Entry]) -> Result<()
             | Action::ResolvedLocal((_, _), change)
             | Action::ResolvedRemote((_, _), change) => Some(change),
             Action::Conflict(_, _) | Action::Identical(_, _) => None,
+            Action::LocalMove(_) | Action::RemoteMove(_) => {
+                unreachable!("validate_actions rejects moves")
+            }
         };
         if let Some(Change::Modified(old, new)) = effective_change {
             if old.is_dir() && !new.is_dir() {
@@ -1099,6 +1125,9 @@ fn validate_staged_structure(actions: &[Action], all_old: &[Entry]) -> Result<()
             Action::Conflict(left, right) | Action::Identical(left, right) => vec![left, right],
             Action::ResolvedLocal((left, right), resolved)
             | Action::ResolvedRemote((left, right), resolved) => vec![left, right, resolved],
+            Action::LocalMove(_) | Action::RemoteMove(_) => {
+                unreachable!("validate_actions rejects moves")
+            }
         };
         let mut paths = Vec::with_capacity(changes.len());
         for change in changes {
@@ -1149,7 +1178,12 @@ fn validate_staged_structure(actions: &[Action], all_old: &[Entry]) -> Result<()
 }
 
 pub fn validate_strong_actions(actions: &[Action]) -> Result<()> {
-    validate_actions(actions)?;
+    for action in actions {
+        match action {
+            Action::LocalMove(m) | Action::RemoteMove(m) => validate_move(m)?,
+            _ => validate_actions(std::slice::from_ref(action))?,
+        }
+    }
     for action in actions {
         match action {
             Action::Local(change) | Action::Remote(change) => validate_strong_change(change)?,
@@ -1163,6 +1197,58 @@ pub fn validate_strong_actions(actions: &[Action]) -> Result<()> {
                 validate_strong_change(right)?;
                 validate_strong_change(change)?;
             }
+            Action::LocalMove(m) | Action::RemoteMove(m) => {
+                validate_strong_entry(&m.from)?;
+                validate_strong_entry(&m.to)?;
+                for (old, new) in &m.contents {
+                    validate_strong_entry(old)?;
+                    validate_strong_entry(new)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn validate_move(m: &Move) -> Result<()> {
+    validate_entry_path(&m.from)?;
+    validate_entry_path(&m.to)?;
+    let (from, to) = (m.from.path(), m.to.path());
+    if to.starts_with(from) || from.starts_with(to) {
+        return Err(eyre!(
+            "move of {} to {} must not nest source and destination",
+            from.display(),
+            to.display()
+        ));
+    }
+    if m.from.is_dir() != m.to.is_dir() || m.from.target() != m.to.target() {
+        return Err(eyre!(
+            "move of {} to {} must preserve the entry type",
+            from.display(),
+            to.display()
+        ));
+    }
+    if !m.from.is_dir() && !m.contents.is_empty() {
+        return Err(eyre!(
+            "move of non-directory {} has contents",
+            from.display()
+        ));
+    }
+    for (old, new) in &m.contents {
+        validate_entry_path(old)?;
+        validate_entry_path(new)?;
+        let relocated = old
+            .path()
+            .strip_prefix(from)
+            .ok()
+            .filter(|relative| new.path().strip_prefix(to).ok() == Some(*relative));
+        if relocated.is_none() {
+            return Err(eyre!(
+                "moved entry {} does not correspond to {} below {}",
+                new.path().display(),
+                old.path().display(),
+                to.display()
+            ));
         }
     }
     Ok(())
@@ -1234,7 +1320,7 @@ fn validate_change_paths(change: &Change) -> Result<()> {
     }
 }
 
-fn validate_entry_path(entry: &Entry) -> Result<()> {
+pub(crate) fn validate_entry_path(entry: &Entry) -> Result<()> {
     validate_canonical_entry_path(entry.path())
         .wrap_err_with(|| format!("invalid action entry path {}", entry.path().display()))
 }
@@ -1268,8 +1354,9 @@ pub fn get_signatures_with_config(
             | Action::ResolvedLocal((_, _), Change::Modified(e1, e2)) => {
                 if e1.is_file() && e2.is_file() && !e1.same_contents(&e2) {
                     let f = fs::File::open(safe_join(base, e1.path())?)?;
+                    let len = f.metadata()?.len();
                     let block = vec![0; window_config.window_for_size(e1.size())];
-                    let sig = signature(f, block)?;
+                    let sig = signature_sparse(&f, block, len, |offset| hole_end(&f, offset, len))?;
                     signatures.push(SignatureWithPath(e1.path().clone(), sig));
                 }
             }
@@ -1303,9 +1390,110 @@ pub enum DetailPayload {
     FileBytes(Vec<u8>),
     FileEnd,
     DiffBegin,
-    DiffCopy { offset: u64, len: u64 },
+    DiffCopy {
+        offset: u64,
+        len: u64,
+    },
     DiffBytes(Vec<u8>),
     DiffEnd,
+    /// A run of zeros the source holds as a hole; sent only to peers with `sparse-files-v1`.
+    FileHole(u64),
+    DiffHole(u64),
+    /// Deflated `FileBytes` and `DiffBytes`; sent only to peers with `compressed-details-v1`.
+    FileBytesCompressed(CompressedBytes),
+    DiffBytesCompressed(CompressedBytes),
+    /// The whole source of a `FileBegin`, to copy from where it is; sent only between a client
+    /// and its in-process server, which share a filesystem.
+    FileCopy {
+        source: PathBuf,
+        len: u64,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompressedBytes {
+    /// The length of the bytes once inflated.
+    pub len: u32,
+    pub data: serde_bytes::ByteBuf,
+}
+
+impl CompressedBytes {
+    /// Deflates `bytes` at `level`, unless that saves less than a sixteenth of them.
+    fn new(bytes: &[u8], level: u8) -> Option<Self> {
+        let len = u32::try_from(bytes.len()).ok()?;
+        let data = miniz_oxide::deflate::compress_to_vec(bytes, level.min(MAX_COMPRESSION_LEVEL));
+        if data.len() > bytes.len() - bytes.len() / 16 {
+            return None;
+        }
+        Some(Self {
+            len,
+            data: serde_bytes::ByteBuf::from(data),
+        })
+    }
+
+    fn inflate(&self) -> Result<Vec<u8>> {
+        let bytes =
+            miniz_oxide::inflate::decompress_to_vec_with_limit(&self.data, self.len as usize)
+                .map_err(|e| eyre!("unable to inflate detail frame: {:?}", e.status))?;
+        if bytes.len() != self.len as usize {
+            return Err(eyre!(
+                "inflated detail frame has {} bytes, expected {}",
+                bytes.len(),
+                self.len
+            ));
+        }
+        Ok(bytes)
+    }
+}
+
+impl DetailFrame {
+    /// The frame with its literal bytes inflated, as appliers expect them.
+    pub fn decompressed(self) -> Result<Self> {
+        let payload = match self.payload {
+            DetailPayload::FileBytesCompressed(bytes) => DetailPayload::FileBytes(bytes.inflate()?),
+            DetailPayload::DiffBytesCompressed(bytes) => DetailPayload::DiffBytes(bytes.inflate()?),
+            payload => payload,
+        };
+        Ok(Self {
+            action_index: self.action_index,
+            payload,
+        })
+    }
+}
+
+/// Deflates the literal bytes of the frames a producer sends. Once a chunk of an action's data
+/// doesn't compress, as media and archives don't, the rest of that action goes as it is.
+#[derive(Debug, Default)]
+struct FrameCompressor {
+    level: u8,
+    incompressible_action: Option<u32>,
+}
+
+impl FrameCompressor {
+    fn compress(&mut self, frame: DetailFrame) -> DetailFrame {
+        let bytes = match &frame.payload {
+            DetailPayload::FileBytes(bytes) | DetailPayload::DiffBytes(bytes) => bytes,
+            _ => return frame,
+        };
+        if self.level == 0
+            || bytes.len() < MIN_COMPRESSED_LITERAL_BYTES
+            || self.incompressible_action == Some(frame.action_index)
+        {
+            return frame;
+        }
+        let Some(compressed) = CompressedBytes::new(bytes, self.level) else {
+            self.incompressible_action = Some(frame.action_index);
+            return frame;
+        };
+        let payload = match frame.payload {
+            DetailPayload::FileBytes(_) => DetailPayload::FileBytesCompressed(compressed),
+            _ => DetailPayload::DiffBytesCompressed(compressed),
+        };
+        DetailFrame {
+            action_index: frame.action_index,
+            payload,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -1338,7 +1526,13 @@ pub fn detail_transfer_bytes(actions: &[Action]) -> u64 {
 pub fn detail_frame_transfer_bytes(frame: &DetailFrame) -> u64 {
     match &frame.payload {
         DetailPayload::FileBytes(bytes) | DetailPayload::DiffBytes(bytes) => bytes.len() as u64,
-        DetailPayload::DiffCopy { len, .. } => *len,
+        DetailPayload::FileBytesCompressed(bytes) | DetailPayload::DiffBytesCompressed(bytes) => {
+            u64::from(bytes.len)
+        }
+        DetailPayload::DiffCopy { len, .. }
+        | DetailPayload::FileHole(len)
+        | DetailPayload::DiffHole(len)
+        | DetailPayload::FileCopy { len, .. } => *len,
         DetailPayload::FileBegin
         | DetailPayload::FileEnd
         | DetailPayload::DiffBegin
@@ -1350,13 +1544,29 @@ pub fn detail_frames_transfer_bytes(frames: &[DetailFrame]) -> u64 {
     frames.iter().map(detail_frame_transfer_bytes).sum()
 }
 
+/// The literal bytes frames carry over the connection, deflated or not.
+pub fn detail_frames_wire_bytes(frames: &[DetailFrame]) -> u64 {
+    frames
+        .iter()
+        .map(|frame| match &frame.payload {
+            DetailPayload::FileBytes(bytes) | DetailPayload::DiffBytes(bytes) => bytes.len() as u64,
+            DetailPayload::FileBytesCompressed(bytes)
+            | DetailPayload::DiffBytesCompressed(bytes) => bytes.data.len() as u64,
+            _ => 0,
+        })
+        .sum()
+}
+
 fn action_detail_bytes(action: &Action) -> u64 {
     let change = match action {
         Action::Local(change)
         | Action::Remote(change)
         | Action::ResolvedLocal((_, _), change)
         | Action::ResolvedRemote((_, _), change) => change,
-        Action::Conflict(_, _) | Action::Identical(_, _) => return 0,
+        Action::Conflict(_, _)
+        | Action::Identical(_, _)
+        | Action::LocalMove(_)
+        | Action::RemoteMove(_) => return 0,
     };
 
     match change {
@@ -1379,7 +1589,10 @@ pub fn can_stream_details(actions: &[Action]) -> bool {
             | Action::Remote(change)
             | Action::ResolvedLocal((_, _), change)
             | Action::ResolvedRemote((_, _), change) => change,
-            Action::Conflict(_, _) | Action::Identical(_, _) => return true,
+            Action::Conflict(_, _)
+            | Action::Identical(_, _)
+            | Action::LocalMove(_)
+            | Action::RemoteMove(_) => return true,
         };
 
         !matches!(change, Change::Modified(old, new) if old.is_dir() && !new.is_dir())
@@ -1494,6 +1707,42 @@ pub fn preflight_apply_report(
     removal_blocker_report(base, &plan.removed_destination_paths, &plan.removal_policy)
 }
 
+/// Lists the applied entries whose ownership cannot be set here, because this process may not
+/// change it or a user or group name is unknown; apply leaves their ownership as it finds it.
+pub fn ownership_report(base: &Path, actions: &[Action]) -> Result<Vec<OwnershipSkip>> {
+    let (euid, egid) = unsafe { (libc::geteuid(), libc::getegid()) };
+    let mut skipped = Vec::new();
+    for action in actions {
+        let (entry, replaced) = match applied_change(action) {
+            Some(Change::Added(entry)) => (entry, true),
+            Some(Change::Modified(old, new)) => {
+                let metadata_only = old.is_dir() && new.is_dir() || old.same_contents(new);
+                (new, !metadata_only)
+            }
+            _ => continue,
+        };
+        let Some(ownership) = entry.ownership() else {
+            continue;
+        };
+        // replaced and added entries are published from files this process creates
+        let (uid, gid) = if replaced {
+            (euid, egid)
+        } else {
+            let path = safe_join(base, entry.path())?;
+            let meta = fs::symlink_metadata(&path)
+                .wrap_err_with(|| format!("unable to preflight ownership of {}", path.display()))?;
+            (meta.uid(), meta.gid())
+        };
+        if let Some(reason) = ownership.skip_reason(uid, gid) {
+            skipped.push(OwnershipSkip {
+                path: entry.path().clone(),
+                reason,
+            });
+        }
+    }
+    Ok(skipped)
+}
+
 pub fn preflight_state_save(state_path: &Path) -> Result<()> {
     let parent = state_path.parent().ok_or_else(|| {
         eyre!(
@@ -2906,9 +3155,16 @@ fn apply_attempt_contents(
 fn apply_attempt_operations(actions: &[Action]) -> Vec<String> {
     let mut operations: Vec<_> = actions
         .iter()
-        .map(|action| {
-            let change = action_change(action);
-            format!("{} {}", change_operation(change), change.path().display())
+        .map(|action| match action {
+            Action::LocalMove(m) | Action::RemoteMove(m) => format!(
+                "move {} -> {}",
+                m.from.path().display(),
+                m.to.path().display()
+            ),
+            action => {
+                let change = action_change(action);
+                format!("{} {}", change_operation(change), change.path().display())
+            }
         })
         .collect();
     operations.sort();
@@ -2919,6 +3175,7 @@ fn apply_attempt_operations(actions: &[Action]) -> Vec<String> {
 fn apply_attempt_unstaged_operations(actions: &[Action]) -> Vec<String> {
     let mut operations: Vec<_> = actions
         .iter()
+        .filter(|action| !action.is_move())
         .filter_map(|action| {
             unstaged_change_operation(action_change(action))
                 .map(|op| format!("{} {}", op, action.path().display()))
@@ -2936,6 +3193,9 @@ fn action_change(action: &Action) -> &Change {
         | Action::ResolvedLocal((_, _), change)
         | Action::ResolvedRemote((_, _), change) => change,
         Action::Conflict(left, _) | Action::Identical(left, _) => left,
+        Action::LocalMove(_) | Action::RemoteMove(_) => {
+            unreachable!("validate_actions rejects moves")
+        }
     }
 }
 
@@ -2991,9 +3251,11 @@ fn entry_operation(prefix: &'static str, entry: &Entry) -> &'static str {
     match (prefix, entry.is_dir(), entry.is_symlink()) {
         ("add", true, _) => "add-dir",
         ("add", _, true) => "add-symlink",
+        ("add", _, _) if entry.is_special() => "add-special",
         ("add", _, _) => "add-file",
         ("remove", true, _) => "remove-dir",
         ("remove", _, true) => "remove-symlink",
+        ("remove", _, _) if entry.is_special() => "remove-special",
         ("remove", _, _) => "remove-file",
         _ => prefix,
     }
@@ -3878,10 +4140,28 @@ impl MutationDurabilityLedger {
         self.arm(token)
     }
 
+    fn mknod_staged(&mut self, token: MutationDurabilityToken, entry: &Entry) -> Result<()> {
+        self.before_namespace_mutation(&token.mutation_path)?;
+        let created = cvt(unsafe {
+            libc::mknodat(
+                token.directory.as_raw_fd(),
+                token.name.as_ptr(),
+                special_creation_mode(entry),
+                entry.rdev() as libc::dev_t,
+            )
+        })
+        .map_err(|error| special_creation_error(error, &token.mutation_path, entry));
+        let restore = self.after_namespace_mutation(&token.mutation_path);
+        created?;
+        restore?;
+        self.arm(token)
+    }
+
     fn symlink_times_staged(
         &mut self,
         token: MutationDurabilityToken,
         requested_mtime: i64,
+        requested_nsec: u32,
     ) -> Result<u64> {
         verify_directory_handle_identity(
             &token.directory,
@@ -3918,7 +4198,7 @@ impl MutationDurabilityLedger {
             stat_atime_timespec(&before),
             libc::timespec {
                 tv_sec: mtime_sec,
-                tv_nsec: 0,
+                tv_nsec: requested_nsec as libc::c_long,
             },
         ];
         self.before_namespace_mutation(&token.mutation_path)?;
@@ -4923,8 +5203,8 @@ fn apply_parent_mutations(actions: &[Action]) -> Vec<ParentMutation> {
 
 struct RemovalBlockerPolicy {
     locations: Locations,
-    ignore: Vec<(String, regex::Regex)>,
-    prune: Vec<(String, regex::Regex)>,
+    ignore: Patterns,
+    prune: Patterns,
     prune_ignored: bool,
     excludes: Vec<PathBuf>,
 }
@@ -4961,28 +5241,15 @@ impl RemovalBlockerPolicy {
         let Some(scan_policy) = scan_policy else {
             return Ok(Self {
                 locations: Vec::new(),
-                ignore: Vec::new(),
-                prune: Vec::new(),
+                ignore: Patterns::default(),
+                prune: Patterns::default(),
                 prune_ignored: apply_options.prune_ignored,
                 excludes: Vec::new(),
             });
         };
 
-        use fnmatch_regex::glob_to_regex;
-        let compile_patterns =
-            |patterns: &[String], kind: &str| -> Result<Vec<(String, regex::Regex)>> {
-                let mut compiled = Vec::new();
-                for pattern in patterns {
-                    compiled.push((
-                        pattern.clone(),
-                        glob_to_regex(pattern)
-                            .wrap_err_with(|| format!("invalid {kind} pattern {pattern}"))?,
-                    ));
-                }
-                Ok(compiled)
-            };
-        let ignore = compile_patterns(&scan_policy.ignore, "ignore")?;
-        let prune = compile_patterns(&scan_policy.prune, "prune")?;
+        let ignore = Patterns::new(&scan_policy.ignore, "ignore")?;
+        let prune = Patterns::new(&scan_policy.prune, "prune")?;
 
         let locations = crate::scan::location::canonicalize(&scan_policy.locations);
         Ok(Self {
@@ -4994,7 +5261,7 @@ impl RemovalBlockerPolicy {
         })
     }
 
-    fn classify<'a>(&'a self, relative_path: &Path) -> RemovalBlockerKind<'a> {
+    fn classify<'a>(&'a self, relative_path: &Path, is_dir: bool) -> RemovalBlockerKind<'a> {
         if self
             .excludes
             .iter()
@@ -5005,10 +5272,10 @@ impl RemovalBlockerPolicy {
         if self.is_excluded(relative_path) {
             return RemovalBlockerKind::Excluded;
         }
-        if let Some(pattern) = self.matching_prune_pattern(relative_path) {
+        if let Some(pattern) = self.prune.matching(relative_path, is_dir) {
             return RemovalBlockerKind::Prune(pattern);
         }
-        if let Some(pattern) = self.matching_ignore_pattern(relative_path) {
+        if let Some(pattern) = self.ignore.matching(relative_path, is_dir) {
             return RemovalBlockerKind::Ignored(pattern);
         }
         RemovalBlockerKind::Unexpected
@@ -5019,25 +5286,6 @@ impl RemovalBlockerPolicy {
             || (matches!(kind, RemovalBlockerKind::Ignored(_)) && self.prune_ignored)
     }
 
-    fn matching_prune_pattern(&self, relative_path: &Path) -> Option<&str> {
-        Self::matching_pattern(&self.prune, relative_path)
-    }
-
-    fn matching_ignore_pattern(&self, relative_path: &Path) -> Option<&str> {
-        Self::matching_pattern(&self.ignore, relative_path)
-    }
-
-    fn matching_pattern<'a>(
-        patterns: &'a [(String, regex::Regex)],
-        relative_path: &Path,
-    ) -> Option<&'a str> {
-        let filename = relative_path.file_name()?.to_str()?;
-        patterns
-            .iter()
-            .find(|(_, regex)| regex.is_match(filename))
-            .map(|(pattern, _)| pattern.as_str())
-    }
-
     fn is_excluded(&self, relative_path: &Path) -> bool {
         if self
             .excludes
@@ -5212,11 +5460,11 @@ fn collect_removed_directory_blockers(
             )
         })?;
         if !removed_paths.contains(relative_path) {
-            let kind = policy.classify(relative_path);
+            let file_type = entry.file_type().wrap_err_with(|| {
+                format!("unable to preflight directory entry {}", path.display())
+            })?;
+            let kind = policy.classify(relative_path, file_type.is_dir());
             if policy.should_prune(&kind) {
-                let file_type = entry.file_type().wrap_err_with(|| {
-                    format!("unable to preflight directory entry {}", path.display())
-                })?;
                 let base_dev = fs::symlink_metadata(base)
                     .wrap_err_with(|| {
                         format!("failed to read sync base metadata for {}", base.display())
@@ -5270,11 +5518,11 @@ fn preflight_removed_directory_contents(
             )
         })?;
         if !removed_paths.contains(relative_path) {
-            let kind = policy.classify(relative_path);
+            let file_type = entry.file_type().wrap_err_with(|| {
+                format!("unable to preflight directory entry {}", path.display())
+            })?;
+            let kind = policy.classify(relative_path, file_type.is_dir());
             if policy.should_prune(&kind) {
-                let file_type = entry.file_type().wrap_err_with(|| {
-                    format!("unable to preflight directory entry {}", path.display())
-                })?;
                 let base_dev = fs::symlink_metadata(base)
                     .wrap_err_with(|| {
                         format!("failed to read sync base metadata for {}", base.display())
@@ -5345,7 +5593,7 @@ fn prune_ignored_removal_blockers(
             continue;
         }
 
-        let kind = policy.classify(relative_path);
+        let kind = policy.classify(relative_path, file_type.is_dir());
         if policy.should_prune(&kind) {
             if file_type.is_dir() {
                 let base_dev = fs::symlink_metadata(base)
@@ -5744,6 +5992,7 @@ enum ProducerState {
     File {
         action_index: u32,
         file: fs::File,
+        offset: u64,
         remaining: u64,
     },
     Diff {
@@ -5757,6 +6006,9 @@ pub struct DetailProducer {
     actions: Vec<Action>,
     signatures: Vec<SignatureWithPath>,
     max_chunk_bytes: usize,
+    holes: bool,
+    compressor: FrameCompressor,
+    file_copies: bool,
     action_index: usize,
     signature_index: usize,
     pending: VecDeque<DetailFrame>,
@@ -5775,6 +6027,9 @@ impl DetailProducer {
             actions,
             signatures,
             max_chunk_bytes: max_chunk_bytes.max(1),
+            holes: false,
+            compressor: FrameCompressor::default(),
+            file_copies: false,
             action_index: 0,
             signature_index: 0,
             pending: VecDeque::new(),
@@ -5782,16 +6037,42 @@ impl DetailProducer {
         }
     }
 
+    /// Sends holes in the source files as hole frames instead of zeros; the applying side
+    /// must support `sparse-files-v1`.
+    pub fn with_holes(mut self, holes: bool) -> Self {
+        self.holes = holes;
+        self
+    }
+
+    /// Deflates literal bytes at `level`, 0 for none; the applying side must support
+    /// `compressed-details-v1`.
+    pub fn with_compression(mut self, level: u8) -> Self {
+        self.compressor.level = level;
+        self
+    }
+
+    /// Sends whole files as `FileCopy` frames naming the source; the applier must be in this
+    /// process and accept them.
+    pub fn with_file_copies(mut self, file_copies: bool) -> Self {
+        self.file_copies = file_copies;
+        self
+    }
+
     pub fn next_frame(&mut self) -> Result<Option<DetailFrame>> {
         if let Some(frame) = self.pending.pop_front() {
             return Ok(Some(frame));
         }
+        let frame = self.next_source_frame()?;
+        Ok(frame.map(|frame| self.compressor.compress(frame)))
+    }
 
+    fn next_source_frame(&mut self) -> Result<Option<DetailFrame>> {
         if let Some(state) = self.state.take() {
             match state {
                 ProducerState::File {
                     action_index,
-                    mut file,
+                    file,
+                    mut offset,
                     mut remaining,
                 } => {
                     if remaining == 0 {
@@ -5801,9 +6082,30 @@ impl DetailProducer {
                         }));
                     }
 
-                    let chunk_bytes = remaining.min(self.max_chunk_bytes as u64) as usize;
-                    let mut buf = vec![0; chunk_bytes];
-                    let n = file.read(&mut buf)?;
+                    let end = offset + remaining;
+                    let mut chunk_bytes = remaining.min(self.max_chunk_bytes as u64);
+                    if self.holes {
+                        if let Some(hole_end) = hole_end(&file, offset, end)? {
+                            let len = hole_end.min(end) - offset;
+                            self.state = Some(ProducerState::File {
+                                action_index,
+                                file,
+                                offset: offset + len,
+                                remaining: remaining - len,
+                            });
+                            return Ok(Some(DetailFrame {
+                                action_index,
+                                payload: DetailPayload::FileHole(len),
+                            }));
+                        }
+                        let hole = next_hole(&file, offset, end)?;
+                        if hole > offset {
+                            chunk_bytes = chunk_bytes.min(hole - offset);
+                        }
+                    }
+
+                    let mut buf = vec![0; chunk_bytes as usize];
+                    let n = file.read_at(&mut buf, offset)?;
                     if n == 0 {
                         return Ok(Some(DetailFrame {
                             action_index,
@@ -5812,10 +6114,12 @@ impl DetailProducer {
                     }
 
                     buf.truncate(n);
+                    offset += n as u64;
                     remaining = remaining.saturating_sub(n as u64);
                     self.state = Some(ProducerState::File {
                         action_index,
                         file,
+                        offset,
                         remaining,
                     });
                     return Ok(Some(DetailFrame {
@@ -5852,11 +6156,31 @@ impl DetailProducer {
 
             match kind {
                 SourceDetailKind::File(path) => {
-                    let file = fs::File::open(safe_join(&self.base, path)?)?;
+                    let source = safe_join(&self.base, path)?;
+                    let file = fs::File::open(&source)?;
                     let remaining = file.metadata()?.len();
+                    if self.file_copies {
+                        for payload in [
+                            DetailPayload::FileCopy {
+                                source,
+                                len: remaining,
+                            },
+                            DetailPayload::FileEnd,
+                        ] {
+                            self.pending.push_back(DetailFrame {
+                                action_index,
+                                payload,
+                            });
+                        }
+                        return Ok(Some(DetailFrame {
+                            action_index,
+                            payload: DetailPayload::FileBegin,
+                        }));
+                    }
                     self.state = Some(ProducerState::File {
                         action_index,
                         file,
+                        offset: 0,
                         remaining,
                     });
                     return Ok(Some(DetailFrame {
@@ -5889,6 +6213,7 @@ impl DetailProducer {
 
                     let file_path = safe_join(&self.base, path)?;
                     let max_chunk_bytes = self.max_chunk_bytes;
+                    let holes = self.holes;
                     let (sender, receiver) = mpsc::sync_channel(4);
                     let handle = thread::spawn(move || {
                         let result = stream_diff_frames(
@@ -5896,6 +6221,7 @@ impl DetailProducer {
                             action_index,
                             signature,
                             max_chunk_bytes,
+                            holes,
                             sender.clone(),
                         );
                         if let Err(error) = result {
@@ -5950,11 +6276,17 @@ impl DetailProducer {
 fn detail_payload_bytes(payload: &DetailPayload) -> usize {
     match payload {
         DetailPayload::FileBytes(bytes) | DetailPayload::DiffBytes(bytes) => bytes.len(),
+        DetailPayload::FileBytesCompressed(bytes) | DetailPayload::DiffBytesCompressed(bytes) => {
+            bytes.data.len()
+        }
         DetailPayload::FileBegin
         | DetailPayload::FileEnd
         | DetailPayload::DiffBegin
         | DetailPayload::DiffCopy { .. }
-        | DetailPayload::DiffEnd => 0,
+        | DetailPayload::DiffEnd
+        | DetailPayload::FileHole(_)
+        | DetailPayload::DiffHole(_)
+        | DetailPayload::FileCopy { .. } => 0,
     }
 }
 
@@ -5989,6 +6321,7 @@ fn stream_diff_frames(
     action_index: u32,
     signature: Signature,
     max_chunk_bytes: usize,
+    holes: bool,
     sender: mpsc::SyncSender<Result<DetailFrame>>,
 ) -> Result<()> {
     validate_signature_window(signature.window)?;
@@ -6029,7 +6362,14 @@ fn stream_diff_frames(
             }
             DeltaOp::Literal(bytes) => {
                 flush_copy(&mut pending_copy)?;
-                send_frame(DetailPayload::DiffBytes(bytes))?;
+                if holes
+                    && bytes.len() as u64 >= SPARSE_BLOCK_BYTES
+                    && bytes.iter().all(|byte| *byte == 0)
+                {
+                    send_frame(DetailPayload::DiffHole(bytes.len() as u64))?;
+                } else {
+                    send_frame(DetailPayload::DiffBytes(bytes))?;
+                }
             }
         }
         Ok(())
@@ -6279,6 +6619,61 @@ impl StagingState {
     }
 }
 
+/// Passes what `inner` reads on to `verifier`, so a copy is verified as it is written.
+struct VerifyingReader<'a, R> {
+    inner: R,
+    verifier: &'a mut StreamedOutputVerifier,
+}
+
+impl<R: Read> Read for VerifyingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.verifier.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Appends `len` bytes of `source` to `output`, feeding them into `verifier` as they are copied.
+fn copy_local_file(
+    source: &Path,
+    len: u64,
+    output: &mut fs::File,
+    verifier: &mut StreamedOutputVerifier,
+) -> Result<()> {
+    let source_file = fs::File::open(source)
+        .wrap_err_with(|| format!("failed to open copy source {}", source.display()))?;
+    let mut reader = VerifyingReader {
+        inner: (&source_file).take(len),
+        verifier,
+    };
+    let copied = io::copy(&mut reader, output)
+        .wrap_err_with(|| format!("failed to copy {}", source.display()))?;
+    if copied != len {
+        return Err(eyre!(
+            "copy source {} shrank to {} bytes from {}",
+            source.display(),
+            copied,
+            len
+        ));
+    }
+    Ok(())
+}
+
+/// Makes the empty `output` a reflink of `source`, and returns whether the filesystem could.
+#[cfg(target_os = "linux")]
+pub(crate) fn reflink_file(output: &fs::File, source: &fs::File) -> io::Result<bool> {
+    match cvt(unsafe { libc::ioctl(output.as_raw_fd(), libc::FICLONE as _, source.as_raw_fd()) }) {
+        Ok(()) => Ok(true),
+        Err(error) if clone_error_is_unsupported(&error) => Ok(false),
+        Err(error) => Err(error),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn reflink_file(_output: &fs::File, _source: &fs::File) -> io::Result<bool> {
+    Ok(false)
+}
+
 fn clone_error_is_unsupported(error: &io::Error) -> bool {
     let Some(code) = error.raw_os_error() else {
         return false;
@@ -6756,10 +7151,12 @@ impl TempOutput {
                 self.temp_path.display()
             )
         })?;
+        apply_ownership(file, entry, &self.temp_path)?;
+        apply_xattrs(file, entry, &self.temp_path)?;
         filetime::set_file_handle_times(
             file,
             Some(filetime::FileTime::from_unix_time(meta.atime(), 0)),
-            Some(filetime::FileTime::from_unix_time(entry.mtime(), 0)),
+            Some(entry.mtime_filetime()),
         )
         .wrap_err_with(|| {
             format!(
@@ -6782,7 +7179,7 @@ impl TempOutput {
             )
         })?;
         if synced_mode(final_meta.mode()) != synced_mode(entry.mode())
-            || final_meta.mtime() != entry.mtime()
+            || !entry.mtime_matches(&final_meta)
         {
             return Err(eyre!(
                 "temporary file {} metadata did not match the requested mode and mtime",
@@ -6792,6 +7189,7 @@ impl TempOutput {
 
         let mut final_entry = entry.clone();
         final_entry.set_ino(final_meta.ino());
+        record_ownership(&mut final_entry, &final_meta);
         Ok(final_entry)
     }
 
@@ -8615,6 +9013,11 @@ impl ApplyRecorder {
 }
 
 fn ensure_parent_directory(path: &Path) -> Result<()> {
+    ensure_parent_directory_created(path).map(|_| ())
+}
+
+fn ensure_parent_directory_created(path: &Path) -> Result<Vec<PathBuf>> {
+    let mut created = Vec::new();
     if let Some(parent) = path.parent() {
         let mut missing = Vec::new();
         let mut current = parent;
@@ -8634,9 +9037,10 @@ fn ensure_parent_directory(path: &Path) -> Result<()> {
         }
         for directory in missing.into_iter().rev() {
             create_private_directory(directory)?;
+            created.push(directory.to_path_buf());
         }
     }
-    Ok(())
+    Ok(created)
 }
 
 fn ensure_parent_directory_staged(
@@ -8723,7 +9127,8 @@ fn update_meta_staged(
 ) -> Result<Entry> {
     if entry.is_symlink() {
         let token = ledger.prepare_parent(path)?;
-        let ino = ledger.symlink_times_staged(token, entry.mtime())?;
+        let ino =
+            ledger.symlink_times_staged(token, entry.mtime(), entry.mtime_nsec().unwrap_or(0))?;
         let mut result = entry.clone();
         result.set_ino(ino);
         return Ok(result);
@@ -8760,12 +9165,14 @@ fn update_directory_meta_staged(
     let desired_mode = synced_mode(entry.mode());
     ledger.before_namespace_mutation(path)?;
     let update = (|| {
+        apply_ownership(&directory, entry, path)?;
+        apply_xattrs(&directory, entry, path)?;
         set_retained_directory_mode(&directory, desired_mode, path)
             .wrap_err_with(|| format!("failed to set permissions for {}", path.display()))?;
         filetime::set_file_handle_times(
             &directory,
             Some(filetime::FileTime::from_unix_time(metadata.atime(), 0)),
-            Some(filetime::FileTime::from_unix_time(entry.mtime(), 0)),
+            Some(entry.mtime_filetime()),
         )
         .wrap_err_with(|| format!("failed to set time for {}", path.display()))?;
         sync_exact_directory_descriptor(&directory, path, identity, desired_mode)
@@ -8780,6 +9187,7 @@ fn update_directory_meta_staged(
         .wrap_err_with(|| format!("failed to verify metadata for {}", path.display()))?;
     let mut result = entry.clone();
     result.set_ino(final_metadata.ino());
+    record_ownership(&mut result, &final_metadata);
     ledger.mark_synced(path, identity);
     Ok(result)
 }
@@ -8887,6 +9295,7 @@ enum ApplyState {
         action_index: usize,
         output: TempOutput,
         verifier: StreamedOutputVerifier,
+        sparse: bool,
     },
     Diff {
         action_index: usize,
@@ -8895,6 +9304,7 @@ enum ApplyState {
         verifier: StreamedOutputVerifier,
         output_position: u64,
         clone_backed: bool,
+        sparse: bool,
     },
 }
 
@@ -8924,6 +9334,16 @@ impl StreamedOutputVerifier {
         }
     }
 
+    fn update_zeros(&mut self, len: u64) {
+        let zeros = [0; COPY_BUFFER_BYTES];
+        let mut remaining = len;
+        while remaining > 0 {
+            let n = remaining.min(zeros.len() as u64) as usize;
+            self.update(&zeros[..n]);
+            remaining -= n as u64;
+        }
+    }
+
     fn verify(self, entry: &Entry) -> Result<()> {
         if self.bytes != entry.size() {
             return Err(eyre!(
@@ -8985,6 +9405,8 @@ pub struct DetailApplier {
     // Drop pending outputs before removing their shared staging directory.
     staging: Option<StagingArea>,
     staging_space_monitor: Option<StagingSpaceMonitor>,
+    /// Whether `FileCopy` frames are accepted, which only a producer in this process may send.
+    file_copies: bool,
     failed: Option<String>,
 }
 
@@ -9246,15 +9668,24 @@ impl DetailApplier {
             output_batch: FilePublicationBatch::new(),
             prepared_outputs,
             preparing_marker: None,
+            file_copies: false,
             failed: None,
         }
     }
 
+    /// Accepts `FileCopy` frames, copying their sources from this filesystem.
+    pub fn with_file_copies(mut self, file_copies: bool) -> Self {
+        self.file_copies = file_copies;
+        self
+    }
+
     pub fn apply_frame(&mut self, frame: DetailFrame) -> Result<()> {
         if let Some(failed) = &self.failed {
             return Err(eyre!("detail apply stream already failed: {}", failed));
         }
-        let result = self.apply_frame_inner(frame);
+        let result = frame
+            .decompressed()
+            .and_then(|frame| self.apply_frame_inner(frame));
         if let Err(error) = &result {
             self.failed = Some(format!("{:#}", error));
         }
@@ -9271,11 +9702,13 @@ impl DetailApplier {
         }
 
         let staging_space_monitor = self.staging_space_monitor.clone();
+        let file_copies = self.file_copies;
         match &mut self.state {
             Some(ApplyState::File {
                 action_index,
                 output,
                 verifier,
+                sparse,
             }) => {
                 if *action_index != frame_index {
                     return Err(eyre!(
@@ -9289,14 +9722,50 @@ impl DetailApplier {
                         if let Some(monitor) = &staging_space_monitor {
                             monitor.check(&output.final_path, bytes.len() as u64)?;
                         }
+                        let output_file = output
+                            .file
+                            .as_mut()
+                            .ok_or_else(|| eyre!("temporary output is closed"))?;
+                        if *sparse {
+                            write_sparse(output_file, &bytes)?;
+                        } else {
+                            output_file.write_all(&bytes)?;
+                        }
+                        verifier.update(&bytes);
+                    }
+                    DetailPayload::FileCopy { source, len } if file_copies => {
+                        if let Some(monitor) = &staging_space_monitor {
+                            monitor.check(&output.final_path, len)?;
+                        }
+                        let output_file = output
+                            .file
+                            .as_mut()
+                            .ok_or_else(|| eyre!("temporary output is closed"))?;
+                        copy_local_file(&source, len, output_file, verifier)?;
+                    }
+                    DetailPayload::FileHole(len) => {
+                        // the output is new, so skipping over the hole leaves it unallocated
+                        *sparse = true;
                         output
                             .file
                             .as_mut()
                             .ok_or_else(|| eyre!("temporary output is closed"))?
-                            .write_all(&bytes)?;
-                        verifier.update(&bytes);
+                            .seek(SeekFrom::Current(
+                                i64::try_from(len).map_err(|_| eyre!("file hole is too long"))?,
+                            ))?;
+                        verifier.update_zeros(len);
+                    }
+                    DetailPayload::FileEnd => {
+                        if *sparse {
+                            let output_file = output
+                                .file
+                                .as_mut()
+                                .ok_or_else(|| eyre!("temporary output is closed"))?;
+                            let end = output_file.stream_position()?;
+                            output_file.set_len(end)?;
+                        }
+                        self.finish_file_detail()?
                     }
-                    DetailPayload::FileEnd => self.finish_file_detail()?,
                     _ => return Err(eyre!("unexpected file detail frame")),
                 }
                 return Ok(());
@@ -9308,6 +9777,7 @@ impl DetailApplier {
                 verifier,
                 output_position,
                 clone_backed,
+                sparse,
             }) => {
                 if *action_index != frame_index {
                     return Err(eyre!(
@@ -9333,6 +9803,7 @@ impl DetailApplier {
                             verifier,
                             output_position,
                             *clone_backed,
+                            *sparse && !*clone_backed,
                             offset,
                             len,
                         )?;
@@ -9345,7 +9816,27 @@ impl DetailApplier {
                             .file
                             .as_mut()
                             .ok_or_else(|| eyre!("temporary output is closed"))?;
-                        apply_diff_bytes(output_file, verifier, output_position, &bytes)?;
+                        apply_diff_bytes(
+                            output_file,
+                            verifier,
+                            output_position,
+                            *sparse && !*clone_backed,
+                            &bytes,
+                        )?;
+                    }
+                    DetailPayload::DiffHole(len) => {
+                        *sparse = true;
+                        let output_file = output
+                            .file
+                            .as_mut()
+                            .ok_or_else(|| eyre!("temporary output is closed"))?;
+                        apply_diff_hole(
+                            output_file,
+                            verifier,
+                            output_position,
+                            *clone_backed,
+                            len,
+                        )?;
                     }
                     DetailPayload::DiffEnd => {
                         output
@@ -9527,6 +10018,20 @@ impl DetailApplier {
                         create_private_directory_staged(&filename, durability)?;
                         self.recorder
                             .record_committed_step("create-dir", e.path())?;
+                    } else if e.is_special() {
+                        let token = durability.prepare_parent(&filename)?;
+                        durability.mknod_staged(token, e)?;
+                        self.recorder
+                            .record_committed_step("create-special", e.path())?;
+                        let result = update_meta_staged(&filename, e, durability)?;
+                        assign_local_result(
+                            &self.actions,
+                            &mut self.local_results,
+                            action_index,
+                            result,
+                        )?;
+                        self.recorder
+                            .record_committed_step("update-metadata", e.path())?;
                     } else {
                         return Err(eyre!("missing file detail for {}", e.path().display()));
                     }
@@ -9576,6 +10081,20 @@ impl DetailApplier {
                                 create_private_directory_staged(&filename, durability)?;
                                 self.recorder
                                     .record_committed_step("create-dir", e2.path())?;
+                            } else if e2.is_special() {
+                                let token = durability.prepare_parent(&filename)?;
+                                durability.mknod_staged(token, e2)?;
+                                self.recorder
+                                    .record_committed_step("create-special", e2.path())?;
+                                let result = update_meta_staged(&filename, e2, durability)?;
+                                assign_local_result(
+                                    &self.actions,
+                                    &mut self.local_results,
+                                    action_index,
+                                    result,
+                                )?;
+                                self.recorder
+                                    .record_committed_step("update-metadata", e2.path())?;
                             } else {
                                 return Err(eyre!(
                                     "unsupported new entry for {}",
@@ -9583,7 +10102,18 @@ impl DetailApplier {
                                 ));
                             }
                         }
-                    } else if e1.is_symlink() {
+                    } else if e1.same_special_node(e2) {
+                        verify_current_matches_entry(&filename, e1, "metadata target")?;
+                        let result = update_meta_staged(&filename, e2, durability)?;
+                        assign_local_result(
+                            &self.actions,
+                            &mut self.local_results,
+                            action_index,
+                            result,
+                        )?;
+                        self.recorder
+                            .record_committed_step("update-metadata", e2.path())?;
+                    } else if e1.is_symlink() || e1.is_special() {
                         if e2.is_file() {
                             return Err(eyre!("missing file detail for {}", e2.path().display()));
                         }
@@ -9591,7 +10121,7 @@ impl DetailApplier {
                         let token = durability.prepare_parent(&filename)?;
                         durability.unlink_staged(token, 0)?;
                         self.recorder
-                            .record_committed_step("remove-symlink", e1.path())?;
+                            .record_committed_step(entry_operation("remove", e1), e1.path())?;
                         if let Some(p) = e2.target() {
                             let token = durability.prepare_parent(&filename)?;
                             durability.symlink_staged(token, p)?;
@@ -9610,6 +10140,20 @@ impl DetailApplier {
                             create_private_directory_staged(&filename, durability)?;
                             self.recorder
                                 .record_committed_step("create-dir", e2.path())?;
+                        } else if e2.is_special() {
+                            let token = durability.prepare_parent(&filename)?;
+                            durability.mknod_staged(token, e2)?;
+                            self.recorder
+                                .record_committed_step("create-special", e2.path())?;
+                            let result = update_meta_staged(&filename, e2, durability)?;
+                            assign_local_result(
+                                &self.actions,
+                                &mut self.local_results,
+                                action_index,
+                                result,
+                            )?;
+                            self.recorder
+                                .record_committed_step("update-metadata", e2.path())?;
                         }
                     } else if e1.is_dir() {
                         if e2.is_file() {
@@ -9624,6 +10168,9 @@ impl DetailApplier {
             },
             Action::Remote(_) | Action::ResolvedRemote((_, _), _) | Action::Identical(_, _) => {}
             Action::Conflict(_, _) => {}
+            Action::LocalMove(_) | Action::RemoteMove(_) => {
+                unreachable!("validate_actions rejects moves")
+            }
         }
         if let Some(change) = applied_change(&self.actions[action_index]) {
             if !change.is_dir() {
@@ -9637,6 +10184,7 @@ impl DetailApplier {
     fn begin_file_detail(&mut self, action_index: usize) -> Result<()> {
         let entry = action_output_entry(&self.actions[action_index])?;
         let output_bytes = entry.size();
+        let sparse = entry.is_sparse();
         let verifier = StreamedOutputVerifier::new(entry);
         self.flush_before_output(output_bytes)?;
         let filename = detail_filename(&self.base, &self.actions[action_index])?;
@@ -9645,6 +10193,7 @@ impl DetailApplier {
             action_index,
             output,
             verifier,
+            sparse,
         });
         Ok(())
     }
@@ -9652,6 +10201,7 @@ impl DetailApplier {
     fn begin_diff_detail(&mut self, action_index: usize) -> Result<()> {
         let entry = action_output_entry(&self.actions[action_index])?;
         let output_bytes = entry.size();
+        let sparse = entry.is_sparse();
         let verifier = StreamedOutputVerifier::new(entry);
         self.flush_before_output(output_bytes)?;
         let filename = detail_filename(&self.base, &self.actions[action_index])?;
@@ -9674,6 +10224,7 @@ impl DetailApplier {
             verifier,
             output_position: 0,
             clone_backed,
+            sparse,
         });
         Ok(())
     }
@@ -9752,6 +10303,7 @@ impl DetailApplier {
                 action_index,
                 output,
                 verifier,
+                ..
             } => (action_index, output, verifier),
             ApplyState::Diff {
                 action_index,
@@ -10018,6 +10570,9 @@ fn merge_staged_manifest(
                     merged.push(entry);
                 }
             }
+            Action::LocalMove(_) | Action::RemoteMove(_) => {
+                unreachable!("validate_actions rejects moves")
+            }
         }
     }
     merged.extend(old);
@@ -10515,12 +11070,14 @@ fn fallback_action_can_follow_pending(action: &Action) -> bool {
     )
 }
 
+#[allow(clippy::too_many_arguments)]
 fn apply_diff_copy(
     source: &mut fs::File,
     output: &mut fs::File,
     verifier: &mut StreamedOutputVerifier,
     output_position: &mut u64,
     clone_backed: bool,
+    sparse: bool,
     offset: u64,
     len: u64,
 ) -> Result<()> {
@@ -10535,7 +11092,7 @@ fn apply_diff_copy(
 
     source.seek(SeekFrom::Start(offset))?;
     output.seek(SeekFrom::Start(*output_position))?;
-    let copied = copy_and_hash(source, output, verifier, len)?;
+    let copied = copy_and_hash(source, output, verifier, sparse, len)?;
     *output_position = output_position
         .checked_add(copied)
         .ok_or_else(|| eyre!("diff output position overflow"))?;
@@ -10546,10 +11103,15 @@ fn apply_diff_bytes(
     output: &mut fs::File,
     verifier: &mut StreamedOutputVerifier,
     output_position: &mut u64,
+    sparse: bool,
     bytes: &[u8],
 ) -> Result<()> {
     output.seek(SeekFrom::Start(*output_position))?;
-    output.write_all(bytes)?;
+    if sparse {
+        write_sparse(output, bytes)?;
+    } else {
+        output.write_all(bytes)?;
+    }
     verifier.update(bytes);
     *output_position = output_position
         .checked_add(bytes.len() as u64)
@@ -10557,22 +11119,157 @@ fn apply_diff_bytes(
     Ok(())
 }
 
-fn copy_and_hash(
-    source: &mut fs::File,
+/// Applies a run of zeros. A fresh output is left unallocated there; a clone of the old file
+/// has the run punched out, since it still holds the old contents.
+fn apply_diff_hole(
     output: &mut fs::File,
     verifier: &mut StreamedOutputVerifier,
+    output_position: &mut u64,
+    clone_backed: bool,
     len: u64,
-) -> Result<u64> {
-    let mut remaining = len;
-    let mut copied = 0u64;
-    let mut buf = vec![0; COPY_BUFFER_BYTES];
-    while remaining > 0 {
-        let want = std::cmp::min(remaining as usize, buf.len());
+) -> Result<()> {
+    if clone_backed {
+        punch_hole(output, *output_position, len)?;
+    }
+    verifier.update_zeros(len);
+    *output_position = output_position
+        .checked_add(len)
+        .ok_or_else(|| eyre!("diff output position overflow"))?;
+    Ok(())
+}
+
+/// Granularity at which runs of zeros are left as holes in sparse outputs.
+const SPARSE_BLOCK_BYTES: u64 = 4096;
+
+/// Writes `bytes` at the current position, seeking over aligned all-zero blocks instead of
+/// writing them, so that they stay unallocated in a new output.
+fn write_sparse(output: &mut fs::File, bytes: &[u8]) -> io::Result<()> {
+    let mut position = output.stream_position()?;
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let to_boundary = SPARSE_BLOCK_BYTES - position % SPARSE_BLOCK_BYTES;
+        let n = rest.len().min(to_boundary as usize);
+        let (block, tail) = rest.split_at(n);
+        if n as u64 == SPARSE_BLOCK_BYTES && block.iter().all(|byte| *byte == 0) {
+            output.seek(SeekFrom::Current(n as i64))?;
+        } else {
+            output.write_all(block)?;
+        }
+        position += n as u64;
+        rest = tail;
+    }
+    Ok(())
+}
+
+/// Deallocates `len` bytes at `offset`, falling back to writing zeros where the filesystem
+/// cannot punch holes.
+fn punch_hole(file: &fs::File, offset: u64, len: u64) -> Result<()> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        let punched = cvt(unsafe {
+            libc::fallocate(
+                file.as_raw_fd(),
+                libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                libc::off_t::try_from(offset).map_err(|_| eyre!("hole offset is too large"))?,
+                libc::off_t::try_from(len).map_err(|_| eyre!("hole is too long"))?,
+            )
+        });
+        match punched {
+            Ok(()) => return Ok(()),
+            Err(error) if error.raw_os_error() == Some(libc::EOPNOTSUPP) => {}
+            Err(error) => return Err(error).wrap_err("failed to punch a hole in diff output"),
+        }
+    }
+    let zeros = [0; COPY_BUFFER_BYTES];
+    let mut written = 0;
+    while written < len {
+        let n = (len - written).min(zeros.len() as u64) as usize;
+        file.write_all_at(&zeros[..n], offset + written)?;
+        written += n as u64;
+    }
+    Ok(())
+}
+
+/// Start of the first data region at or after `offset`, or `None` when only a hole remains.
+/// Filesystems that do not report holes hold data everywhere.
+fn next_data(file: &fs::File, offset: u64) -> io::Result<Option<u64>> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        let start = libc::off_t::try_from(offset)
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        let result = unsafe { libc::lseek(file.as_raw_fd(), start, libc::SEEK_DATA) };
+        if result >= 0 {
+            return Ok(Some(result as u64));
+        }
+        let error = io::Error::last_os_error();
+        return match error.raw_os_error() {
+            Some(libc::ENXIO) => Ok(None),
+            Some(libc::EINVAL) | Some(libc::EOPNOTSUPP) => Ok(Some(offset)),
+            _ => Err(error),
+        };
+    }
+    #[allow(unreachable_code)]
+    {
+        let _ = file;
+        Ok(Some(offset))
+    }
+}
+
+/// Start of the first hole at or after `offset`, counting the end of the file at `len`.
+fn next_hole(file: &fs::File, offset: u64, len: u64) -> io::Result<u64> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        let start = libc::off_t::try_from(offset)
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        let result = unsafe { libc::lseek(file.as_raw_fd(), start, libc::SEEK_HOLE) };
+        if result >= 0 {
+            return Ok((result as u64).min(len));
+        }
+        let error = io::Error::last_os_error();
+        return match error.raw_os_error() {
+            Some(libc::ENXIO) => Ok(offset),
+            Some(libc::EINVAL) | Some(libc::EOPNOTSUPP) => Ok(len),
+            _ => Err(error),
+        };
+    }
+    #[allow(unreachable_code)]
+    {
+        let _ = (file, offset);
+        Ok(len)
+    }
+}
+
+/// End of the hole containing `offset` in a file of length `len`, or `None` if `offset` holds
+/// data.
+fn hole_end(file: &fs::File, offset: u64, len: u64) -> io::Result<Option<u64>> {
+    Ok(match next_data(file, offset)? {
+        Some(data) if data <= offset => None,
+        Some(data) => Some(data.min(len)),
+        None => Some(len),
+    })
+}
+
+fn copy_and_hash(
+    source: &mut fs::File,
+    output: &mut fs::File,
+    verifier: &mut StreamedOutputVerifier,
+    sparse: bool,
+    len: u64,
+) -> Result<u64> {
+    let mut remaining = len;
+    let mut copied = 0u64;
+    let mut buf = vec![0; COPY_BUFFER_BYTES];
+    while remaining > 0 {
+        let want = std::cmp::min(remaining as usize, buf.len());
         let n = source.read(&mut buf[..want])?;
         if n == 0 {
             break;
         }
-        output.write_all(&buf[..n])?;
+        if sparse {
+            write_sparse(output, &buf[..n])?;
+        } else {
+            output.write_all(&buf[..n])?;
+        }
         verifier.update(&buf[..n]);
         remaining -= n as u64;
         copied += n as u64;
@@ -10601,6 +11298,465 @@ fn hash_file_range(
     Ok(hashed)
 }
 
+/// Renames the `LocalMove`s among `actions` below `base` and records the `RemoteMove`s,
+/// which the peer performs, so that `all_old` describes the tree after every move. All
+/// sources are checked against the snapshot before the first rename. Returns the missing
+/// destination parents that had to be created.
+pub fn apply_moves(
+    base: &Path,
+    actions: &[Action],
+    all_old: &mut Vec<Entry>,
+) -> Result<Vec<Entry>> {
+    for action in actions {
+        match action {
+            Action::LocalMove(m) => {
+                validate_move(m)?;
+                verify_move_source(base, m, all_old)?;
+            }
+            Action::RemoteMove(m) => validate_move(m)?,
+            _ => {
+                return Err(eyre!(
+                    "expected only moves, got an action for {}",
+                    action.path().display()
+                ))
+            }
+        }
+    }
+
+    let mut created = Vec::new();
+    let mut moved_from = HashSet::new();
+    let mut moved = Vec::new();
+    for action in actions {
+        let m = match action {
+            Action::LocalMove(m) => {
+                let source = safe_join(base, m.from.path())?;
+                let destination = safe_join(base, m.to.path())?;
+                for directory in ensure_parent_directory_created(&destination)? {
+                    let meta = fs::symlink_metadata(&directory).wrap_err_with(|| {
+                        format!("failed to read metadata for {}", directory.display())
+                    })?;
+                    let path = directory.strip_prefix(base)?.to_path_buf();
+                    created.push(Entry::created_directory(path, &meta));
+                }
+                rename_without_replacing(&source, &destination)?;
+                moved.push(update_meta(&destination, &m.to)?);
+                for (_, new) in &m.contents {
+                    let filename = safe_join(base, new.path())?;
+                    let meta = fs::symlink_metadata(&filename).wrap_err_with(|| {
+                        format!("failed to read metadata for {}", filename.display())
+                    })?;
+                    let mut entry = new.clone();
+                    entry.set_ino(meta.ino());
+                    moved.push(entry);
+                }
+                m
+            }
+            Action::RemoteMove(m) => {
+                moved.push(m.to.clone());
+                moved.extend(m.contents.iter().map(|(_, new)| new.clone()));
+                m
+            }
+            _ => unreachable!("non-move actions were rejected"),
+        };
+        moved_from.insert(m.from.path().clone());
+    }
+    all_old.retain(|entry| {
+        !entry
+            .path()
+            .ancestors()
+            .any(|ancestor| moved_from.contains(ancestor))
+    });
+    all_old.extend(moved);
+    all_old.sort();
+    Ok(created)
+}
+
+fn verify_move_source(base: &Path, m: &Move, all_old: &[Entry]) -> Result<()> {
+    let start = all_old
+        .binary_search_by(|entry| entry.path().cmp(m.from.path()))
+        .map_err(|_| {
+            eyre!(
+                "move source {} is not in the snapshot",
+                m.from.path().display()
+            )
+        })?;
+    verify_move_entry(base, &all_old[start], &m.from)?;
+    let descendants: Vec<&Entry> = all_old[start + 1..]
+        .iter()
+        .take_while(|entry| entry.path().starts_with(m.from.path()))
+        .collect();
+    if descendants.len() != m.contents.len()
+        || descendants
+            .iter()
+            .zip(&m.contents)
+            .any(|(current, (expected, _))| current.path() != expected.path())
+    {
+        return Err(eyre!(
+            "move source {} no longer has the contents that were moved",
+            m.from.path().display()
+        ));
+    }
+    for (current, (expected, _)) in descendants.into_iter().zip(&m.contents) {
+        verify_move_entry(base, current, expected)?;
+    }
+    Ok(())
+}
+
+// The snapshot entry must describe the same contents the peer moved, and the file on
+// disk must still have the scan identity recorded in the snapshot.
+fn verify_move_entry(base: &Path, old: &Entry, moved: &Entry) -> Result<()> {
+    let same_contents = old.is_dir() == moved.is_dir()
+        && old.target() == moved.target()
+        && (!old.is_file()
+            || (old.size() == moved.size()
+                && old.digest().is_some()
+                && old.digest() == moved.digest()));
+    if !same_contents {
+        return Err(eyre!(
+            "move source {} differs from the entry moved by the peer",
+            old.path().display()
+        ));
+    }
+    let filename = safe_join(base, old.path())?;
+    let meta = fs::symlink_metadata(&filename)
+        .wrap_err_with(|| format!("failed to read metadata for {}", filename.display()))?;
+    let unchanged = if old.is_dir() {
+        meta.is_dir()
+    } else {
+        let same_type = if old.is_symlink() {
+            meta.file_type().is_symlink()
+        } else {
+            meta.is_file()
+        };
+        same_type
+            && meta.size() == old.size()
+            && old.mtime_matches(&meta)
+            && meta.ino() == old.ino()
+    };
+    if !unchanged {
+        return Err(eyre!(
+            "move source {} changed since it was scanned",
+            old.path().display()
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) fn rename_without_replacing(source: &Path, destination: &Path) -> Result<()> {
+    match rename_noreplace(source, destination) {
+        Ok(()) => {}
+        Err(error) if error.raw_os_error() == Some(libc::EEXIST) => {
+            return Err(eyre!(
+                "move destination {} already exists",
+                destination.display()
+            ))
+        }
+        Err(error) => {
+            return Err(error).wrap_err_with(|| {
+                format!(
+                    "failed to move {} to {}",
+                    source.display(),
+                    destination.display()
+                )
+            })
+        }
+    }
+    sync_directory(output_parent(source))?;
+    sync_directory(output_parent(destination))
+}
+
+fn path_cstring(path: &Path) -> io::Result<std::ffi::CString> {
+    std::ffi::CString::new(path.as_os_str().as_bytes()).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("path contains an interior NUL byte: {}", path.display()),
+        )
+    })
+}
+
+/// Renames in one step that fails with `EEXIST` rather than replace an existing destination.
+#[cfg(target_os = "linux")]
+fn rename_noreplace(source: &Path, destination: &Path) -> io::Result<()> {
+    let (source, destination) = (path_cstring(source)?, path_cstring(destination)?);
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_renameat2,
+            libc::AT_FDCWD,
+            source.as_ptr(),
+            libc::AT_FDCWD,
+            destination.as_ptr(),
+            libc::RENAME_NOREPLACE,
+        )
+    };
+    if result == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Without `renameat2`, `link(2)` fails with `EEXIST` on an existing destination, and the
+/// source name is dropped once the destination holds the file.
+#[cfg(not(target_os = "linux"))]
+fn rename_noreplace(source: &Path, destination: &Path) -> io::Result<()> {
+    fs::hard_link(source, destination)?;
+    fs::remove_file(source)
+}
+
+/// A file the receiving side creates as a hard link to `target`, which holds the same
+/// contents once the regular apply finishes, instead of transferring it again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HardLink {
+    pub target: Entry,
+    pub action: Action,
+}
+
+impl HardLink {
+    pub fn reverse(&self) -> Self {
+        Self {
+            target: self.target.clone(),
+            action: crate::actions::reverse(&vec![self.action.clone()]).remove(0),
+        }
+    }
+
+    fn entry(&self) -> &Entry {
+        match &self.action {
+            Action::Local(Change::Added(new) | Change::Modified(_, new))
+            | Action::Remote(Change::Added(new) | Change::Modified(_, new)) => new,
+            _ => unreachable!("validate_hard_link accepts only one-sided additions"),
+        }
+    }
+}
+
+fn validate_hard_link(link: &HardLink) -> Result<()> {
+    validate_actions(std::slice::from_ref(&link.action))?;
+    validate_entry_path(&link.target)?;
+    let new = match &link.action {
+        Action::Local(Change::Added(new) | Change::Modified(_, new))
+        | Action::Remote(Change::Added(new) | Change::Modified(_, new)) => new,
+        _ => {
+            return Err(eyre!(
+                "hard link {} must add or modify a file on one side",
+                link.action.path().display()
+            ))
+        }
+    };
+    if !new.is_file() || !link.target.is_file() || new.path() == link.target.path() {
+        return Err(eyre!(
+            "hard link {} must name a different regular file as its target",
+            new.path().display()
+        ));
+    }
+    if new.digest().is_none()
+        || new.digest() != link.target.digest()
+        || new.size() != link.target.size()
+    {
+        return Err(eyre!(
+            "hard link {} does not have the contents of its target {}",
+            new.path().display(),
+            link.target.path().display()
+        ));
+    }
+    Ok(())
+}
+
+/// Creates the hard links that apply on this side, after the regular apply has written their
+/// targets, and records every link in `all_old`.
+pub fn apply_hard_links(base: &Path, links: &[HardLink], all_old: &mut Vec<Entry>) -> Result<()> {
+    for link in links {
+        validate_hard_link(link)?;
+    }
+
+    let mut linked = Vec::with_capacity(links.len());
+    for link in links {
+        let new = link.entry();
+        if let Action::Local(change) = &link.action {
+            let target = safe_join(base, link.target.path())?;
+            let destination = safe_join(base, new.path())?;
+            verify_file_matches_entry(&target, &link.target, "hard link target")?;
+            match change {
+                Change::Added(_) => fs::hard_link(&target, &destination).wrap_err_with(|| {
+                    format!(
+                        "failed to link {} to {}",
+                        destination.display(),
+                        target.display()
+                    )
+                })?,
+                Change::Modified(old, _) => {
+                    verify_current_matches_entry(&destination, old, "hard link destination")?;
+                    replace_with_hard_link(&target, &destination)?;
+                }
+                Change::Removed(_) => unreachable!("validate_hard_link rejects removals"),
+            }
+            sync_directory(output_parent(&destination))?;
+            let meta = fs::symlink_metadata(&destination).wrap_err_with(|| {
+                format!("failed to read metadata for {}", destination.display())
+            })?;
+            let mut entry = new.clone();
+            entry.set_ino(meta.ino());
+            linked.push(entry);
+        } else {
+            linked.push(new.clone());
+        }
+    }
+
+    let linked_paths: HashSet<&PathBuf> = linked.iter().map(Entry::path).collect();
+    all_old.retain(|entry| !linked_paths.contains(entry.path()));
+    all_old.extend(linked);
+    all_old.sort();
+    Ok(())
+}
+
+fn replace_with_hard_link(target: &Path, destination: &Path) -> Result<()> {
+    let parent = output_parent(destination);
+    for _ in 0..64 {
+        let temp = parent.join(format!(".duet-l-{:016x}", temp_nonce()));
+        match fs::hard_link(target, &temp) {
+            Ok(()) => {
+                return fs::rename(&temp, destination).wrap_err_with(|| {
+                    let _ = fs::remove_file(&temp);
+                    format!(
+                        "failed to replace {} with a link to {}",
+                        destination.display(),
+                        target.display()
+                    )
+                });
+            }
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(error) => {
+                return Err(error).wrap_err_with(|| {
+                    format!("failed to link {} to {}", temp.display(), target.display())
+                })
+            }
+        }
+    }
+    Err(eyre!(
+        "unable to choose a unique temporary link name for {}",
+        destination.display()
+    ))
+}
+
+/// Renames the current versions of files that resolved conflicts are about to overwrite or
+/// remove to sidecar names next to them, so that the losing edits survive the apply, and returns
+/// the sidecars' entries.
+pub fn keep_conflict_sidecars(base: &Path, entries: &[Entry]) -> Result<Vec<Entry>> {
+    for entry in entries {
+        validate_entry_path(entry)?;
+        if !entry.is_file() {
+            return Err(eyre!(
+                "conflict sidecar for {} must keep a regular file",
+                entry.path().display()
+            ));
+        }
+    }
+
+    let host = host_name();
+    let stamp = chrono::Utc::now().format("%Y%m%d-%H%M%S").to_string();
+    let mut kept = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let source = safe_join(base, entry.path())?;
+        verify_file_matches_entry(&source, entry, "conflict sidecar source")?;
+        let mut sidecar = None;
+        for attempt in 0..64 {
+            let candidate = sidecar_path(entry.path(), &host, &stamp, attempt);
+            match fs::symlink_metadata(safe_join(base, &candidate)?) {
+                Ok(_) => {}
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                    sidecar = Some(candidate);
+                    break;
+                }
+                Err(error) => {
+                    return Err(error).wrap_err_with(|| {
+                        format!("failed to check sidecar name {}", candidate.display())
+                    })
+                }
+            }
+        }
+        let sidecar = sidecar.ok_or_else(|| {
+            eyre!(
+                "unable to choose a unique conflict sidecar name for {}",
+                entry.path().display()
+            )
+        })?;
+        rename_without_replacing(&source, &safe_join(base, &sidecar)?)?;
+        let mut entry = entry.clone();
+        entry.set_path(sidecar);
+        kept.push(entry);
+    }
+    Ok(kept)
+}
+
+/// The detail that rewrites the file `entry` describes with the result of a clean three-way
+/// merge: a diff against the file's blocks, as a peer sends for a modification, so that the
+/// merge is applied like any other local change.
+pub fn merged_file_detail(
+    base: &Path,
+    entry: &Entry,
+    contents: &[u8],
+    window_config: SignatureWindowConfig,
+) -> Result<ChangeDetails> {
+    validate_entry_path(entry)?;
+    if !entry.is_file() {
+        return Err(eyre!(
+            "merge target {} must be a regular file",
+            entry.path().display()
+        ));
+    }
+    let filename = safe_join(base, entry.path())?;
+    let file = fs::File::open(&filename)
+        .wrap_err_with(|| format!("failed to open merge target {}", filename.display()))?;
+    let len = file.metadata()?.len();
+    let block = vec![0; window_config.window_for_size(entry.size())];
+    let signature = signature_sparse(&file, block, len, |offset| hole_end(&file, offset, len))?;
+    let block = vec![0; signature.window];
+    Ok(ChangeDetails::Diff(compare(&signature, contents, block)?))
+}
+
+/// `name.ext` becomes `name.duet-conflict-<host>-<stamp>.ext`, with a counter after the stamp
+/// on later attempts.
+fn sidecar_path(path: &Path, host: &str, stamp: &str, attempt: usize) -> PathBuf {
+    let name = path
+        .file_name()
+        .expect("validated entry paths end in a name")
+        .as_bytes();
+    let (stem, extension) = match name.iter().rposition(|&byte| byte == b'.') {
+        Some(dot) if dot > 0 => name.split_at(dot),
+        _ => (name, &b""[..]),
+    };
+    let mut sidecar = stem.to_vec();
+    sidecar.extend_from_slice(format!(".duet-conflict-{host}-{stamp}").as_bytes());
+    if attempt > 0 {
+        sidecar.extend_from_slice(format!("-{}", attempt + 1).as_bytes());
+    }
+    sidecar.extend_from_slice(extension);
+    path.with_file_name(std::ffi::OsStr::from_bytes(&sidecar))
+}
+
+/// The short host name, restricted to characters that are safe in a file name.
+fn host_name() -> String {
+    let mut buffer = [0u8; 256];
+    let result = unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) };
+    let len = buffer.iter().position(|&byte| byte == 0).unwrap_or(0);
+    let name: String = String::from_utf8_lossy(&buffer[..len])
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if result != 0 || name.is_empty() {
+        "unknown".to_string()
+    } else {
+        name
+    }
+}
+
 #[allow(dead_code)]
 pub fn apply_detailed_changes(
     base: &PathBuf,
@@ -10729,6 +11885,11 @@ fn apply_detailed_changes_with_output_batch(
                             record_committed_step(attempt_state, "create-dir", e.path())?;
                             // new entry gets updated in the second pass, after all the updates in
                             // the directory are finished
+                        } else if e.is_special() {
+                            create_special(&filename, e)?;
+                            record_committed_step(attempt_state, "create-special", e.path())?;
+                            new_entries.push(update_meta(&filename, e)?);
+                            record_committed_step(attempt_state, "update-metadata", e.path())?;
                         } else {
                             log::debug!("Adding {}", e.path().display());
                             let detail = next_detail(&mut details_iter, e.path())?;
@@ -10836,6 +11997,19 @@ fn apply_detailed_changes_with_output_batch(
                                 } else if e2.is_dir() {
                                     create_private_directory(&filename)?;
                                     record_committed_step(attempt_state, "create-dir", e2.path())?;
+                                } else if e2.is_special() {
+                                    create_special(&filename, e2)?;
+                                    record_committed_step(
+                                        attempt_state,
+                                        "create-special",
+                                        e2.path(),
+                                    )?;
+                                    new_entries.push(update_meta(&filename, e2)?);
+                                    record_committed_step(
+                                        attempt_state,
+                                        "update-metadata",
+                                        e2.path(),
+                                    )?;
                                 } else {
                                     return Err(eyre!(
                                         "unsupported new entry for {}",
@@ -10843,13 +12017,21 @@ fn apply_detailed_changes_with_output_batch(
                                     ));
                                 }
                             }
-                        } else if e1.is_symlink() {
-                            // remove the symlink
+                        } else if e1.same_special_node(e2) {
+                            verify_current_matches_entry(&filename, e1, "metadata target")?;
+                            new_entries.push(update_meta(&filename, e2)?);
+                            record_committed_step(attempt_state, "update-metadata", e2.path())?;
+                        } else if e1.is_symlink() || e1.is_special() {
+                            // remove the symlink or special file
                             verify_current_matches_entry(&filename, e1, "replace target")?;
                             fs::remove_file(&filename).wrap_err_with(|| {
                                 format!("failed to remove file {}", filename.display())
                             })?;
-                            record_committed_step(attempt_state, "remove-symlink", e1.path())?;
+                            record_committed_step(
+                                attempt_state,
+                                entry_operation("remove", e1),
+                                e1.path(),
+                            )?;
                             if e2.is_file() {
                                 let detail = next_detail(&mut details_iter, e2.path())?;
                                 if output_batch.should_flush_before(e2.size()) {
@@ -10884,6 +12066,11 @@ fn apply_detailed_changes_with_output_batch(
                                 record_committed_step(attempt_state, "create-dir", e2.path())?;
                                 // new entry gets updated in the second pass, after all the updates in
                                 // the directory are finished
+                            } else if e2.is_special() {
+                                create_special(&filename, e2)?;
+                                record_committed_step(attempt_state, "create-special", e2.path())?;
+                                new_entries.push(update_meta(&filename, e2)?);
+                                record_committed_step(attempt_state, "update-metadata", e2.path())?;
                             }
                         } else if e1.is_dir() {
                             if e2.is_file() {
@@ -10919,6 +12106,9 @@ fn apply_detailed_changes_with_output_batch(
                 }
             },
             Action::Conflict(_, _) => {} // skip conflicts; only way we get here with them, if we are in the batch force mode
+            Action::LocalMove(_) | Action::RemoteMove(_) => {
+                unreachable!("validate_actions rejects moves")
+            }
         }
     }
 
@@ -10985,6 +12175,9 @@ fn apply_detailed_changes_with_output_batch(
                                     )
                                 })?;
                                 record_committed_step(attempt_state, "create-symlink", e2.path())?;
+                            } else if e2.is_special() {
+                                create_special(&dirname, e2)?;
+                                record_committed_step(attempt_state, "create-special", e2.path())?;
                             } else if e2.is_file() {
                                 let detail = details_iter.next().ok_or_else(|| {
                                     eyre!("missing detail for {}", e2.path().display())
@@ -11150,7 +12343,11 @@ fn create_file_with_contents(
     Ok(())
 }
 
-fn verify_file_matches_entry(filename: &Path, entry: &Entry, description: &str) -> Result<()> {
+pub(crate) fn verify_file_matches_entry(
+    filename: &Path,
+    entry: &Entry,
+    description: &str,
+) -> Result<()> {
     let mut file = fs::OpenOptions::new()
         .read(true)
         .custom_flags(libc::O_NOFOLLOW | libc::O_CLOEXEC)
@@ -11300,6 +12497,16 @@ fn verify_current_matches_entry(filename: &Path, entry: &Entry, description: &st
                 target.display()
             ));
         }
+    } else if entry.is_special() {
+        if meta.mode() & libc::S_IFMT != entry.mode() & libc::S_IFMT
+            || (entry.is_device() && meta.rdev() != entry.rdev())
+        {
+            return Err(eyre!(
+                "{} {} is not the expected special file",
+                description,
+                entry.path().display()
+            ));
+        }
     } else {
         return Err(eyre!("unsupported entry for {}", entry.path().display()));
     }
@@ -11313,13 +12520,15 @@ fn verify_current_matches_entry(filename: &Path, entry: &Entry, description: &st
             synced_mode(meta.mode())
         ));
     }
-    if !entry.is_dir() && meta.mtime() != entry.mtime() {
+    if !entry.is_dir() && !entry.mtime_matches(&meta) {
         return Err(eyre!(
-            "{} {} mtime mismatch: expected {}, got {}",
+            "{} {} mtime mismatch: expected {}.{:09}, got {}.{:09}",
             description,
             entry.path().display(),
             entry.mtime(),
-            meta.mtime()
+            entry.mtime_nsec().unwrap_or(0),
+            meta.mtime(),
+            meta.mtime_nsec()
         ));
     }
 
@@ -11365,7 +12574,20 @@ fn update_meta(path: &PathBuf, e: &Entry) -> Result<Entry> {
         filetime::set_symlink_file_times(
             path,
             filetime::FileTime::from_unix_time(meta.atime(), 0),
-            filetime::FileTime::from_unix_time(e.mtime(), 0),
+            e.mtime_filetime(),
+        )
+        .wrap_err_with(|| format!("failed to set time for {}", path.display()))?;
+    } else if e.is_special() {
+        // opening a FIFO blocks until the other end appears, so special files are updated by path
+        if let Some(ownership) = e.ownership() {
+            crate::scan::ownership::apply_path(path, ownership)?;
+        }
+        fs::set_permissions(path, fs::Permissions::from_mode(synced_mode(e.mode())))
+            .wrap_err_with(|| format!("failed to set permissions for {}", path.display()))?;
+        filetime::set_symlink_file_times(
+            path,
+            filetime::FileTime::from_unix_time(meta.atime(), 0),
+            e.mtime_filetime(),
         )
         .wrap_err_with(|| format!("failed to set time for {}", path.display()))?;
     } else {
@@ -11389,12 +12611,14 @@ fn update_meta(path: &PathBuf, e: &Entry) -> Result<Entry> {
                 });
             }
         };
+        apply_ownership(&file, e, path)?;
+        apply_xattrs(&file, e, path)?;
         file.set_permissions(fs::Permissions::from_mode(desired_mode))
             .wrap_err_with(|| format!("failed to set permissions for {}", path.display()))?;
         filetime::set_file_handle_times(
             &file,
             Some(filetime::FileTime::from_unix_time(meta.atime(), 0)),
-            Some(filetime::FileTime::from_unix_time(e.mtime(), 0)),
+            Some(e.mtime_filetime()),
         )
         .wrap_err_with(|| format!("failed to set time for {}", path.display()))?;
         file.sync_all()
@@ -11404,9 +12628,66 @@ fn update_meta(path: &PathBuf, e: &Entry) -> Result<Entry> {
     let final_meta = fs::symlink_metadata(path)
         .wrap_err_with(|| format!("failed to verify metadata for {}", path.display()))?;
     new_entry.set_ino(final_meta.ino());
+    record_ownership(&mut new_entry, &final_meta);
     Ok(new_entry)
 }
 
+/// Creates the FIFO or device node described by `e`; its final mode and times are set afterwards.
+fn create_special(path: &Path, e: &Entry) -> Result<()> {
+    let name = std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| eyre!("path contains an interior NUL byte: {}", path.display()))?;
+    cvt(unsafe {
+        libc::mknod(
+            name.as_ptr(),
+            special_creation_mode(e),
+            e.rdev() as libc::dev_t,
+        )
+    })
+    .map_err(|error| special_creation_error(error, path, e))
+}
+
+fn special_creation_mode(e: &Entry) -> libc::mode_t {
+    (e.mode() & libc::S_IFMT) | 0o600
+}
+
+fn special_creation_error(error: io::Error, path: &Path, e: &Entry) -> color_eyre::Report {
+    if e.is_device() && error.kind() == io::ErrorKind::PermissionDenied {
+        eyre!(
+            "creating device node {} requires root: {}",
+            path.display(),
+            error
+        )
+    } else {
+        color_eyre::Report::new(error)
+            .wrap_err(format!("failed to create special file {}", path.display()))
+    }
+}
+
+/// Changes the owner and group captured for `e` where this process may; entries scanned without
+/// ownership are left alone.
+fn apply_ownership(file: &fs::File, e: &Entry, path: &Path) -> Result<()> {
+    match e.ownership() {
+        Some(ownership) => crate::scan::ownership::apply(file, ownership, path),
+        None => Ok(()),
+    }
+}
+
+/// Records the ownership an applied entry actually ended up with, so a skipped change is not
+/// reported back as a local modification by the next scan.
+fn record_ownership(e: &mut Entry, meta: &fs::Metadata) {
+    if let Some(mode) = e.ownership().map(|ownership| ownership.mode()) {
+        e.set_ownership(Some(crate::scan::Ownership::from_metadata(meta, mode)));
+    }
+}
+
+/// Sets the extended attributes captured for `e`; entries scanned without them are left alone.
+fn apply_xattrs(file: &fs::File, e: &Entry, path: &Path) -> Result<()> {
+    match e.xattrs() {
+        Some(xattrs) => crate::scan::xattr::apply(file, xattrs, path),
+        None => Ok(()),
+    }
+}
+
 fn open_metadata_target(path: &Path, is_dir: bool) -> io::Result<fs::File> {
     let flags = libc::O_CLOEXEC | libc::O_NOFOLLOW;
     if is_dir {
@@ -11436,7 +12717,7 @@ fn synced_mode(mode: u32) -> u32 {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::rustsync::Block;
+    use crate::rustsync::{signature, Block};
     use rand::{RngCore, SeedableRng};
     use std::os::unix::fs::PermissionsExt;
     use std::sync::Condvar;
@@ -11493,6 +12774,177 @@ mod tests {
         }
     }
 
+    fn move_fixture(base: &Path, to: &str) -> (Action, Vec<Entry>) {
+        fs::write(base.join("a.txt"), b"moved").unwrap();
+        let mut from = Entry::test_file_from_path(PathBuf::from("a.txt"), &base.join("a.txt"));
+        from.set_digest(Some(content_digest(b"moved")));
+        let mut destination = Entry::test_file_from_path(PathBuf::from(to), &base.join("a.txt"));
+        destination.set_digest(Some(content_digest(b"moved")));
+        let m = Move {
+            from: from.clone(),
+            to: destination,
+            contents: Vec::new(),
+        };
+        (Action::LocalMove(m), vec![from])
+    }
+
+    #[test]
+    fn apply_moves_renames_into_created_parents_and_updates_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let (action, mut all_old) = move_fixture(dir.path(), "dir/b.txt");
+        let ino = fs::metadata(dir.path().join("a.txt")).unwrap().ino();
+
+        let created = apply_moves(dir.path(), &[action], &mut all_old).unwrap();
+
+        assert!(!dir.path().join("a.txt").exists());
+        assert_eq!(
+            fs::metadata(dir.path().join("dir/b.txt")).unwrap().ino(),
+            ino
+        );
+        assert_eq!(created.len(), 1);
+        assert_eq!(created[0].path(), Path::new("dir"));
+        assert!(created[0].is_dir());
+        assert_eq!(all_old.len(), 1);
+        assert_eq!(all_old[0].path(), Path::new("dir/b.txt"));
+        assert_eq!(all_old[0].ino(), ino);
+    }
+
+    #[test]
+    fn apply_moves_refuses_changed_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let (action, mut all_old) = move_fixture(dir.path(), "b.txt");
+        fs::write(dir.path().join("a.txt"), b"edited").unwrap();
+
+        let error = apply_moves(dir.path(), &[action], &mut all_old).unwrap_err();
+
+        assert!(format!("{:#}", error).contains("a.txt"), "{:#}", error);
+        assert!(dir.path().join("a.txt").exists());
+        assert!(!dir.path().join("b.txt").exists());
+        assert_eq!(all_old[0].path(), Path::new("a.txt"));
+    }
+
+    #[test]
+    fn apply_moves_does_not_replace_existing_destination() {
+        let dir = tempfile::tempdir().unwrap();
+        let (action, mut all_old) = move_fixture(dir.path(), "b.txt");
+        fs::write(dir.path().join("b.txt"), b"unrelated").unwrap();
+
+        assert!(apply_moves(dir.path(), &[action], &mut all_old).is_err());
+
+        assert_eq!(fs::read(dir.path().join("b.txt")).unwrap(), b"unrelated");
+        assert_eq!(fs::read(dir.path().join("a.txt")).unwrap(), b"moved");
+    }
+
+    #[test]
+    fn rename_without_replacing_fails_on_an_existing_destination_in_one_step() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), b"moved").unwrap();
+        fs::write(dir.path().join("b.txt"), b"unrelated").unwrap();
+        fs::create_dir(dir.path().join("d")).unwrap();
+
+        for destination in ["b.txt", "d"] {
+            let error =
+                rename_without_replacing(&dir.path().join("a.txt"), &dir.path().join(destination))
+                    .unwrap_err();
+            assert!(error.to_string().contains("already exists"), "{}", error);
+        }
+        assert_eq!(fs::read(dir.path().join("b.txt")).unwrap(), b"unrelated");
+
+        rename_without_replacing(&dir.path().join("a.txt"), &dir.path().join("c.txt")).unwrap();
+        assert!(!dir.path().join("a.txt").exists());
+        assert_eq!(fs::read(dir.path().join("c.txt")).unwrap(), b"moved");
+    }
+
+    #[test]
+    fn moves_are_rejected_by_the_regular_apply_validation() {
+        let dir = tempfile::tempdir().unwrap();
+        let (action, _) = move_fixture(dir.path(), "b.txt");
+
+        assert!(validate_actions(std::slice::from_ref(&action)).is_err());
+        assert!(validate_strong_actions(&[action]).is_ok());
+    }
+
+    fn hard_link_fixture(base: &Path, change: impl FnOnce(Entry) -> Change) -> HardLink {
+        fs::write(base.join("a.txt"), b"linked").unwrap();
+        let mut target = Entry::test_file_from_path(PathBuf::from("a.txt"), &base.join("a.txt"));
+        target.set_digest(Some(content_digest(b"linked")));
+        let mut new = Entry::test_file_from_path(PathBuf::from("b.txt"), &base.join("a.txt"));
+        new.set_digest(Some(content_digest(b"linked")));
+        HardLink {
+            target,
+            action: Action::Local(change(new)),
+        }
+    }
+
+    #[test]
+    fn apply_hard_links_links_added_and_modified_names_to_their_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let link = hard_link_fixture(dir.path(), Change::Added);
+        let mut all_old = Vec::new();
+
+        apply_hard_links(dir.path(), &[link], &mut all_old).unwrap();
+
+        let ino = fs::metadata(dir.path().join("a.txt")).unwrap().ino();
+        assert_eq!(fs::metadata(dir.path().join("b.txt")).unwrap().ino(), ino);
+        assert_eq!(all_old.len(), 1);
+        assert_eq!(all_old[0].ino(), ino);
+
+        fs::remove_file(dir.path().join("b.txt")).unwrap();
+        fs::write(dir.path().join("b.txt"), b"old").unwrap();
+        let mut old = Entry::test_file_from_path(PathBuf::from("b.txt"), &dir.path().join("b.txt"));
+        old.set_digest(Some(content_digest(b"old")));
+        let link = hard_link_fixture(dir.path(), |new| Change::Modified(old, new));
+
+        apply_hard_links(dir.path(), &[link], &mut all_old).unwrap();
+
+        assert_eq!(fs::metadata(dir.path().join("b.txt")).unwrap().ino(), ino);
+        assert_eq!(all_old.len(), 1);
+    }
+
+    #[test]
+    fn apply_hard_links_refuses_a_target_with_other_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let link = hard_link_fixture(dir.path(), Change::Added);
+        fs::write(dir.path().join("a.txt"), b"edited").unwrap();
+
+        assert!(apply_hard_links(dir.path(), &[link], &mut Vec::new()).is_err());
+        assert!(!dir.path().join("b.txt").exists());
+    }
+
+    #[test]
+    fn sidecar_names_keep_the_extension_and_count_attempts() {
+        let sidecar = |path: &str, attempt| sidecar_path(Path::new(path), "host", "ts", attempt);
+        assert_eq!(
+            sidecar("dir/a.tar.gz", 0),
+            Path::new("dir/a.tar.duet-conflict-host-ts.gz")
+        );
+        assert_eq!(
+            sidecar(".bashrc", 0),
+            Path::new(".bashrc.duet-conflict-host-ts")
+        );
+        assert_eq!(
+            sidecar("Makefile", 2),
+            Path::new("Makefile.duet-conflict-host-ts-3")
+        );
+    }
+
+    #[test]
+    fn keep_conflict_sidecars_renames_the_verified_file() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), b"mine").unwrap();
+        let entry = test_file_entry("a.txt", b"mine");
+        let stale = test_file_entry("a.txt", b"older");
+
+        assert!(keep_conflict_sidecars(dir.path(), &[stale]).is_err());
+        let kept = keep_conflict_sidecars(dir.path(), &[entry]).unwrap();
+
+        assert_eq!(kept.len(), 1);
+        assert!(!dir.path().join("a.txt").exists());
+        let name = kept[0].path().to_str().unwrap();
+        assert!(name.starts_with("a.duet-conflict-") && name.ends_with(".txt"));
+        assert_eq!(fs::read(dir.path().join(kept[0].path())).unwrap(), b"mine");
+    }
+
     #[test]
     fn staging_budget_applies_reserve_and_limit() {
         assert_eq!(
@@ -12501,6 +13953,204 @@ mod tests {
         assert!(duplicate.to_string().contains("duplicate"));
     }
 
+    #[test]
+    fn holes_stream_as_hole_frames_and_stay_unallocated_in_the_output() {
+        const LEN: usize = 1024 * 1024;
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let mut contents = vec![0; LEN];
+        contents[LEN / 2..LEN / 2 + 4].copy_from_slice(b"data");
+        let file = fs::File::create(source.path().join("image")).unwrap();
+        file.set_len(LEN as u64).unwrap();
+        file.write_all_at(b"data", (LEN / 2) as u64).unwrap();
+        drop(file);
+        let holes_supported =
+            fs::metadata(source.path().join("image")).unwrap().blocks() * 512 < LEN as u64;
+
+        let mut entry = test_file_entry("image", &contents);
+        entry.set_allocated(Some(SPARSE_BLOCK_BYTES));
+        let mut producer = DetailProducer::new(
+            source.path().to_path_buf(),
+            vec![Action::Remote(Change::Added(entry.clone()))],
+            Vec::new(),
+            64 * 1024,
+        )
+        .with_holes(true);
+        let mut frames = Vec::new();
+        while let Some(frame) = producer.next_frame().unwrap() {
+            frames.push(frame);
+        }
+        if holes_supported {
+            assert!(frames
+                .iter()
+                .any(|frame| matches!(frame.payload, DetailPayload::FileHole(_))));
+        }
+        assert!(detail_frames_transfer_bytes(&frames) == LEN as u64);
+
+        let actions = vec![Action::Local(Change::Added(entry))];
+        let mut applier =
+            DetailApplier::new_with_attempt(target.path().to_path_buf(), actions, Vec::new(), None);
+        for frame in frames {
+            applier.apply_frame(frame).unwrap();
+        }
+        applier.finish().unwrap();
+
+        let output = target.path().join("image");
+        assert_eq!(fs::read(&output).unwrap(), contents);
+        if holes_supported {
+            assert!(fs::metadata(&output).unwrap().blocks() * 512 < LEN as u64);
+        }
+    }
+
+    #[test]
+    fn compressed_frames_inflate_on_apply_and_incompressible_files_go_as_they_are() {
+        use rand::Rng;
+        const LEN: usize = 256 * 1024;
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let text = b"a line of text that repeats\n".repeat(LEN / 28);
+        let mut noise = vec![0; LEN];
+        rand::thread_rng().fill(&mut noise[..]);
+        fs::write(source.path().join("text"), &text).unwrap();
+        fs::write(source.path().join("noise"), &noise).unwrap();
+        let entries = [
+            test_file_entry("noise", &noise),
+            test_file_entry("text", &text),
+        ];
+
+        let mut producer = DetailProducer::new(
+            source.path().to_path_buf(),
+            entries
+                .iter()
+                .map(|entry| Action::Remote(Change::Added(entry.clone())))
+                .collect(),
+            Vec::new(),
+            64 * 1024,
+        )
+        .with_compression(DEFAULT_COMPRESSION_LEVEL);
+        let mut frames = Vec::new();
+        while let Some(frame) = producer.next_frame().unwrap() {
+            frames.push(frame);
+        }
+        let compressed = |index| {
+            frames
+                .iter()
+                .filter(|frame| frame.action_index == index)
+                .filter(|frame| matches!(frame.payload, DetailPayload::FileBytesCompressed(_)))
+                .count()
+        };
+        // the first chunk of noise is tried and sent as it is, and so is the rest of the file
+        assert_eq!(compressed(0), 0);
+        assert_eq!(compressed(1), 4);
+        assert_eq!(
+            detail_frames_transfer_bytes(&frames),
+            (text.len() + noise.len()) as u64
+        );
+
+        let actions = entries
+            .iter()
+            .map(|entry| Action::Local(Change::Added(entry.clone())))
+            .collect();
+        let mut applier =
+            DetailApplier::new_with_attempt(target.path().to_path_buf(), actions, Vec::new(), None);
+        for frame in frames {
+            applier.apply_frame(frame).unwrap();
+        }
+        applier.finish().unwrap();
+        assert_eq!(fs::read(target.path().join("text")).unwrap(), text);
+        assert_eq!(fs::read(target.path().join("noise")).unwrap(), noise);
+
+        let mut truncated = CompressedBytes::new(&text, 1).unwrap();
+        truncated.len -= 1;
+        let frame = DetailFrame {
+            action_index: 0,
+            payload: DetailPayload::FileBytesCompressed(truncated),
+        };
+        assert!(frame.decompressed().is_err());
+    }
+
+    #[test]
+    fn file_copies_are_sent_as_paths_and_applied_only_when_enabled() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let bytes = b"copied without frames\n".repeat(4096);
+        fs::write(source.path().join("a"), &bytes).unwrap();
+        let entry = test_file_entry("a", &bytes);
+
+        let mut producer = DetailProducer::new(
+            source.path().to_path_buf(),
+            vec![Action::Remote(Change::Added(entry.clone()))],
+            Vec::new(),
+            64 * 1024,
+        )
+        .with_file_copies(true);
+        let mut frames = Vec::new();
+        while let Some(frame) = producer.next_frame().unwrap() {
+            frames.push(frame);
+        }
+        assert_eq!(frames.len(), 3);
+        assert!(matches!(
+            &frames[1].payload,
+            DetailPayload::FileCopy { source: path, len }
+                if path == &source.path().join("a") && *len == bytes.len() as u64
+        ));
+        assert_eq!(detail_frames_transfer_bytes(&frames), bytes.len() as u64);
+
+        let applier = |file_copies| {
+            DetailApplier::new_with_attempt(
+                target.path().to_path_buf(),
+                vec![Action::Local(Change::Added(entry.clone()))],
+                Vec::new(),
+                None,
+            )
+            .with_file_copies(file_copies)
+        };
+        // a peer that did not offer copies could otherwise read any path it names
+        let mut refusing = applier(false);
+        refusing.apply_frame(frames[0].clone()).unwrap();
+        assert!(refusing.apply_frame(frames[1].clone()).is_err());
+        drop(refusing);
+
+        let mut copying = applier(true);
+        for frame in frames.clone() {
+            copying.apply_frame(frame).unwrap();
+        }
+        copying.finish().unwrap();
+        assert_eq!(fs::read(target.path().join("a")).unwrap(), bytes);
+
+        // the copied bytes are verified as they pass, so a source rewritten since the scan fails
+        let mut rewritten = bytes.clone();
+        rewritten[0] ^= 1;
+        fs::write(source.path().join("a"), &rewritten).unwrap();
+        fs::remove_file(target.path().join("a")).unwrap();
+        let mut copying = applier(true);
+        let result = frames
+            .into_iter()
+            .try_for_each(|frame| copying.apply_frame(frame))
+            .and_then(|_| copying.finish());
+        assert!(result.is_err());
+        assert!(!target.path().join("a").exists());
+    }
+
+    #[test]
+    fn write_sparse_skips_aligned_zero_blocks() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut file = fs::File::create(dir.path().join("out")).unwrap();
+        let block = SPARSE_BLOCK_BYTES as usize;
+        let mut bytes = vec![0; block * 3 + 10];
+        bytes[1] = 1;
+        bytes[block * 3 + 9] = 2;
+        file.write_all(b"xyz").unwrap();
+        write_sparse(&mut file, &bytes).unwrap();
+        let end = file.stream_position().unwrap();
+        file.set_len(end).unwrap();
+        drop(file);
+
+        let mut expected = b"xyz".to_vec();
+        expected.extend_from_slice(&bytes);
+        assert_eq!(fs::read(dir.path().join("out")).unwrap(), expected);
+    }
+
     #[test]
     fn staged_commit_revalidates_target_after_explicit_validation() {
         let dir = tempfile::tempdir().unwrap();
@@ -13637,7 +15287,9 @@ mod tests {
         .unwrap();
 
         assert!(plan.removed_destination_paths.contains(Path::new("gone")));
-        let kind = plan.removal_policy.classify(Path::new("gone/blocker.tmp"));
+        let kind = plan
+            .removal_policy
+            .classify(Path::new("gone/blocker.tmp"), false);
         assert!(plan.removal_policy.should_prune(&kind));
     }
 
@@ -15760,9 +17412,29 @@ mod tests {
             expected,
             true,
             |source, output, verifier, position, clone_backed| {
-                apply_diff_copy(source, output, verifier, position, clone_backed, 0, 3).unwrap();
-                apply_diff_bytes(output, verifier, position, b"xyz").unwrap();
-                apply_diff_copy(source, output, verifier, position, clone_backed, 6, 3).unwrap();
+                apply_diff_copy(
+                    source,
+                    output,
+                    verifier,
+                    position,
+                    clone_backed,
+                    false,
+                    0,
+                    3,
+                )
+                .unwrap();
+                apply_diff_bytes(output, verifier, position, false, b"xyz").unwrap();
+                apply_diff_copy(
+                    source,
+                    output,
+                    verifier,
+                    position,
+                    clone_backed,
+                    false,
+                    6,
+                    3,
+                )
+                .unwrap();
             },
         );
 
@@ -15777,8 +17449,28 @@ mod tests {
             b"defabc",
             true,
             |source, output, verifier, position, clone_backed| {
-                apply_diff_copy(source, output, verifier, position, clone_backed, 3, 3).unwrap();
-                apply_diff_copy(source, output, verifier, position, clone_backed, 0, 3).unwrap();
+                apply_diff_copy(
+                    source,
+                    output,
+                    verifier,
+                    position,
+                    clone_backed,
+                    false,
+                    3,
+                    3,
+                )
+                .unwrap();
+                apply_diff_copy(
+                    source,
+                    output,
+                    verifier,
+                    position,
+                    clone_backed,
+                    false,
+                    0,
+                    3,
+                )
+                .unwrap();
             },
         );
 
@@ -15793,8 +17485,18 @@ mod tests {
             b"abcdef",
             true,
             |source, output, verifier, position, clone_backed| {
-                apply_diff_copy(source, output, verifier, position, clone_backed, 0, 3).unwrap();
-                apply_diff_bytes(output, verifier, position, b"def").unwrap();
+                apply_diff_copy(
+                    source,
+                    output,
+                    verifier,
+                    position,
+                    clone_backed,
+                    false,
+                    0,
+                    3,
+                )
+                .unwrap();
+                apply_diff_bytes(output, verifier, position, false, b"def").unwrap();
             },
         );
 
@@ -15809,7 +17511,17 @@ mod tests {
             b"abc",
             true,
             |source, output, verifier, position, clone_backed| {
-                apply_diff_copy(source, output, verifier, position, clone_backed, 0, 3).unwrap();
+                apply_diff_copy(
+                    source,
+                    output,
+                    verifier,
+                    position,
+                    clone_backed,
+                    false,
+                    0,
+                    3,
+                )
+                .unwrap();
             },
         );
 
@@ -15841,9 +17553,29 @@ mod tests {
             b"abXYZf",
             false,
             |source, output, verifier, position, clone_backed| {
-                apply_diff_copy(source, output, verifier, position, clone_backed, 0, 2).unwrap();
-                apply_diff_bytes(output, verifier, position, b"XYZ").unwrap();
-                apply_diff_copy(source, output, verifier, position, clone_backed, 5, 1).unwrap();
+                apply_diff_copy(
+                    source,
+                    output,
+                    verifier,
+                    position,
+                    clone_backed,
+                    false,
+                    0,
+                    2,
+                )
+                .unwrap();
+                apply_diff_bytes(output, verifier, position, false, b"XYZ").unwrap();
+                apply_diff_copy(
+                    source,
+                    output,
+                    verifier,
+                    position,
+                    clone_backed,
+                    false,
+                    5,
+                    1,
+                )
+                .unwrap();
             },
         );
 
@@ -16067,6 +17799,109 @@ mod tests {
         assert!(stage_directories(&fallback_base).is_empty());
     }
 
+    #[test]
+    fn fallback_metadata_update_sets_and_removes_captured_xattrs() {
+        use crate::scan::{xattr, Xattrs};
+
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().to_path_buf();
+        let old = synced_existing_file_entry(&base, "file.txt", b"contents");
+        let file = fs::File::open(base.join("file.txt")).unwrap();
+        let stale = Xattrs::test_new(&["user"], &[("user.stale", "old"), ("user.tag", "old")]);
+        xattr::apply(&file, &stale, &base.join("file.txt")).unwrap();
+        let mut new = old.clone();
+        new.set_xattrs(Some(Xattrs::test_new(&["user"], &[("user.tag", "new")])));
+        let actions = vec![Action::Local(Change::Modified(old.clone(), new.clone()))];
+        let mut all_old = vec![old];
+
+        apply_detailed_changes(&base, &actions, &Vec::new(), &mut all_old, None).unwrap();
+
+        let captured = xattr::read(&base.join("file.txt"), &["user".to_string()])
+            .unwrap()
+            .unwrap();
+        assert_eq!(captured.values(), new.xattrs().unwrap().values());
+        assert_eq!(fs::read(base.join("file.txt")).unwrap(), b"contents");
+    }
+
+    #[test]
+    fn fallback_metadata_update_records_the_ownership_it_applied() {
+        use crate::scan::{Ownership, OwnershipMode};
+
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().to_path_buf();
+        let old = synced_existing_file_entry(&base, "file.txt", b"contents");
+        let mut new = old.clone();
+        new.set_ownership(Some(Ownership::test_new(
+            OwnershipMode::Numeric,
+            4242,
+            4242,
+            None,
+            None,
+        )));
+        let actions = vec![Action::Local(Change::Modified(old.clone(), new))];
+        let mut all_old = vec![old];
+
+        apply_detailed_changes(&base, &actions, &Vec::new(), &mut all_old, None).unwrap();
+
+        // only root may give the file away; other users keep their ownership and record it
+        let meta = fs::symlink_metadata(base.join("file.txt")).unwrap();
+        if unsafe { libc::geteuid() } == 0 {
+            assert_eq!((meta.uid(), meta.gid()), (4242, 4242));
+        }
+        assert_eq!(
+            all_old[0].ownership(),
+            Some(&Ownership::from_metadata(&meta, OwnershipMode::Numeric))
+        );
+    }
+
+    #[test]
+    fn ownership_report_lists_entries_with_unknown_names() {
+        use crate::scan::{Ownership, OwnershipMode};
+
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().to_path_buf();
+        let old = synced_existing_file_entry(&base, "file.txt", b"contents");
+        let mut new = old.clone();
+        new.set_ownership(Some(Ownership::test_new(
+            OwnershipMode::Group,
+            0,
+            4242,
+            None,
+            Some("duet-no-such-group"),
+        )));
+        let actions = vec![
+            Action::Local(Change::Modified(old.clone(), new.clone())),
+            Action::Remote(Change::Modified(old, new)),
+        ];
+
+        assert_eq!(
+            ownership_report(&base, &actions).unwrap(),
+            vec![OwnershipSkip {
+                path: PathBuf::from("file.txt"),
+                reason: "unknown group duet-no-such-group".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn staged_directory_metadata_sets_captured_xattrs() {
+        use crate::scan::{xattr, Xattrs};
+
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target");
+        fs::create_dir(&target).unwrap();
+        let mut entry = Entry::test_dir(PathBuf::from("target"));
+        entry.set_xattrs(Some(Xattrs::test_new(&["user"], &[("user.tag", "dir")])));
+        let mut ledger = MutationDurabilityLedger::new();
+
+        update_directory_meta_staged(&target, &entry, &mut ledger).unwrap();
+
+        let captured = xattr::read(&target, &["user".to_string()])
+            .unwrap()
+            .unwrap();
+        assert_eq!(captured.values(), entry.xattrs().unwrap().values());
+    }
+
     #[test]
     fn fallback_diff_and_directory_replacement_apply_metadata_before_publication() {
         let diff_dir = tempfile::tempdir().unwrap();
@@ -16126,7 +17961,7 @@ mod tests {
         let sig = signature(fs::File::open(&path).unwrap(), [0; WINDOW]).unwrap();
         let (sender, receiver) = mpsc::sync_channel(16);
 
-        stream_diff_frames(path, 0, sig, 1024 * 1024, sender).unwrap();
+        stream_diff_frames(path, 0, sig, 1024 * 1024, false, sender).unwrap();
 
         let frames = receiver
             .into_iter()
@@ -16611,6 +18446,8 @@ mod tests {
         ];
 
         assert_eq!(detail_frames_transfer_bytes(&frames), 31);
+        // the copied range stays on the other side
+        assert_eq!(detail_frames_wire_bytes(&frames), 20);
     }
 
     #[test]
@@ -0,0 +1,251 @@
+//! Pre-shared-key authentication for `tcp://` remotes and `duet --server --listen`.
+//!
+//! Each side proves that it holds the key by answering the other's random challenge, before any
+//! RPC message is exchanged:
+//!
+//! ```text
+//! client -> server   MAGIC, client nonce
+//! server -> client   MAGIC, server nonce, proof("server")
+//! client -> server   proof("client")
+//! server -> client   ACCEPTED
+//! ```
+//!
+//! A proof is a BLAKE2b MAC, keyed with the key, of its label and both nonces, so neither a
+//! recorded handshake nor one side's proof replayed to it answers a fresh challenge. The
+//! connection is authenticated once and not encrypted, so it belongs on a trusted network.
+
+use std::io::{self, Read};
+use std::path::Path;
+
+use blake2_rfc::blake2b::{blake2b, Blake2b, Blake2bResult};
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Identifies the protocol and its version, so that anything else listening on the port fails
+/// the handshake plainly.
+const MAGIC: &[u8; 12] = b"duet-psk-v1\n";
+const NONCE_BYTES: usize = 32;
+const PROOF_BYTES: usize = 32;
+const ACCEPTED: u8 = 1;
+/// Key files shorter than this are refused as guessable.
+const MIN_KEY_BYTES: usize = 16;
+
+pub(crate) const SCHEME: &str = "tcp://";
+
+type Nonce = [u8; NONCE_BYTES];
+
+/// The address of a `tcp://host:port` server, if `server` is one.
+pub(crate) fn address(server: &str) -> Option<&str> {
+    server.strip_prefix(SCHEME)
+}
+
+/// A pre-shared key, read from a file that only its owner can read.
+pub(crate) struct Key([u8; 64]);
+
+impl Key {
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let metadata = std::fs::metadata(path)
+                .wrap_err_with(|| format!("unable to read key file {}", path.display()))?;
+            if metadata.permissions().mode() & 0o077 != 0 {
+                return Err(eyre!(
+                    "key file {} is accessible by other users; `chmod 600` it",
+                    path.display()
+                ));
+            }
+        }
+        let contents = std::fs::read(path)
+            .wrap_err_with(|| format!("unable to read key file {}", path.display()))?;
+        Self::from_contents(&contents)
+            .wrap_err_with(|| format!("invalid key file {}", path.display()))
+    }
+
+    /// Whatever the file holds, past trailing whitespace, is hashed into the MAC key.
+    fn from_contents(contents: &[u8]) -> Result<Self> {
+        let end = contents
+            .iter()
+            .rposition(|byte| !byte.is_ascii_whitespace())
+            .map_or(0, |index| index + 1);
+        if end < MIN_KEY_BYTES {
+            return Err(eyre!("the key must be at least {} bytes", MIN_KEY_BYTES));
+        }
+        let mut key = [0; 64];
+        key.copy_from_slice(blake2b(64, &[], &contents[..end]).as_bytes());
+        Ok(Self(key))
+    }
+
+    fn mac(&self, label: &[u8], client: &Nonce, server: &Nonce) -> Blake2bResult {
+        let mut mac = Blake2b::with_key(PROOF_BYTES, &self.0);
+        mac.update(label);
+        mac.update(client);
+        mac.update(server);
+        mac.finalize()
+    }
+
+    fn proof(&self, label: &[u8], client: &Nonce, server: &Nonce) -> [u8; PROOF_BYTES] {
+        let mut proof = [0; PROOF_BYTES];
+        proof.copy_from_slice(self.mac(label, client, server).as_bytes());
+        proof
+    }
+
+    fn verify(&self, label: &[u8], client: &Nonce, server: &Nonce, proof: &[u8]) -> bool {
+        // compared in constant time
+        self.mac(label, client, server) == *proof
+    }
+}
+
+fn nonce() -> io::Result<Nonce> {
+    let mut nonce = [0; NONCE_BYTES];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut nonce)?;
+    Ok(nonce)
+}
+
+/// Authenticates a client that connected to `duet --server --listen`, and proves the server's
+/// own knowledge of the key to it.
+pub(crate) async fn accept<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    key: &Key,
+) -> Result<()> {
+    let mut hello = [0; MAGIC.len() + NONCE_BYTES];
+    stream
+        .read_exact(&mut hello)
+        .await
+        .wrap_err("failed to read the client's challenge")?;
+    if &hello[..MAGIC.len()] != MAGIC {
+        return Err(eyre!("the client does not speak the duet key handshake"));
+    }
+    let mut client = [0; NONCE_BYTES];
+    client.copy_from_slice(&hello[MAGIC.len()..]);
+    let server = nonce().wrap_err("failed to generate a challenge")?;
+
+    let mut reply = Vec::with_capacity(MAGIC.len() + NONCE_BYTES + PROOF_BYTES);
+    reply.extend_from_slice(MAGIC);
+    reply.extend_from_slice(&server);
+    reply.extend_from_slice(&key.proof(b"server", &client, &server));
+    stream.write_all(&reply).await?;
+    stream.flush().await?;
+
+    let mut proof = [0; PROOF_BYTES];
+    stream
+        .read_exact(&mut proof)
+        .await
+        .wrap_err("failed to read the client's proof")?;
+    if !key.verify(b"client", &client, &server, &proof) {
+        return Err(eyre!("the client does not hold the key"));
+    }
+    stream.write_all(&[ACCEPTED]).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Authenticates the server a `tcp://` remote connected to, and proves the client's knowledge
+/// of the key to it.
+pub(crate) async fn connect<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    key: &Key,
+) -> Result<()> {
+    let client = nonce().wrap_err("failed to generate a challenge")?;
+    let mut hello = Vec::with_capacity(MAGIC.len() + NONCE_BYTES);
+    hello.extend_from_slice(MAGIC);
+    hello.extend_from_slice(&client);
+    stream.write_all(&hello).await?;
+    stream.flush().await?;
+
+    let mut reply = [0; MAGIC.len() + NONCE_BYTES + PROOF_BYTES];
+    stream
+        .read_exact(&mut reply)
+        .await
+        .wrap_err("the server closed the connection during the key handshake")?;
+    if &reply[..MAGIC.len()] != MAGIC {
+        return Err(eyre!("the server does not speak the duet key handshake"));
+    }
+    let mut server = [0; NONCE_BYTES];
+    server.copy_from_slice(&reply[MAGIC.len()..MAGIC.len() + NONCE_BYTES]);
+    if !key.verify(
+        b"server",
+        &client,
+        &server,
+        &reply[MAGIC.len() + NONCE_BYTES..],
+    ) {
+        return Err(eyre!("the server does not hold the key"));
+    }
+
+    stream
+        .write_all(&key.proof(b"client", &client, &server))
+        .await?;
+    stream.flush().await?;
+    let mut status = [0];
+    match stream.read_exact(&mut status).await {
+        Ok(_) if status[0] == ACCEPTED => Ok(()),
+        _ => Err(eyre!("the server rejected the key")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(contents: &str) -> Key {
+        Key::from_contents(contents.as_bytes()).unwrap()
+    }
+
+    /// Runs both sides of a handshake over a socket pair.
+    fn handshake(client_key: Key, server_key: Key) -> (Result<()>, Result<()>) {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async move {
+            let (mut client, mut server) = tokio::net::UnixStream::pair().unwrap();
+            let server = tokio::spawn(async move { accept(&mut server, &server_key).await });
+            let result = connect(&mut client, &client_key).await;
+            // a client that gave up closes the connection the server waits on
+            drop(client);
+            (result, server.await.unwrap())
+        })
+    }
+
+    #[test]
+    fn both_sides_prove_the_same_key() {
+        let (client, server) = handshake(
+            key("correct horse battery staple\n"),
+            key("correct horse battery staple"),
+        );
+        client.unwrap();
+        server.unwrap();
+
+        let (client, server) = handshake(
+            key("correct horse battery staple"),
+            key("incorrect horse battery staple"),
+        );
+        assert!(client
+            .unwrap_err()
+            .to_string()
+            .contains("server does not hold the key"));
+        assert!(server.is_err());
+    }
+
+    #[test]
+    fn proofs_depend_on_label_and_both_nonces() {
+        let key = key("correct horse battery staple");
+        let (a, b) = ([1; NONCE_BYTES], [2; NONCE_BYTES]);
+        let proof = key.proof(b"server", &a, &b);
+        assert!(key.verify(b"server", &a, &b, &proof));
+        assert!(!key.verify(b"client", &a, &b, &proof));
+        assert!(!key.verify(b"server", &b, &a, &proof));
+        assert!(!key.verify(b"server", &a, &b, &proof[1..]));
+    }
+
+    #[test]
+    fn refuses_short_keys_and_key_files_others_can_read() {
+        assert!(Key::from_contents(b"short   \n").is_err());
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("duet.key");
+        std::fs::write(&path, "correct horse battery staple").unwrap();
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(Key::load(&path)
+            .is_err_and(|error| error.to_string().contains("accessible by other users")));
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        Key::load(&path).unwrap();
+    }
+}
@@ -0,0 +1,515 @@
+use std::fs;
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Component, Path, PathBuf};
+
+use chrono::{NaiveDateTime, Utc};
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use serde::{Deserialize, Serialize};
+
+use crate::actions::Action;
+use crate::profile;
+use crate::scan::{Change, DirEntryWithMeta as Entry};
+use crate::sync;
+
+/// Directory under the sync base that holds each profile's trash, skipped by scans of profiles
+/// with a `[trash]` section.
+pub const TRASH_DIR: &str = ".duet-trash";
+
+/// Runs are named after the UTC time they were made, with a counter on collisions.
+const RUN_FORMAT: &str = "%Y%m%d-%H%M%S";
+const RUN_STAMP_LEN: usize = 15;
+
+/// How long trash runs are kept and how much space they may take; unset limits keep everything.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrashRetention {
+    /// Runs older than this many seconds are purged.
+    pub max_age_secs: Option<u64>,
+    /// The oldest runs are purged while the trash holds more than this many bytes.
+    pub max_bytes: Option<u64>,
+}
+
+impl TrashRetention {
+    /// Retention that purges every run.
+    pub fn everything() -> Self {
+        Self {
+            max_age_secs: None,
+            max_bytes: Some(0),
+        }
+    }
+
+    pub fn is_limited(&self) -> bool {
+        self.max_age_secs.is_some() || self.max_bytes.is_some()
+    }
+}
+
+/// A file kept in the trash: `path` is where it was, relative to the sync base.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrashItem {
+    pub run: String,
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+impl TrashItem {
+    /// The name `list` shows and `restore` accepts.
+    pub fn name(&self) -> PathBuf {
+        Path::new(&self.run).join(&self.path)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrashPurge {
+    pub runs: usize,
+    pub bytes: u64,
+}
+
+/// The regular files the apply removes or replaces with different contents, on the local and
+/// the remote side. Each entry describes the current file, as the apply verifies it.
+pub fn replaced_files(actions: &[Action]) -> (Vec<Entry>, Vec<Entry>) {
+    let mut local = Vec::new();
+    let mut remote = Vec::new();
+    for action in actions {
+        let (side, change) = match action {
+            Action::Local(change) | Action::ResolvedLocal(_, change) => (&mut local, change),
+            Action::Remote(change) | Action::ResolvedRemote(_, change) => (&mut remote, change),
+            _ => continue,
+        };
+        match change {
+            Change::Removed(old) if old.is_file() => side.push(old.clone()),
+            Change::Modified(old, new)
+                if old.is_file() && !(new.is_file() && old.same_contents(new)) =>
+            {
+                side.push(old.clone())
+            }
+            _ => {}
+        }
+    }
+    (local, remote)
+}
+
+/// Copies the current versions of `entries` into a new run of the profile's trash, before the
+/// apply removes or replaces them. Older
+/// runs are then purged according to `retention`; the new run is always kept. Returns the new
+/// run's name, if any files were kept.
+pub fn keep(
+    base: &Path,
+    id: &str,
+    entries: &[Entry],
+    retention: TrashRetention,
+) -> Result<Option<String>> {
+    for entry in entries {
+        sync::validate_entry_path(entry)?;
+        if !entry.is_file() {
+            return Err(eyre!(
+                "trash for {} must keep a regular file",
+                entry.path().display()
+            ));
+        }
+    }
+
+    let trash = trash_dir(base, id)?;
+    let run = if entries.is_empty() {
+        None
+    } else {
+        let run = create_run(&trash)?;
+        for entry in entries {
+            let source = sync::safe_join(base, entry.path())?;
+            sync::verify_file_matches_entry(&source, entry, "trash source")?;
+            let destination = trash.join(&run).join(entry.path());
+            create_private_dir_all(destination.parent().expect("trash paths have a parent"))?;
+            copy_into_trash(&source, &destination)?;
+        }
+        Some(run)
+    };
+    if retention.is_limited() {
+        purge_runs(&trash, retention, run.as_deref())?;
+    }
+    Ok(run)
+}
+
+/// Every file in the profile's trash, oldest run first.
+pub fn list(base: &Path, id: &str) -> Result<Vec<TrashItem>> {
+    let trash = trash_dir(base, id)?;
+    let mut items = Vec::new();
+    for (run, _) in runs(&trash)? {
+        let mut files = Vec::new();
+        collect_files(&trash.join(&run), Path::new(""), &mut files)?;
+        items.extend(files.into_iter().map(|(path, size)| TrashItem {
+            run: run.clone(),
+            path,
+            size,
+        }));
+    }
+    Ok(items)
+}
+
+/// Moves `name` (a run followed by the original path) back to its original path, which must not
+/// exist, and returns that path. The next sync picks it up as an addition.
+pub fn restore(base: &Path, id: &str, name: &Path) -> Result<PathBuf> {
+    sync::validate_relative_path(name)?;
+    let mut components = name.components();
+    let run = match components.next() {
+        Some(Component::Normal(run)) => run.to_str().filter(|run| run_time(run).is_some()),
+        _ => None,
+    };
+    let path = components.as_path().to_path_buf();
+    let Some(run) = run.filter(|_| !path.as_os_str().is_empty() && !path.starts_with(TRASH_DIR))
+    else {
+        return Err(eyre!(
+            "{} does not name a file in the trash; expected <run>/<path> as listed",
+            name.display()
+        ));
+    };
+
+    let trash = trash_dir(base, id)?;
+    let source = trash.join(name);
+    let meta = fs::symlink_metadata(&source)
+        .wrap_err_with(|| format!("{} is not in the trash", name.display()))?;
+    if !meta.is_file() {
+        return Err(eyre!("{} is not a file in the trash", name.display()));
+    }
+    let destination = sync::safe_join(base, &path)?;
+    fs::create_dir_all(destination.parent().expect("restored paths have a parent"))
+        .wrap_err_with(|| format!("failed to create parent of {}", destination.display()))?;
+    sync::rename_without_replacing(&source, &destination)?;
+
+    // drop the directories the restored file leaves empty, up to and including its run
+    let run_dir = trash.join(run);
+    let mut parent = source.parent();
+    while let Some(dir) = parent.filter(|dir| dir.starts_with(&run_dir)) {
+        if fs::remove_dir(dir).is_err() {
+            break;
+        }
+        parent = dir.parent();
+    }
+    Ok(path)
+}
+
+/// Removes the runs that fall outside `retention`.
+pub fn purge(base: &Path, id: &str, retention: TrashRetention) -> Result<TrashPurge> {
+    purge_runs(&trash_dir(base, id)?, retention, None)
+}
+
+fn trash_dir(base: &Path, id: &str) -> Result<PathBuf> {
+    profile::validate_remote_state_id(id).wrap_err_with(|| format!("invalid trash id {}", id))?;
+    Ok(base.join(TRASH_DIR).join(id))
+}
+
+fn create_run(trash: &Path) -> Result<String> {
+    create_private_dir_all(trash)?;
+    let stamp = Utc::now().format(RUN_FORMAT).to_string();
+    for attempt in 0..64 {
+        let run = if attempt == 0 {
+            stamp.clone()
+        } else {
+            format!("{}-{}", stamp, attempt + 1)
+        };
+        match fs::DirBuilder::new().mode(0o700).create(trash.join(&run)) {
+            Ok(()) => return Ok(run),
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(error) => {
+                return Err(error).wrap_err_with(|| {
+                    format!("failed to create trash run {}", trash.join(&run).display())
+                })
+            }
+        }
+    }
+    Err(eyre!(
+        "unable to choose a unique trash run name in {}",
+        trash.display()
+    ))
+}
+
+fn create_private_dir_all(path: &Path) -> Result<()> {
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(path)
+        .wrap_err_with(|| format!("failed to create trash directory {}", path.display()))
+}
+
+/// Copies the current version of a file into the trash, as a reflink where the filesystem can
+/// make one, so that later writes to the live file leave the kept version alone. The copy keeps
+/// the mode and modification time of the file.
+fn copy_into_trash(source: &Path, destination: &Path) -> Result<()> {
+    let copy = || -> io::Result<()> {
+        let mut input = fs::File::open(source)?;
+        let meta = input.metadata()?;
+        let mut output = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(destination)?;
+        if !sync::reflink_file(&output, &input)? {
+            io::copy(&mut input, &mut output)?;
+        }
+        output.set_permissions(meta.permissions())?;
+        filetime::set_file_handle_times(
+            &output,
+            None,
+            Some(filetime::FileTime::from_last_modification_time(&meta)),
+        )?;
+        output.sync_all()
+    };
+    copy().wrap_err_with(|| {
+        format!(
+            "failed to keep {} in the trash as {}",
+            source.display(),
+            destination.display()
+        )
+    })
+}
+
+/// The seconds since the epoch encoded in a run name.
+fn run_time(run: &str) -> Option<i64> {
+    let stamp = run.get(..RUN_STAMP_LEN)?;
+    let suffix = &run[RUN_STAMP_LEN..];
+    if !suffix.is_empty()
+        && !suffix.strip_prefix('-').is_some_and(|counter| {
+            !counter.is_empty() && counter.bytes().all(|b| b.is_ascii_digit())
+        })
+    {
+        return None;
+    }
+    NaiveDateTime::parse_from_str(stamp, RUN_FORMAT)
+        .ok()
+        .map(|time| time.and_utc().timestamp())
+}
+
+/// The runs in `trash`, oldest first, with their times; other names are left alone.
+fn runs(trash: &Path) -> Result<Vec<(String, i64)>> {
+    let dir = match fs::read_dir(trash) {
+        Ok(dir) => dir,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => {
+            return Err(error).wrap_err_with(|| format!("failed to read {}", trash.display()))
+        }
+    };
+    let mut runs = Vec::new();
+    for child in dir {
+        let child = child.wrap_err_with(|| format!("failed to read {}", trash.display()))?;
+        let Some(run) = child.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        if let Some(time) = run_time(&run) {
+            if child.file_type()?.is_dir() {
+                runs.push((run, time));
+            }
+        }
+    }
+    runs.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+    Ok(runs)
+}
+
+fn collect_files(dir: &Path, relative: &Path, files: &mut Vec<(PathBuf, u64)>) -> Result<()> {
+    let mut children = fs::read_dir(dir)
+        .wrap_err_with(|| format!("failed to read {}", dir.display()))?
+        .collect::<io::Result<Vec<_>>>()
+        .wrap_err_with(|| format!("failed to read {}", dir.display()))?;
+    children.sort_by_key(|child| child.file_name());
+    for child in children {
+        let path = relative.join(child.file_name());
+        let meta = fs::symlink_metadata(child.path())
+            .wrap_err_with(|| format!("failed to read metadata for {}", child.path().display()))?;
+        if meta.is_dir() {
+            collect_files(&child.path(), &path, files)?;
+        } else {
+            files.push((path, meta.len()));
+        }
+    }
+    Ok(())
+}
+
+fn purge_runs(trash: &Path, retention: TrashRetention, keep: Option<&str>) -> Result<TrashPurge> {
+    let mut sized = Vec::new();
+    for (run, time) in runs(trash)? {
+        let mut files = Vec::new();
+        collect_files(&trash.join(&run), Path::new(""), &mut files)?;
+        sized.push((run, time, files.iter().map(|(_, size)| size).sum::<u64>()));
+    }
+    let mut total: u64 = sized.iter().map(|(_, _, bytes)| bytes).sum();
+
+    // oldest first, so the size limit drops the oldest runs
+    let now = Utc::now().timestamp();
+    let mut purged = TrashPurge::default();
+    for (run, time, bytes) in sized {
+        let expired = retention
+            .max_age_secs
+            .is_some_and(|max_age| now.saturating_sub(time) > max_age as i64);
+        let over_size = retention
+            .max_bytes
+            .is_some_and(|max_bytes| total > max_bytes);
+        if !(expired || over_size) || Some(run.as_str()) == keep {
+            continue;
+        }
+        let path = trash.join(&run);
+        fs::remove_dir_all(&path)
+            .wrap_err_with(|| format!("failed to purge trash run {}", path.display()))?;
+        total -= bytes;
+        purged.runs += 1;
+        purged.bytes += bytes;
+    }
+    Ok(purged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_entry(path: &str, contents: &[u8]) -> Entry {
+        Entry::test_file_with_size(
+            PathBuf::from(path),
+            contents.len() as u64,
+            adler32::adler32(contents).unwrap(),
+        )
+    }
+
+    #[test]
+    fn replaced_files_are_removals_and_content_changes_of_regular_files() {
+        let old = file_entry("a", b"old");
+        let new = file_entry("a", b"newer");
+        let dir = Entry::test_dir(PathBuf::from("d"));
+        let actions = vec![
+            Action::Local(Change::Removed(old.clone())),
+            Action::Local(Change::Modified(old.clone(), old.clone())),
+            Action::Local(Change::Removed(dir)),
+            Action::Remote(Change::Modified(old.clone(), new.clone())),
+            Action::Remote(Change::Added(new.clone())),
+            Action::Conflict(Change::Removed(old.clone()), Change::Removed(old.clone())),
+        ];
+
+        let (local, remote) = replaced_files(&actions);
+
+        assert_eq!(local.len(), 1);
+        assert_eq!(remote.len(), 1);
+        assert_eq!(remote[0].size(), old.size());
+    }
+
+    #[test]
+    fn kept_files_can_be_listed_and_restored() {
+        let base = tempfile::tempdir().unwrap();
+        fs::create_dir(base.path().join("dir")).unwrap();
+        fs::write(base.path().join("dir/a.txt"), b"kept").unwrap();
+        let entry = file_entry("dir/a.txt", b"kept");
+
+        assert!(keep(
+            base.path(),
+            "id",
+            &[file_entry("dir/a.txt", b"other")],
+            Default::default()
+        )
+        .is_err());
+        let run = keep(base.path(), "id", &[entry], Default::default())
+            .unwrap()
+            .unwrap();
+        fs::remove_file(base.path().join("dir/a.txt")).unwrap();
+
+        let items = list(base.path(), "id").unwrap();
+        assert_eq!(
+            items,
+            vec![TrashItem {
+                run: run.clone(),
+                path: PathBuf::from("dir/a.txt"),
+                size: 4,
+            }]
+        );
+        assert!(restore(base.path(), "id", Path::new("dir/a.txt")).is_err());
+        assert_eq!(
+            restore(base.path(), "id", &items[0].name()).unwrap(),
+            PathBuf::from("dir/a.txt")
+        );
+        assert_eq!(fs::read(base.path().join("dir/a.txt")).unwrap(), b"kept");
+        assert!(!base.path().join(TRASH_DIR).join("id").join(&run).exists());
+        assert!(list(base.path(), "id").unwrap().is_empty());
+        assert!(list(base.path(), "../id").is_err());
+    }
+
+    #[test]
+    fn kept_copy_keeps_the_old_bytes_when_the_live_file_is_modified_in_place() {
+        use std::io::Write;
+        use std::os::unix::fs::MetadataExt;
+
+        let base = tempfile::tempdir().unwrap();
+        fs::write(base.path().join("a.txt"), b"kept").unwrap();
+        filetime::set_file_mtime(
+            base.path().join("a.txt"),
+            filetime::FileTime::from_unix_time(1_000_000, 0),
+        )
+        .unwrap();
+        let run = keep(
+            base.path(),
+            "id",
+            &[file_entry("a.txt", b"kept")],
+            Default::default(),
+        )
+        .unwrap()
+        .unwrap();
+        let kept = base
+            .path()
+            .join(TRASH_DIR)
+            .join("id")
+            .join(&run)
+            .join("a.txt");
+        let live = base.path().join("a.txt");
+        assert_ne!(
+            fs::metadata(&kept).unwrap().ino(),
+            fs::metadata(&live).unwrap().ino()
+        );
+
+        let mut file = fs::OpenOptions::new().write(true).open(&live).unwrap();
+        file.write_all(b"edit").unwrap();
+        drop(file);
+
+        assert_eq!(fs::read(&live).unwrap(), b"edit");
+        assert_eq!(fs::read(&kept).unwrap(), b"kept");
+        assert_eq!(fs::metadata(&kept).unwrap().mtime(), 1_000_000);
+    }
+
+    #[test]
+    fn purge_drops_expired_runs_and_then_the_oldest_over_the_size_limit() {
+        let base = tempfile::tempdir().unwrap();
+        let trash = base.path().join(TRASH_DIR).join("id");
+        let now = Utc::now();
+        let runs: Vec<String> = [40, 20, 10, 0]
+            .iter()
+            .map(|days| {
+                (now - chrono::Duration::days(*days))
+                    .format(RUN_FORMAT)
+                    .to_string()
+            })
+            .collect();
+        for run in &runs {
+            fs::create_dir_all(trash.join(run)).unwrap();
+            fs::write(trash.join(run).join("file"), [0u8; 100]).unwrap();
+        }
+        fs::create_dir_all(trash.join("unrelated")).unwrap();
+
+        let purged = purge(
+            base.path(),
+            "id",
+            TrashRetention {
+                max_age_secs: Some(30 * 24 * 60 * 60),
+                max_bytes: Some(250),
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            purged,
+            TrashPurge {
+                runs: 2,
+                bytes: 200
+            }
+        );
+        assert!(!trash.join(&runs[1]).exists());
+        assert!(trash.join(&runs[2]).exists());
+        assert!(trash.join("unrelated").exists());
+
+        let purged = purge_runs(&trash, TrashRetention::everything(), Some(&runs[3])).unwrap();
+        assert_eq!(purged.runs, 1);
+        assert!(trash.join(&runs[3]).exists());
+        assert_eq!(run_time(&format!("{}-2", runs[3])), run_time(&runs[3]));
+        assert_eq!(run_time("20260101-000000-"), None);
+    }
+}
@@ -206,6 +206,21 @@ fn excluded_change_is_discovered_when_rerun_without_exclusion() {
     assert_eq!(read(&case.remote.join("held/file.txt")), "accumulated");
 }
 
+#[test]
+fn profile_option_excludes_apply_until_negated_on_the_command_line() {
+    let case = SyncCase::new_with_rules("+.\n[options]\nexclude = held\n");
+    fs::create_dir(case.local.join("held")).unwrap();
+    write(&case.local.join("held/file.txt"), "held back");
+    write(&case.local.join("a.txt"), "from local");
+
+    assert_success(case.sync());
+    assert_eq!(read(&case.remote.join("a.txt")), "from local");
+    assert!(!case.remote.join("held").exists());
+
+    assert_success(case.sync_with_args(&["--no-exclude"]));
+    assert_eq!(read(&case.remote.join("held/file.txt")), "held back");
+}
+
 #[test]
 fn exclusion_composes_with_restricted_synchronization() {
     let case = SyncCase::new_with_rules("+.\n");
@@ -283,6 +298,340 @@ fn checkpointed_staging_runs_multiple_bidirectional_waves() {
     assert_success(case.sync_with_args(&["--staging-limit", "4KiB"]));
 }
 
+#[test]
+fn compressed_details_count_wire_and_logical_literal_bytes() {
+    let case = SyncCase::new_with_rules("+.\n");
+    let text = "a line of text that repeats\n".repeat(4096);
+    write(&case.local.join("local.txt"), &text);
+    write(&case.remote.join("remote.txt"), &text);
+    let profile_json = case.local.parent().unwrap().join("compression.json");
+
+    // an in-process server copies file data directly, so compression applies over pipes only
+    assert_success(case.sync_with_args(&[
+        "--server-process",
+        "--compression",
+        "6",
+        "--profile-performance-json",
+        profile_json.to_str().unwrap(),
+    ]));
+
+    assert_eq!(read(&case.remote.join("local.txt")), text);
+    assert_eq!(read(&case.local.join("remote.txt")), text);
+    let profile: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&profile_json).unwrap()).unwrap();
+    let streaming = &profile["counters"]["streaming"];
+    for direction in ["remote_to_local", "local_to_remote"] {
+        let stats = &streaming[direction];
+        assert_eq!(stats["literal_logical_bytes"], text.len(), "{}", direction);
+        assert!(
+            stats["compressed_frames"].as_u64().unwrap() > 0,
+            "{}",
+            direction
+        );
+        assert!(
+            stats["literal_wire_bytes"].as_u64().unwrap() < text.len() as u64 / 10,
+            "{}",
+            direction
+        );
+    }
+}
+
+#[test]
+fn bwlimit_paces_both_directions_and_reports_achieved_throughput() {
+    let case = SyncCase::new_with_rules("+.\n");
+    let bytes = patterned_bytes(256 * 1024);
+    write_bytes(&case.local.join("local.bin"), &bytes);
+    write_bytes(&case.remote.join("remote.bin"), &bytes);
+    let profile_json = case.local.parent().unwrap().join("bwlimit.json");
+
+    let start = std::time::Instant::now();
+    let output = case.sync_with_args(&[
+        "--server-process",
+        "--compression",
+        "0",
+        "--bwlimit",
+        "256KiB",
+        "--profile-performance",
+        "--profile-performance-json",
+        profile_json.to_str().unwrap(),
+    ]);
+    let elapsed = start.elapsed();
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    assert_success(output);
+
+    assert_eq!(fs::read(case.remote.join("local.bin")).unwrap(), bytes);
+    assert_eq!(fs::read(case.local.join("remote.bin")).unwrap(), bytes);
+    // a quarter second of each direction passes at once, and the rest at 256KiB/s
+    assert!(elapsed.as_millis() >= 750, "{:?}", elapsed);
+    assert!(stdout.contains("bandwidth upload: achieved="), "{}", stdout);
+    let profile: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&profile_json).unwrap()).unwrap();
+    let bandwidth = &profile["counters"]["bandwidth"];
+    // which direction waits depends on how the two streams interleave, but together they do
+    let throttled_ms: u64 = ["upload", "download"]
+        .iter()
+        .map(|direction| bandwidth[direction]["throttled_ms"].as_u64().unwrap())
+        .sum();
+    assert!(throttled_ms > 0, "{}", bandwidth);
+    for direction in ["upload", "download"] {
+        let stats = &bandwidth[direction];
+        assert_eq!(stats["cap_bytes_per_sec"], 256 * 1024, "{}", direction);
+        assert_eq!(stats["bytes"], bytes.len(), "{}", direction);
+        assert!(
+            stats["achieved_bytes_per_sec"].as_u64().unwrap() > 0,
+            "{}",
+            direction
+        );
+    }
+    assert_eq!(
+        profile["sync_tuning"]["detail_chunk_bytes"],
+        64 * 1024,
+        "frames shrink to a quarter second at the cap"
+    );
+}
+
+#[test]
+fn in_process_server_copies_file_data_without_literal_frames() {
+    let case = SyncCase::new_with_rules("+.\n");
+    let bytes = patterned_bytes(256 * 1024);
+    write_bytes(&case.local.join("local.bin"), &bytes);
+    write_bytes(&case.remote.join("remote.bin"), &bytes);
+    let profile_json = case.local.parent().unwrap().join("in-process.json");
+
+    assert_success(
+        case.sync_with_args(&["--profile-performance-json", profile_json.to_str().unwrap()]),
+    );
+
+    assert_eq!(fs::read(case.remote.join("local.bin")).unwrap(), bytes);
+    assert_eq!(fs::read(case.local.join("remote.bin")).unwrap(), bytes);
+    let profile: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&profile_json).unwrap()).unwrap();
+    let streaming = &profile["counters"]["streaming"];
+    for direction in ["remote_to_local", "local_to_remote"] {
+        let stats = &streaming[direction];
+        assert_eq!(stats["literal_logical_bytes"], bytes.len(), "{}", direction);
+        assert_eq!(stats["literal_wire_bytes"], 0, "{}", direction);
+    }
+}
+
+/// Runs a sync of `case` over an SSH remote with `transport = system`, with `ssh` on the path
+/// replaced by `script`. SSH remotes need a named profile, so it is kept in a temporary home.
+fn sync_through_system_ssh(case: &SyncCase, script: &str) -> Output {
+    let home = case.local.parent().unwrap();
+    let bin = home.join("bin");
+    let config = home.join(".config/duet");
+    fs::create_dir_all(&bin).unwrap();
+    fs::create_dir_all(&config).unwrap();
+    let ssh = bin.join("ssh");
+    write(&ssh, script);
+    fs::set_permissions(&ssh, fs::Permissions::from_mode(0o755)).unwrap();
+    write(
+        &config.join("over-ssh.prf"),
+        &format!(
+            "{}\nssh backup-host {} {}\n+a.txt\n[staging]\nreserve = 0%\n[ssh]\nport = 2222\ntransport = system\n",
+            case.local.display(),
+            duet_bin().display(),
+            case.remote.display(),
+        ),
+    );
+    let path = format!(
+        "{}:{}",
+        bin.display(),
+        std::env::var("PATH").unwrap_or_default()
+    );
+    Command::new(duet_bin())
+        .arg("over-ssh")
+        .arg("-b")
+        .env("PATH", path)
+        .env("HOME", home)
+        .env("DUET_SERVER_LOG", home.join("server.log"))
+        .env("NO_COLOR", "1")
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn system_ssh_transport_runs_the_server_through_ssh() {
+    let case = SyncCase::new();
+    write(&case.local.join("a.txt"), "from local");
+    // runs the remote command here, after recording the arguments
+    let script = "#!/bin/sh\n\
+                  echo \"$@\" > \"$0.args\"\n\
+                  while [ \"$1\" != -- ]; do shift; done\n\
+                  exec sh -c \"$3\"\n";
+
+    assert_success(sync_through_system_ssh(&case, script));
+
+    assert_eq!(read(&case.remote.join("a.txt")), "from local");
+    let args = read(&case.local.parent().unwrap().join("bin/ssh.args"));
+    assert!(args.starts_with("-T -p 2222 -- backup-host "), "{}", args);
+    assert!(args.trim_end().ends_with(" --server"), "{}", args);
+}
+
+#[test]
+fn system_ssh_transport_failures_carry_ssh_hints() {
+    let case = SyncCase::new();
+    write(&case.local.join("a.txt"), "from local");
+    let script = "#!/bin/sh\n\
+                  echo 'backup@backup-host: Permission denied (publickey).' >&2\n\
+                  exit 255\n";
+
+    let output = sync_through_system_ssh(&case, script);
+
+    assert!(!output.status.success());
+    let output = combined_output(&output);
+    assert!(
+        output.contains("public-key authentication failed"),
+        "{}",
+        output
+    );
+    assert!(
+        output.contains("profile [ssh] settings: port=2222, transport=system"),
+        "{}",
+        output
+    );
+    assert!(!case.remote.join("a.txt").exists());
+}
+
+/// A `duet --server --listen` on a free localhost port, stopped when dropped.
+struct ListeningServer {
+    child: std::process::Child,
+    port: u16,
+}
+
+impl ListeningServer {
+    fn start(home: &Path, key_file: &Path) -> Self {
+        use std::io::BufRead;
+        let mut child = Command::new(duet_bin())
+            .args(["--server", "--listen", "127.0.0.1:0", "--key-file"])
+            .arg(key_file)
+            .env("HOME", home)
+            .env("DUET_SERVER_LOG", home.join("server.log"))
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut line = String::new();
+        std::io::BufReader::new(child.stderr.take().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        let port = line
+            .trim()
+            .rsplit(':')
+            .next()
+            .and_then(|port| port.parse().ok())
+            .unwrap_or_else(|| panic!("unexpected server output {:?}", line));
+        Self { child, port }
+    }
+}
+
+impl Drop for ListeningServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn write_key(path: &Path, key: &str) {
+    write(path, key);
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600)).unwrap();
+}
+
+#[test]
+fn tcp_remote_syncs_with_a_listening_server_holding_the_same_key() {
+    let case = SyncCase::new();
+    let home = case.local.parent().unwrap();
+    let config = home.join(".config/duet");
+    fs::create_dir_all(&config).unwrap();
+    write_key(
+        &home.join("server.key"),
+        "a key shared by the lab machines\n",
+    );
+    write_key(&home.join("client.key"), "a key shared by the lab machines");
+    write_key(
+        &home.join("other.key"),
+        "a key the lab machines don't share",
+    );
+    let server = ListeningServer::start(home, &home.join("server.key"));
+    let sync = |key: &str| {
+        write(
+            &config.join("lab.prf"),
+            &format!(
+                "{}\ntcp://127.0.0.1:{}{}\n+a.txt\n[staging]\nreserve = 0%\n[tcp]\nkey-file = {}\n",
+                case.local.display(),
+                server.port,
+                case.remote.display(),
+                home.join(key).display(),
+            ),
+        );
+        Command::new(duet_bin())
+            .args(["lab", "-b"])
+            .env("HOME", home)
+            .env("NO_COLOR", "1")
+            .output()
+            .unwrap()
+    };
+    write(&case.local.join("a.txt"), "from local");
+
+    let rejected = sync("other.key");
+    assert!(!rejected.status.success());
+    let output = combined_output(&rejected);
+    assert!(
+        output.contains("server does not hold the key"),
+        "{}",
+        output
+    );
+    assert!(!case.remote.join("a.txt").exists());
+
+    assert_success(sync("client.key"));
+    assert_eq!(read(&case.remote.join("a.txt")), "from local");
+
+    // the server keeps listening for the next sync
+    write(&case.remote.join("a.txt"), "from remote");
+    assert_success(sync("client.key"));
+    assert_eq!(read(&case.local.join("a.txt")), "from remote");
+}
+
+#[test]
+fn listening_server_drops_connections_beyond_the_pending_handshakes() {
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    let case = SyncCase::new();
+    let home = case.local.parent().unwrap();
+    write_key(&home.join("server.key"), "a key shared by the lab machines");
+    let server = ListeningServer::start(home, &home.join("server.key"));
+    let connect = || {
+        let stream = TcpStream::connect(("127.0.0.1", server.port)).unwrap();
+        stream
+            .set_read_timeout(Some(std::time::Duration::from_secs(10)))
+            .unwrap();
+        stream
+    };
+    // whether the server answers a challenge on the connection, rather than closing it
+    let challenge = |mut stream: TcpStream| {
+        stream.write_all(b"duet-psk-v1\n").unwrap();
+        stream.write_all(&[0; 32]).unwrap();
+        let mut magic = [0; 12];
+        stream.read_exact(&mut magic).is_ok()
+    };
+
+    let silent: Vec<TcpStream> = (0..64).map(|_| connect()).collect();
+    let mut dropped = connect();
+    let mut byte = [0];
+    match dropped.read(&mut byte) {
+        Ok(0) => {}
+        Err(error) if error.kind() == std::io::ErrorKind::ConnectionReset => {}
+        other => panic!("connection over the cap was not closed: {:?}", other),
+    }
+
+    // the handshakes of closed connections end, and new clients are served again
+    drop(silent);
+    assert!((0..50).any(|_| {
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        challenge(connect())
+    }));
+}
+
 #[test]
 fn legacy_migration_reports_metadata_hidden_adler_collision() {
     let case = SyncCase::new();
@@ -413,6 +762,145 @@ fn dry_run_reports_ignored_directory_removal_blockers() {
     assert!(case.remote.join("dir/__pycache__/cache.pyc").exists());
 }
 
+#[test]
+fn path_aware_ignore_patterns_apply_on_both_sides() {
+    let case = SyncCase::new_with_rules("+.\n\n[ignore]\nbuild/**/*.o\n*.tmp\n!keep.tmp\n/out/\n");
+    for side in [&case.local, &case.remote] {
+        fs::create_dir_all(side.join("build/x")).unwrap();
+        fs::create_dir_all(side.join("src/out")).unwrap();
+        fs::create_dir_all(side.join("out")).unwrap();
+    }
+    write(&case.local.join("build/x/a.o"), "object");
+    write(&case.local.join("build/a.c"), "source");
+    write(&case.local.join("scratch.tmp"), "scratch");
+    write(&case.local.join("keep.tmp"), "kept");
+    write(&case.remote.join("out/result"), "root output");
+    write(&case.remote.join("src/out/result"), "nested output");
+
+    assert_success(case.sync());
+
+    assert!(!case.remote.join("build/x/a.o").exists());
+    assert_eq!(read(&case.remote.join("build/a.c")), "source");
+    assert!(!case.remote.join("scratch.tmp").exists());
+    assert_eq!(read(&case.remote.join("keep.tmp")), "kept");
+    assert!(!case.local.join("out/result").exists());
+    assert_eq!(read(&case.local.join("src/out/result")), "nested output");
+}
+
+#[test]
+fn duetignore_files_scope_to_their_directory_and_edits_keep_other_side_copies() {
+    let case = SyncCase::new_with_rules("+.\n");
+    fs::create_dir_all(case.local.join("proj/target")).unwrap();
+    write(&case.local.join("proj/.duetignore"), "target/\n");
+    write(&case.local.join("proj/target/out.bin"), "local build");
+    write(&case.local.join("proj/src.rs"), "source");
+    write(&case.local.join("proj/notes.log"), "notes");
+    write(&case.local.join("top.log"), "top");
+
+    assert_success(case.sync());
+    assert_eq!(read(&case.remote.join("proj/src.rs")), "source");
+    assert!(!case.remote.join("proj/target").exists());
+
+    fs::create_dir_all(case.remote.join("proj/target")).unwrap();
+    write(&case.remote.join("proj/target/out.bin"), "remote build");
+    write(&case.local.join("proj/.duetignore"), "target/\n*.log\n");
+    assert_success(case.sync());
+
+    assert_eq!(read(&case.local.join("proj/target/out.bin")), "local build");
+    assert_eq!(
+        read(&case.remote.join("proj/.duetignore")),
+        "target/\n*.log\n"
+    );
+    assert_eq!(read(&case.remote.join("proj/notes.log")), "notes");
+    assert_eq!(read(&case.local.join("proj/notes.log")), "notes");
+
+    write(&case.remote.join("proj/notes.log"), "remote notes");
+    write(&case.remote.join("top.log"), "remote top");
+    assert_success(case.sync());
+    assert_eq!(read(&case.local.join("proj/notes.log")), "notes");
+    assert_eq!(read(&case.local.join("top.log")), "remote top");
+}
+
+#[test]
+fn filtered_entries_are_reported_and_stop_being_tracked() {
+    let case =
+        SyncCase::new_with_rules("+.\n\n[filter]\nmax-size = 1KiB\ntypes = files, directories\n");
+    write(&case.local.join("small.txt"), "small");
+    write_bytes(&case.local.join("big.bin"), &patterned_bytes(4096));
+    std::os::unix::fs::symlink("small.txt", case.local.join("link")).unwrap();
+
+    let output = case.sync_with_args(&["--dry-run"]);
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    assert_success(output);
+    assert!(
+        stdout.contains("local entries filtered by [filter]:"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("big.bin (max-size)"), "{}", stdout);
+    assert!(stdout.contains("link (types)"), "{}", stdout);
+
+    assert_success(case.sync());
+    assert_eq!(read(&case.remote.join("small.txt")), "small");
+    assert!(!case.remote.join("big.bin").exists());
+    assert!(fs::symlink_metadata(case.remote.join("link")).is_err());
+
+    write_bytes(&case.local.join("small.txt"), &patterned_bytes(2048));
+    assert_success(case.sync());
+    assert_eq!(read(&case.remote.join("small.txt")), "small");
+
+    write(&case.remote.join("small.txt"), "remote edit");
+    assert_success(case.sync());
+    assert_eq!(
+        fs::read(case.local.join("small.txt")).unwrap(),
+        patterned_bytes(2048)
+    );
+    assert_eq!(read(&case.remote.join("small.txt")), "remote edit");
+}
+
+#[test]
+fn further_remote_lines_sync_each_peer_in_turn() {
+    let temp = tempfile::tempdir().unwrap();
+    let peer = temp.path().join("peer");
+    fs::create_dir(&peer).unwrap();
+    let peer_line = format!("{} {}", duet_bin().display(), peer.display());
+    let case = SyncCase::new_with_rules(&format!("{}\n\n+.\n", peer_line));
+    let remote_line = format!("{} {}", duet_bin().display(), case.remote.display());
+
+    write(&case.local.join("a.txt"), "from local");
+    assert_success(case.sync());
+    assert_eq!(read(&case.remote.join("a.txt")), "from local");
+    assert_eq!(read(&peer.join("a.txt")), "from local");
+
+    write(&case.remote.join("b.txt"), "from remote");
+    let output = case.sync();
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    assert_success(output);
+    assert!(
+        stdout.contains(&format!("Peer 2/2: {}", peer_line)),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains(&format!(
+            "b.txt from {} to local, {}",
+            remote_line, peer_line
+        )),
+        "{}",
+        stdout
+    );
+    assert_eq!(read(&case.local.join("b.txt")), "from remote");
+    assert_eq!(read(&peer.join("b.txt")), "from remote");
+
+    // what the last peer sends reaches the earlier ones on the next sync
+    write(&peer.join("c.txt"), "from peer");
+    assert_success(case.sync());
+    assert_eq!(read(&case.local.join("c.txt")), "from peer");
+    assert!(!case.remote.join("c.txt").exists());
+    assert_success(case.sync());
+    assert_eq!(read(&case.remote.join("c.txt")), "from peer");
+}
+
 #[test]
 fn dry_run_validates_remote_apply_preflight() {
     let case = SyncCase::new();
@@ -515,83 +1003,771 @@ fn nested_tracked_directory_removal_removes_children_before_parents() {
 }
 
 #[test]
-fn batch_conflict_aborts_without_changing_files() {
-    let case = SyncCase::new();
-    let local_file = case.local.join("a.txt");
-    let remote_file = case.remote.join("a.txt");
-    write(&local_file, "initial");
+fn local_moved_file_is_renamed_on_remote() {
+    let case = SyncCase::new_with_rules("+.\n");
+    write(&case.local.join("a.txt"), "moved contents");
     assert_success(case.sync());
+    let remote_ino = fs::metadata(case.remote.join("a.txt")).unwrap().ino();
 
-    write(&local_file, "local changed");
-    write(&remote_file, "remote changed");
+    fs::create_dir(case.local.join("dir")).unwrap();
+    fs::rename(case.local.join("a.txt"), case.local.join("dir/b.txt")).unwrap();
+    assert_success(case.sync());
 
-    let output = case.sync();
+    assert!(!case.remote.join("a.txt").exists());
+    assert_eq!(read(&case.remote.join("dir/b.txt")), "moved contents");
+    assert_eq!(
+        fs::metadata(case.remote.join("dir/b.txt")).unwrap().ino(),
+        remote_ino
+    );
 
-    assert_eq!(output.status.code(), Some(1));
-    assert_eq!(read(&local_file), "local changed");
-    assert_eq!(read(&remote_file), "remote changed");
+    let rerun = case.sync_with_args(&["--dry-run"]);
+    assert!(rerun.status.success(), "{}", combined_output(&rerun));
+    assert!(!combined_output(&rerun).contains("b.txt"));
 }
 
 #[test]
-fn forced_descendant_conflict_keeps_destructive_ancestor_pending() {
+fn remote_moved_directory_is_renamed_locally() {
     let case = SyncCase::new_with_rules("+.\n");
-    fs::create_dir(case.local.join("tree")).unwrap();
-    write(&case.local.join("tree/conflict.txt"), "initial");
-    write(&case.local.join("independent.txt"), "initial");
+    fs::create_dir_all(case.local.join("tree/child")).unwrap();
+    write(&case.local.join("tree/root.txt"), "root");
+    write(&case.local.join("tree/child/leaf.txt"), "leaf");
     assert_success(case.sync());
+    let local_ino = fs::metadata(case.local.join("tree/child/leaf.txt"))
+        .unwrap()
+        .ino();
 
-    fs::remove_dir_all(case.local.join("tree")).unwrap();
-    write(&case.remote.join("tree/conflict.txt"), "remote changed");
-    write(&case.local.join("independent.txt"), "local changed");
-
-    let output = case.sync_with_args(&["--force"]);
-    let text = combined_output(&output);
+    fs::rename(case.remote.join("tree"), case.remote.join("renamed")).unwrap();
+    assert_success(case.sync());
 
-    assert_success(output);
-    assert!(
-        text.contains("Skipping tree") && text.contains("structurally dependent"),
-        "{}",
-        text
-    );
     assert!(!case.local.join("tree").exists());
+    assert_eq!(read(&case.local.join("renamed/root.txt")), "root");
     assert_eq!(
-        read(&case.remote.join("tree/conflict.txt")),
-        "remote changed"
+        fs::metadata(case.local.join("renamed/child/leaf.txt"))
+            .unwrap()
+            .ino(),
+        local_ino
     );
-    assert_eq!(read(&case.remote.join("independent.txt")), "local changed");
 }
 
 #[test]
-fn debug_info_reports_negotiated_capabilities() {
-    let case = SyncCase::new();
-    write(&case.local.join("a.txt"), "from local");
-
-    let output = case.sync_with_args(&["--debug-info"]);
-
-    assert_success(output);
+fn dry_run_shows_moves_without_applying_them() {
+    let case = SyncCase::new_with_rules("+.\n");
+    write(&case.local.join("a.txt"), "moved contents");
+    assert_success(case.sync());
 
-    let output = case.sync_with_args(&["--debug-info"]);
-    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-    assert_success(output);
-    assert!(stdout.contains("Debug information:"), "{}", stdout);
-    assert!(stdout.contains("client protocol:"), "{}", stdout);
-    assert!(stdout.contains("server protocol:"), "{}", stdout);
-    assert!(
-        stdout.contains(
-            "agreed capabilities: profile-file-state-dir, streamed-details-v1, streamed-detail-batches-v1"
-        ),
-        "{}",
-        stdout
-    );
-    assert!(stdout.contains("sync-tuning-v1"), "{}", stdout);
-    assert!(stdout.contains("stream-performance-v1"), "{}", stdout);
-    assert!(stdout.contains("file-byte-chunks-v1"), "{}", stdout);
-    assert!(stdout.contains("sync tuning:"), "{}", stdout);
-    assert!(stdout.contains("detail-batch-frames=256"), "{}", stdout);
+    fs::rename(case.local.join("a.txt"), case.local.join("b.txt")).unwrap();
+    let dry_run = case.sync_with_args(&["--dry-run"]);
+    assert!(dry_run.status.success(), "{}", combined_output(&dry_run));
+    assert!(combined_output(&dry_run).contains("(from a.txt)"));
+    assert!(case.remote.join("a.txt").exists());
+    assert!(!case.remote.join("b.txt").exists());
 }
 
 #[test]
-fn named_profile_debug_info_reports_negotiated_capabilities() {
+fn failed_preflight_leaves_planned_moves_unapplied() {
+    let case = SyncCase::new_with_rules("+.\n");
+    write(&case.local.join("a.txt"), "moved contents");
+    write(&case.local.join("c.txt"), "removed on remote");
+    assert_success(case.sync());
+
+    fs::rename(case.local.join("a.txt"), case.local.join("b.txt")).unwrap();
+    fs::remove_file(case.remote.join("c.txt")).unwrap();
+    let mut permissions = fs::metadata(&case.local).unwrap().permissions();
+    permissions.set_mode(0o555);
+    fs::set_permissions(&case.local, permissions).unwrap();
+
+    let output = case.sync();
+
+    let mut permissions = fs::metadata(&case.local).unwrap().permissions();
+    permissions.set_mode(0o755);
+    fs::set_permissions(&case.local, permissions).unwrap();
+
+    let output_text = combined_output(&output);
+    assert!(!output.status.success(), "{}", output_text);
+    assert!(output_text.contains("not writable"), "{}", output_text);
+    assert!(case.remote.join("a.txt").exists());
+    assert!(!case.remote.join("b.txt").exists());
+    assert!(case.local.join("b.txt").exists());
+    assert!(case.local.join("c.txt").exists());
+
+    assert_success(case.sync());
+    assert_eq!(read(&case.remote.join("b.txt")), "moved contents");
+    assert!(!case.remote.join("a.txt").exists());
+    assert!(!case.local.join("c.txt").exists());
+}
+
+#[test]
+fn hard_links_are_recreated_on_remote() {
+    let case = SyncCase::new_with_rules("+.\n");
+    write(&case.local.join("a.txt"), "shared");
+    fs::create_dir(case.local.join("dir")).unwrap();
+    fs::hard_link(case.local.join("a.txt"), case.local.join("dir/b.txt")).unwrap();
+    assert_success(case.sync());
+
+    let ino = |path: &Path| fs::metadata(path).unwrap().ino();
+    assert_eq!(read(&case.remote.join("dir/b.txt")), "shared");
+    assert_eq!(
+        ino(&case.remote.join("a.txt")),
+        ino(&case.remote.join("dir/b.txt"))
+    );
+
+    fs::hard_link(case.local.join("a.txt"), case.local.join("c.txt")).unwrap();
+    assert_success(case.sync());
+    assert_eq!(
+        ino(&case.remote.join("a.txt")),
+        ino(&case.remote.join("c.txt"))
+    );
+
+    write(&case.local.join("c.txt"), "edited contents");
+    assert_success(case.sync());
+    assert_eq!(read(&case.remote.join("dir/b.txt")), "edited contents");
+    assert_eq!(
+        ino(&case.remote.join("a.txt")),
+        ino(&case.remote.join("dir/b.txt"))
+    );
+    assert_eq!(
+        ino(&case.remote.join("a.txt")),
+        ino(&case.remote.join("c.txt"))
+    );
+
+    let rerun = case.sync_with_args(&["--dry-run"]);
+    assert!(rerun.status.success(), "{}", combined_output(&rerun));
+    assert!(
+        !combined_output(&rerun).contains(".txt"),
+        "{}",
+        combined_output(&rerun)
+    );
+}
+
+fn set_xattr(path: &Path, name: &str, value: &str) {
+    let path = std::ffi::CString::new(path.as_os_str().as_encoded_bytes()).unwrap();
+    let name = std::ffi::CString::new(name).unwrap();
+    let result = unsafe {
+        libc::setxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+        )
+    };
+    assert_eq!(result, 0, "{}", std::io::Error::last_os_error());
+}
+
+fn remove_xattr(path: &Path, name: &str) {
+    let path = std::ffi::CString::new(path.as_os_str().as_encoded_bytes()).unwrap();
+    let name = std::ffi::CString::new(name).unwrap();
+    let result = unsafe { libc::removexattr(path.as_ptr(), name.as_ptr()) };
+    assert_eq!(result, 0, "{}", std::io::Error::last_os_error());
+}
+
+fn get_xattr(path: &Path, name: &str) -> Option<String> {
+    let path = std::ffi::CString::new(path.as_os_str().as_encoded_bytes()).unwrap();
+    let name = std::ffi::CString::new(name).unwrap();
+    let mut buffer = vec![0_u8; 256];
+    let read = unsafe {
+        libc::getxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            buffer.as_mut_ptr().cast(),
+            buffer.len(),
+        )
+    };
+    if read < 0 {
+        return None;
+    }
+    buffer.truncate(read as usize);
+    Some(String::from_utf8(buffer).unwrap())
+}
+
+#[test]
+fn user_xattrs_round_trip_and_conflict() {
+    let case = SyncCase::new_with_rules("+.\n[xattrs]\nuser\n");
+    write(&case.local.join("a.txt"), "tagged");
+    fs::create_dir(case.local.join("dir")).unwrap();
+    set_xattr(&case.local.join("a.txt"), "user.tag", "one");
+    set_xattr(&case.local.join("dir"), "user.tag", "directory");
+    assert_success(case.sync());
+    assert_eq!(
+        get_xattr(&case.remote.join("a.txt"), "user.tag").as_deref(),
+        Some("one")
+    );
+    assert_eq!(
+        get_xattr(&case.remote.join("dir"), "user.tag").as_deref(),
+        Some("directory")
+    );
+
+    set_xattr(&case.remote.join("a.txt"), "user.tag", "two");
+    set_xattr(&case.remote.join("a.txt"), "user.extra", "more");
+    assert_success(case.sync());
+    assert_eq!(
+        get_xattr(&case.local.join("a.txt"), "user.tag").as_deref(),
+        Some("two")
+    );
+    assert_eq!(
+        get_xattr(&case.local.join("a.txt"), "user.extra").as_deref(),
+        Some("more")
+    );
+
+    remove_xattr(&case.local.join("a.txt"), "user.extra");
+    assert_success(case.sync());
+    assert_eq!(get_xattr(&case.remote.join("a.txt"), "user.extra"), None);
+    assert_eq!(read(&case.remote.join("a.txt")), "tagged");
+
+    set_xattr(&case.local.join("a.txt"), "user.tag", "local");
+    set_xattr(&case.remote.join("a.txt"), "user.tag", "remote");
+    let output = case.sync();
+    assert_eq!(
+        output.status.code(),
+        Some(1),
+        "{}",
+        combined_output(&output)
+    );
+    assert_eq!(
+        get_xattr(&case.local.join("a.txt"), "user.tag").as_deref(),
+        Some("local")
+    );
+    assert_eq!(
+        get_xattr(&case.remote.join("a.txt"), "user.tag").as_deref(),
+        Some("remote")
+    );
+}
+
+#[test]
+fn numeric_ownership_round_trips_when_running_as_root() {
+    use std::os::unix::fs::{chown, MetadataExt};
+
+    if unsafe { libc::geteuid() } != 0 {
+        return;
+    }
+    let case = SyncCase::new_with_rules("+.\n[ownership]\nmode = numeric\n");
+    write(&case.local.join("a.txt"), "owned");
+    chown(case.local.join("a.txt"), Some(4242), Some(4343)).unwrap();
+    assert_success(case.sync());
+    let meta = fs::metadata(case.remote.join("a.txt")).unwrap();
+    assert_eq!((meta.uid(), meta.gid()), (4242, 4343));
+
+    chown(case.remote.join("a.txt"), None, Some(4444)).unwrap();
+    assert_success(case.sync());
+    let meta = fs::metadata(case.local.join("a.txt")).unwrap();
+    assert_eq!((meta.uid(), meta.gid()), (4242, 4444));
+    assert_eq!(read(&case.local.join("a.txt")), "owned");
+}
+
+#[test]
+fn fifos_are_recreated_and_sockets_are_skipped_when_enabled() {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    let case = SyncCase::new_with_rules("+.\n[special]\nfifo\nsocket\n");
+    let fifo = std::ffi::CString::new(case.local.join("pipe").as_os_str().as_bytes()).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o640) }, 0);
+    let _listener = std::os::unix::net::UnixListener::bind(case.local.join("socket")).unwrap();
+    write(&case.local.join("a.txt"), "data");
+
+    let output = case.sync();
+    let text = combined_output(&output);
+    assert_success(output);
+    assert!(text.contains("sockets skipped"), "{}", text);
+    let meta = fs::symlink_metadata(case.remote.join("pipe")).unwrap();
+    assert!(meta.file_type().is_fifo());
+    assert_eq!(meta.permissions().mode() & 0o777, 0o640);
+    assert!(!case.remote.join("socket").exists());
+    assert_eq!(read(&case.remote.join("a.txt")), "data");
+
+    fs::remove_file(case.remote.join("pipe")).unwrap();
+    assert_success(case.sync());
+    assert!(fs::symlink_metadata(case.local.join("pipe")).is_err());
+}
+
+#[test]
+fn nanosecond_mtimes_are_compared_and_applied() {
+    use std::os::unix::fs::MetadataExt;
+
+    let case = SyncCase::new();
+    let local_file = case.local.join("a.txt");
+    let remote_file = case.remote.join("a.txt");
+    write(&local_file, "data");
+    let set = |nsec| {
+        filetime::set_file_mtime(
+            &local_file,
+            filetime::FileTime::from_unix_time(1_000_000, nsec),
+        )
+        .unwrap()
+    };
+    set(100);
+    assert_success(case.sync());
+    let meta = fs::metadata(&remote_file).unwrap();
+    assert_eq!((meta.mtime(), meta.mtime_nsec()), (1_000_000, 100));
+
+    set(200);
+    assert_success(case.sync());
+    let meta = fs::metadata(&remote_file).unwrap();
+    assert_eq!((meta.mtime(), meta.mtime_nsec()), (1_000_000, 200));
+}
+
+fn sidecars_in(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.contains(".duet-conflict-"))
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn forced_batch_keeps_the_older_conflicting_version_as_a_tracked_sidecar() {
+    let case = SyncCase::new_with_rules("+.\n[conflicts]\nsidecar = tracked\nforce = newer\n");
+    let local_file = case.local.join("a.txt");
+    let remote_file = case.remote.join("a.txt");
+    write(&local_file, "initial");
+    assert_success(case.sync());
+
+    write(&local_file, "local changed");
+    write(&remote_file, "remote changed");
+    filetime::set_file_mtime(&local_file, filetime::FileTime::from_unix_time(1_000, 0)).unwrap();
+
+    let output = case.sync_with_args(&["--force"]);
+    let text = combined_output(&output);
+    assert_success(output);
+    assert!(text.contains("Kept the local version"), "{}", text);
+    assert_eq!(read(&local_file), "remote changed");
+    assert_eq!(read(&remote_file), "remote changed");
+    let local_sidecars = sidecars_in(&case.local);
+    assert_eq!(local_sidecars, sidecars_in(&case.remote));
+    assert_eq!(local_sidecars.len(), 1);
+    assert!(
+        local_sidecars[0].starts_with("a.duet-conflict-") && local_sidecars[0].ends_with(".txt")
+    );
+    assert_eq!(read(&case.remote.join(&local_sidecars[0])), "local changed");
+
+    let output = case.sync();
+    let text = combined_output(&output);
+    assert_success(output);
+    assert!(text.contains("No changes detected"), "{}", text);
+}
+
+#[test]
+fn ignored_sidecar_stays_on_the_side_that_lost() {
+    let case = SyncCase::new_with_rules("+.\n[conflicts]\nsidecar = ignored\nforce = newer\n");
+    let local_file = case.local.join("a.txt");
+    let remote_file = case.remote.join("a.txt");
+    write(&local_file, "initial");
+    assert_success(case.sync());
+
+    write(&local_file, "local changed");
+    write(&remote_file, "remote changed");
+    filetime::set_file_mtime(&remote_file, filetime::FileTime::from_unix_time(1_000, 0)).unwrap();
+
+    assert_success(case.sync_with_args(&["--force"]));
+    assert_eq!(read(&local_file), "local changed");
+    assert_eq!(read(&remote_file), "local changed");
+    let remote_sidecars = sidecars_in(&case.remote);
+    assert_eq!(remote_sidecars.len(), 1);
+    assert_eq!(
+        read(&case.remote.join(&remote_sidecars[0])),
+        "remote changed"
+    );
+    assert!(sidecars_in(&case.local).is_empty());
+
+    let output = case.sync();
+    let text = combined_output(&output);
+    assert_success(output);
+    assert!(text.contains("No changes detected"), "{}", text);
+}
+
+#[test]
+fn forced_batch_skips_conflicts_unless_the_profile_opts_into_newer() {
+    let case = SyncCase::new_with_rules("+.\n[conflicts]\nsidecar = tracked\n");
+    let local_file = case.local.join("a.txt");
+    let remote_file = case.remote.join("a.txt");
+    write(&local_file, "initial");
+    assert_success(case.sync());
+
+    write(&local_file, "local changed");
+    write(&remote_file, "remote changed");
+    assert_success(case.sync_with_args(&["--force"]));
+    assert_eq!(read(&local_file), "local changed");
+    assert_eq!(read(&remote_file), "remote changed");
+    assert!(sidecars_in(&case.local).is_empty());
+    assert!(sidecars_in(&case.remote).is_empty());
+}
+
+#[test]
+fn failed_preflight_leaves_conflict_losers_in_place() {
+    let case = SyncCase::new_with_rules("+.\n[conflicts]\nsidecar = tracked\nforce = newer\n");
+    let local_file = case.local.join("a.txt");
+    let remote_file = case.remote.join("a.txt");
+    write(&local_file, "initial");
+    write(&case.local.join("c.txt"), "removed on remote");
+    assert_success(case.sync());
+
+    write(&local_file, "local changed");
+    write(&remote_file, "remote changed");
+    filetime::set_file_mtime(&local_file, filetime::FileTime::from_unix_time(1_000, 0)).unwrap();
+    fs::remove_file(case.remote.join("c.txt")).unwrap();
+    let mut permissions = fs::metadata(&case.local).unwrap().permissions();
+    permissions.set_mode(0o555);
+    fs::set_permissions(&case.local, permissions).unwrap();
+
+    let output = case.sync_with_args(&["--force"]);
+
+    let mut permissions = fs::metadata(&case.local).unwrap().permissions();
+    permissions.set_mode(0o755);
+    fs::set_permissions(&case.local, permissions).unwrap();
+
+    let output_text = combined_output(&output);
+    assert!(!output.status.success(), "{}", output_text);
+    assert!(output_text.contains("not writable"), "{}", output_text);
+    assert_eq!(read(&local_file), "local changed");
+    assert_eq!(read(&remote_file), "remote changed");
+    assert!(sidecars_in(&case.local).is_empty());
+    assert!(sidecars_in(&case.remote).is_empty());
+}
+
+#[test]
+fn resolve_rules_settle_conflicts_before_batch_aborts() {
+    let case =
+        SyncCase::new_with_rules("+.\n[resolve]\nkeep/* = keep-both\n*.log = larger\n* = local\n");
+    let conflict = |path: &str, local: &str, remote: &str| {
+        write(&case.local.join(path), local);
+        write(&case.remote.join(path), remote);
+    };
+    fs::create_dir_all(case.local.join("keep")).unwrap();
+    for path in ["a.txt", "b.log", "keep/c.md"] {
+        write(&case.local.join(path), "initial");
+    }
+    assert_success(case.sync());
+
+    conflict("a.txt", "local a", "remote a");
+    conflict("b.log", "local", "remote, longer");
+    conflict("keep/c.md", "local c", "remote c");
+    filetime::set_file_mtime(
+        case.remote.join("keep/c.md"),
+        filetime::FileTime::from_unix_time(1_000, 0),
+    )
+    .unwrap();
+
+    let output = case.sync_with_args(&["--dry-run"]);
+    let text = combined_output(&output);
+    assert_success(output);
+    assert!(
+        text.contains("Resolved a.txt by [resolve] rule * = local"),
+        "{}",
+        text
+    );
+    assert!(
+        text.contains("Resolved b.log by [resolve] rule *.log = larger"),
+        "{}",
+        text
+    );
+    assert!(
+        text.contains("Resolved keep/c.md by [resolve] rule keep/* = keep-both"),
+        "{}",
+        text
+    );
+    assert_eq!(read(&case.remote.join("a.txt")), "remote a");
+
+    assert_success(case.sync());
+    for root in [&case.local, &case.remote] {
+        assert_eq!(read(&root.join("a.txt")), "local a");
+        assert_eq!(read(&root.join("b.log")), "remote, longer");
+        assert_eq!(read(&root.join("keep/c.md")), "local c");
+    }
+    let sidecars = sidecars_in(&case.local.join("keep"));
+    assert_eq!(sidecars, sidecars_in(&case.remote.join("keep")));
+    assert_eq!(sidecars.len(), 1);
+    assert_eq!(
+        read(&case.local.join("keep").join(&sidecars[0])),
+        "remote c"
+    );
+
+    let output = case.sync();
+    let text = combined_output(&output);
+    assert_success(output);
+    assert!(text.contains("No changes detected"), "{}", text);
+}
+
+#[test]
+fn text_conflicts_merge_against_the_stored_base() {
+    let case = SyncCase::new_with_rules("+.\n[merge]\n*.md\n");
+    let local_file = case.local.join("notes.md");
+    let remote_file = case.remote.join("notes.md");
+    write(&local_file, "one\ntwo\nthree\n");
+    assert_success(case.sync());
+
+    write(&local_file, "one local\ntwo\nthree\n");
+    write(&remote_file, "one\ntwo\nthree remote\n");
+    let output = case.sync();
+    let text = combined_output(&output);
+    assert_success(output);
+    assert!(text.contains("Merged notes.md cleanly"), "{}", text);
+    assert_eq!(read(&local_file), "one local\ntwo\nthree remote\n");
+    assert_eq!(read(&remote_file), "one local\ntwo\nthree remote\n");
+
+    let output = case.sync();
+    let text = combined_output(&output);
+    assert_success(output);
+    assert!(text.contains("No changes detected"), "{}", text);
+
+    write(&local_file, "one local\ntwo from local\nthree remote\n");
+    write(
+        &remote_file,
+        "one local\ntwo from remote side\nthree remote\n",
+    );
+    let output = case.sync();
+    let text = combined_output(&output);
+    assert!(text.contains("aborting"), "{}", text);
+    let marked = text
+        .lines()
+        .find_map(|line| line.split_once("the merge with markers is in "))
+        .map(|(_, path)| read(Path::new(path.trim())))
+        .unwrap_or_else(|| panic!("{}", text));
+    assert!(marked.contains("<<<<<<<"), "{}", marked);
+    assert!(marked.contains("two from local"), "{}", marked);
+    assert!(marked.contains("two from remote side"), "{}", marked);
+    assert_eq!(
+        read(&local_file),
+        "one local\ntwo from local\nthree remote\n"
+    );
+}
+
+#[test]
+fn failed_preflight_leaves_clean_merges_unwritten() {
+    let case = SyncCase::new_with_rules("+.\n[merge]\n*.md\n");
+    let local_file = case.local.join("notes.md");
+    let remote_file = case.remote.join("notes.md");
+    write(&local_file, "one\ntwo\nthree\n");
+    write(&case.local.join("c.txt"), "removed on remote");
+    assert_success(case.sync());
+
+    write(&local_file, "one local\ntwo\nthree\n");
+    write(&remote_file, "one\ntwo\nthree remote\n");
+    fs::remove_file(case.remote.join("c.txt")).unwrap();
+    let mut permissions = fs::metadata(&case.local).unwrap().permissions();
+    permissions.set_mode(0o555);
+    fs::set_permissions(&case.local, permissions).unwrap();
+
+    let output = case.sync();
+
+    let mut permissions = fs::metadata(&case.local).unwrap().permissions();
+    permissions.set_mode(0o755);
+    fs::set_permissions(&case.local, permissions).unwrap();
+
+    let output_text = combined_output(&output);
+    assert!(!output.status.success(), "{}", output_text);
+    assert!(output_text.contains("not writable"), "{}", output_text);
+    assert_eq!(read(&local_file), "one local\ntwo\nthree\n");
+    assert_eq!(read(&remote_file), "one\ntwo\nthree remote\n");
+
+    assert_success(case.sync());
+    assert_eq!(read(&local_file), "one local\ntwo\nthree remote\n");
+    assert_eq!(read(&remote_file), "one local\ntwo\nthree remote\n");
+}
+
+/// The files in the trash under `root`, as (original path, contents).
+fn trashed_in(root: &Path) -> Vec<(String, String)> {
+    fn walk(dir: &Path, files: &mut Vec<PathBuf>) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                walk(&path, files);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    let trash = root.join(".duet-trash");
+    let mut files = Vec::new();
+    if trash.exists() {
+        walk(&trash, &mut files);
+    }
+    // below the trash come the profile's id and the run
+    let mut files: Vec<(String, String)> = files
+        .iter()
+        .map(|path| {
+            let original: PathBuf = path.strip_prefix(&trash).unwrap().iter().skip(2).collect();
+            (original.display().to_string(), read(path))
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+#[test]
+fn removed_and_replaced_files_are_kept_in_the_trash() {
+    let case = SyncCase::new_with_rules("+.\n[trash]\nmax-age = 30d\n");
+    write(&case.local.join("a.txt"), "first a");
+    write(&case.local.join("b.txt"), "first b");
+    assert_success(case.sync());
+
+    fs::remove_file(case.local.join("a.txt")).unwrap();
+    write(&case.local.join("b.txt"), "second b, longer");
+    assert_success(case.sync());
+
+    assert!(!case.remote.join("a.txt").exists());
+    assert_eq!(read(&case.remote.join("b.txt")), "second b, longer");
+    assert_eq!(
+        trashed_in(&case.remote),
+        vec![
+            ("a.txt".to_string(), "first a".to_string()),
+            ("b.txt".to_string(), "first b".to_string()),
+        ]
+    );
+    assert!(trashed_in(&case.local).is_empty());
+
+    let output = case.sync();
+    let text = combined_output(&output);
+    assert_success(output);
+    assert!(text.contains("No changes detected"), "{}", text);
+}
+
+#[test]
+fn trash_commands_list_restore_and_purge_each_side() {
+    let temp = tempfile::tempdir().unwrap();
+    let home = temp.path().join("home");
+    let config = home.join(".config").join("duet");
+    let local = temp.path().join("local");
+    let remote = temp.path().join("remote");
+    fs::create_dir_all(&config).unwrap();
+    fs::create_dir(&local).unwrap();
+    fs::create_dir(&remote).unwrap();
+    fs::write(
+        config.join("work.prf"),
+        format!(
+            "{}\n{} {}\n+.\n[staging]\nreserve = 0%\n[trash]\n",
+            local.display(),
+            duet_bin().display(),
+            remote.display()
+        ),
+    )
+    .unwrap();
+    let duet = |args: &[&str]| {
+        Command::new(duet_bin())
+            .args(args)
+            .env("HOME", &home)
+            .env("NO_COLOR", "1")
+            .output()
+            .unwrap()
+    };
+
+    write(&local.join("a.txt"), "kept");
+    assert_success(duet(&["work", "-b"]));
+    fs::remove_file(local.join("a.txt")).unwrap();
+    assert_success(duet(&["work", "-b"]));
+    assert!(!remote.join("a.txt").exists());
+
+    let output = duet(&["trash", "list", "work"]);
+    let text = combined_output(&output);
+    assert_success(output);
+    assert!(text.contains("The local trash is empty"), "{}", text);
+
+    let output = duet(&["trash", "list", "--remote", "work"]);
+    let text = String::from_utf8_lossy(&output.stdout).to_string();
+    assert_success(output);
+    let name = text
+        .lines()
+        .find_map(|line| line.trim().rsplit_once("  ").map(|(_, name)| name))
+        .filter(|name| name.ends_with("/a.txt"))
+        .unwrap_or_else(|| panic!("no trash item in {}", text))
+        .to_string();
+
+    assert_success(duet(&["trash", "restore", "--remote", "work", &name]));
+    assert_eq!(read(&remote.join("a.txt")), "kept");
+    assert_success(duet(&["work", "-b"]));
+    assert_eq!(read(&local.join("a.txt")), "kept");
+
+    write(&local.join("a.txt"), "rewritten");
+    assert_success(duet(&["work", "-b"]));
+    let output = duet(&["trash", "purge", "--remote", "work"]);
+    assert!(!output.status.success());
+    assert!(combined_output(&output).contains("--all"));
+    let output = duet(&["trash", "purge", "--all", "--remote", "work"]);
+    let text = combined_output(&output);
+    assert_success(output);
+    assert!(text.contains("Purged 1 runs"), "{}", text);
+    assert!(trashed_in(&remote).is_empty());
+}
+
+#[test]
+fn batch_conflict_aborts_without_changing_files() {
+    let case = SyncCase::new();
+    let local_file = case.local.join("a.txt");
+    let remote_file = case.remote.join("a.txt");
+    write(&local_file, "initial");
+    assert_success(case.sync());
+
+    write(&local_file, "local changed");
+    write(&remote_file, "remote changed");
+
+    let output = case.sync();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(read(&local_file), "local changed");
+    assert_eq!(read(&remote_file), "remote changed");
+}
+
+#[test]
+fn forced_descendant_conflict_keeps_destructive_ancestor_pending() {
+    let case = SyncCase::new_with_rules("+.\n");
+    fs::create_dir(case.local.join("tree")).unwrap();
+    write(&case.local.join("tree/conflict.txt"), "initial");
+    write(&case.local.join("independent.txt"), "initial");
+    assert_success(case.sync());
+
+    fs::remove_dir_all(case.local.join("tree")).unwrap();
+    write(&case.remote.join("tree/conflict.txt"), "remote changed");
+    write(&case.local.join("independent.txt"), "local changed");
+
+    let output = case.sync_with_args(&["--force"]);
+    let text = combined_output(&output);
+
+    assert_success(output);
+    assert!(
+        text.contains("Skipping tree") && text.contains("structurally dependent"),
+        "{}",
+        text
+    );
+    assert!(!case.local.join("tree").exists());
+    assert_eq!(
+        read(&case.remote.join("tree/conflict.txt")),
+        "remote changed"
+    );
+    assert_eq!(read(&case.remote.join("independent.txt")), "local changed");
+}
+
+#[test]
+fn debug_info_reports_negotiated_capabilities() {
+    let case = SyncCase::new();
+    write(&case.local.join("a.txt"), "from local");
+
+    let output = case.sync_with_args(&["--debug-info"]);
+
+    assert_success(output);
+
+    let output = case.sync_with_args(&["--debug-info"]);
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    assert_success(output);
+    assert!(stdout.contains("Debug information:"), "{}", stdout);
+    assert!(stdout.contains("client protocol:"), "{}", stdout);
+    assert!(stdout.contains("server protocol:"), "{}", stdout);
+    assert!(
+        stdout.contains(
+            "agreed capabilities: profile-file-state-dir, streamed-details-v1, streamed-detail-batches-v1"
+        ),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("sync-tuning-v1"), "{}", stdout);
+    assert!(stdout.contains("stream-performance-v1"), "{}", stdout);
+    assert!(stdout.contains("file-byte-chunks-v1"), "{}", stdout);
+    assert!(stdout.contains("sync tuning:"), "{}", stdout);
+    assert!(stdout.contains("detail-batch-frames=256"), "{}", stdout);
+}
+
+#[test]
+fn named_profile_debug_info_reports_negotiated_capabilities() {
     let temp = tempfile::tempdir().unwrap();
     let home = temp.path().join("home");
     let config = home.join(".config").join("duet");
@@ -640,6 +1816,209 @@ fn named_profile_debug_info_reports_negotiated_capabilities() {
     assert_eq!(read(&remote.join("a.txt")), "from local");
 }
 
+#[test]
+fn info_shows_the_merged_profile_with_rule_sources() {
+    let temp = tempfile::tempdir().unwrap();
+    let home = temp.path().join("home");
+    let config = home.join(".config").join("duet");
+    fs::create_dir_all(&config).unwrap();
+    fs::write(config.join("common.prf"), "[ignore]\n*.tmp\n").unwrap();
+    fs::write(
+        config.join("work.prf"),
+        "/local\nremote /remote\n+docs\ninclude common.prf\n",
+    )
+    .unwrap();
+
+    let output = Command::new(duet_bin())
+        .arg("_info")
+        .arg("work")
+        .env("HOME", &home)
+        .env("NO_COLOR", "1")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+
+    assert_success(output);
+    let work = config.join("work.prf");
+    let common = config.join("common.prf");
+    assert!(
+        stdout.contains(&format!("+docs {}# {}:3", " ".repeat(35), work.display())),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("[ignore]\n*.tmp"), "{}", stdout);
+    assert!(
+        stdout.contains(&format!("# {}:2", common.display())),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn quoted_remote_settings_sync_paths_with_spaces() {
+    let temp = tempfile::tempdir().unwrap();
+    let local = temp.path().join("local dir");
+    let remote = temp.path().join("remote dir");
+    fs::create_dir(&local).unwrap();
+    fs::create_dir(&remote).unwrap();
+    let profile = temp.path().join("spaces.prf");
+    fs::write(
+        &profile,
+        format!(
+            "'{}'\ncommand='{}' base=\"${{DUET_TEST_REMOTE}}\"\n+'a b.txt'\n",
+            local.display(),
+            duet_bin().display(),
+        ),
+    )
+    .unwrap();
+    write(&local.join("a b.txt"), "spaced");
+
+    let output = Command::new(duet_bin())
+        .arg("--profile-file")
+        .arg(&profile)
+        .arg("-b")
+        .env("DUET_TEST_REMOTE", &remote)
+        .env("NO_COLOR", "1")
+        .output()
+        .unwrap();
+
+    assert_success(output);
+    assert_eq!(read(&remote.join("a b.txt")), "spaced");
+}
+
+#[test]
+fn profile_check_prints_the_effective_profile_or_the_error_position() {
+    let temp = tempfile::tempdir().unwrap();
+    let home = temp.path().join("home");
+    let config = home.join(".config").join("duet");
+    fs::create_dir_all(&config).unwrap();
+    let work = config.join("work.prf");
+    fs::write(
+        &work,
+        "~/work\nssh host '/opt/my duet/duet' ~/work\n+\"${DUET_TEST_DIR}/x\"\n[ignore]\n*.tmp\n",
+    )
+    .unwrap();
+    let check = || {
+        Command::new(duet_bin())
+            .args(["profile", "check", "work"])
+            .env("HOME", &home)
+            .env("DUET_TEST_DIR", "docs")
+            .env("NO_COLOR", "1")
+            .output()
+            .unwrap()
+    };
+
+    let output = check();
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    assert_success(output);
+    assert!(
+        stdout.contains(&format!("local    {}/work\n", home.display())),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("remote   server=host command='/opt/my duet/duet' base=~/work"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("\n+docs/x\n"), "{}", stdout);
+    assert!(
+        stdout.contains(&format!(
+            "[ignore]\n*.tmp {}# {}:5",
+            " ".repeat(35),
+            work.display()
+        )),
+        "{}",
+        stdout
+    );
+
+    fs::write(&work, "~/work\nssh host duet ~/work\n+\"docs\n").unwrap();
+    let output = check();
+    let text = combined_output(&output);
+    assert!(!output.status.success(), "{}", text);
+    assert!(
+        text.contains(&format!(
+            "{}:3:2: unterminated double quote",
+            work.display()
+        )),
+        "{}",
+        text
+    );
+}
+
+#[test]
+fn status_shows_each_profile_with_its_snapshots_and_reports_broken_ones() {
+    let temp = tempfile::tempdir().unwrap();
+    let home = temp.path().join("home");
+    let config = home.join(".config").join("duet");
+    let local = temp.path().join("local");
+    let remote = temp.path().join("remote");
+    fs::create_dir_all(&config).unwrap();
+    fs::create_dir(&local).unwrap();
+    fs::create_dir(&remote).unwrap();
+    fs::write(
+        config.join("work.prf"),
+        format!(
+            "{}\n{} {}\n+.\n",
+            local.display(),
+            duet_bin().display(),
+            remote.display()
+        ),
+    )
+    .unwrap();
+    write(&local.join("a.txt"), "from local");
+    write(&local.join("b.txt"), "from local");
+    let duet = |args: &[&str]| {
+        Command::new(duet_bin())
+            .args(args)
+            .env("HOME", &home)
+            .env("NO_COLOR", "1")
+            .output()
+            .unwrap()
+    };
+
+    let output = duet(&["status", "work"]);
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    assert_success(output);
+    assert!(
+        stdout.contains(&format!("work\n  local    {}\n", local.display())),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("    synced   never\n"), "{}", stdout);
+
+    assert_success(duet(&["-b", "work"]));
+    let output = duet(&["status", "--remote", "work"]);
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    assert_success(output);
+    assert!(stdout.contains(" 2 entries ("), "{}", stdout);
+    assert!(stdout.contains("    server   "), "{}", stdout);
+    assert!(!stdout.contains("never"), "{}", stdout);
+    assert!(!stdout.contains("no successful sync"), "{}", stdout);
+    assert!(!stdout.contains("unfinished apply"), "{}", stdout);
+
+    // a dry run is not a sync that finished, so neither side's time moves
+    let before_dry_run = stdout;
+    assert_success(duet(&["-b", "-n", "work"]));
+    let output = duet(&["status", "--remote", "work"]);
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    assert_success(output);
+    assert_eq!(stdout, before_dry_run);
+
+    fs::write(config.join("broken.prf"), "/local\n\"remote\n").unwrap();
+    let output = duet(&["status"]);
+    let text = combined_output(&output);
+    assert!(!output.status.success(), "{}", text);
+    assert!(text.contains("broken\n  "), "{}", text);
+    assert!(text.contains("unterminated double quote"), "{}", text);
+    assert!(text.contains("work\n  local"), "{}", text);
+    assert!(
+        text.contains("unable to show the status of 1 of 2 profiles"),
+        "{}",
+        text
+    );
+}
+
 #[test]
 fn performance_profile_reports_human_and_json_output() {
     let case = SyncCase::new();
@@ -139,13 +139,13 @@ fn apply_marker_for(state_path: &Path) -> PathBuf {
     state_path.with_file_name(format!(".{}.duet-apply", file_name))
 }
 
+// the snapshot, not the hidden files kept beside it
 fn remote_state_file(case: &SyncCase) -> PathBuf {
     fs::read_dir(case.remote_state_dir())
         .unwrap()
-        .next()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| !path.file_name().unwrap().to_string_lossy().starts_with('.'))
         .unwrap()
-        .unwrap()
-        .path()
 }
 
 fn wait_for_path_while_child_runs(path: &Path, child: &mut Child) {